@@ -1,27 +1,69 @@
-static const uint MAX_INFLUENTIAL_LIGHTS = 8;
-
-enum LightType : uint
+// Point and spot lights, clustered per view (see passes/light_cull.rs)
+struct Light
 {
-    LightType_Point,
+    float3 position;
+    float range; // no influence past this distance
+    float3 color; // linear, scaled by intensity
+    float cos_half_angle; // the cone of spot lights, -1 for point lights
+    float3 direction; // spot lights only
 };
 
-struct Light
+[[vk::binding(0, 5)]]
+cbuffer ClusterGrid
 {
-    LightType type;
-    float3 position;
-    float3 direction;
-    uint color_rgb;
+    float4x4 ClusterView;
+    uint3 ClusterGridSize;
+    uint ClusterLightCount;
+    float2 ClusterScreenSize;
+    float ClusterDepthScale;
+    float ClusterDepthBias;
 };
 
-struct Lights
+[[vk::binding(1, 5)]]
+StructuredBuffer<Light> Lights;
+[[vk::binding(2, 5)]]
+StructuredBuffer<uint2> ClusterRanges; // (offset, count) into ClusterLightIndices
+[[vk::binding(3, 5)]]
+StructuredBuffer<uint> ClusterLightIndices;
+
+// The range of lights in ClusterLightIndices that may influence a pixel
+uint2 GetClusterLights(float2 pixel_position, float3 world_position)
 {
-    uint ambient_color_rgb;
+    float view_depth = mul(ClusterView, float4(world_position, 1)).z;
 
-    uint num_influential_lights;
-    uint influential_lights[MAX_INFLUENTIAL_LIGHTS];
-};
+    uint3 cluster;
+    cluster.xy = uint2(pixel_position / ClusterScreenSize * float2(ClusterGridSize.xy));
+    cluster.z = uint(max(log(max(view_depth, 1e-6)) * ClusterDepthScale + ClusterDepthBias, 0));
+    cluster = min(cluster, ClusterGridSize - 1);
+
+    return ClusterRanges[(cluster.z * ClusterGridSize.y + cluster.y) * ClusterGridSize.x + cluster.x];
+}
+
+// The radiance arriving at a world position from a light, along with the direction towards the light
+float3 LightRadiance(Light light, float3 world_position, out float3 to_light)
+{
+    float3 offset = light.position - world_position;
+    float dist_sq = max(dot(offset, offset), 1e-6);
+    to_light = offset * rsqrt(dist_sq);
+
+    // inverse square falloff, windowed to reach zero at the light's range
+    float range_frac = dist_sq / (light.range * light.range);
+    float window = saturate(1 - range_frac * range_frac);
+    float attenuation = window * window / max(dist_sq, 0.01);
+
+    if (light.cos_half_angle > -1)
+    {
+        float cos_angle = dot(-to_light, light.direction);
+        float edge = lerp(light.cos_half_angle, 1, 0.1); // soften the outer tenth of the cone
+        attenuation *= smoothstep(light.cos_half_angle, edge, cos_angle);
+    }
+
+    return light.color * attenuation;
+}
 
-struct Env
+// The direction from a world position towards the camera, ClusterView is the (rigid) view matrix
+float3 ToViewer(float3 world_position)
 {
-    Lights lights;
-};
\ No newline at end of file
+    float3 view_position = mul(ClusterView, float4(world_position, 1)).xyz;
+    return -normalize(mul(view_position, (float3x3)ClusterView));
+}
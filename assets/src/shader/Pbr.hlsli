@@ -0,0 +1,137 @@
+// The material bindings and lighting shared by the PBR pixel shaders
+#include "Colors.hlsli"
+#include "Lighting.hlsli"
+#include "Shadows.hlsli"
+
+[[vk::binding(0, 3)]]
+cbuffer PerMesh_Pbr
+{
+    uint albedo_color;
+    float metallicity;
+    float roughness;
+    float alpha_cutoff;
+    uint emissive_color;
+};
+
+// unset textures are bound to defaults that leave the factors above unchanged (see PbrTextureSlot::default_texel)
+[[vk::binding(1, 3)]]
+SamplerState Sampler;
+[[vk::binding(2, 3)]]
+Texture2D<float4> AlbedoTex;
+[[vk::binding(3, 3)]]
+Texture2D<float4> MetallicRoughnessTex; // metallicity in B, roughness in G
+[[vk::binding(4, 3)]]
+Texture2D<float4> NormalTex; // tangent space XY in [0, 1]
+[[vk::binding(5, 3)]]
+Texture2D<float4> OcclusionTex; // R
+[[vk::binding(6, 3)]]
+Texture2D<float4> EmissiveTex;
+
+struct PixelInput
+{
+    float4 world_position: POSITION;
+    float4 clip_position: SV_POSITION;
+    float4 normal: NORMAL;
+    float2 tex_coord: TEXCOORD0;
+};
+
+struct PbrSurface
+{
+    float3 albedo;
+    float alpha;
+    float metallicity;
+    float roughness;
+    float occlusion;
+    float3 emissive;
+    float3 normal;
+};
+
+static const float PI = 3.14159265359;
+
+// Apply a tangent space normal map, with the tangent frame derived from screen space derivatives (vertices have no tangents)
+float3 PerturbNormal(float3 normal, float3 world_position, float2 tex_coord, float2 map_xy)
+{
+    float3 tangent_normal;
+    tangent_normal.xy = map_xy * 2 - 1;
+    tangent_normal.z = sqrt(saturate(1 - dot(tangent_normal.xy, tangent_normal.xy)));
+
+    float3 dp_dx = ddx(world_position);
+    float3 dp_dy = ddy(world_position);
+    float2 duv_dx = ddx(tex_coord);
+    float2 duv_dy = ddy(tex_coord);
+
+    float3 dp_dy_perp = cross(dp_dy, normal);
+    float3 dp_dx_perp = cross(normal, dp_dx);
+    float3 tangent = dp_dy_perp * duv_dx.x + dp_dx_perp * duv_dy.x;
+    float3 bitangent = dp_dy_perp * duv_dx.y + dp_dx_perp * duv_dy.y;
+    float frame_scale = rsqrt(max(max(dot(tangent, tangent), dot(bitangent, bitangent)), 1e-12));
+
+    return normalize((tangent * tangent_normal.x + bitangent * tangent_normal.y) * frame_scale + normal * tangent_normal.z);
+}
+
+// The material's textures combined with its factors
+PbrSurface SamplePbrSurface(PixelInput in_pixel)
+{
+    float4 albedo = AlbedoTex.Sample(Sampler, in_pixel.tex_coord) * UnpackRgba(albedo_color);
+    float4 metallic_roughness = MetallicRoughnessTex.Sample(Sampler, in_pixel.tex_coord);
+
+    PbrSurface surface;
+    surface.albedo = albedo.rgb;
+    surface.alpha = albedo.a;
+    surface.metallicity = saturate(metallic_roughness.b * metallicity);
+    surface.roughness = saturate(metallic_roughness.g * roughness);
+    surface.occlusion = OcclusionTex.Sample(Sampler, in_pixel.tex_coord).r;
+    surface.emissive = EmissiveTex.Sample(Sampler, in_pixel.tex_coord).rgb * UnpackRgba(emissive_color).rgb;
+    surface.normal = PerturbNormal(normalize(in_pixel.normal.xyz), in_pixel.world_position.xyz, in_pixel.tex_coord,
+        NormalTex.Sample(Sampler, in_pixel.tex_coord).xy);
+    return surface;
+}
+
+// The reflected radiance (per unit of incoming radiance) towards the viewer, Cook-Torrance GGX with a Lambert diffuse
+float3 PbrBrdf(PbrSurface surface, float3 to_viewer, float3 to_light)
+{
+    float n_dot_l = max(dot(surface.normal, to_light), 0.0);
+    float n_dot_v = max(dot(surface.normal, to_viewer), 1e-4);
+    float3 halfway = normalize(to_viewer + to_light);
+    float n_dot_h = max(dot(surface.normal, halfway), 0.0);
+    float v_dot_h = max(dot(to_viewer, halfway), 0.0);
+
+    float alpha = max(surface.roughness * surface.roughness, 1e-3);
+    float alpha_sq = alpha * alpha;
+    float d_denom = n_dot_h * n_dot_h * (alpha_sq - 1) + 1;
+    float distribution = alpha_sq / (PI * d_denom * d_denom);
+
+    float k = (surface.roughness + 1) * (surface.roughness + 1) / 8;
+    float geometry = (n_dot_v / (n_dot_v * (1 - k) + k)) * (n_dot_l / (n_dot_l * (1 - k) + k));
+
+    float3 f0 = lerp(0.04, surface.albedo, surface.metallicity);
+    float3 fresnel = f0 + (1 - f0) * pow(1 - v_dot_h, 5);
+
+    float3 specular = distribution * geometry * fresnel / (4 * n_dot_v * max(n_dot_l, 1e-4));
+    float3 diffuse = (1 - fresnel) * (1 - surface.metallicity) * surface.albedo / PI;
+    return (diffuse + specular) * n_dot_l;
+}
+
+// The lit color of a surface, from the clustered lights and the (shadowed) sun
+float3 PbrShade(PixelInput in_pixel, PbrSurface surface)
+{
+    float3 ambient = 0.03;
+    float3 to_viewer = ToViewer(in_pixel.world_position.xyz);
+
+    float3 Lo = 0;
+    uint2 cluster_lights = GetClusterLights(in_pixel.clip_position.xy, in_pixel.world_position.xyz);
+    for (uint i = 0; i < cluster_lights.y; ++i)
+    {
+        Light light = Lights[ClusterLightIndices[cluster_lights.x + i]];
+        float3 to_light;
+        float3 radiance = LightRadiance(light, in_pixel.world_position.xyz, to_light);
+        Lo += PbrBrdf(surface, to_viewer, to_light) * radiance;
+    }
+
+    if (ShadowCascadeCount > 0)
+    {
+        Lo += PbrBrdf(surface, to_viewer, -ShadowLightDirection) * SampleShadow(in_pixel.world_position.xyz);
+    }
+
+    return Lo + ambient * surface.albedo * surface.occlusion + surface.emissive;
+}
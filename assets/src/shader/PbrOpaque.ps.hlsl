@@ -1,57 +1,8 @@
-#include "Colors.hlsli"
-#include "Lighting.hlsli"
+#include "Pbr.hlsli"
 
-[[vk::binding(0, 3)]]
-cbuffer PerMesh_Pbr
-{
-    uint albedo_color;
-    float metallicity;
-    float roughness;
-};
-
-[[vk::binding(1, 3)]]
-SamplerState Sampler;
-[[vk::binding(2, 3)]]
-Texture2D<float4> Tex;
-
-struct PixelInput
-{
-    float4 world_position: POSITION;
-    float4 clip_position: SV_POSITION;
-    float4 normal: NORMAL;
-    float2 tex_coord: TEXCOORD0;
-};
-
-static const float PI = 3.14159265359;
 float4 ps_main(PixelInput in_pixel) : SV_Target
 {
-    Light light =
-    {
-        LightType_Point,
-        float3(0, 5, -5),
-        float3(0.2673, 0.5345, 0.8018),
-        PackRgba(float4(1, 1, 1, 1)),
-    };
-    float3 ambient = 0.03;
-
-    float3 albedo = 1;
-    float metallicity = 0.5;
-    float roughness = 0.2;
-    float ao = 0.5;
-
-    float light_dist_sq = max(0, dot(light.position, in_pixel.world_position.xyz));
-    float attenuation = 1 / light_dist_sq; // rcp?
-    float3 radiance = light.color_rgb * attenuation;
-
-    float n_dot_l = max(dot(in_pixel.normal.xyz, light.direction), 0.0);
-
-    float kD = 0.5; // TODO
-    float specular = 0;
-
-    float3 Lo = 0.1;
-    Lo += (kD * albedo / PI + specular) * radiance * n_dot_l;
-//     return float4(Lo, 1) + float4(ambient * albedo * ao, 0);
-
-    float4 tex_col = Tex.Sample(Sampler, in_pixel.tex_coord);
-    return tex_col * float4(Lo, 1) + float4(ambient * albedo * ao, 0);
-}
\ No newline at end of file
+    PbrSurface surface = SamplePbrSurface(in_pixel);
+    clip(surface.alpha - alpha_cutoff);
+    return float4(PbrShade(in_pixel, surface), surface.alpha);
+}
@@ -0,0 +1,8 @@
+#include "Pbr.hlsli"
+
+// Outputs premultiplied alpha, masked materials are drawn with PbrOpaque instead
+float4 ps_main(PixelInput in_pixel) : SV_Target
+{
+    PbrSurface surface = SamplePbrSurface(in_pixel);
+    return float4(PbrShade(in_pixel, surface) * surface.alpha, surface.alpha);
+}
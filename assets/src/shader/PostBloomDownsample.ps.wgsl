@@ -0,0 +1,32 @@
+// Downsample a bloom mip into the next (half size) one with a 13 tap filter
+// see "Next Generation Post Processing in Call of Duty: Advanced Warfare" (Jimenez, 2014)
+@group(0) @binding(1)
+var source: texture_2d<f32>;
+@group(0) @binding(4)
+var linear_sampler: sampler;
+
+struct VertexOutput
+{
+    @builtin(position) clip_position: vec4f,
+    @location(0) uv: vec2f,
+};
+
+fn tap(uv: vec2f, offset: vec2f, texel: vec2f) -> vec3f
+{
+    return textureSampleLevel(source, linear_sampler, uv + offset * texel, 0.0).rgb;
+}
+
+@fragment
+fn ps_main(in_frag: VertexOutput) -> @location(0) vec4f
+{
+    let texel = 1.0 / vec2f(textureDimensions(source));
+    let uv = in_frag.uv;
+
+    let outer_corners = tap(uv, vec2f(-2.0, -2.0), texel) + tap(uv, vec2f(2.0, -2.0), texel) + tap(uv, vec2f(-2.0, 2.0), texel) + tap(uv, vec2f(2.0, 2.0), texel);
+    let outer_edges = tap(uv, vec2f(0.0, -2.0), texel) + tap(uv, vec2f(-2.0, 0.0), texel) + tap(uv, vec2f(2.0, 0.0), texel) + tap(uv, vec2f(0.0, 2.0), texel);
+    let inner = tap(uv, vec2f(-1.0, -1.0), texel) + tap(uv, vec2f(1.0, -1.0), texel) + tap(uv, vec2f(-1.0, 1.0), texel) + tap(uv, vec2f(1.0, 1.0), texel);
+    let center = tap(uv, vec2f(0.0, 0.0), texel);
+
+    let color = center * 0.125 + outer_corners * 0.03125 + outer_edges * 0.0625 + inner * 0.125;
+    return vec4f(color, 1.0);
+}
@@ -0,0 +1,68 @@
+// The first bloom downsample, keeping only the (exposed) colors brighter than the threshold
+struct PostUniform
+{
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    low_percentile: f32,
+    high_percentile: f32,
+    exposure_adaptation: f32,
+    exposure_compensation: f32,
+    min_log_exposure: f32,
+    max_log_exposure: f32,
+    manual_exposure: f32,
+    auto_exposure: u32,
+    bloom_threshold: f32,
+    bloom_knee: f32,
+    bloom_intensity: f32,
+    tonemapper: u32,
+    lut_strength: f32,
+    encode_srgb: u32,
+};
+struct Exposure
+{
+    exposure: f32, // scene colors are multiplied by this
+    average_luminance: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> post: PostUniform;
+@group(0) @binding(1)
+var source: texture_2d<f32>;
+@group(0) @binding(3)
+var<storage, read> exposure: Exposure;
+@group(0) @binding(4)
+var linear_sampler: sampler;
+
+struct VertexOutput
+{
+    @builtin(position) clip_position: vec4f,
+    @location(0) uv: vec2f,
+};
+
+const LUMINANCE = vec3f(0.2126, 0.7152, 0.0722);
+
+@fragment
+fn ps_main(in_frag: VertexOutput) -> @location(0) vec4f
+{
+    // four bilinear taps cover the 4x4 source texels under this (half resolution) pixel
+    // taps are weighted by their inverse luminance (Karis average), so single bright pixels don't flicker
+    let texel = 1.0 / vec2f(textureDimensions(source));
+    var color = vec3f(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < 4u; i++)
+    {
+        let offset = vec2f(f32(i & 1u), f32(i >> 1u)) * 2.0 - 1.0;
+        let tap = textureSample(source, linear_sampler, in_frag.uv + offset * texel).rgb * exposure.exposure;
+        let tap_weight = 1.0 / (1.0 + dot(tap, LUMINANCE));
+        color += tap * tap_weight;
+        weight += tap_weight;
+    }
+    color /= weight;
+
+    // a quadratic curve around the threshold avoids a hard cut-off
+    let brightness = max(color.r, max(color.g, color.b));
+    var soft = clamp(brightness - post.bloom_threshold + post.bloom_knee, 0.0, 2.0 * post.bloom_knee);
+    soft = (soft * soft) / (4.0 * post.bloom_knee + 0.00001);
+    let contribution = max(soft, brightness - post.bloom_threshold) / max(brightness, 0.00001);
+    return vec4f(color * contribution, 1.0);
+}
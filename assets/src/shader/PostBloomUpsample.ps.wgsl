@@ -0,0 +1,30 @@
+// Upsample a bloom mip with a 3x3 tent filter, added (by the blend state) onto the next larger mip
+@group(0) @binding(1)
+var source: texture_2d<f32>;
+@group(0) @binding(4)
+var linear_sampler: sampler;
+
+struct VertexOutput
+{
+    @builtin(position) clip_position: vec4f,
+    @location(0) uv: vec2f,
+};
+
+fn tap(uv: vec2f, offset: vec2f, texel: vec2f) -> vec3f
+{
+    return textureSampleLevel(source, linear_sampler, uv + offset * texel, 0.0).rgb;
+}
+
+@fragment
+fn ps_main(in_frag: VertexOutput) -> @location(0) vec4f
+{
+    let texel = 1.0 / vec2f(textureDimensions(source));
+    let uv = in_frag.uv;
+
+    let corners = tap(uv, vec2f(-1.0, -1.0), texel) + tap(uv, vec2f(1.0, -1.0), texel) + tap(uv, vec2f(-1.0, 1.0), texel) + tap(uv, vec2f(1.0, 1.0), texel);
+    let edges = tap(uv, vec2f(0.0, -1.0), texel) + tap(uv, vec2f(-1.0, 0.0), texel) + tap(uv, vec2f(1.0, 0.0), texel) + tap(uv, vec2f(0.0, 1.0), texel);
+    let center = tap(uv, vec2f(0.0, 0.0), texel);
+
+    let color = (center * 4.0 + edges * 2.0 + corners) / 16.0;
+    return vec4f(color, 1.0);
+}
@@ -0,0 +1,91 @@
+// Average the luminance histogram and adapt the exposure towards it, run as a single workgroup
+struct PostUniform
+{
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    low_percentile: f32,
+    high_percentile: f32,
+    exposure_adaptation: f32,
+    exposure_compensation: f32,
+    min_log_exposure: f32,
+    max_log_exposure: f32,
+    manual_exposure: f32,
+    auto_exposure: u32,
+    bloom_threshold: f32,
+    bloom_knee: f32,
+    bloom_intensity: f32,
+    tonemapper: u32,
+    lut_strength: f32,
+    encode_srgb: u32,
+};
+struct Exposure
+{
+    exposure: f32, // scene colors are multiplied by this
+    average_luminance: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> post: PostUniform;
+@group(0) @binding(2)
+var<storage, read_write> histogram: array<u32, 256>;
+@group(0) @binding(3)
+var<storage, read_write> exposure: Exposure;
+
+var<workgroup> bins: array<u32, 256>;
+
+// The log2 luminance at the center of a bin (see luminance_bin() in PostHistogram.cs.wgsl)
+fn bin_log_luminance(bin: u32) -> f32
+{
+    return post.min_log_luminance + min((f32(bin) - 0.5) / 254.0, 1.0) * post.log_luminance_range;
+}
+
+@compute @workgroup_size(256)
+fn cs_main(@builtin(local_invocation_index) local_index: u32)
+{
+    bins[local_index] = histogram[local_index];
+    histogram[local_index] = 0u; // ready for the next frame
+    workgroupBarrier();
+
+    if (local_index != 0u)
+    {
+        return;
+    }
+
+    if (post.auto_exposure == 0u)
+    {
+        exposure.exposure = post.manual_exposure;
+        return;
+    }
+
+    var total = 0u;
+    for (var bin = 1u; bin < 256u; bin++)
+    {
+        total += bins[bin];
+    }
+
+    // only the pixels between the low and high percentiles are averaged
+    let low = f32(total) * post.low_percentile;
+    let high = f32(total) * post.high_percentile;
+    var below = 0.0;
+    var sum = 0.0;
+    var weight = 0.0;
+    for (var bin = 1u; bin < 256u; bin++)
+    {
+        let count = f32(bins[bin]);
+        let included = max(min(below + count, high) - max(below, low), 0.0);
+        sum += included * bin_log_luminance(bin);
+        weight += included;
+        below += count;
+    }
+    if (weight <= 0.0)
+    {
+        return; // nothing to adapt to, keep the current exposure
+    }
+
+    // expose the average to middle gray
+    let average_log = sum / weight;
+    let target_log = clamp(log2(0.18) - average_log, post.min_log_exposure, post.max_log_exposure) + post.exposure_compensation;
+    let current_log = log2(max(exposure.exposure, 1e-8));
+    exposure.exposure = exp2(mix(current_log, target_log, post.exposure_adaptation));
+    exposure.average_luminance = exp2(average_log);
+}
@@ -0,0 +1,17 @@
+// A single triangle covering the screen, drawn without any vertex buffers
+struct VertexOutput
+{
+    @builtin(position) clip_position: vec4f,
+    @location(0) uv: vec2f, // (0, 0) is the top left of the screen
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput
+{
+    let uv = vec2f(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+
+    var out_vertex: VertexOutput;
+    out_vertex.clip_position = vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out_vertex.uv = uv;
+    return out_vertex;
+}
@@ -0,0 +1,64 @@
+// Bin the luminance of every scene pixel, for auto exposure (see PostExposure.cs.wgsl)
+struct PostUniform
+{
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    low_percentile: f32,
+    high_percentile: f32,
+    exposure_adaptation: f32,
+    exposure_compensation: f32,
+    min_log_exposure: f32,
+    max_log_exposure: f32,
+    manual_exposure: f32,
+    auto_exposure: u32,
+    bloom_threshold: f32,
+    bloom_knee: f32,
+    bloom_intensity: f32,
+    tonemapper: u32,
+    lut_strength: f32,
+    encode_srgb: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> post: PostUniform;
+@group(0) @binding(1)
+var source: texture_2d<f32>;
+@group(0) @binding(2)
+var<storage, read_write> histogram: array<atomic<u32>, 256>;
+
+var<workgroup> local_bins: array<atomic<u32>, 256>;
+
+const LUMINANCE = vec3f(0.2126, 0.7152, 0.0722);
+
+// Bin 0 holds (nearly) black pixels, which are ignored when averaging
+fn luminance_bin(luminance: f32) -> u32
+{
+    if (luminance < 0.0001)
+    {
+        return 0u;
+    }
+    let t = saturate((log2(luminance) - post.min_log_luminance) / post.log_luminance_range);
+    return u32(t * 254.0 + 1.0);
+}
+
+@compute @workgroup_size(16, 16)
+fn cs_main(@builtin(global_invocation_id) id: vec3u, @builtin(local_invocation_index) local_index: u32)
+{
+    atomicStore(&local_bins[local_index], 0u);
+    workgroupBarrier();
+
+    let size = textureDimensions(source);
+    if (all(id.xy < size))
+    {
+        let color = textureLoad(source, id.xy, 0).rgb;
+        atomicAdd(&local_bins[luminance_bin(dot(color, LUMINANCE))], 1u);
+    }
+    workgroupBarrier();
+
+    // one global atomic per bin per workgroup, rather than per pixel
+    let count = atomicLoad(&local_bins[local_index]);
+    if (count > 0u)
+    {
+        atomicAdd(&histogram[local_index], count);
+    }
+}
@@ -0,0 +1,130 @@
+// Expose the scene, add bloom, tonemap to the display range and color grade
+// The tonemapping curves are mirrored on the CPU by passes::post::Tonemapper
+struct PostUniform
+{
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    low_percentile: f32,
+    high_percentile: f32,
+    exposure_adaptation: f32,
+    exposure_compensation: f32,
+    min_log_exposure: f32,
+    max_log_exposure: f32,
+    manual_exposure: f32,
+    auto_exposure: u32,
+    bloom_threshold: f32,
+    bloom_knee: f32,
+    bloom_intensity: f32,
+    tonemapper: u32,
+    lut_strength: f32,
+    encode_srgb: u32,
+};
+struct Exposure
+{
+    exposure: f32, // scene colors are multiplied by this
+    average_luminance: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> post: PostUniform;
+@group(0) @binding(1)
+var source: texture_2d<f32>;
+@group(0) @binding(3)
+var<storage, read> exposure: Exposure;
+@group(0) @binding(4)
+var linear_sampler: sampler;
+@group(0) @binding(5)
+var bloom: texture_2d<f32>;
+@group(0) @binding(6)
+var color_grading_lut: texture_3d<f32>;
+
+struct VertexOutput
+{
+    @builtin(position) clip_position: vec4f,
+    @location(0) uv: vec2f,
+};
+
+const TONEMAPPER_ACES = 0u;
+const TONEMAPPER_AGX = 1u;
+
+// Stephen Hill's fit of the ACES RRT and ODT (sRGB output)
+fn tonemap_aces(color: vec3f) -> vec3f
+{
+    let input_mtx = mat3x3f(
+        0.59719, 0.07600, 0.02840,
+        0.35458, 0.90834, 0.13383,
+        0.04823, 0.01566, 0.83777);
+    let output_mtx = mat3x3f(
+        1.60475, -0.10256, -0.00327,
+        -0.53108, 1.10813, -0.07276,
+        -0.07367, -0.00605, 1.07602);
+
+    let v = input_mtx * color;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return saturate(output_mtx * (a / b));
+}
+
+// Troy Sobotka's AgX, using Benjamin Wrensch's polynomial fit of the default contrast curve
+fn tonemap_agx(color: vec3f) -> vec3f
+{
+    let inset_mtx = mat3x3f(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104);
+    let outset_mtx = mat3x3f(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116);
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    let log_color = clamp(log2(max(inset_mtx * color, vec3f(1e-10))), vec3f(min_ev), vec3f(max_ev));
+    let x = (log_color - min_ev) / (max_ev - min_ev);
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+    return pow(saturate(outset_mtx * curve), vec3f(2.2));
+}
+
+fn linear_to_srgb(color: vec3f) -> vec3f
+{
+    return select(1.055 * pow(color, vec3f(1.0 / 2.4)) - 0.055, color * 12.92, color <= vec3f(0.0031308));
+}
+
+fn srgb_to_linear(color: vec3f) -> vec3f
+{
+    return select(pow((color + 0.055) / 1.055, vec3f(2.4)), color / 12.92, color <= vec3f(0.04045));
+}
+
+@fragment
+fn ps_main(in_frag: VertexOutput) -> @location(0) vec4f
+{
+    let scene = textureLoad(source, vec2u(in_frag.clip_position.xy), 0).rgb;
+    let bloom_color = textureSampleLevel(bloom, linear_sampler, in_frag.uv, 0.0).rgb;
+    let hdr = scene * exposure.exposure + bloom_color * post.bloom_intensity;
+
+    var color: vec3f;
+    switch (post.tonemapper)
+    {
+        case TONEMAPPER_AGX: { color = tonemap_agx(hdr); }
+        default: { color = tonemap_aces(hdr); }
+    }
+
+    // LUTs are authored against (and output) sRGB encoded colors
+    var display = linear_to_srgb(color);
+    if (post.lut_strength > 0.0)
+    {
+        let lut_size = f32(textureDimensions(color_grading_lut).x);
+        let lut_uvw = display * ((lut_size - 1.0) / lut_size) + 0.5 / lut_size;
+        let graded = textureSampleLevel(color_grading_lut, linear_sampler, lut_uvw, 0.0).rgb;
+        display = mix(display, graded, post.lut_strength);
+    }
+
+    // sRGB render targets encode (linear) output themselves, other targets are written the encoded colors
+    if (post.encode_srgb != 0u)
+    {
+        return vec4f(display, 1.0);
+    }
+    return vec4f(srgb_to_linear(display), 1.0);
+}
@@ -11,3 +11,20 @@ cbuffer World
 {
     float4x4 World;
 };
+
+#ifdef INSTANCED
+// Per-instance vertex attributes, following the per-vertex attributes (see VertexCaps::Instanced)
+struct InstanceInput
+{
+    float4 world_0 : INSTANCE_WORLD0; // columns of the world transform
+    float4 world_1 : INSTANCE_WORLD1;
+    float4 world_2 : INSTANCE_WORLD2;
+    float4 world_3 : INSTANCE_WORLD3;
+};
+
+float4x4 InstanceWorld(InstanceInput instance)
+{
+    // matrix constructors take rows
+    return transpose(float4x4(instance.world_0, instance.world_1, instance.world_2, instance.world_3));
+}
+#endif
@@ -0,0 +1,47 @@
+static const uint MAX_SHADOW_CASCADES = 4;
+
+[[vk::binding(0, 4)]]
+cbuffer Shadows
+{
+    float4x4 CascadeProjViews[MAX_SHADOW_CASCADES];
+    float3 ShadowLightDirection;
+    uint ShadowCascadeCount;
+    float ShadowTexelSize; // in UVs
+};
+
+[[vk::binding(1, 4)]]
+Texture2DArray<float> ShadowCascades;
+[[vk::binding(2, 4)]]
+SamplerComparisonState ShadowSampler;
+
+// How lit a world position is by the shadowed light, 0 is fully shadowed
+float SampleShadow(float3 world_position)
+{
+    for (uint cascade = 0; cascade < ShadowCascadeCount; ++cascade)
+    {
+        float4 shadow_position = mul(CascadeProjViews[cascade], float4(world_position, 1));
+        float3 uvz = shadow_position.xyz / shadow_position.w;
+        float2 uv = uvz.xy * float2(0.5, -0.5) + 0.5;
+
+        // cascades are ordered near to far, use the first (most detailed) one that fits the filter
+        float border = ShadowTexelSize * 2;
+        if (any(uv < border) || any(uv > 1 - border) || uvz.z > 1)
+        {
+            continue;
+        }
+
+        // 3x3 PCF, each tap is also bilinearly filtered by the comparison sampler
+        float lit = 0;
+        [unroll] for (int y = -1; y <= 1; ++y)
+        {
+            [unroll] for (int x = -1; x <= 1; ++x)
+            {
+                float2 tap_uv = uv + float2(x, y) * ShadowTexelSize;
+                lit += ShadowCascades.SampleCmpLevelZero(ShadowSampler, float3(tap_uv, cascade), uvz.z);
+            }
+        }
+        return lit / 9;
+    }
+
+    return 1;
+}
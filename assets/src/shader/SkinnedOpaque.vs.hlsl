@@ -16,10 +16,18 @@ cbuffer PerModel_World
 
 #define MAX_SKINNED_BONES 128
 
+// see SkinnedBone in skeleton_poser.rs
+struct SkinnedBone
+{
+    DualQuat pose;
+    float4 scale[3]; // rows of an affine matrix that scales around the bone in bind space, applied before the pose
+    float4 bulge_axis; // xyz: the bone's direction, w: how much bulging to remove along it
+};
+
 [[vk::binding(0, 2)]]
 cbuffer PerModel_SkinnedPoses
 {
-    DualQuat SkinnedPoses[MAX_SKINNED_BONES];
+    SkinnedBone SkinnedBones[MAX_SKINNED_BONES];
 }
 
 struct VertexOutput
@@ -39,15 +47,47 @@ VertexOutput vs_main(
 {
     VertexOutput out_vertex;
 
+    SkinnedBone bones[4] = { SkinnedBones[indices.x], SkinnedBones[indices.y], SkinnedBones[indices.z], SkinnedBones[indices.w] };
+    float bone_weights[4] = { weights.x, weights.y, weights.z, weights.w };
+
+    // scales are blended linearly (in bind space), before skinning with dual quats
+    // https://rodolphe-vaillant.fr/entry/78/dual-quaternion-skinning-with-scale
+    float3x4 scale = 0;
+    [unroll] for (int i = 0; i < 4; ++i)
+    {
+        scale += float3x4(bones[i].scale[0], bones[i].scale[1], bones[i].scale[2]) * bone_weights[i];
+    }
+    float3 scaled_pos = mul(scale, float4(in_position, 1.0));
+    // normals are transformed by the inverse transpose, the cofactor matrix is the same up to scale
+    float3 r0 = scale[0].xyz, r1 = scale[1].xyz, r2 = scale[2].xyz;
+    float3 scaled_norm = normalize(mul(float3x3(cross(r1, r2), cross(r2, r0), cross(r0, r1)), in_normal));
+
     DualQuat blended = DualQuatBlend4(
-        SkinnedPoses[indices.x], weights.x,
-        SkinnedPoses[indices.y], weights.y,
-        SkinnedPoses[indices.z], weights.z,
-        SkinnedPoses[indices.w], weights.w);
+        bones[0].pose, bone_weights[0],
+        bones[1].pose, bone_weights[1],
+        bones[2].pose, bone_weights[2],
+        bones[3].pose, bone_weights[3]);
     blended = DualQuatNormalize(blended);
-    float3 transformed_pos = DualQuatTransformPoint(blended, in_position);
+    float3 transformed_pos = DualQuatTransformPoint(blended, scaled_pos);
+
+    // dual quats keep verts the same distance from each joint, which bulges out verts along the bone around bent joints
+    // linear blending does not bulge along the bone (but loses volume across it), so use its along-the-bone position
+    // https://rodolphe-vaillant.fr/entry/72/bulge-free-dual-quaternion-skinning-trick
+    float3 linear_pos = 0;
+    float4 bulge_axis = 0;
+    [unroll] for (int j = 0; j < 4; ++j)
+    {
+        linear_pos += DualQuatTransformPoint(bones[j].pose, scaled_pos) * bone_weights[j];
+        bulge_axis += bones[j].bulge_axis * bone_weights[j];
+    }
+    float axis_length = length(bulge_axis.xyz);
+    if (axis_length > 0.0001)
+    {
+        float3 axis = bulge_axis.xyz / axis_length;
+        transformed_pos += axis * (dot(linear_pos - transformed_pos, axis) * bulge_axis.w);
+    }
 
-    float3 transform_norm = DualQuatTransformDirection(blended, in_normal);
+    float3 transform_norm = DualQuatTransformDirection(blended, scaled_norm);
 //     float4 transform_tan = float4(DualQuatTransformDirection(blended, in_tangent.xyz), in_tangent.w);
 
 out_vertex.world_position = mul(World, float4(transformed_pos, 1.0));
@@ -12,10 +12,20 @@ struct VertexOutput
 VertexOutput vs_main(
     float3 in_position : POSITION,
     float3 in_normal : NORMAL,
-    float3 in_texcoord : TEXCOORD0)
+    float3 in_texcoord : TEXCOORD0
+#ifdef INSTANCED
+    , InstanceInput in_instance
+#endif
+    )
 {
+#ifdef INSTANCED
+    float4x4 world = InstanceWorld(in_instance);
+#else
+    float4x4 world = World;
+#endif
+
     VertexOutput out_vertex;
-    out_vertex.world_position = mul(World, float4(in_position, 1));
+    out_vertex.world_position = mul(world, float4(in_position, 1));
     out_vertex.clip_position = mul(ProjView, out_vertex.world_position);
     out_vertex.normal = float4(in_normal, 1);
     out_vertex.texcoord = in_texcoord;
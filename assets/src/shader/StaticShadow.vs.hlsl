@@ -1,7 +1,18 @@
 #include "Scene.hlsli"
 
-float4 vs_main(float3 in_position : POSITION) : SV_POSITION
+float4 vs_main(
+    float3 in_position : POSITION
+#ifdef INSTANCED
+    , InstanceInput in_instance
+#endif
+    ) : SV_POSITION
 {
-    float4 world_position = mul(World, float4(in_position, 1));
+#ifdef INSTANCED
+    float4x4 world = InstanceWorld(in_instance);
+#else
+    float4x4 world = World;
+#endif
+
+    float4 world_position = mul(world, float4(in_position, 1));
     return mul(ProjView, world_position);
 }
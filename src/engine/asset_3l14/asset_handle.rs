@@ -11,7 +11,7 @@ use std::mem::swap;
 use std::ops::Deref;
 use std::pin::Pin;
 use std::ptr::NonNull;
-use std::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicU32, Ordering};
 use std::task::{Context, Poll, Waker};
 use arc_swap::Guard;
 use triomphe::Arc;
@@ -138,6 +138,8 @@ pub(super) struct AshInnerHeader
 
     // todo: re-evaluate
     pub is_reloading: AtomicBool, // cleared before payload is set
+
+    pub generation: AtomicU32, // incremented each time a payload is stored
 }
 impl AshInnerHeader
 {
@@ -191,6 +193,7 @@ impl<A: Asset> AshInner<A>
         log::debug!("{:?} storing new payload", self.key());
 
         self.data.store(new_data.map(|d| Arc::new(d)));
+        self.header.generation.fetch_add(1, Ordering::Release);
 
         let mut waker_guard = self.header.ready_waker.lock();
         waker_guard.take().map(|waker| waker.wake());
@@ -223,6 +226,7 @@ impl ErasedAsh
                 dropper,
                 ready_waker: Mutex::new(None),
                 is_reloading: AtomicBool::new(false),
+                generation: AtomicU32::new(0),
             },
             data: AssetRefCnt::new(None),
             #[cfg(feature = "asset_debug_data")]
@@ -376,6 +380,10 @@ impl<A: Asset> Ash<A>
     #[inline] #[must_use]
     pub fn ref_count(&self) -> isize { self.inner().header.ref_count() }
 
+    // Changes each time the asset is (re)loaded, so that data derived from the asset can be rebuilt
+    #[inline] #[must_use]
+    pub fn generation(&self) -> u32 { self.inner().header.generation.load(Ordering::Acquire) }
+
     // // Is this asset + all dependencies loaded
     #[inline] #[must_use]
     pub fn is_loaded_recursive(&self) -> bool
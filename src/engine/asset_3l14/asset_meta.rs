@@ -47,6 +47,11 @@ pub struct SourceMetadata
 {
     pub source_id: AssetKeySourceId,
     pub version_hash: VersionHash,
+    // assets of other sources read by the last build, and a hash of their built data. This source is rebuilt if they change
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub build_inputs: Vec<AssetKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_inputs_hash: Option<VersionHash>,
     // is_dependent? (don't self build, omit source_id)
     pub build_config: toml::Value, // default to empty table?
     pub notes: Option<String>, // author/user provided notes
@@ -23,8 +23,10 @@ pub enum AssetTypeId
     Model = 0xa,
     Look = 0xb,
     SkeletalAnimation = 0xc,
+    AnimGraph = 0x11,
 
     Circuit = 0xd,
+    EntityDef = 0x10,
 
     Map = 0xe,
     MapChunk = 0xf,
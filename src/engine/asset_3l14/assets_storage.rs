@@ -288,7 +288,7 @@ pub struct AssetsConfig
 }
 impl AssetsConfig
 {
-    #[cfg(test)]
+    #[cfg(any(test, feature = "test-utils"))]
     pub fn test() -> Self
     {
         Self { assets_root: PathBuf::from("TEST_DIR"), enable_fs_watcher: false }
@@ -465,6 +465,25 @@ impl Assets
         self.enqueue_load(asset_key, |h| AssetLifecycleRequest::LoadFromMemory(h, input_data))
     }
 
+    // Serialize a file as the asset builder would, load it, and wait for it to finish loading (not including its dependencies)
+    // Panics if the asset fails to load
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn load_encoded_for_test<A: Asset>(&self, asset_key: AssetKey, file: &impl bitcode::Encode) -> Ash<A>
+    {
+        let encoded = bitcode::encode(file);
+        let mut input = Vec::new();
+        nab_3l14::utils::varint::encode_into(encoded.len() as u64, &mut input).expect("Failed to write the file's size");
+        input.extend_from_slice(&encoded);
+
+        let handle = self.load_from(asset_key, input.into_boxed_slice());
+        match futures::executor::block_on(&handle)
+        {
+            AssetSnapshot::Available(_) => handle,
+            AssetSnapshot::Unavailable(err) => panic!("Failed to load {asset_key:?}: {err:?}"),
+            AssetSnapshot::Pending => unreachable!("Loads are waited on until they finish"),
+        }
+    }
+
     #[must_use]
     #[cfg(test)]
     pub fn load_direct_from<A: Asset>(
@@ -943,8 +962,27 @@ mod tests
                 _ => panic!("Asset not available"),
             }
         }
+
+        #[test]
+        fn generation()
+        {
+            let lifecyclers = AssetLifecyclers::default()
+                .add_lifecycler(TestAssetLifecycler::default());
+            let assets = Assets::new(lifecyclers, AssetsConfig::test());
+
+            set_passthru::<_, TestAssetLifecycler>(&assets, Some(|_req: AssetLoadRequest|
+            {
+                Ok(TestAsset { value: 1, nested: None })
+            }));
+
+            let req = assets.load_direct_from::<TestAsset>(TEST_ASSET_1, &[]);
+            let first_generation = req.generation();
+
+            let reloaded = assets.load_direct_from::<TestAsset>(TEST_ASSET_1, &[]);
+            assert_eq!(req, reloaded);
+            assert_ne!(first_generation, req.generation());
+        }
     }
 
     // TODO: asset dependency lifetimes
-    // TODO: generation
 }
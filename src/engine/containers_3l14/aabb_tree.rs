@@ -514,6 +514,29 @@ impl AabbTree
         }
     }
 
+    // Walk the tree depth-first, left to right. visit_node decides whether to enter each node (internal or leaf),
+    // returning the state its children are visited with. Leaves that are entered are passed to visit_leaf
+    pub fn walk<S: Copy>(&self, root_state: S, mut visit_node: impl FnMut(AABB, S) -> Option<S>, mut visit_leaf: impl FnMut(AABB, u32, S))
+    {
+        if self.root_index.is_none() { return; }
+
+        let mut stack: SmallVec<[_; 32]> = smallvec![(self.root_index.0, root_state)];
+        while let Some((index, state)) = stack.pop()
+        {
+            let node = self.node(index);
+            let Some(state) = visit_node(node.bounds, state) else { continue; };
+
+            if node.is_leaf()
+            {
+                visit_leaf(node.bounds, node.right_or_userdata, state);
+                continue;
+            }
+
+            stack.push((node.right_or_userdata, state));
+            stack.push((node.left_or_nextfree, state));
+        }
+    }
+
     // Re-order the tree for more efficient traversal
     pub fn repack(&mut self)
     {
@@ -949,4 +972,36 @@ mod tests
         // todo: test all invalid states
     }
 
+    #[test]
+    fn walk()
+    {
+        let mut tree = AabbTree::new();
+        for i in 0..16
+        {
+            let min = Vec3::new(i as f32 * 10.0, 0.0, 0.0);
+            tree.insert(AABB::new(min, min + Vec3::ONE), i);
+        }
+
+        // enters every node, in the same order as an unbounded overlap query
+        let mut walked = Vec::new();
+        tree.walk((), |_, _| Some(()), |bounds, value, _| walked.push((bounds, value)));
+        let overlapping: Vec<_> = tree.iter_overlapping(AABB::MIN_MAX).collect();
+        assert_eq!(walked, overlapping);
+
+        // the state is passed down to children, and skipped nodes skip their subtree
+        let query = AABB::new(Vec3::new(25.0, 0.0, 0.0), Vec3::new(65.0, 1.0, 1.0));
+        let mut depths = Vec::new();
+        let mut entered = 0;
+        tree.walk(0, |bounds, depth|
+        {
+            entered += 1;
+            bounds.overlaps(query).then_some(depth + 1)
+        },
+        |_, value, depth| depths.push((value, depth)));
+        let mut values: Vec<_> = depths.iter().map(|(v, _)| *v).collect();
+        values.sort();
+        assert_eq!(values, vec![3, 4, 5, 6]);
+        assert!(depths.iter().all(|(_, depth)| *depth > 1));
+        assert!(entered < tree.len());
+    }
 }
@@ -0,0 +1,22 @@
+use std::error::Error;
+use crate::{EntityId, EntityStore};
+
+// Build-time info for a component type, registered with #[derive(EntityComponent)]
+pub struct ComponentBuildMeta
+{
+    pub type_name: &'static str,
+    pub type_name_hash: u64,
+    pub deserialize_and_encode_fn: fn(&mut dyn erased_serde::Deserializer) -> Result<Vec<u8>, erased_serde::Error>,
+}
+::inventory::collect!(ComponentBuildMeta);
+
+// Runtime info for a component type, registered with #[derive(EntityComponent)]
+pub struct ComponentRuntimeMeta
+{
+    #[cfg(debug_assertions)]
+    pub type_name: &'static str,
+
+    pub type_name_hash: u64,
+    pub decode_and_insert_fn: fn(&[u8], EntityId, &mut EntityStore) -> Result<(), Box<dyn Error>>,
+}
+::inventory::collect!(ComponentRuntimeMeta);
@@ -0,0 +1,156 @@
+use std::any::Any;
+use crate::EntityId;
+
+// Any type that can be attached to an entity
+pub trait Component: Any + Send + Sync { }
+impl<T: Any + Send + Sync> Component for T { }
+
+const NO_COMPONENT: u32 = u32::MAX;
+
+// A sparse set of components of a single type, stored densely for fast iteration
+pub struct ComponentStorage<C: Component>
+{
+    sparse: Vec<u32>, // entity index -> dense index
+    entities: Vec<EntityId>, // parallel to components
+    components: Vec<C>,
+}
+impl<C: Component> Default for ComponentStorage<C>
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            sparse: Vec::new(),
+            entities: Vec::new(),
+            components: Vec::new(),
+        }
+    }
+}
+impl<C: Component> ComponentStorage<C>
+{
+    #[inline] #[must_use]
+    pub fn len(&self) -> usize { self.components.len() }
+    #[inline] #[must_use]
+    pub fn is_empty(&self) -> bool { self.components.is_empty() }
+
+    #[inline] #[must_use]
+    fn dense_index(&self, entity: EntityId) -> Option<usize>
+    {
+        let dense = *self.sparse.get(entity.index() as usize)?;
+        (dense != NO_COMPONENT && self.entities[dense as usize] == entity).then_some(dense as usize)
+    }
+
+    // Add or replace the component for an entity, returning the replaced component
+    pub fn insert(&mut self, entity: EntityId, component: C) -> Option<C>
+    {
+        if let Some(dense) = self.dense_index(entity)
+        {
+            return Some(std::mem::replace(&mut self.components[dense], component));
+        }
+
+        let index = entity.index() as usize;
+        if index >= self.sparse.len()
+        {
+            self.sparse.resize(index + 1, NO_COMPONENT);
+        }
+        self.sparse[index] = self.components.len() as u32;
+        self.entities.push(entity);
+        self.components.push(component);
+        None
+    }
+
+    pub fn remove(&mut self, entity: EntityId) -> Option<C>
+    {
+        let dense = self.dense_index(entity)?;
+        self.sparse[entity.index() as usize] = NO_COMPONENT;
+
+        self.entities.swap_remove(dense);
+        let removed = self.components.swap_remove(dense);
+        if let Some(moved) = self.entities.get(dense)
+        {
+            self.sparse[moved.index() as usize] = dense as u32;
+        }
+        Some(removed)
+    }
+
+    #[inline] #[must_use]
+    pub fn contains(&self, entity: EntityId) -> bool { self.dense_index(entity).is_some() }
+
+    #[inline] #[must_use]
+    pub fn get(&self, entity: EntityId) -> Option<&C>
+    {
+        self.dense_index(entity).map(|i| &self.components[i])
+    }
+
+    #[inline] #[must_use]
+    pub fn get_mut(&mut self, entity: EntityId) -> Option<&mut C>
+    {
+        self.dense_index(entity).map(|i| &mut self.components[i])
+    }
+
+    // Iterate all components in storage order (which changes as components are removed)
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item=(EntityId, &C)>
+    {
+        self.entities.iter().copied().zip(self.components.iter())
+    }
+
+    #[inline]
+    pub fn iter_mut(&mut self) -> impl Iterator<Item=(EntityId, &mut C)>
+    {
+        self.entities.iter().copied().zip(self.components.iter_mut())
+    }
+}
+
+// Type-erased access to component storages, for operations across all component types
+pub(super) trait ErasedStorage: Send + Sync
+{
+    fn remove_entity(&mut self, entity: EntityId);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+impl<C: Component> ErasedStorage for ComponentStorage<C>
+{
+    fn remove_entity(&mut self, entity: EntityId) { self.remove(entity); }
+    fn as_any(&self) -> &dyn Any { self }
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::num::NonZeroU32;
+    use super::*;
+
+    fn entity(index: u32, generation: u32) -> EntityId
+    {
+        EntityId { index, generation: NonZeroU32::new(generation).unwrap() }
+    }
+
+    #[test]
+    fn insert_remove()
+    {
+        let mut storage = ComponentStorage::<&str>::default();
+        assert_eq!(storage.insert(entity(3, 1), "a"), None);
+        assert_eq!(storage.insert(entity(0, 1), "b"), None);
+        assert_eq!(storage.insert(entity(7, 2), "c"), None);
+        assert_eq!(storage.insert(entity(0, 1), "B"), Some("b"));
+        assert_eq!(storage.len(), 3);
+
+        assert_eq!(storage.remove(entity(3, 1)), Some("a"));
+        assert_eq!(storage.remove(entity(3, 1)), None);
+        assert_eq!(storage.get(entity(7, 2)), Some(&"c"));
+        assert_eq!(storage.get(entity(0, 1)), Some(&"B"));
+        assert_eq!(storage.len(), 2);
+    }
+
+    #[test]
+    fn stale_generation()
+    {
+        let mut storage = ComponentStorage::<u32>::default();
+        storage.insert(entity(1, 1), 5);
+        assert!(!storage.contains(entity(1, 2)));
+        assert_eq!(storage.remove(entity(1, 2)), None);
+        assert_eq!(storage.get(entity(1, 1)), Some(&5));
+    }
+}
@@ -0,0 +1,46 @@
+use std::fmt::{Debug, Formatter};
+use std::num::NonZeroU32;
+use bitcode::{Decode, Encode};
+use serde::Deserialize;
+use nab_3l14::Ident;
+
+// A generational handle to an entity. Handles to despawned entities are never reused
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Encode, Decode)]
+pub struct EntityId
+{
+    pub(super) index: u32,
+    pub(super) generation: NonZeroU32,
+}
+impl EntityId
+{
+    // The slot this entity occupies, reused (with a new generation) after the entity is despawned
+    #[inline] #[must_use]
+    pub fn index(self) -> u32 { self.index }
+
+    #[inline] #[must_use]
+    pub fn generation(self) -> u32 { self.generation.get() }
+}
+impl Debug for EntityId
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+    {
+        f.write_fmt(format_args!("Entity{{{}v{}}}", self.index, self.generation))
+    }
+}
+
+// A reference to an entity that may be authored in data (by name) or set at runtime (by ID)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Encode, Decode, Deserialize)]
+pub enum EntityRef
+{
+    Named(Ident),
+    #[serde(skip_deserializing)] // IDs are only meaningful at runtime
+    Id(EntityId),
+}
+impl From<EntityId> for EntityRef
+{
+    fn from(id: EntityId) -> Self { Self::Id(id) }
+}
+impl From<Ident> for EntityRef
+{
+    fn from(ident: Ident) -> Self { Self::Named(ident) }
+}
@@ -0,0 +1,323 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::num::NonZeroU32;
+use nab_3l14::Ident;
+use crate::{Component, ComponentStorage, EntityId, EntityRef};
+use crate::component_storage::ErasedStorage;
+
+#[derive(Debug)]
+pub enum EntityError
+{
+    NotAlive(EntityId),
+    DuplicateIdent(Ident, EntityId), // the ident is already used by this entity
+}
+impl Display for EntityError
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { Debug::fmt(self, f) }
+}
+impl Error for EntityError { }
+
+struct EntitySlot
+{
+    generation: NonZeroU32,
+    is_alive: bool,
+    name: Option<Ident>,
+}
+
+// Stores all entities and their components
+#[derive(Default)]
+pub struct EntityStore
+{
+    slots: Vec<EntitySlot>,
+    free_slots: Vec<u32>,
+    alive_count: u32,
+
+    components: HashMap<TypeId, Box<dyn ErasedStorage>>,
+    names: HashMap<Ident, EntityId>,
+}
+impl EntityStore
+{
+    #[must_use]
+    pub fn new() -> Self { Self::default() }
+
+    // The number of live entities
+    #[inline] #[must_use]
+    pub fn len(&self) -> u32 { self.alive_count }
+    #[inline] #[must_use]
+    pub fn is_empty(&self) -> bool { self.alive_count == 0 }
+
+    #[must_use]
+    pub fn spawn(&mut self) -> EntityId
+    {
+        self.alive_count += 1;
+
+        if let Some(index) = self.free_slots.pop()
+        {
+            let slot = &mut self.slots[index as usize];
+            slot.is_alive = true;
+            return EntityId { index, generation: slot.generation };
+        }
+
+        let index = self.slots.len() as u32;
+        let generation = NonZeroU32::MIN;
+        self.slots.push(EntitySlot { generation, is_alive: true, name: None });
+        EntityId { index, generation }
+    }
+
+    // Spawn an entity that can be found by name. Names must be unique among live entities
+    pub fn spawn_named(&mut self, name: Ident) -> Result<EntityId, EntityError>
+    {
+        if let Some(existing) = self.names.get(&name)
+        {
+            return Err(EntityError::DuplicateIdent(name, *existing));
+        }
+
+        let entity = self.spawn();
+        self.slots[entity.index as usize].name = Some(name);
+        self.names.insert(name, entity);
+        Ok(entity)
+    }
+
+    // Despawn an entity and drop all of its components. Returns false if the entity was not alive
+    pub fn despawn(&mut self, entity: EntityId) -> bool
+    {
+        if !self.is_alive(entity) { return false; }
+
+        for storage in self.components.values_mut()
+        {
+            storage.remove_entity(entity);
+        }
+
+        let slot = &mut self.slots[entity.index as usize];
+        if let Some(name) = slot.name.take()
+        {
+            self.names.remove(&name);
+        }
+        slot.is_alive = false;
+        self.alive_count -= 1;
+
+        // slots that exhaust their generations are retired so that stale IDs can never alias a new entity
+        if let Some(next_generation) = slot.generation.checked_add(1)
+        {
+            slot.generation = next_generation;
+            self.free_slots.push(entity.index);
+        }
+        true
+    }
+
+    #[inline] #[must_use]
+    pub fn is_alive(&self, entity: EntityId) -> bool
+    {
+        self.slots.get(entity.index as usize).is_some_and(|s| s.is_alive && s.generation == entity.generation)
+    }
+
+    // Find a live entity by name
+    #[inline] #[must_use]
+    pub fn find(&self, name: Ident) -> Option<EntityId>
+    {
+        self.names.get(&name).copied()
+    }
+
+    #[inline] #[must_use]
+    pub fn name_of(&self, entity: EntityId) -> Option<Ident>
+    {
+        if !self.is_alive(entity) { return None; }
+        self.slots[entity.index as usize].name
+    }
+
+    // Get the live entity an entity reference refers to
+    #[must_use]
+    pub fn resolve(&self, entity_ref: EntityRef) -> Option<EntityId>
+    {
+        match entity_ref
+        {
+            EntityRef::Named(name) => self.find(name),
+            EntityRef::Id(id) => self.is_alive(id).then_some(id),
+        }
+    }
+
+    // Add or replace a component on an entity, returning the replaced component
+    pub fn insert<C: Component>(&mut self, entity: EntityId, component: C) -> Result<Option<C>, EntityError>
+    {
+        if !self.is_alive(entity) { return Err(EntityError::NotAlive(entity)); }
+
+        let storage = self.components.entry(TypeId::of::<C>())
+            .or_insert_with(|| Box::new(ComponentStorage::<C>::default()));
+        let storage = storage.as_any_mut().downcast_mut::<ComponentStorage<C>>().unwrap();
+        Ok(storage.insert(entity, component))
+    }
+
+    pub fn remove<C: Component>(&mut self, entity: EntityId) -> Option<C>
+    {
+        self.storage_mut::<C>()?.remove(entity)
+    }
+
+    #[inline] #[must_use]
+    pub fn has<C: Component>(&self, entity: EntityId) -> bool
+    {
+        self.storage::<C>().is_some_and(|s| s.contains(entity))
+    }
+
+    #[inline] #[must_use]
+    pub fn get<C: Component>(&self, entity: EntityId) -> Option<&C>
+    {
+        self.storage::<C>()?.get(entity)
+    }
+
+    #[inline] #[must_use]
+    pub fn get_mut<C: Component>(&mut self, entity: EntityId) -> Option<&mut C>
+    {
+        self.storage_mut::<C>()?.get_mut(entity)
+    }
+
+    // All components of a type, if any have ever been added
+    #[must_use]
+    pub fn storage<C: Component>(&self) -> Option<&ComponentStorage<C>>
+    {
+        self.components.get(&TypeId::of::<C>())
+            .map(|s| s.as_any().downcast_ref::<ComponentStorage<C>>().unwrap())
+    }
+
+    #[must_use]
+    pub fn storage_mut<C: Component>(&mut self) -> Option<&mut ComponentStorage<C>>
+    {
+        self.components.get_mut(&TypeId::of::<C>())
+            .map(|s| s.as_any_mut().downcast_mut::<ComponentStorage<C>>().unwrap())
+    }
+
+    // Iterate all entities with a component
+    pub fn query<C: Component>(&self) -> impl Iterator<Item=(EntityId, &C)>
+    {
+        self.storage::<C>().into_iter().flat_map(|s| s.iter())
+    }
+
+    pub fn query_mut<C: Component>(&mut self) -> impl Iterator<Item=(EntityId, &mut C)>
+    {
+        self.storage_mut::<C>().into_iter().flat_map(|s| s.iter_mut())
+    }
+
+    // Iterate all entities with both components
+    pub fn query2<A: Component, B: Component>(&self) -> impl Iterator<Item=(EntityId, &A, &B)>
+    {
+        let b_storage = self.storage::<B>();
+        self.query::<A>().filter_map(move |(entity, a)| Some((entity, a, b_storage?.get(entity)?)))
+    }
+
+    // Iterate all entities with both components, mutating the first
+    // Panics if A and B are the same type
+    pub fn query2_mut<A: Component, B: Component>(&mut self) -> impl Iterator<Item=(EntityId, &mut A, &B)>
+    {
+        let [a_storage, b_storage] = self.components.get_disjoint_mut([&TypeId::of::<A>(), &TypeId::of::<B>()]);
+        let a_storage = a_storage.map(|s| s.as_any_mut().downcast_mut::<ComponentStorage<A>>().unwrap());
+        let b_storage = b_storage.map(|s| &*s.as_any_mut().downcast_mut::<ComponentStorage<B>>().unwrap());
+
+        a_storage.into_iter()
+            .flat_map(|s| s.iter_mut())
+            .filter_map(move |(entity, a)| Some((entity, a, b_storage?.get(entity)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Health(u32);
+    #[derive(Debug, PartialEq)]
+    struct Speed(f32);
+
+    #[test]
+    fn spawn_despawn()
+    {
+        let mut store = EntityStore::new();
+        let a = store.spawn();
+        let b = store.spawn();
+        assert_eq!(store.len(), 2);
+
+        assert!(store.despawn(a));
+        assert!(!store.despawn(a));
+        assert!(!store.is_alive(a));
+        assert!(store.is_alive(b));
+
+        // the slot is reused with a new generation
+        let c = store.spawn();
+        assert_eq!(c.index(), a.index());
+        assert_ne!(c, a);
+        assert!(!store.is_alive(a));
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn components()
+    {
+        let mut store = EntityStore::new();
+        let a = store.spawn();
+        let b = store.spawn();
+
+        store.insert(a, Health(10)).unwrap();
+        store.insert(a, Speed(1.5)).unwrap();
+        store.insert(b, Health(20)).unwrap();
+        assert_eq!(store.insert(b, Health(25)).unwrap(), Some(Health(20)));
+
+        assert!(store.has::<Speed>(a));
+        assert!(!store.has::<Speed>(b));
+        assert_eq!(store.get::<Health>(b), Some(&Health(25)));
+
+        store.get_mut::<Health>(a).unwrap().0 -= 5;
+        assert_eq!(store.get::<Health>(a), Some(&Health(5)));
+
+        assert_eq!(store.remove::<Speed>(a), Some(Speed(1.5)));
+        assert!(!store.has::<Speed>(a));
+
+        store.despawn(b);
+        assert!(store.get::<Health>(b).is_none());
+        assert!(matches!(store.insert(b, Speed(0.0)), Err(EntityError::NotAlive(_))));
+    }
+
+    #[test]
+    fn queries()
+    {
+        let mut store = EntityStore::new();
+        let a = store.spawn();
+        let b = store.spawn();
+        let c = store.spawn();
+        store.insert(a, Health(1)).unwrap();
+        store.insert(b, Health(2)).unwrap();
+        store.insert(c, Health(3)).unwrap();
+        store.insert(b, Speed(2.0)).unwrap();
+        store.insert(c, Speed(3.0)).unwrap();
+
+        assert_eq!(store.query::<Health>().map(|(_, h)| h.0).sum::<u32>(), 6);
+        assert_eq!(store.query::<String>().count(), 0);
+
+        for (_, health, speed) in store.query2_mut::<Health, Speed>()
+        {
+            health.0 *= speed.0 as u32;
+        }
+        let mut both: Vec<_> = store.query2::<Health, Speed>().map(|(e, h, _)| (e, h.0)).collect();
+        both.sort_by_key(|(e, _)| e.index());
+        assert_eq!(both, [(b, 4), (c, 9)]);
+        assert_eq!(store.get::<Health>(a), Some(&Health(1)));
+    }
+
+    #[test]
+    fn names()
+    {
+        let mut store = EntityStore::new();
+        let door = store.spawn_named(Ident::test('d')).unwrap();
+        assert!(matches!(store.spawn_named(Ident::test('d')), Err(EntityError::DuplicateIdent(_, e)) if e == door));
+
+        assert_eq!(store.find(Ident::test('d')), Some(door));
+        assert_eq!(store.name_of(door), Some(Ident::test('d')));
+        assert_eq!(store.resolve(EntityRef::Named(Ident::test('d'))), Some(door));
+        assert_eq!(store.resolve(EntityRef::Id(door)), Some(door));
+
+        store.despawn(door);
+        assert_eq!(store.find(Ident::test('d')), None);
+        assert_eq!(store.resolve(EntityRef::Id(door)), None);
+        assert!(store.spawn_named(Ident::test('d')).is_ok());
+    }
+}
@@ -0,0 +1,10 @@
+mod entity_id;
+pub use entity_id::*;
+
+mod component_storage;
+pub use component_storage::*;
+
+mod entity_store;
+pub use entity_store::*;
+
+pub mod component_meta;
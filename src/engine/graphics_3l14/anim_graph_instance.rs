@@ -0,0 +1,445 @@
+use std::ops::Deref;
+use crate::assets::{AnimEvent, AnimGraphDef, AnimIndex, AnimParamIndex, AnimParamKind, AnimStateIndex, AnimTransition, SkeletalAnimation};
+use crate::skeleton_poser::{BoneRemap, PoseBlendMode, SkeletonPoser};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct StatePlayback
+{
+    state: AnimStateIndex,
+    phase: f32, // normalized
+    previous_phase: f32, // the phase before the last update
+    advance: f32, // how far the last update moved the phase (not wrapped when looping)
+}
+impl StatePlayback
+{
+    #[inline] #[must_use]
+    fn new(state: AnimStateIndex, phase: f32) -> Self
+    {
+        Self { state, phase, previous_phase: phase, advance: 0.0 }
+    }
+
+    // The frame positions the last update moved between, see SkeletalAnimation::root_motion_between()
+    #[inline] #[must_use]
+    fn frame_range(&self, animation: &SkeletalAnimation) -> (f32, f32)
+    {
+        let frame_count = animation.frame_count.0 as f32;
+        (self.previous_phase * frame_count, (self.previous_phase + self.advance) * frame_count)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Crossfade
+{
+    from: StatePlayback,
+    elapsed_secs: f32,
+    duration_secs: f32,
+}
+
+// Advance a state by a time step, based on the (weighted) duration of its animations
+fn advance_state<A: Deref<Target=SkeletalAnimation>>(def: &AnimGraphDef, animations: &[A], parameters: &[f32], playback: &mut StatePlayback, delta_secs: f32)
+{
+    let state = &def.states[playback.state as usize];
+
+    playback.previous_phase = playback.phase;
+    playback.advance = 0.0;
+
+    let mut duration_secs = 0.0;
+    state.motion.sample_weights(parameters, |anim, weight| duration_secs += weight * animations[anim as usize].duration_secs());
+    if duration_secs <= 0.0 { return; }
+
+    let phase = playback.phase + (delta_secs * state.speed) / duration_secs;
+    let phase = if state.looping { phase } else { phase.clamp(0.0, 1.0) };
+    playback.advance = phase - playback.phase;
+    playback.phase = if state.looping { phase.rem_euclid(1.0) } else { phase };
+}
+
+#[inline] #[must_use]
+fn is_synced(def: &AnimGraphDef, a: AnimStateIndex, b: AnimStateIndex) -> bool
+{
+    let group_a = def.states[a as usize].sync_group;
+    group_a.is_some() && group_a == def.states[b as usize].sync_group
+}
+
+// The runtime state of an anim graph, for a single skeleton
+pub struct AnimGraphInstance
+{
+    parameters: Box<[f32]>,
+    current: StatePlayback,
+    crossfade: Option<Crossfade>,
+    bone_remaps: Vec<Option<BoneRemap>>, // built as each animation is first used
+}
+impl AnimGraphInstance
+{
+    #[must_use]
+    pub fn new(def: &AnimGraphDef) -> Self
+    {
+        Self
+        {
+            parameters: def.parameters.iter().map(|p| p.default).collect(),
+            current: StatePlayback::new(def.entry_state, 0.0),
+            crossfade: None,
+            bone_remaps: Vec::new(),
+        }
+    }
+
+    #[inline] #[must_use]
+    pub fn parameter(&self, parameter: AnimParamIndex) -> f32 { self.parameters[parameter as usize] }
+
+    // Set a parameter's value. Bools are zero for false, and triggers are set with any non-zero value
+    #[inline]
+    pub fn set_parameter(&mut self, parameter: AnimParamIndex, value: f32) { self.parameters[parameter as usize] = value; }
+
+    // Set a parameter's value by name, returns false if the graph does not have the parameter
+    pub fn set_parameter_named(&mut self, def: &AnimGraphDef, name: &str, value: f32) -> bool
+    {
+        let Some(parameter) = def.parameter_index(name) else { return false };
+        self.set_parameter(parameter, value);
+        true
+    }
+
+    #[inline] #[must_use]
+    pub fn current_state(&self) -> AnimStateIndex { self.current.state }
+
+    // The normalized progress through the current state
+    #[inline] #[must_use]
+    pub fn phase(&self) -> f32 { self.current.phase }
+
+    #[inline] #[must_use]
+    pub fn is_crossfading(&self) -> bool { self.crossfade.is_some() }
+
+    // Advance time and take at most one transition
+    pub fn update<A: Deref<Target=SkeletalAnimation>>(&mut self, def: &AnimGraphDef, animations: &[A], delta_secs: f32)
+    {
+        puffin::profile_function!();
+
+        advance_state(def, animations, &self.parameters, &mut self.current, delta_secs);
+
+        if let Some(mut crossfade) = self.crossfade.take()
+        {
+            crossfade.elapsed_secs += delta_secs;
+            if crossfade.elapsed_secs < crossfade.duration_secs
+            {
+                if is_synced(def, crossfade.from.state, self.current.state)
+                {
+                    crossfade.from = StatePlayback { state: crossfade.from.state, .. self.current };
+                }
+                else
+                {
+                    advance_state(def, animations, &self.parameters, &mut crossfade.from, delta_secs);
+                }
+                self.crossfade = Some(crossfade);
+            }
+        }
+
+        if let Some(transition) = def.transitions.iter().find(|t| self.can_take(t))
+        {
+            self.take(def, transition);
+        }
+    }
+
+    #[must_use]
+    fn can_take(&self, transition: &AnimTransition) -> bool
+    {
+        transition.to != self.current.state &&
+            transition.from.is_none_or(|from| from == self.current.state) &&
+            transition.exit_phase.is_none_or(|exit| self.current.phase >= exit) &&
+            transition.conditions.iter().all(|c| c.is_met(&self.parameters))
+    }
+
+    // Interrupting a crossfade drops the state being faded out
+    fn take(&mut self, def: &AnimGraphDef, transition: &AnimTransition)
+    {
+        for condition in &transition.conditions
+        {
+            if def.parameters[condition.parameter as usize].kind == AnimParamKind::Trigger
+            {
+                self.parameters[condition.parameter as usize] = 0.0;
+            }
+        }
+
+        let phase = if is_synced(def, self.current.state, transition.to) { self.current.phase } else { 0.0 };
+        self.crossfade = (transition.duration_secs > 0.0).then_some(Crossfade
+        {
+            from: self.current,
+            elapsed_secs: 0.0,
+            duration_secs: transition.duration_secs,
+        });
+        self.current = StatePlayback::new(transition.to, phase);
+    }
+
+    // Every animation currently playing, and its weight
+    #[must_use]
+    fn samples(&self, def: &AnimGraphDef) -> Vec<(StatePlayback, AnimIndex, f32)>
+    {
+        let mut samples = Vec::new();
+        let fade_in = match self.crossfade
+        {
+            Some(crossfade) =>
+            {
+                let fade_in = crossfade.elapsed_secs / crossfade.duration_secs;
+                def.states[crossfade.from.state as usize].motion.sample_weights(&self.parameters,
+                    |anim, weight| samples.push((crossfade.from, anim, weight * (1.0 - fade_in))));
+                fade_in
+            },
+            None => 1.0,
+        };
+        def.states[self.current.state as usize].motion.sample_weights(&self.parameters,
+            |anim, weight| samples.push((self.current, anim, weight * fade_in)));
+        samples
+    }
+
+    // Pose a skeleton with the graph's current state, and accumulate the root motion from the last update
+    // The poser should only ever be for one skeleton
+    pub fn apply<A: Deref<Target=SkeletalAnimation>>(&mut self, def: &AnimGraphDef, animations: &[A], poser: &mut SkeletonPoser)
+    {
+        puffin::profile_function!();
+
+        if self.bone_remaps.len() != animations.len()
+        {
+            self.bone_remaps.clear();
+            self.bone_remaps.resize_with(animations.len(), || None);
+        }
+
+        // accumulate each sample, so that every sample ends up with its relative weight
+        let mut total_weight = 0.0;
+        for (playback, anim, weight) in self.samples(def)
+        {
+            if weight <= 0.0 { continue; }
+
+            let blend_weight = weight / (total_weight + weight);
+            let mode = if total_weight == 0.0 { PoseBlendMode::Replace } else { PoseBlendMode::Blend(blend_weight) };
+            total_weight += weight;
+
+            let animation = &*animations[anim as usize];
+            let looping = def.states[playback.state as usize].looping;
+            let remap = self.bone_remaps[anim as usize].get_or_insert_with(|| BoneRemap::new(poser.skeleton(), animation));
+            poser.blend_at_phase(animation, remap, mode, None, playback.phase, looping);
+
+            let (from, to) = playback.frame_range(animation);
+            poser.blend_root_motion(animation.root_motion_between(from, to, looping), blend_weight);
+        }
+    }
+
+    // Call back with each event passed during the last update, and the weight of the animation it came from
+    pub fn events<A: Deref<Target=SkeletalAnimation>>(&self, def: &AnimGraphDef, animations: &[A], mut on_event: impl FnMut(&AnimEvent, f32))
+    {
+        for (playback, anim, weight) in self.samples(def)
+        {
+            if weight <= 0.0 { continue; }
+
+            let animation = &*animations[anim as usize];
+            let (from, to) = playback.frame_range(animation);
+            animation.events_between(from, to, def.states[playback.state as usize].looping, |event| on_event(event, weight));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use glam::{Quat, Vec3};
+    use math_3l14::{DualQuat, Ratio};
+    use crate::assets::{AnimCompare, AnimCompression, AnimCondition, AnimMotion, AnimParameter, AnimState, BlendSample1D, BoneId, Skeleton};
+    use super::*;
+
+    fn generate_skeleton() -> Skeleton
+    {
+        Skeleton
+        {
+            bone_ids: Box::new([BoneId(0)]),
+            parent_indices: Box::new([-1]),
+            bind_poses: Box::new([DualQuat::IDENTITY]),
+            inverse_bind_poses: Box::new([DualQuat::IDENTITY]),
+        }
+    }
+
+    // a one second, single bone animation that holds a translation
+    fn generate_animation(translation: Vec3) -> SkeletalAnimation
+    {
+        SkeletalAnimation::compress(
+            Ratio::new(2, 1),
+            Box::new([BoneId(0)]),
+            2,
+            &[Quat::IDENTITY; 2],
+            &[translation; 2],
+            &[],
+            AnimCompression::KEEP_KEYS)
+    }
+
+    fn state(name: &str, motion: AnimMotion, sync_group: Option<u16>) -> AnimState
+    {
+        AnimState { name: name.to_string(), motion, speed: 1.0, looping: true, sync_group }
+    }
+
+    fn generate_graph() -> AnimGraphDef
+    {
+        AnimGraphDef
+        {
+            parameters: Box::new([
+                AnimParameter { name: "speed".to_string(), kind: AnimParamKind::Float, default: 0.0 },
+                AnimParameter { name: "jump".to_string(), kind: AnimParamKind::Trigger, default: 0.0 },
+            ]),
+            states: Box::new([
+                state("idle", AnimMotion::Clip(0), Some(0)),
+                state("move", AnimMotion::BlendSpace1D
+                {
+                    parameter: 0,
+                    samples: Box::new([
+                        BlendSample1D { position: 1.0, animation: 1 },
+                        BlendSample1D { position: 3.0, animation: 2 },
+                    ]),
+                }, Some(0)),
+                state("jump", AnimMotion::Clip(3), None),
+            ]),
+            transitions: Box::new([
+                AnimTransition
+                {
+                    from: Some(0),
+                    to: 1,
+                    duration_secs: 0.5,
+                    exit_phase: None,
+                    conditions: Box::new([AnimCondition { parameter: 0, compare: AnimCompare::Greater, value: 0.0 }]),
+                },
+                AnimTransition
+                {
+                    from: None,
+                    to: 2,
+                    duration_secs: 0.0,
+                    exit_phase: Some(0.5),
+                    conditions: Box::new([AnimCondition { parameter: 1, compare: AnimCompare::NotEqual, value: 0.0 }]),
+                },
+            ]),
+            entry_state: 0,
+        }
+    }
+
+    fn generate_animations() -> Vec<SkeletalAnimation>
+    {
+        vec![
+            generate_animation(Vec3::ZERO),
+            generate_animation(Vec3::X),
+            generate_animation(Vec3::X * 3.0),
+            generate_animation(Vec3::Y),
+        ]
+    }
+
+    fn posed_translation(instance: &mut AnimGraphInstance, def: &AnimGraphDef, animations: &[&SkeletalAnimation], skeleton: &Skeleton) -> Vec3
+    {
+        let mut poser = SkeletonPoser::new(skeleton);
+        instance.apply(def, animations, &mut poser);
+        poser.local_poses()[0].translation()
+    }
+
+    #[test]
+    fn playback()
+    {
+        let def = generate_graph();
+        let animations = generate_animations();
+        let animations: Vec<_> = animations.iter().collect();
+
+        let mut instance = AnimGraphInstance::new(&def);
+        assert_eq!(instance.current_state(), 0);
+
+        instance.update(&def, &animations, 0.25);
+        assert!((instance.phase() - 0.25).abs() < 1e-5);
+        instance.update(&def, &animations, 1.0);
+        assert!((instance.phase() - 0.25).abs() < 1e-5, "looping states wrap");
+    }
+
+    #[test]
+    fn crossfade()
+    {
+        let def = generate_graph();
+        let skeleton = generate_skeleton();
+        let animations = generate_animations();
+        let animations: Vec<_> = animations.iter().collect();
+
+        let mut instance = AnimGraphInstance::new(&def);
+        instance.update(&def, &animations, 0.0);
+        assert_eq!(instance.current_state(), 0);
+
+        instance.set_parameter_named(&def, "speed", 1.0);
+        instance.update(&def, &animations, 0.0);
+        assert_eq!(instance.current_state(), 1);
+        assert!(instance.is_crossfading());
+        assert!(posed_translation(&mut instance, &def, &animations, &skeleton).abs_diff_eq(Vec3::ZERO, 1e-3));
+
+        instance.update(&def, &animations, 0.25);
+        assert!(posed_translation(&mut instance, &def, &animations, &skeleton).abs_diff_eq(Vec3::X * 0.5, 1e-2));
+
+        instance.update(&def, &animations, 0.25);
+        assert!(!instance.is_crossfading());
+        assert!(posed_translation(&mut instance, &def, &animations, &skeleton).abs_diff_eq(Vec3::X, 1e-2));
+
+        // blend space
+        instance.set_parameter_named(&def, "speed", 2.0);
+        assert!(posed_translation(&mut instance, &def, &animations, &skeleton).abs_diff_eq(Vec3::X * 2.0, 1e-2));
+    }
+
+    #[test]
+    fn sync_groups()
+    {
+        let def = generate_graph();
+        let animations = generate_animations();
+        let animations: Vec<_> = animations.iter().collect();
+
+        let mut instance = AnimGraphInstance::new(&def);
+        instance.update(&def, &animations, 0.4);
+        instance.set_parameter_named(&def, "speed", 1.0);
+        instance.update(&def, &animations, 0.0);
+        assert_eq!(instance.current_state(), 1);
+        assert!((instance.phase() - 0.4).abs() < 1e-5);
+    }
+
+    #[test]
+    fn triggers_and_exit_phase()
+    {
+        let def = generate_graph();
+        let animations = generate_animations();
+        let animations: Vec<_> = animations.iter().collect();
+
+        let mut instance = AnimGraphInstance::new(&def);
+        instance.set_parameter_named(&def, "jump", 1.0);
+        instance.update(&def, &animations, 0.25);
+        assert_eq!(instance.current_state(), 0, "the exit phase has not been reached");
+
+        instance.update(&def, &animations, 0.25);
+        assert_eq!(instance.current_state(), 2);
+        assert!(!instance.is_crossfading());
+        assert_eq!(instance.phase(), 0.0);
+        assert_eq!(instance.parameter(1), 0.0, "triggers are consumed");
+    }
+
+    #[test]
+    fn root_motion_and_events()
+    {
+        let def = generate_graph();
+        let skeleton = generate_skeleton();
+        let mut animations = generate_animations();
+        // idle moves forward one unit per frame, and has an event on the second frame
+        animations[0].root_motion = (0..3).map(|i| DualQuat::from_rot_trans(Quat::IDENTITY, Vec3::Z * i as f32)).collect();
+        animations[0].events = Box::new([AnimEvent { frame: 1.0, name: "step".to_string(), signal: None }]);
+        let animations: Vec<_> = animations.iter().collect();
+
+        let mut instance = AnimGraphInstance::new(&def);
+        instance.update(&def, &animations, 0.75);
+
+        let mut poser = SkeletonPoser::new(&skeleton);
+        instance.apply(&def, &animations, &mut poser);
+        assert!(poser.root_motion().translation().abs_diff_eq(Vec3::Z * 1.5, 1e-4));
+
+        let mut events = Vec::new();
+        instance.events(&def, &animations, |event, weight| events.push((event.name.clone(), weight)));
+        assert_eq!(events, [("step".to_string(), 1.0)]);
+
+        // wraps around to the start
+        instance.update(&def, &animations, 0.5);
+        let mut poser = SkeletonPoser::new(&skeleton);
+        instance.apply(&def, &animations, &mut poser);
+        assert!(poser.root_motion().translation().abs_diff_eq(Vec3::Z, 1e-4));
+
+        events.clear();
+        instance.events(&def, &animations, |event, weight| events.push((event.name.clone(), weight)));
+        assert!(events.is_empty());
+    }
+}
@@ -0,0 +1,303 @@
+use std::error::Error;
+use asset_3l14::{Ash, AssetKey, AssetLifecycler, AssetLoadRequest, AssetSnapshot, AssetView};
+use bitcode::{Decode, Encode};
+use glam::Vec2;
+use proc_macros_3l14::{asset, LayoutHash};
+use crate::assets::SkeletalAnimation;
+
+pub type AnimParamIndex = u16;
+pub type AnimStateIndex = u16;
+pub type AnimIndex = u16; // index into an anim graph's animations
+
+// An animation state machine, see AnimGraphInstance
+#[asset]
+pub struct AnimGraph
+{
+    pub def: AnimGraphDef,
+    pub animations: Box<[Ash<SkeletalAnimation>]>,
+}
+impl AnimGraph
+{
+    // Get all of the graph's animations, if they are all loaded
+    #[must_use]
+    pub fn loaded_animations(&self) -> Option<Box<[AssetView<SkeletalAnimation>]>>
+    {
+        self.animations.iter().map(|anim| match anim.data()
+        {
+            AssetSnapshot::Available(view) => Some(view),
+            _ => None,
+        }).collect()
+    }
+}
+
+#[derive(LayoutHash, Encode, Decode)]
+pub struct AnimGraphFile
+{
+    pub def: AnimGraphDef,
+    pub animations: Box<[AssetKey]>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct AnimGraphDef
+{
+    pub parameters: Box<[AnimParameter]>,
+    pub states: Box<[AnimState]>,
+    pub transitions: Box<[AnimTransition]>, // checked in order, the first valid transition is taken
+    pub entry_state: AnimStateIndex,
+}
+impl AnimGraphDef
+{
+    #[must_use]
+    pub fn parameter_index(&self, name: &str) -> Option<AnimParamIndex>
+    {
+        self.parameters.iter().position(|p| p.name == name).map(|i| i as AnimParamIndex)
+    }
+
+    #[must_use]
+    pub fn state_index(&self, name: &str) -> Option<AnimStateIndex>
+    {
+        self.states.iter().position(|s| s.name == name).map(|i| i as AnimStateIndex)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum AnimParamKind
+{
+    Float,
+    Bool, // zero is false, anything else is true
+    Trigger, // set until consumed by a transition
+}
+
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct AnimParameter
+{
+    pub name: String,
+    pub kind: AnimParamKind,
+    pub default: f32,
+}
+
+#[derive(Debug, Clone, Copy, Encode, Decode)]
+pub struct BlendSample1D
+{
+    pub position: f32,
+    pub animation: AnimIndex,
+}
+
+#[derive(Debug, Clone, Copy, Encode, Decode)]
+pub struct BlendSample2D
+{
+    pub position: Vec2,
+    pub animation: AnimIndex,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub enum AnimMotion
+{
+    Clip(AnimIndex),
+    // blend between the two samples surrounding the parameter's value
+    BlendSpace1D { parameter: AnimParamIndex, samples: Box<[BlendSample1D]> }, // samples are sorted by position
+    // blend between all samples, weighted by the parameters' position relative to each sample (gradient band interpolation)
+    BlendSpace2D { parameters: [AnimParamIndex; 2], samples: Box<[BlendSample2D]> },
+}
+impl AnimMotion
+{
+    // Calculate the weight of each animation in this motion. Weights are normalized
+    pub fn sample_weights(&self, parameters: &[f32], mut add_sample: impl FnMut(AnimIndex, f32))
+    {
+        match self
+        {
+            AnimMotion::Clip(animation) => add_sample(*animation, 1.0),
+            AnimMotion::BlendSpace1D { parameter, samples } =>
+            {
+                let value = parameters[*parameter as usize];
+                let upper = samples.partition_point(|s| s.position <= value);
+                if upper == 0
+                {
+                    add_sample(samples[0].animation, 1.0);
+                }
+                else if upper == samples.len()
+                {
+                    add_sample(samples[upper - 1].animation, 1.0);
+                }
+                else
+                {
+                    let (a, b) = (&samples[upper - 1], &samples[upper]);
+                    let t = (value - a.position) / (b.position - a.position);
+                    add_sample(a.animation, 1.0 - t);
+                    add_sample(b.animation, t);
+                }
+            },
+            AnimMotion::BlendSpace2D { parameters: [x, y], samples } =>
+            {
+                let point = Vec2::new(parameters[*x as usize], parameters[*y as usize]);
+
+                // https://runevision.com/thesis/rune_skovbo_johansen_thesis.pdf (6.3)
+                let weight = |i: usize|
+                {
+                    let from_i = point - samples[i].position;
+                    samples.iter().enumerate()
+                        .filter(|(j, _)| *j != i)
+                        .map(|(_, sj)|
+                        {
+                            let i_to_j = sj.position - samples[i].position;
+                            (1.0 - from_i.dot(i_to_j) / i_to_j.length_squared()).clamp(0.0, 1.0)
+                        })
+                        .fold(1.0, f32::min)
+                };
+
+                let total: f32 = (0..samples.len()).map(weight).sum();
+                if total <= 0.0 { return; }
+                for (i, sample) in samples.iter().enumerate()
+                {
+                    add_sample(sample.animation, weight(i) / total);
+                }
+            },
+        }
+    }
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct AnimState
+{
+    pub name: String,
+    pub motion: AnimMotion,
+    pub speed: f32,
+    pub looping: bool,
+    pub sync_group: Option<u16>, // states in the same sync group keep their phase when transitioning between each other
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum AnimCompare
+{
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+#[derive(Debug, Clone, Copy, Encode, Decode)]
+pub struct AnimCondition
+{
+    pub parameter: AnimParamIndex,
+    pub compare: AnimCompare,
+    pub value: f32,
+}
+impl AnimCondition
+{
+    #[must_use]
+    pub fn is_met(&self, parameters: &[f32]) -> bool
+    {
+        let param = parameters[self.parameter as usize];
+        match self.compare
+        {
+            AnimCompare::Less => param < self.value,
+            AnimCompare::LessEqual => param <= self.value,
+            AnimCompare::Greater => param > self.value,
+            AnimCompare::GreaterEqual => param >= self.value,
+            AnimCompare::Equal => param == self.value,
+            AnimCompare::NotEqual => param != self.value,
+        }
+    }
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct AnimTransition
+{
+    pub from: Option<AnimStateIndex>, // none for any state
+    pub to: AnimStateIndex,
+    pub duration_secs: f32, // crossfade time
+    pub exit_phase: Option<f32>, // the (normalized) phase the source state must reach before transitioning
+    pub conditions: Box<[AnimCondition]>, // all must be met
+}
+
+pub struct AnimGraphLifecycler;
+impl AssetLifecycler for AnimGraphLifecycler
+{
+    type Asset = AnimGraph;
+
+    fn load(&self, mut request: AssetLoadRequest) -> Result<Self::Asset, Box<dyn Error>>
+    {
+        let file: AnimGraphFile = request.deserialize()?;
+        Ok(AnimGraph
+        {
+            def: file.def,
+            animations: file.animations.iter().map(|key| request.load_dependency(*key)).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn collect_weights(motion: &AnimMotion, parameters: &[f32]) -> Vec<(AnimIndex, f32)>
+    {
+        let mut weights = Vec::new();
+        motion.sample_weights(parameters, |anim, weight| weights.push((anim, weight)));
+        weights
+    }
+
+    #[test]
+    fn blend_space_1d()
+    {
+        let motion = AnimMotion::BlendSpace1D
+        {
+            parameter: 0,
+            samples: Box::new([
+                BlendSample1D { position: 0.0, animation: 0 },
+                BlendSample1D { position: 2.0, animation: 1 },
+                BlendSample1D { position: 6.0, animation: 2 },
+            ]),
+        };
+
+        assert_eq!(collect_weights(&motion, &[-1.0]), [(0, 1.0)]);
+        assert_eq!(collect_weights(&motion, &[1.0]), [(0, 0.5), (1, 0.5)]);
+        assert_eq!(collect_weights(&motion, &[2.0]), [(1, 1.0), (2, 0.0)]);
+        assert_eq!(collect_weights(&motion, &[5.0]), [(1, 0.25), (2, 0.75)]);
+        assert_eq!(collect_weights(&motion, &[10.0]), [(2, 1.0)]);
+    }
+
+    #[test]
+    fn blend_space_2d()
+    {
+        let motion = AnimMotion::BlendSpace2D
+        {
+            parameters: [0, 1],
+            samples: Box::new([
+                BlendSample2D { position: Vec2::new(0.0, 0.0), animation: 0 },
+                BlendSample2D { position: Vec2::new(1.0, 0.0), animation: 1 },
+                BlendSample2D { position: Vec2::new(0.0, 1.0), animation: 2 },
+            ]),
+        };
+
+        // exactly on a sample
+        let weights = collect_weights(&motion, &[1.0, 0.0]);
+        assert_eq!(weights, [(0, 0.0), (1, 1.0), (2, 0.0)]);
+
+        // between two samples
+        let weights = collect_weights(&motion, &[0.5, 0.0]);
+        assert!((weights[0].1 - 0.5).abs() < 1e-5);
+        assert!((weights[1].1 - 0.5).abs() < 1e-5);
+        assert!(weights[2].1.abs() < 1e-5);
+
+        // always normalized
+        let weights = collect_weights(&motion, &[0.3, 0.4]);
+        assert!((weights.iter().map(|(_, w)| w).sum::<f32>() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn conditions()
+    {
+        let cond = |compare| AnimCondition { parameter: 0, compare, value: 1.0 };
+        assert!(cond(AnimCompare::Less).is_met(&[0.5]));
+        assert!(!cond(AnimCompare::Less).is_met(&[1.0]));
+        assert!(cond(AnimCompare::LessEqual).is_met(&[1.0]));
+        assert!(cond(AnimCompare::Greater).is_met(&[1.5]));
+        assert!(cond(AnimCompare::GreaterEqual).is_met(&[1.0]));
+        assert!(cond(AnimCompare::Equal).is_met(&[1.0]));
+        assert!(cond(AnimCompare::NotEqual).is_met(&[0.0]));
+    }
+}
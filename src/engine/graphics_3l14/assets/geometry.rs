@@ -1,5 +1,6 @@
 use crate::{debug_label, Renderer};
 use asset_3l14::{AssetLifecycler, AssetLoadRequest};
+use arrayvec::ArrayVec;
 use bitcode::{Decode, Encode};
 use enumflags2::BitFlags;
 use enumflags2::_internal::RawBitFlags;
@@ -45,13 +46,31 @@ pub struct GeometryFile
     pub meshes: Box<[GeometryMesh]>, // TODO: make variable sized tail (then put boxes above after in payload)
 }
 
+pub const MAX_GEOMETRY_LODS: usize = 6;
+
+#[derive(Encode, Decode)]
+pub struct GeometryMeshLod
+{
+    pub index_range: (u32, u32), // start, end
+    pub max_screen_size: f32, // this LOD is used when the mesh covers at most this fraction of the screen height
+}
+
 #[derive(Encode, Decode)]
 pub struct GeometryMesh
 {
     pub bounds_aabb: AABB, // note; these are untransformed
     pub bounds_sphere: Sphere,
-    pub vertex_range: (u32, u32), // start, end
-    pub index_range: (u32, u32), // start, end
+    pub vertex_range: (u32, u32), // start, end; shared by all LODs
+    pub lods: ArrayVec<GeometryMeshLod, MAX_GEOMETRY_LODS>, // LOD 0 is full detail, sorted by decreasing screen size
+}
+impl GeometryMesh
+{
+    // Select the least detailed LOD for the mesh's projected size (see GeometryMeshLod::max_screen_size)
+    #[must_use]
+    pub fn select_lod(&self, screen_size: f32) -> u8
+    {
+        self.lods.iter().rposition(|l| screen_size <= l.max_screen_size).unwrap_or(0) as u8
+    }
 }
 
 #[asset]
@@ -120,3 +139,30 @@ impl DebugGui for GeometryLifecycler
     fn display_name(&self) -> &str { "Geometry" }
     fn debug_gui(&self, _ui: &mut egui::Ui) { }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn lod_selection()
+    {
+        let mut mesh = GeometryMesh
+        {
+            bounds_aabb: AABB::MAX_MIN,
+            bounds_sphere: Sphere::EMPTY,
+            vertex_range: (0, 0),
+            lods: ArrayVec::new(),
+        };
+        mesh.lods.push(GeometryMeshLod { index_range: (0, 300), max_screen_size: f32::INFINITY });
+        assert_eq!(mesh.select_lod(0.01), 0);
+
+        mesh.lods.push(GeometryMeshLod { index_range: (300, 450), max_screen_size: 0.5 });
+        mesh.lods.push(GeometryMeshLod { index_range: (450, 510), max_screen_size: 0.1 });
+        assert_eq!(mesh.select_lod(2.0), 0);
+        assert_eq!(mesh.select_lod(0.5), 1);
+        assert_eq!(mesh.select_lod(0.2), 1);
+        assert_eq!(mesh.select_lod(0.05), 2);
+    }
+}
@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 use std::error::Error;
 use triomphe::Arc;
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
-use wgpu::{Buffer, BufferUsages};
+use wgpu::{BlendComponent, BlendFactor, BlendOperation, BlendState, Buffer, BufferUsages};
 use asset_3l14::{Ash, Asset, AssetKey, AssetLifecycler, AssetLoadRequest, AssetTypeId};
 use debug_3l14::debug_gui::DebugGui;
 use proc_macros_3l14::LayoutHash;
@@ -14,11 +14,64 @@ use crate::material_classes::MaterialClass;
 
 pub const MAX_MATERIAL_TEXTURE_BINDINGS: usize = 16;
 
+#[repr(u8)]
+#[derive(Default, PartialEq, Eq, Copy, Clone, Debug, Hash, Serialize, Deserialize, Encode, Decode)]
+pub enum MaterialAlphaMode
+{
+    #[default]
+    Opaque,
+    Mask, // alpha tested against the material's cutoff
+    Blend, // drawn in the transparent pass, without writing depth
+}
+
+// How blended materials are composited with what is behind them, pixel shaders output premultiplied alpha
+#[repr(u8)]
+#[derive(Default, PartialEq, Eq, Copy, Clone, Debug, Hash, Serialize, Deserialize, Encode, Decode)]
+pub enum MaterialBlendMode
+{
+    #[default]
+    Premultiplied, // 'over' blending, requires drawing back-to-front
+    Additive, // order-independent, but only brightens (e.g. fire, glows)
+}
+impl MaterialBlendMode
+{
+    #[must_use]
+    pub const fn blend_state(self) -> BlendState
+    {
+        match self
+        {
+            MaterialBlendMode::Premultiplied => BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+            MaterialBlendMode::Additive => BlendState
+            {
+                color: BlendComponent
+                {
+                    src_factor: BlendFactor::One,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+                alpha: BlendComponent::OVER,
+            },
+        }
+    }
+}
+
+// Material settings that affect the render pipeline (rather than the material's uniforms)
+#[derive(Default, PartialEq, Eq, Copy, Clone, Debug, Hash, Serialize, Deserialize, Encode, Decode)]
+pub struct MaterialRenderState
+{
+    pub alpha_mode: MaterialAlphaMode,
+    pub double_sided: bool,
+    #[serde(default)]
+    pub blend_mode: MaterialBlendMode, // only for blended materials
+}
+
 #[derive(Serialize, Deserialize, Encode, Decode)]
 pub struct MaterialFile
 {
     pub class: MaterialClass,
-    pub textures: ArrayVec<AssetKey, MAX_MATERIAL_TEXTURE_BINDINGS>,
+    #[serde(default)]
+    pub render_state: MaterialRenderState,
+    pub textures: ArrayVec<Option<AssetKey>, MAX_MATERIAL_TEXTURE_BINDINGS>, // in binding order, unset slots use a default texture (see PbrTextureSlot::default_texel)
     pub props: Box<[u8]>,
 }
 
@@ -26,8 +79,9 @@ pub struct MaterialFile
 pub struct Material
 {
     pub class: MaterialClass,
+    pub render_state: MaterialRenderState,
     pub props: Buffer,
-    pub textures: ArrayVec<Ash<Texture>, MAX_MATERIAL_TEXTURE_BINDINGS>,
+    pub textures: ArrayVec<Option<Ash<Texture>>, MAX_MATERIAL_TEXTURE_BINDINGS>,
 }
 impl Asset for Material
 {
@@ -35,7 +89,7 @@ impl Asset for Material
     fn asset_type() -> AssetTypeId { AssetTypeId::Material }
     fn all_dependencies_loaded(&self) -> bool
     {
-        self.textures.iter().all(|t| t.is_loaded_recursive())
+        self.textures.iter().flatten().all(|t| t.is_loaded_recursive())
     }
 }
 
@@ -58,9 +112,9 @@ impl AssetLifecycler for MaterialLifecycler
     {
         let mtl_file: MaterialFile = request.deserialize()?;
 
-        let textures: ArrayVec<Ash<Texture>, MAX_MATERIAL_TEXTURE_BINDINGS> = mtl_file.textures.iter().map(|t|
+        let textures: ArrayVec<Option<Ash<Texture>>, MAX_MATERIAL_TEXTURE_BINDINGS> = mtl_file.textures.iter().map(|t|
         {
-           request.load_dependency(*t)
+           t.map(|t| request.load_dependency(t))
         }).collect();
 
         let props = self.renderer.device().create_buffer_init(&BufferInitDescriptor
@@ -73,6 +127,7 @@ impl AssetLifecycler for MaterialLifecycler
         Ok(Material
         {
             class: mtl_file.class,
+            render_state: mtl_file.render_state,
             textures,
             props,
         })
@@ -18,3 +18,6 @@ pub use skeleton::*;
 
 mod skeletal_animation;
 pub use skeletal_animation::*;
+
+mod anim_graph;
+pub use anim_graph::*;
@@ -1,7 +1,7 @@
 use std::borrow::Cow;
 use crate::{debug_label, Renderer};
 use bitcode::{Decode, Encode};
-use proc_macros_3l14::{asset, FancyEnum};
+use proc_macros_3l14::{asset, FancyEnum, LayoutHash};
 use serde::{Deserialize, Serialize};
 use std::default::Default;
 use std::error::Error;
@@ -12,6 +12,8 @@ use wgpu::{ShaderModuleDescriptor, ShaderModuleDescriptorPassthrough};
 use asset_3l14::{AssetKeySynthHash, AssetLifecycler, AssetLoadRequest};
 use debug_3l14::debug_gui::DebugGui;
 use crate::material_classes::MaterialClass;
+use crate::passes::post::PostEffect;
+use crate::shader_reflection::ShaderReflection;
 use crate::vertex_layouts::VertexCaps;
 
 #[repr(u8)]
@@ -32,7 +34,7 @@ pub enum ShaderStage
 // move?
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, Hash, Serialize, Deserialize)]
-pub enum EngineRenderPass // todo: better name to not clash with wgpu::RenderPass -- graph node?
+pub enum EngineRenderPass // todo: better name to not clash with wgpu::RenderPass (the frame is built of render_graph nodes)
 {
     Debug,
     // depth pre-pass?
@@ -41,9 +43,22 @@ pub enum EngineRenderPass // todo: better name to not clash with wgpu::RenderPas
     // CutoutShadowMap?
     Opaque,
     Transparent,
-    // post-fx? fog, color grading, bloom, etc
+    PostProcess, // exposure, bloom, tonemapping and color grading (see passes::post), todo: fog
     UI,
 }
+impl EngineRenderPass
+{
+    // The pass whose vertex shaders this pass uses, transparent geometry is transformed the same as opaque
+    #[inline] #[must_use]
+    pub const fn vertex_pass(self) -> Self
+    {
+        match self
+        {
+            EngineRenderPass::Transparent => EngineRenderPass::Opaque,
+            other => other,
+        }
+    }
+}
 
 /* TODO:
  * Are shaders one->many? (one source file generates several permutes)
@@ -59,7 +74,7 @@ pub mod shader_key
     {
         let mut val = 0b0001u32 << 28;
         val |= (pass as u32) << 20;
-        val |= (layout.bits() as u32) << 12;
+        val |= (VertexCaps::shader_layout(layout).bits() as u32) << 12;
         AssetKeySynthHash(val as u64)
     }
 
@@ -71,6 +86,15 @@ pub mod shader_key
         val |= (class as u32) << 12;
         AssetKeySynthHash(val as u64)
     }
+
+    #[inline] #[must_use]
+    pub fn post(effect: PostEffect) -> AssetKeySynthHash
+    {
+        let mut val = 0b0011u32 << 28;
+        val |= (EngineRenderPass::PostProcess as u32) << 20;
+        val |= (effect as u32) << 12;
+        AssetKeySynthHash(val as u64)
+    }
 }
 pub mod shader_source
 {
@@ -80,7 +104,7 @@ pub mod shader_source
     #[must_use]
     pub fn vertex(output: &mut impl Write, layout: BitFlags<VertexCaps>, pass: EngineRenderPass)
     {
-        for cap in layout
+        for cap in VertexCaps::shader_layout(layout)
         {
             let _ = write!(output, "{cap:?}");
         }
@@ -94,11 +118,12 @@ pub mod shader_source
     }
 }
 
-#[derive(Encode, Decode, Debug)]
+#[derive(LayoutHash, Encode, Decode, Debug)]
 pub struct ShaderFile
 {
     pub stage: ShaderStage,
     pub module_bytes: Box<[u8]>, // can this be a ref?
+    pub reflection: ShaderReflection,
 }
 
 #[asset(debug_type = ShaderDebugData)]
@@ -106,12 +131,14 @@ pub struct Shader
 {
     pub stage: ShaderStage,
     pub module: wgpu::ShaderModule,
+    pub reflection: ShaderReflection,
 }
 
 #[derive(Encode, Decode)]
 pub struct ShaderDebugData
 {
     pub source_file: String,
+    pub binding_names: Box<[String]>, // parallel to ShaderReflection::bindings
 }
 
 pub struct ShaderLifecycler
@@ -161,6 +188,7 @@ impl AssetLifecycler for ShaderLifecycler
         {
             stage: shader_file.stage,
             module,
+            reflection: shader_file.reflection,
         })
     }
 }
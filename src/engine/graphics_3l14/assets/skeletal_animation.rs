@@ -2,33 +2,415 @@ use asset_3l14::TrivialAssetLifecycler;
 use bitcode::{Decode, Encode};
 use debug_3l14::debug_gui::DebugGui;
 use egui::Ui;
-use math_3l14::{Ratio, PackedTransform};
+use glam::{Quat, Vec3};
+use math_3l14::{DualQuat, NQuat48, Ratio};
+use nab_3l14::Signal;
 use proc_macros_3l14::asset;
+use serde::{Deserialize, Serialize};
 use crate::assets::BoneId;
 
 // todo: standardize
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Encode, Decode)]
 pub struct AnimFrameNumber(pub u32);
 
+// Keys are stored as frame numbers, so animations are limited in length
+pub const MAX_ANIM_FRAMES: u32 = u16::MAX as u32 + 1;
+
+// A range of keys for one channel of one bone, indexes into the animation's keys and values
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct AnimTrack
+{
+    pub first_key: u32,
+    pub key_count: u32, // a single key means the track is constant
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct BoneTracks
+{
+    pub rotation: AnimTrack,
+    pub translation: AnimTrack,
+    pub scale: AnimTrack, // no keys if the bone is not scaled
+}
+
+// All translations (or scales) in an animation are quantized to 16 bits per component within this range
+#[derive(Debug, Default, Clone, Copy, PartialEq, Encode, Decode)]
+pub struct TranslationRange
+{
+    pub min: Vec3,
+    pub extent: Vec3,
+}
+impl TranslationRange
+{
+    #[must_use]
+    pub fn from_points(points: &[Vec3]) -> Self
+    {
+        let (min, max) = points.iter().fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), p| (min.min(*p), max.max(*p)));
+        if min.cmple(max).all() { Self { min, extent: max - min } } else { Self::default() }
+    }
+
+    #[inline] #[must_use]
+    pub fn quantize(&self, translation: Vec3) -> [u16; 3]
+    {
+        let normalized = ((translation - self.min) / self.extent).clamp(Vec3::ZERO, Vec3::ONE);
+        // constant axes divide by zero
+        let normalized = Vec3::select(self.extent.cmpgt(Vec3::ZERO), normalized, Vec3::ZERO);
+        (normalized * u16::MAX as f32).round().as_u16vec3().to_array()
+    }
+
+    #[inline] #[must_use]
+    pub fn dequantize(&self, quantized: [u16; 3]) -> Vec3
+    {
+        self.min + glam::U16Vec3::from_array(quantized).as_vec3() * (self.extent / u16::MAX as f32)
+    }
+}
+
+// A named point in an animation, e.g. a footstep
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct AnimEvent
+{
+    pub frame: f32,
+    pub name: String,
+    pub signal: Option<Signal>, // optionally sent to latch circuits
+}
+
+// How much error is allowed when removing keys
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AnimCompression
+{
+    pub rotation_tolerance: f32, // in radians
+    pub translation_tolerance: f32, // in skeleton units
+    pub scale_tolerance: f32, // per-axis
+}
+impl AnimCompression
+{
+    // Only quantize, keep every key that isn't part of a constant track
+    pub const KEEP_KEYS: Self = Self { rotation_tolerance: 0.0, translation_tolerance: 0.0, scale_tolerance: 0.0 };
+}
+impl Default for AnimCompression
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            rotation_tolerance: 0.001,
+            translation_tolerance: 0.0005,
+            scale_tolerance: 0.001,
+        }
+    }
+}
+
 #[asset]
 #[derive(Encode, Decode)]
 pub struct SkeletalAnimation
 {
-    pub sample_rate: Ratio<u32>, // todo: hard code into flags?
+    pub sample_rate: Ratio<u32>, // frames per second, todo: hard code into flags?
     // flags
-    // TODO: sparse frames.
     pub frame_count: AnimFrameNumber,
     pub bones: Box<[BoneId]>, // sorted by ID
-    pub poses: Box<[PackedTransform]>, // 2D array, # of bones (ordered by bone ID) * # of keyframes
+    pub tracks: Box<[BoneTracks]>, // one per bone
+    pub translation_range: TranslationRange,
+    pub scale_range: TranslationRange,
+    // each track's keys are sorted by frame, and always start at frame 0
+    pub rotation_keys: Box<[u16]>,
+    pub rotations: Box<[NQuat48]>,
+    pub translation_keys: Box<[u16]>,
+    pub translations: Box<[[u16; 3]]>,
+    pub scale_keys: Box<[u16]>,
+    pub scales: Box<[[u16; 3]]>,
+    // the root bone's motion relative to the first frame, removed from the root bone's track. Empty if not extracted
+    // has an extra entry at the end, for the motion after looping back to the first frame
+    pub root_motion: Box<[DualQuat]>,
+    pub events: Box<[AnimEvent]>, // sorted by frame
 }
 impl SkeletalAnimation
 {
-    pub fn get_pose_for_frame(&self, frame: AnimFrameNumber) -> &[PackedTransform]
+    // Build an animation from dense samples, removing any keys that can be reconstructed within the tolerance
+    // rotations, translations, and scales are 2D arrays of # of bones * # of frames (each bone's frames are contiguous)
+    // scales can be empty if no bones are scaled
+    #[must_use]
+    pub fn compress(
+        sample_rate: Ratio<u32>,
+        bones: Box<[BoneId]>,
+        frame_count: u32,
+        rotations: &[Quat],
+        translations: &[Vec3],
+        scales: &[Vec3],
+        compression: AnimCompression) -> Self
+    {
+        assert!(frame_count > 0 && frame_count <= MAX_ANIM_FRAMES, "Animations must have between 1 and {MAX_ANIM_FRAMES} frames");
+        assert_eq!(rotations.len(), bones.len() * frame_count as usize);
+        assert_eq!(translations.len(), bones.len() * frame_count as usize);
+        assert!(scales.is_empty() || scales.len() == bones.len() * frame_count as usize);
+
+        let translation_range = TranslationRange::from_points(translations);
+        let scale_range = TranslationRange::from_points(scales);
+
+        let mut rotation_keys = Vec::new();
+        let mut rotation_values = Vec::new();
+        let mut translation_keys = Vec::new();
+        let mut translation_values = Vec::new();
+        let mut scale_keys = Vec::new();
+        let mut scale_values = Vec::new();
+
+        let tracks = rotations.chunks_exact(frame_count as usize)
+            .zip(translations.chunks_exact(frame_count as usize))
+            .enumerate()
+            .map(|(bone, (bone_rotations, bone_translations))|
+            {
+                let bone_scales = scales.get(bone * frame_count as usize..(bone + 1) * frame_count as usize).unwrap_or_default();
+                let is_scaled = bone_scales.iter().any(|s| !s.abs_diff_eq(Vec3::ONE, compression.scale_tolerance));
+                BoneTracks
+                {
+                    rotation: reduce_keys(&RotationCodec, bone_rotations, compression.rotation_tolerance, &mut rotation_keys, &mut rotation_values),
+                    translation: reduce_keys(&translation_range, bone_translations, compression.translation_tolerance, &mut translation_keys, &mut translation_values),
+                    scale: if is_scaled
+                    {
+                        reduce_keys(&ScaleCodec(scale_range), bone_scales, compression.scale_tolerance, &mut scale_keys, &mut scale_values)
+                    }
+                    else { AnimTrack::default() },
+                }
+            })
+            .collect();
+
+        Self
+        {
+            sample_rate,
+            frame_count: AnimFrameNumber(frame_count),
+            bones,
+            tracks,
+            translation_range,
+            rotation_keys: rotation_keys.into_boxed_slice(),
+            rotations: rotation_values.into_boxed_slice(),
+            translation_keys: translation_keys.into_boxed_slice(),
+            translations: translation_values.into_boxed_slice(),
+            scale_range,
+            scale_keys: scale_keys.into_boxed_slice(),
+            scales: scale_values.into_boxed_slice(),
+            root_motion: Box::new([]),
+            events: Box::new([]),
+        }
+    }
+
+    // Get the (local space) pose of a bone (by its index in this animation) at a frame
+    #[must_use]
+    pub fn sample_bone(&self, bone: usize, frame: AnimFrameNumber) -> DualQuat
+    {
+        let tracks = &self.tracks[bone];
+        let rotation = sample_track(&RotationCodec, tracks.rotation, &self.rotation_keys, &self.rotations, frame);
+        let translation = sample_track(&self.translation_range, tracks.translation, &self.translation_keys, &self.translations, frame);
+        DualQuat::from_rot_trans(rotation, translation)
+    }
+
+    // Get the (local space) scale of a bone (by its index in this animation) at a frame
+    #[must_use]
+    pub fn sample_scale(&self, bone: usize, frame: AnimFrameNumber) -> Vec3
+    {
+        let track = self.tracks[bone].scale;
+        if track.key_count == 0 { return Vec3::ONE; }
+        sample_track(&ScaleCodec(self.scale_range), track, &self.scale_keys, &self.scales, frame)
+    }
+
+    #[inline] #[must_use]
+    pub fn has_scale(&self) -> bool { !self.scale_keys.is_empty() }
+
+    #[inline] #[must_use]
+    pub fn has_root_motion(&self) -> bool { !self.root_motion.is_empty() }
+
+    // The root motion at a frame position, clamped to the animation
+    #[must_use]
+    fn root_motion_at(&self, position: f32) -> DualQuat
     {
-        let bone_count = self.bones.len();
-        let start = frame.0 as usize * bone_count;
-        &self.poses[start..start + bone_count]
+        let last = self.root_motion.len() - 1;
+        let position = position.clamp(0.0, last as f32);
+        let frame = position as usize;
+        if frame >= last { return self.root_motion[last]; }
+        self.root_motion[frame].nlerp(self.root_motion[frame + 1], position.fract())
+    }
+
+    // The root motion when moving from one frame position to another (positions are not wrapped when looping)
+    #[must_use]
+    pub fn root_motion_between(&self, from: f32, to: f32, should_loop: bool) -> DualQuat
+    {
+        if !self.has_root_motion() { return DualQuat::IDENTITY; }
+
+        if !should_loop
+        {
+            let last = (self.frame_count.0 - 1) as f32;
+            return self.root_motion_at(from.min(last)).inverse() * self.root_motion_at(to.min(last));
+        }
+
+        let period = self.frame_count.0 as f32;
+        let (from_loop, to_loop) = ((from / period).floor(), (to / period).floor());
+        let loop_motion = self.root_motion[self.frame_count.0 as usize];
+
+        let mut motion = self.root_motion_at(from - from_loop * period).inverse();
+        for _ in 0..(to_loop - from_loop) as u32
+        {
+            motion = motion * loop_motion;
+        }
+        motion * self.root_motion_at(to - to_loop * period)
     }
+
+    // Call back for each event in [from, to) (positions are not wrapped when looping)
+    pub fn events_between(&self, from: f32, to: f32, should_loop: bool, mut on_event: impl FnMut(&AnimEvent))
+    {
+        if self.events.is_empty() || to <= from { return; }
+
+        let mut emit = |from: f32, to: f32|
+        {
+            let start = self.events.partition_point(|e| e.frame < from);
+            for event in self.events[start..].iter().take_while(|e| e.frame < to)
+            {
+                on_event(event);
+            }
+        };
+
+        if !should_loop
+        {
+            emit(from, to);
+            return;
+        }
+
+        let period = self.frame_count.0 as f32;
+        let (from_loop, to_loop) = ((from / period).floor(), (to / period).floor());
+        if from_loop == to_loop
+        {
+            emit(from - from_loop * period, to - to_loop * period);
+            return;
+        }
+
+        emit(from - from_loop * period, period);
+        for _ in 1..(to_loop - from_loop) as u32
+        {
+            emit(0.0, period);
+        }
+        emit(0.0, to - to_loop * period);
+    }
+
+    // The size of the animation data, in bytes
+    #[must_use]
+    pub fn data_size(&self) -> usize
+    {
+        size_of_val(&*self.bones) +
+        size_of_val(&*self.tracks) +
+        size_of_val(&*self.rotation_keys) +
+        size_of_val(&*self.rotations) +
+        size_of_val(&*self.translation_keys) +
+        size_of_val(&*self.translations) +
+        size_of_val(&*self.scale_keys) +
+        size_of_val(&*self.scales) +
+        size_of_val(&*self.root_motion) +
+        self.events.iter().map(|e| size_of::<AnimEvent>() + e.name.len()).sum::<usize>()
+    }
+
+    // The length of the animation, in seconds (when looping, this includes blending from the last frame back to the first)
+    #[inline] #[must_use]
+    pub fn duration_secs(&self) -> f32
+    {
+        self.frame_count.0 as f32 * self.sample_rate.to_f32_recip()
+    }
+}
+
+// How a track's values are stored and interpolated
+trait TrackCodec
+{
+    type Value: Copy;
+    type Packed: Copy + PartialEq;
+
+    fn pack(&self, value: Self::Value) -> Self::Packed;
+    fn unpack(&self, packed: Self::Packed) -> Self::Value;
+    fn lerp(a: Self::Value, b: Self::Value, t: f32) -> Self::Value;
+    fn error(a: Self::Value, b: Self::Value) -> f32;
+}
+
+struct RotationCodec;
+impl TrackCodec for RotationCodec
+{
+    type Value = Quat;
+    type Packed = NQuat48;
+
+    fn pack(&self, value: Quat) -> NQuat48 { value.normalize().into() }
+    fn unpack(&self, packed: NQuat48) -> Quat { packed.into() }
+    fn lerp(a: Quat, b: Quat, t: f32) -> Quat { a.lerp(b, t) } // takes the shortest path
+    fn error(a: Quat, b: Quat) -> f32 { 2.0 * a.dot(b).abs().min(1.0).acos() }
+}
+
+impl TrackCodec for TranslationRange
+{
+    type Value = Vec3;
+    type Packed = [u16; 3];
+
+    fn pack(&self, value: Vec3) -> [u16; 3] { self.quantize(value) }
+    fn unpack(&self, packed: [u16; 3]) -> Vec3 { self.dequantize(packed) }
+    fn lerp(a: Vec3, b: Vec3, t: f32) -> Vec3 { a.lerp(b, t) }
+    fn error(a: Vec3, b: Vec3) -> f32 { a.distance(b) }
+}
+
+// Scales are quantized like translations, but compared per-axis (as they are relative)
+struct ScaleCodec(TranslationRange);
+impl TrackCodec for ScaleCodec
+{
+    type Value = Vec3;
+    type Packed = [u16; 3];
+
+    fn pack(&self, value: Vec3) -> [u16; 3] { self.0.quantize(value) }
+    fn unpack(&self, packed: [u16; 3]) -> Vec3 { self.0.dequantize(packed) }
+    fn lerp(a: Vec3, b: Vec3, t: f32) -> Vec3 { a.lerp(b, t) }
+    fn error(a: Vec3, b: Vec3) -> f32 { (a - b).abs().max_element() }
+}
+
+// Greedily extend each key as far as linear interpolation stays within the tolerance
+// Errors are measured against the quantized values, as that precision is lost regardless
+fn reduce_keys<C: TrackCodec>(codec: &C, values: &[C::Value], tolerance: f32, keys: &mut Vec<u16>, packed: &mut Vec<C::Packed>) -> AnimTrack
+{
+    let first_key = keys.len() as u32;
+    let quantized: Box<[C::Value]> = values.iter().map(|v| codec.unpack(codec.pack(*v))).collect();
+
+    let fits = |start: usize, end: usize|
+    {
+        (start + 1..end).all(|i|
+        {
+            let t = (i - start) as f32 / (end - start) as f32;
+            C::error(C::lerp(quantized[start], quantized[end], t), quantized[i]) <= tolerance
+        })
+    };
+
+    keys.push(0);
+    packed.push(codec.pack(values[0]));
+
+    let is_constant = quantized.iter().all(|q| C::error(quantized[0], *q) <= tolerance);
+    if !is_constant
+    {
+        let mut start = 0;
+        while start < values.len() - 1
+        {
+            let mut end = start + 1;
+            while end + 1 < values.len() && fits(start, end + 1) { end += 1; }
+
+            keys.push(end as u16);
+            packed.push(codec.pack(values[end]));
+            start = end;
+        }
+    }
+
+    AnimTrack { first_key, key_count: keys.len() as u32 - first_key }
+}
+
+fn sample_track<C: TrackCodec>(codec: &C, track: AnimTrack, keys: &[u16], packed: &[C::Packed], frame: AnimFrameNumber) -> C::Value
+{
+    let range = track.first_key as usize..(track.first_key + track.key_count) as usize;
+    let (keys, packed) = (&keys[range.clone()], &packed[range]);
+
+    let next = keys.partition_point(|k| (*k as u32) <= frame.0);
+    if next >= keys.len()
+    {
+        return codec.unpack(packed[keys.len() - 1]);
+    }
+
+    // the first key is always frame 0, so next is never 0
+    let prev = next - 1;
+    let t = (frame.0 - keys[prev] as u32) as f32 / (keys[next] - keys[prev]) as f32;
+    C::lerp(codec.unpack(packed[prev]), codec.unpack(packed[next]), t)
 }
 
 pub struct SkeletalAnimationLifecycler;
@@ -42,3 +424,140 @@ impl DebugGui for SkeletalAnimationLifecycler
         // TODO
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use std::f32::consts::FRAC_PI_2;
+    use super::*;
+
+    fn compress(rotations: &[Quat], translations: &[Vec3], compression: AnimCompression) -> SkeletalAnimation
+    {
+        SkeletalAnimation::compress(Ratio::new(30, 1), Box::new([BoneId(0)]), rotations.len() as u32, rotations, translations, &[], compression)
+    }
+
+    #[test]
+    fn constant_tracks()
+    {
+        let anim = compress(&[Quat::from_rotation_x(1.0); 10], &[Vec3::new(1.0, 2.0, 3.0); 10], AnimCompression::KEEP_KEYS);
+        assert_eq!(anim.tracks[0].rotation.key_count, 1);
+        assert_eq!(anim.tracks[0].translation.key_count, 1);
+
+        let pose = anim.sample_bone(0, AnimFrameNumber(7));
+        assert!(pose.rotation().abs_diff_eq(Quat::from_rotation_x(1.0), 1e-4));
+        assert!(pose.translation().abs_diff_eq(Vec3::new(1.0, 2.0, 3.0), 1e-4));
+    }
+
+    #[test]
+    fn linear_keys_removed()
+    {
+        let rotations: Box<[_]> = (0..21).map(|i| Quat::from_rotation_y(i as f32 * 0.01)).collect();
+        // moves linearly, then comes back
+        let translations: Box<[_]> = (0..21).map(|i| Vec3::new((10 - (i - 10i32).abs()) as f32, 0.0, 0.0)).collect();
+
+        let anim = compress(&rotations, &translations, AnimCompression::default());
+        assert_eq!(anim.tracks[0].rotation.key_count, 2); // nlerp barely differs from slerp over small angles
+        assert_eq!(anim.tracks[0].translation.key_count, 3);
+        assert_eq!(&anim.translation_keys[..], &[0, 10, 20]);
+
+        for i in 0..21
+        {
+            let pose = anim.sample_bone(0, AnimFrameNumber(i));
+            assert!(pose.rotation().angle_between(rotations[i as usize]) <= 0.002);
+            assert!(pose.translation().distance(translations[i as usize]) <= 0.001, "{i}: {} != {}", pose.translation(), translations[i as usize]);
+        }
+    }
+
+    #[test]
+    fn tolerance()
+    {
+        let rotations: Box<[_]> = (0..9).map(|i| Quat::from_rotation_z(FRAC_PI_2 * (i as f32 * 0.25).sin())).collect();
+        let translations = [Vec3::ZERO; 9];
+
+        let lossy = compress(&rotations, &translations, AnimCompression { rotation_tolerance: 0.1, ..AnimCompression::KEEP_KEYS });
+        let keep = compress(&rotations, &translations, AnimCompression::KEEP_KEYS);
+        assert!(lossy.tracks[0].rotation.key_count < keep.tracks[0].rotation.key_count);
+        assert_eq!(keep.tracks[0].rotation.key_count, 9);
+        assert!(lossy.data_size() < keep.data_size());
+
+        for i in 0..9
+        {
+            let pose = lossy.sample_bone(0, AnimFrameNumber(i));
+            assert!(pose.rotation().angle_between(rotations[i as usize]) <= 0.1 + 1e-3);
+        }
+    }
+
+    #[test]
+    fn root_motion()
+    {
+        let step = DualQuat::from_rot_trans(Quat::IDENTITY, Vec3::new(0.0, 0.0, 1.0));
+        let mut anim = compress(&[Quat::IDENTITY; 4], &[Vec3::ZERO; 4], AnimCompression::KEEP_KEYS);
+        assert_eq!(anim.root_motion_between(0.0, 2.0, true), DualQuat::IDENTITY);
+
+        // moves 1 unit per frame
+        anim.root_motion = (0..5).map(|i| DualQuat::from_rot_trans(Quat::IDENTITY, Vec3::new(0.0, 0.0, i as f32))).collect();
+        assert!(anim.root_motion_between(1.0, 2.5, false).translation().abs_diff_eq(Vec3::new(0.0, 0.0, 1.5), 1e-5));
+        assert!(anim.root_motion_between(2.0, 10.0, false).translation().abs_diff_eq(step.translation(), 1e-5));
+        // two and a half loops
+        assert!(anim.root_motion_between(3.0, 13.0, true).translation().abs_diff_eq(Vec3::new(0.0, 0.0, 10.0), 1e-4));
+
+        // turning
+        anim.root_motion = (0..5).map(|i| DualQuat::from_rot_trans(Quat::from_rotation_y(FRAC_PI_2 * i as f32), Vec3::ZERO)).collect();
+        let turn = anim.root_motion_between(1.0, 2.0, true);
+        assert!(turn.rotation().abs_diff_eq(Quat::from_rotation_y(FRAC_PI_2), 1e-5));
+    }
+
+    #[test]
+    fn events()
+    {
+        let mut anim = compress(&[Quat::IDENTITY; 4], &[Vec3::ZERO; 4], AnimCompression::KEEP_KEYS);
+        let event = |frame: f32, name: &str| AnimEvent { frame, name: name.to_string(), signal: None };
+        anim.events = Box::new([event(0.0, "a"), event(1.5, "b"), event(3.0, "c")]);
+
+        let collect = |from, to, should_loop|
+        {
+            let mut names = String::new();
+            anim.events_between(from, to, should_loop, |e| names.push_str(&e.name));
+            names
+        };
+
+        assert_eq!(collect(0.0, 1.5, false), "a");
+        assert_eq!(collect(1.5, 4.0, false), "bc");
+        assert_eq!(collect(2.0, 2.0, true), "");
+        assert_eq!(collect(3.0, 5.0, true), "ca");
+        assert_eq!(collect(2.0, 10.0, true), "cabcab");
+    }
+
+    #[test]
+    fn scales()
+    {
+        let bones: Box<[_]> = Box::new([BoneId(0), BoneId(1)]);
+        let rotations = [Quat::IDENTITY; 10];
+        let translations = [Vec3::ZERO; 10];
+        // only the second bone is scaled
+        let scales: Box<[_]> = (0..10).map(|i| if i < 5 { Vec3::ONE } else { Vec3::new(1.0, 1.0 + (i - 5) as f32 * 0.5, 1.0) }).collect();
+
+        let anim = SkeletalAnimation::compress(Ratio::new(30, 1), bones.clone(), 5, &rotations, &translations, &scales, AnimCompression::default());
+        assert!(anim.has_scale());
+        assert_eq!(anim.tracks[0].scale.key_count, 0);
+        assert_eq!(anim.tracks[1].scale.key_count, 2); // linear
+        assert_eq!(anim.sample_scale(0, AnimFrameNumber(3)), Vec3::ONE);
+        assert!(anim.sample_scale(1, AnimFrameNumber(3)).abs_diff_eq(Vec3::new(1.0, 2.5, 1.0), 1e-3));
+
+        let unscaled = SkeletalAnimation::compress(Ratio::new(30, 1), bones, 5, &rotations, &translations, &[], AnimCompression::default());
+        assert!(!unscaled.has_scale());
+        assert_eq!(unscaled.sample_scale(1, AnimFrameNumber(3)), Vec3::ONE);
+        assert!(unscaled.data_size() < anim.data_size());
+    }
+
+    #[test]
+    fn translation_range()
+    {
+        let range = TranslationRange::from_points(&[Vec3::new(-1.0, 5.0, 0.0), Vec3::new(3.0, 5.0, 2.0)]);
+        assert_eq!(range, TranslationRange { min: Vec3::new(-1.0, 5.0, 0.0), extent: Vec3::new(4.0, 0.0, 2.0) });
+
+        let point = Vec3::new(0.5, 5.0, 1.5);
+        assert!(range.dequantize(range.quantize(point)).abs_diff_eq(point, 1e-4));
+        assert_eq!(range.quantize(Vec3::new(10.0, 10.0, -10.0)), [u16::MAX, 0, 0]);
+    }
+}
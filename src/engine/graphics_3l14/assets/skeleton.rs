@@ -9,7 +9,7 @@ use proc_macros_3l14::asset;
 use std::hash::{Hash, Hasher};
 use std::sync::atomic::{AtomicBool, Ordering};
 
-pub const MAX_SKINNED_BONES: usize = 128; // TODO: share with HLSL
+pub const MAX_SKINNED_BONES: usize = 128; // must match the shaders, verified by the shader builder
 
 #[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Encode, Decode, Debug)]
 pub struct BoneId(pub u32); // TODO: 32 bits should be sufficient
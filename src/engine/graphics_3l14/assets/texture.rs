@@ -3,8 +3,7 @@ use egui::Ui;
 use std::error::Error;
 use std::sync::atomic::{AtomicI64, Ordering};
 use triomphe::Arc;
-use wgpu::util::{DeviceExt, TextureDataOrder};
-use wgpu::{Extent3d, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureViewDescriptor};
+use wgpu::{Extent3d, Origin3d, TexelCopyBufferLayout, TexelCopyTextureInfo, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureViewDescriptor};
 use asset_3l14::{AssetLifecycler, AssetLoadRequest};
 use debug_3l14::debug_gui::DebugGui;
 use nab_3l14::format_binary;
@@ -14,7 +13,7 @@ pub const MAX_MIP_COUNT: usize = 16;
 
 // TODO: use wgpu format, but add a note in compiler that it could change when wgpu changes (though unlikely)
 #[repr(u8)]
-#[derive(Encode, Decode)]
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TextureFilePixelFormat
 {
     // Uncompressed formats
@@ -22,9 +21,90 @@ pub enum TextureFilePixelFormat
     Rg8 = 2,
     Rgba8 = 3,
     Rgba8Srgb = 4,
+    Rgba16Float = 5,
+
+    // Block compressed formats (4x4 texel blocks)
+    Bc1 = 16, // RGB, 1-bit alpha
+    Bc1Srgb = 17,
+    Bc3 = 18, // RGBA
+    Bc3Srgb = 19,
+    Bc4 = 20, // R
+    Bc5 = 21, // RG
+    Bc7 = 22, // RGB(A), higher quality than BC1/3
+    Bc7Srgb = 23,
+}
+impl TextureFilePixelFormat
+{
+    #[must_use]
+    pub const fn wgpu_format(self) -> TextureFormat
+    {
+        match self
+        {
+            TextureFilePixelFormat::R8 => TextureFormat::R8Unorm,
+            TextureFilePixelFormat::Rg8 => TextureFormat::Rg8Unorm,
+            TextureFilePixelFormat::Rgba8 => TextureFormat::Rgba8Unorm,
+            TextureFilePixelFormat::Rgba8Srgb => TextureFormat::Rgba8UnormSrgb,
+            TextureFilePixelFormat::Rgba16Float => TextureFormat::Rgba16Float,
+            TextureFilePixelFormat::Bc1 => TextureFormat::Bc1RgbaUnorm,
+            TextureFilePixelFormat::Bc1Srgb => TextureFormat::Bc1RgbaUnormSrgb,
+            TextureFilePixelFormat::Bc3 => TextureFormat::Bc3RgbaUnorm,
+            TextureFilePixelFormat::Bc3Srgb => TextureFormat::Bc3RgbaUnormSrgb,
+            TextureFilePixelFormat::Bc4 => TextureFormat::Bc4RUnorm,
+            TextureFilePixelFormat::Bc5 => TextureFormat::Bc5RgUnorm,
+            TextureFilePixelFormat::Bc7 => TextureFormat::Bc7RgbaUnorm,
+            TextureFilePixelFormat::Bc7Srgb => TextureFormat::Bc7RgbaUnormSrgb,
+        }
+    }
+
+    #[inline] #[must_use]
+    pub const fn is_block_compressed(self) -> bool
+    {
+        (self as u8) >= (TextureFilePixelFormat::Bc1 as u8)
+    }
 
-    // TODO: compressed formats (bc#)
+    // The width and height of a block of texels (1 for uncompressed formats)
+    #[inline] #[must_use]
+    pub const fn block_dimension(self) -> u32
+    {
+        if self.is_block_compressed() { 4 } else { 1 }
+    }
+
+    #[must_use]
+    pub const fn block_bytes(self) -> u32
+    {
+        match self
+        {
+            TextureFilePixelFormat::R8 => 1,
+            TextureFilePixelFormat::Rg8 => 2,
+            TextureFilePixelFormat::Rgba8 |
+            TextureFilePixelFormat::Rgba8Srgb => 4,
+            TextureFilePixelFormat::Rgba16Float => 8,
+            TextureFilePixelFormat::Bc1 |
+            TextureFilePixelFormat::Bc1Srgb |
+            TextureFilePixelFormat::Bc4 => 8,
+            TextureFilePixelFormat::Bc3 |
+            TextureFilePixelFormat::Bc3Srgb |
+            TextureFilePixelFormat::Bc5 |
+            TextureFilePixelFormat::Bc7 |
+            TextureFilePixelFormat::Bc7Srgb => 16,
+        }
+    }
 
+    // The number of blocks wide and high a mip of the given size is (partial blocks are padded)
+    #[inline] #[must_use]
+    pub const fn block_count(self, width: u32, height: u32) -> (u32, u32)
+    {
+        let dim = self.block_dimension();
+        (width.div_ceil(dim), height.div_ceil(dim))
+    }
+
+    // The size, in bytes, of a single mip of the given size
+    #[inline] #[must_use]
+    pub const fn mip_bytes(self, width: u32, height: u32, depth: u32) -> usize
+    {
+        let (blocks_wide, blocks_high) = self.block_count(width, height);
+        blocks_wide as usize * blocks_high as usize * depth as usize * self.block_bytes() as usize
+    }
 }
 
 #[derive(Encode, Decode)]
@@ -34,7 +114,7 @@ pub struct TextureFile
     pub height: u32,
     pub depth: u32,
     pub mip_count: u8, // always <= MAX_MIP_COUNT
-    pub mip_offsets: [usize; MAX_MIP_COUNT], // offsets into the payload (0 being the beginning of the smallest mip)
+    pub mip_offsets: [usize; MAX_MIP_COUNT], // indexed by mip level (0 being the full size), offsets into the payload (0 being the beginning of the smallest mip)
     pub pixel_format: TextureFilePixelFormat,
     // mips are organized from smallest (lowest quality) to largest (highest quality)
     // all mips are stored contiguously w/out gaps
@@ -57,10 +137,11 @@ impl Texture
                 .mip_level_size(mip, self.gpu_tex.dimension())
                 .physical_size(self.gpu_tex.format());
 
-            let area = (size.width as i64) * (size.height as i64) * (size.depth_or_array_layers as i64);
+            let (block_width, block_height) = self.gpu_tex.format().block_dimensions();
+            let blocks = (size.width / block_width) as i64 * (size.height / block_height) as i64 * (size.depth_or_array_layers as i64);
             let block_size = self.gpu_tex.format().block_copy_size(Some(TextureAspect::All));
 
-            total_size += area * block_size.unwrap() as i64;
+            total_size += blocks * block_size.unwrap() as i64;
         }
         total_size
     }
@@ -92,35 +173,61 @@ impl AssetLifecycler for TextureLifecycler
 
         let asskey = request.asset_key;
         let texel_bytes = request.read_to_end()?;
-        let gpu_tex = self.renderer.device().create_texture_with_data(
-            self.renderer.queue(),
-            &TextureDescriptor
+
+        let format = tex_file.pixel_format.wgpu_format();
+        let size = Extent3d
+        {
+            width: tex_file.width,
+            height: tex_file.height,
+            depth_or_array_layers: tex_file.depth,
+        };
+        // Nx1 images (e.g. ramps) are still 2D, 1D textures can't have mips or be bound to 2D slots
+        let dimension =
+            if tex_file.depth > 1 { TextureDimension::D3 }
+            else { TextureDimension::D2 };
+
+        let gpu_tex = self.renderer.device().create_texture(&TextureDescriptor
+        {
+            label: debug_label!(&format!("{:?}", asskey)),
+            size,
+            mip_level_count: tex_file.mip_count as u32,
+            sample_count: 1,
+            dimension,
+            format,
+            usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        // mips are stored smallest first, so they are uploaded individually
+        for mip in 0..(tex_file.mip_count as u32)
+        {
+            let mip_size = size.mip_level_size(mip, dimension);
+            let (blocks_wide, blocks_high) = tex_file.pixel_format.block_count(mip_size.width, mip_size.height);
+            let mip_offset = tex_file.mip_offsets[mip as usize];
+            let mip_bytes = tex_file.pixel_format.mip_bytes(mip_size.width, mip_size.height, mip_size.depth_or_array_layers);
+
+            let Some(mip_texels) = texel_bytes.get(mip_offset..(mip_offset + mip_bytes)) else
             {
-                label: debug_label!(&format!("{:?}", asskey)),
-                size: Extent3d
+                return Err(Box::new(TextureLoadError::TruncatedMip(mip)));
+            };
+
+            self.renderer.queue().write_texture(
+                TexelCopyTextureInfo
                 {
-                    width: tex_file.width,
-                    height: tex_file.height,
-                    depth_or_array_layers: tex_file.depth,
+                    texture: &gpu_tex,
+                    mip_level: mip,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
                 },
-                mip_level_count: tex_file.mip_count as u32,
-                sample_count: 1,
-                dimension:
-                if tex_file.depth > 1 { TextureDimension::D3 }
-                else if tex_file.height > 1 { TextureDimension::D2 }
-                else { TextureDimension::D1 },
-                format: match tex_file.pixel_format
+                mip_texels,
+                TexelCopyBufferLayout
                 {
-                    TextureFilePixelFormat::R8 => TextureFormat::R8Unorm,
-                    TextureFilePixelFormat::Rg8 => TextureFormat::Rg8Unorm,
-                    TextureFilePixelFormat::Rgba8 => TextureFormat::Rgba8Unorm,
-                    TextureFilePixelFormat::Rgba8Srgb => TextureFormat::Rgba8UnormSrgb,
+                    offset: 0,
+                    bytes_per_row: Some(blocks_wide * tex_file.pixel_format.block_bytes()),
+                    rows_per_image: Some(blocks_high),
                 },
-                usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
-                view_formats: &[],
-            },
-            TextureDataOrder::LayerMajor,
-            texel_bytes);
+                mip_size.physical_size(format));
+        }
 
         let view = gpu_tex.create_view(&TextureViewDescriptor
         {
@@ -156,3 +263,14 @@ impl DebugGui for TextureLifecycler
         ui.label(format!("Total device bytes: {:#.2}B", format_binary!(self.device_bytes.load(Ordering::Relaxed))));
     }
 }
+
+#[derive(Debug)]
+pub enum TextureLoadError
+{
+    TruncatedMip(u32),
+}
+impl std::fmt::Display for TextureLoadError
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { std::fmt::Debug::fmt(self, f) }
+}
+impl Error for TextureLoadError { }
@@ -0,0 +1,66 @@
+// Headless culling benchmarks, timed with a plain loop (the built-in bench harness is nightly only)
+// The work counters (CullStats) are deterministic, so they are printed alongside the times for comparing changes
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use glam::{Mat4, Vec3};
+use graphics_3l14::culling::{OcclusionBuffer, VisibleSet};
+use graphics_3l14::culling::fixtures::grid_tree;
+use math_3l14::{Angle, Frustum, AABB};
+
+const GRID_SIZE: i32 = 100; // boxes per side
+const MIN_BENCH_TIME: Duration = Duration::from_millis(500);
+
+fn view_proj() -> Mat4
+{
+    let projection = Mat4::perspective_lh(Angle::from_degrees(70.0).to_radians(), 16.0 / 9.0, 0.5, 150.0);
+    let view = Mat4::look_to_lh(Vec3::new(1.0, 2.0, -3.0), Vec3::new(0.2, -0.1, 1.0), Vec3::Y);
+    projection * view
+}
+
+// Run a function until enough time has passed, and print the average time of each run
+fn bench(name: &str, mut run: impl FnMut())
+{
+    run(); // warm up
+
+    let mut iterations = 0u32;
+    let start = Instant::now();
+    while start.elapsed() < MIN_BENCH_TIME
+    {
+        run();
+        iterations += 1;
+    }
+    println!("{name:<24} {:>10.2?}/iter ({iterations} iterations)", start.elapsed() / iterations);
+}
+
+fn main()
+{
+    let tree = grid_tree(GRID_SIZE);
+    let view_proj = view_proj();
+    let frustum = Frustum::from_matrix(&view_proj);
+
+    let mut occlusion = OcclusionBuffer::new(128, 72);
+    let add_occluders = |occlusion: &mut OcclusionBuffer|
+    {
+        occlusion.begin(view_proj);
+        occlusion.add_box_occluder(AABB::new(Vec3::new(-10.0, -1.0, 12.0), Vec3::new(14.0, 8.0, 13.0)));
+        occlusion.add_box_occluder(AABB::new(Vec3::new(-40.0, -1.0, 60.0), Vec3::new(40.0, 20.0, 62.0)));
+    };
+    add_occluders(&mut occlusion);
+
+    let mut visible = VisibleSet::new();
+    bench("frustum", ||
+    {
+        visible.clear();
+        visible.cull(black_box(&tree), black_box(&frustum), None);
+    });
+    println!("  {} of {} visible, {:?}", visible.len(), GRID_SIZE * GRID_SIZE, visible.stats());
+
+    bench("frustum + occlusion", ||
+    {
+        visible.clear();
+        visible.cull(black_box(&tree), black_box(&frustum), Some(black_box(&occlusion)));
+    });
+    println!("  {} of {} visible, {:?}", visible.len(), GRID_SIZE * GRID_SIZE, visible.stats());
+
+    bench("occluder rasterization", || add_occluders(black_box(&mut occlusion)));
+}
@@ -1,8 +1,9 @@
 use std::time::Duration;
 use egui::Ui;
-use glam::Mat4;
+use glam::{Mat4, Vec2, Vec3, Vec3Swizzles};
 use debug_3l14::debug_gui::DebugGui;
-use math_3l14::{Angle, Transform};
+use math_3l14::{Angle, CanSee, Sphere, Transform, AABB};
+use crate::shader_reflection::{UniformMemberLayout, UniformType};
 
 #[derive(Debug, Clone)]
 pub enum CameraProjection
@@ -12,7 +13,7 @@ pub enum CameraProjection
         fov: Angle,
         aspect_ratio: f32, // width / height
     },
-    Orthographic
+    Orthographic // in view space units, top may be below bottom for y-down views (e.g. UI)
     {
         left: f32,
         top: f32,
@@ -44,7 +45,7 @@ impl CameraProjection
             CameraProjection::Perspective { aspect_ratio, .. } => *aspect_ratio,
             CameraProjection::Orthographic { top, left, right, bottom } =>
             {
-                (right - left) / (bottom - top)
+                ((right - left) / (bottom - top)).abs()
             }
         }
     }
@@ -133,6 +134,122 @@ impl DebugGui for Camera
     }
 }
 
+// A camera's clip volume in a form that is cheap to cull against
+#[derive(Default, Debug, Clone)]
+pub struct CameraClip
+{
+    eye: Vec3,
+    forward: Vec3,
+    right: Vec3,
+    up: Vec3,
+    near_clip: f32,
+    far_clip: f32,
+    center: Vec2, // the view space center of the clip volume's cross-section, only off-axis for orthographic
+    half_extents: Vec2, // the half extents of the cross-section at the eye (zero for perspective)
+    extents_slope: Vec2, // how much the half extents grow per unit of depth (zero for orthographic)
+    sphere_scalar: Vec2, // the length of the (unnormalized) side plane normals, radii are scaled by this
+}
+impl CameraClip
+{
+    #[must_use]
+    pub fn new(camera: &Camera) -> Self
+    {
+        let t = camera.transform();
+
+        let (center, half_extents, extents_slope) = match camera.projection()
+        {
+            CameraProjection::Perspective { fov, aspect_ratio } =>
+            {
+                // W = depth * tan(fov_x / 2), H = depth * tan(fov_y / 2)
+                let depth_scalar = f32::tan(fov.to_radians() / 2.0);
+                (Vec2::ZERO, Vec2::ZERO, Vec2::new(depth_scalar * aspect_ratio, depth_scalar))
+            }
+            CameraProjection::Orthographic { left, top, right, bottom } =>
+            {
+                // W = (right - left) / 2, H = (top - bottom) / 2
+                let center = Vec2::new(left + right, top + bottom) / 2.0;
+                let half_extents = (Vec2::new(right - left, top - bottom) / 2.0).abs();
+                (center, half_extents, Vec2::ZERO)
+            }
+        };
+
+        Self
+        {
+            eye: t.position,
+            forward: t.forward(),
+            right: t.right(),
+            up: t.up(),
+            near_clip: camera.near_clip(),
+            far_clip: camera.far_clip(),
+            center,
+            half_extents,
+            extents_slope,
+            sphere_scalar: Vec2::new(extents_slope.x.hypot(1.0), extents_slope.y.hypot(1.0)), // equivalent to 1.0 / cos(atan(slope))
+        }
+    }
+
+    // A world space point in view space, relative to the center of the clip volume
+    #[inline] #[must_use]
+    fn to_view(&self, point: Vec3) -> Vec3
+    {
+        // TODO: simd (3x3 row matrix of r,u,f * v)
+        let v = point - self.eye;
+        Vec3::new(v.dot(self.right) - self.center.x, v.dot(self.up) - self.center.y, v.dot(self.forward))
+    }
+}
+impl CanSee<Vec3> for CameraClip
+{
+    #[inline]
+    fn can_see(&self, pos: Vec3) -> bool { self.can_see(Sphere::new(pos, 0.0)) }
+}
+impl CanSee<Sphere> for CameraClip
+{
+    fn can_see(&self, other: Sphere) -> bool
+    {
+        let v = self.to_view(other.center());
+        let r = other.radius();
+        if v.z < self.near_clip - r || v.z > self.far_clip + r
+        {
+            return false;
+        }
+
+        // the side planes are at |x|,|y| = half_extents + z * extents_slope
+        let limit = self.half_extents + v.z * self.extents_slope + r * self.sphere_scalar;
+        !v.xy().abs().cmpgt(limit).any()
+    }
+}
+impl CanSee<AABB> for CameraClip
+{
+    // Boxes are tested against each plane separately, so boxes near the corners of the clip volume may pass
+    fn can_see(&self, other: AABB) -> bool
+    {
+        let v = self.to_view(other.centroid());
+        let half = other.half();
+        // the distance from the center of the box to its furthest corner along a (world space) direction
+        let extent = |dir: Vec3| dir.abs().dot(half);
+
+        let depth_extent = extent(self.forward);
+        if v.z < self.near_clip - depth_extent || v.z > self.far_clip + depth_extent
+        {
+            return false;
+        }
+
+        for (axis, dir) in [(0, self.right), (1, self.up)]
+        {
+            // outward side plane normals are +/-dir - slope * forward
+            let slope = self.extents_slope[axis];
+            let limit = self.half_extents[axis] + v.z * slope;
+            if v[axis] - limit > extent(dir - slope * self.forward) ||
+                -v[axis] - limit > extent(dir + slope * self.forward)
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
 #[repr(C, align(16))]
 pub struct CameraUniform
 {
@@ -155,3 +272,105 @@ impl CameraUniform
         }
     }
 }
+impl UniformType for CameraUniform
+{
+    const MEMBERS: &'static [UniformMemberLayout] =
+    &[
+        UniformMemberLayout::new(std::mem::offset_of!(Self, proj_view), size_of::<Mat4>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, total_secs_whole), size_of::<u32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, total_secs_frac), size_of::<f32>()),
+    ];
+}
+
+#[cfg(test)]
+mod tests
+{
+    use glam::{Quat, Vec3};
+    use math_3l14::{Facing, Frustum, GetFacing, Intersection, Intersects};
+    use super::*;
+
+    fn test_cameras() -> [Camera; 3]
+    {
+        let transform = Transform { position: Vec3::new(1.0, 2.0, -5.0), rotation: Quat::from_euler(glam::EulerRot::YXZ, 0.4, -0.2, 0.1), scale: Vec3::ONE };
+        let projections =
+        [
+            CameraProjection::Perspective { fov: Angle::from_degrees(70.0), aspect_ratio: 16.0 / 9.0 },
+            CameraProjection::Orthographic { left: -6.0, top: 5.0, right: 4.0, bottom: -3.0 },
+            CameraProjection::Orthographic { left: 0.0, top: 0.0, right: 16.0, bottom: 9.0 }, // y-down
+        ];
+        projections.map(|projection|
+        {
+            let mut camera = Camera::default();
+            camera.update_projection(projection, 0.5, 20.0);
+            camera.update_view(transform.clone());
+            camera
+        })
+    }
+
+    fn test_points() -> impl Iterator<Item = Vec3>
+    {
+        (-20..=20).flat_map(|x| (-20..=20).flat_map(move |y| (-10..=40).map(move |z|
+            Vec3::new(x as f32, y as f32, z as f32) * 0.73)))
+    }
+
+    #[test]
+    fn clip_points()
+    {
+        for camera in test_cameras()
+        {
+            let clip = CameraClip::new(&camera);
+            let frustum = Frustum::from_matrix(&camera.matrix());
+            let mut num_visible = 0;
+            for point in test_points()
+            {
+                // brute-force against the clip volume
+                let projected = camera.matrix() * point.extend(1.0);
+                let expected = projected.x.abs() <= projected.w && projected.y.abs() <= projected.w && projected.z >= 0.0 && projected.z <= projected.w;
+                assert_eq!(clip.can_see(point), expected, "{point:?} mismatch for {:?}", camera.projection());
+                assert_eq!(matches!(frustum.get_intersection(point), Intersection::Overlapping), expected);
+                num_visible += expected as usize;
+            }
+            assert!(num_visible > 100);
+        }
+    }
+
+    #[test]
+    fn clip_spheres()
+    {
+        for camera in test_cameras()
+        {
+            let clip = CameraClip::new(&camera);
+            let frustum = Frustum::from_matrix(&camera.matrix());
+            for (i, center) in test_points().enumerate().step_by(7)
+            {
+                // spheres are visible unless they are fully behind one of the clip planes
+                let sphere = Sphere::new(center, (i % 5) as f32 * 0.6);
+                let expected = frustum.planes.iter().all(|p| !matches!(p.get_facing(sphere), Facing::Behind));
+                assert_eq!(clip.can_see(sphere), expected, "{sphere:?} mismatch for {:?}", camera.projection());
+            }
+        }
+    }
+
+    #[test]
+    fn clip_boxes()
+    {
+        for camera in test_cameras()
+        {
+            let clip = CameraClip::new(&camera);
+            let frustum = Frustum::from_matrix(&camera.matrix());
+            for (i, center) in test_points().enumerate().step_by(7)
+            {
+                let half = Vec3::new(0.3, 0.8, 1.4) * (i % 3) as f32;
+                let aabb = AABB::new(center - half, center + half);
+                let corners: [Vec3; 8] = std::array::from_fn(|c| center + half * Vec3::new(
+                    if c & 1 == 0 { -1.0 } else { 1.0 },
+                    if c & 2 == 0 { -1.0 } else { 1.0 },
+                    if c & 4 == 0 { -1.0 } else { 1.0 }));
+
+                // boxes are visible unless all of their corners are behind one of the clip planes
+                let expected = frustum.planes.iter().all(|p| corners.iter().any(|c| !matches!(p.get_facing(*c), Facing::Behind)));
+                assert_eq!(clip.can_see(aabb), expected, "{aabb:?} mismatch for {:?}", camera.projection());
+            }
+        }
+    }
+}
@@ -26,22 +26,18 @@ impl Rgba
     #[inline] #[must_use]
     pub fn to_srgb(self) -> Self
     {
-        let f = |xu: u8|
-        {
-            let x = xu as f32 / 255.0;
-            if x > 0.04045
-            {
-                (((x + 0.055) / 1.055).powf(2.4) * 255.0) as u8
-            }
-            else
-            {
-                ((x / 12.92) * 255.0) as u8
-            }
-        };
-
+        let f = |xu: u8| (srgb_to_linear(xu as f32 / 255.0) * 255.0) as u8;
         Rgba { red: f(self.red), green: f(self.green), blue: f(self.blue), alpha: self.alpha }
     }
 
+    // Convert from sRGB to linear without requantizing, alpha is already linear
+    #[inline] #[must_use]
+    pub fn to_linear_f32(self) -> [f32; 4]
+    {
+        let f = |xu: u8| srgb_to_linear(xu as f32 / 255.0);
+        [f(self.red), f(self.green), f(self.blue), self.alpha as f32 / 255.0]
+    }
+
     #[inline] #[must_use]
     pub fn to_bgra(self) -> Self
     {
@@ -87,6 +83,21 @@ impl Default for Rgba
 {
     fn default() -> Self { colors::WHITE }
 }
+
+// Convert an sRGB encoded channel (0-1) to linear
+#[inline] #[must_use]
+pub fn srgb_to_linear(x: f32) -> f32
+{
+    if x > 0.04045
+    {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+    else
+    {
+        x / 12.92
+    }
+}
+
 impl From<u32> for Rgba
 {
     fn from(rgba: u32) -> Self
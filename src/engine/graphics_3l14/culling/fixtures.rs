@@ -0,0 +1,21 @@
+use containers_3l14::AabbTree;
+use glam::Vec3;
+use math_3l14::AABB;
+
+// A deterministic grid of boxes on the ground around the origin, with (an even) `side_count` boxes per side
+#[must_use]
+pub fn grid_tree(side_count: i32) -> AabbTree
+{
+    let mut tree = AabbTree::new();
+    let half = side_count / 2;
+    for x in -half..half
+    {
+        for z in -half..half
+        {
+            let min = Vec3::new(x as f32 * 4.0, 0.0, z as f32 * 4.0);
+            let size = Vec3::new(1.0 + (x & 1) as f32, 1.0 + (z & 3) as f32, 1.0);
+            tree.insert(AABB::new(min, min + size), ((x + half) * side_count + (z + half)) as u32);
+        }
+    }
+    tree
+}
@@ -0,0 +1,9 @@
+mod visible_set;
+pub use visible_set::*;
+
+mod occlusion;
+pub use occlusion::*;
+
+// Scenes shared by the tests and benchmarks
+#[cfg(any(test, feature = "bench"))]
+pub mod fixtures;
@@ -0,0 +1,283 @@
+use glam::{Mat4, Vec2, Vec3, Vec3Swizzles, Vec4Swizzles};
+use math_3l14::AABB;
+
+// Occluders smaller than this fraction of the buffer (on their largest screen axis) are skipped, they rarely hide much
+pub const MIN_OCCLUDER_SCREEN_SIZE: f32 = 0.1;
+
+// A low resolution CPU depth buffer of large occluders, for culling what is hidden behind them
+// Occluders write the pixels whose centers they cover, with their furthest depth across the pixel
+// Depths are conservative, but coverage can reach up to half a pixel past an occluder's edges, hiding anything only visible in that sliver
+pub struct OcclusionBuffer
+{
+    width: u32,
+    height: u32,
+    depths: Box<[f32]>, // the nearest occluder depth of each pixel (0..1 like clip space), rows start at the top
+    view_proj: Mat4,
+}
+impl OcclusionBuffer
+{
+    #[must_use]
+    pub fn new(width: u32, height: u32) -> Self
+    {
+        debug_assert!(width > 0 && height > 0);
+        Self
+        {
+            width,
+            height,
+            depths: vec![1.0; (width * height) as usize].into_boxed_slice(),
+            view_proj: Mat4::IDENTITY,
+        }
+    }
+
+    #[inline] #[must_use] pub fn width(&self) -> u32 { self.width }
+    #[inline] #[must_use] pub fn height(&self) -> u32 { self.height }
+    #[inline] #[must_use] pub fn depth(&self, x: u32, y: u32) -> f32 { self.depths[(y * self.width + x) as usize] }
+
+    // Clear the occluders and start a new view
+    pub fn begin(&mut self, view_proj: Mat4)
+    {
+        self.view_proj = view_proj;
+        self.depths.fill(1.0);
+    }
+
+    // Rasterize a solid box (e.g. a building's occlusion volume), returns false if it was too small to be worth drawing
+    pub fn add_box_occluder(&mut self, bounds: AABB) -> bool
+    {
+        let vertices: [Vec3; 8] = std::array::from_fn(|i| Vec3::new(
+            if i & 1 == 0 { bounds.min.x } else { bounds.max.x },
+            if i & 2 == 0 { bounds.min.y } else { bounds.max.y },
+            if i & 4 == 0 { bounds.min.z } else { bounds.max.z }));
+        const INDICES: [u32; 36] =
+        [
+            0, 2, 1, 1, 2, 3, // -z
+            4, 5, 6, 5, 7, 6, // +z
+            0, 4, 2, 2, 4, 6, // -x
+            1, 3, 5, 3, 7, 5, // +x
+            0, 1, 4, 1, 5, 4, // -y
+            2, 6, 3, 3, 6, 7, // +y
+        ];
+        self.add_occluder(bounds, &vertices, &INDICES)
+    }
+
+    // Rasterize the (world space) triangles of an occluder, returns false if it was too small to be worth drawing
+    // Triangles are drawn regardless of their winding, triangles crossing the near plane are skipped
+    pub fn add_occluder(&mut self, bounds: AABB, vertices: &[Vec3], indices: &[u32]) -> bool
+    {
+        // occluders crossing the near plane have no screen rect, but are likely large
+        if let Some((min, max, _)) = self.screen_rect(bounds)
+        {
+            let size = (max - min) / Vec2::new(self.width as f32, self.height as f32);
+            if size.max_element() < MIN_OCCLUDER_SCREEN_SIZE
+            {
+                return false;
+            }
+        }
+
+        let projected: Vec<_> = vertices.iter().map(|v| self.project(*v)).collect();
+        for triangle in indices.chunks_exact(3)
+        {
+            if let (Some(a), Some(b), Some(c)) = (projected[triangle[0] as usize], projected[triangle[1] as usize], projected[triangle[2] as usize])
+            {
+                self.rasterize_triangle(a, b, c);
+            }
+        }
+        true
+    }
+
+    // Is a (world space) box fully hidden behind the occluders
+    #[must_use]
+    pub fn is_occluded(&self, bounds: AABB) -> bool
+    {
+        let Some((min, max, nearest)) = self.screen_rect(bounds) else { return false; };
+
+        // only the part of the box that is on screen can be seen
+        let min = min.floor().max(Vec2::ZERO);
+        let max = max.ceil().min(Vec2::new(self.width as f32, self.height as f32));
+        if min.x >= max.x || min.y >= max.y
+        {
+            return false; // off screen, which is for frustum culling to decide
+        }
+
+        for y in min.y as u32..max.y as u32
+        {
+            let row = &self.depths[(y * self.width) as usize..][min.x as usize..max.x as usize];
+            if row.iter().any(|d| *d >= nearest)
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    // A world space position in pixels, with its depth in z. None if it is in front of the near plane
+    #[inline] #[must_use]
+    fn project(&self, point: Vec3) -> Option<Vec3>
+    {
+        let clip = self.view_proj * point.extend(1.0);
+        if clip.w <= f32::EPSILON || clip.z < 0.0
+        {
+            return None;
+        }
+
+        let ndc = clip.xyz() / clip.w;
+        Some(Vec3::new(
+            (ndc.x + 1.0) * 0.5 * self.width as f32,
+            (1.0 - ndc.y) * 0.5 * self.height as f32,
+            ndc.z))
+    }
+
+    // The screen space rect (in pixels) and nearest depth of a world space box, None if it crosses the near plane
+    #[must_use]
+    fn screen_rect(&self, bounds: AABB) -> Option<(Vec2, Vec2, f32)>
+    {
+        let mut min = Vec2::MAX;
+        let mut max = Vec2::MIN;
+        let mut nearest = f32::MAX;
+        for i in 0..8
+        {
+            let corner = Vec3::new(
+                if i & 1 == 0 { bounds.min.x } else { bounds.max.x },
+                if i & 2 == 0 { bounds.min.y } else { bounds.max.y },
+                if i & 4 == 0 { bounds.min.z } else { bounds.max.z });
+            let projected = self.project(corner)?;
+            min = min.min(projected.xy());
+            max = max.max(projected.xy());
+            nearest = nearest.min(projected.z);
+        }
+        Some((min, max, nearest))
+    }
+
+    fn rasterize_triangle(&mut self, a: Vec3, b: Vec3, c: Vec3)
+    {
+        // twice the signed area, triangles are re-wound so that the edge functions are positive inside
+        let area = edge(a.xy(), b.xy(), c.xy());
+        if area.abs() <= f32::EPSILON
+        {
+            return;
+        }
+        let (b, c) = if area < 0.0 { (c, b) } else { (b, c) };
+        let area = area.abs();
+
+        let min = a.xy().min(b.xy()).min(c.xy()).floor().max(Vec2::ZERO);
+        let max = a.xy().max(b.xy()).max(c.xy()).ceil().min(Vec2::new(self.width as f32, self.height as f32));
+
+        // how much the depth changes per pixel
+        let (ab, ac) = (b - a, c - a);
+        let depth_dx = (ab.z * ac.y - ac.z * ab.y) / area;
+        let depth_dy = (ac.z * ab.x - ab.z * ac.x) / area;
+        let depth_range = (a.z.min(b.z).min(c.z), a.z.max(b.z).max(c.z));
+
+        for y in min.y as u32..max.y as u32
+        {
+            for x in min.x as u32..max.x as u32
+            {
+                // coverage is sampled at pixel centers (so triangles sharing an edge leave no gaps), which is not conservative at the edges,
+                // but the depth written is the furthest across the whole pixel
+                let center = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let wa = edge(b.xy(), c.xy(), center);
+                let wb = edge(c.xy(), a.xy(), center);
+                let wc = edge(a.xy(), b.xy(), center);
+                if wa < 0.0 || wb < 0.0 || wc < 0.0
+                {
+                    continue;
+                }
+
+                let center_depth = (wa * a.z + wb * b.z + wc * c.z) / area;
+                let furthest = (center_depth + 0.5 * (depth_dx.abs() + depth_dy.abs())).clamp(depth_range.0, depth_range.1);
+                let depth = &mut self.depths[(y * self.width + x) as usize];
+                *depth = depth.min(furthest);
+            }
+        }
+    }
+}
+
+// Twice the signed area of the triangle abp, positive if p is counter-clockwise of ab
+#[inline] #[must_use]
+fn edge(a: Vec2, b: Vec2, p: Vec2) -> f32
+{
+    (b - a).perp_dot(p - a)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use math_3l14::Angle;
+    use super::*;
+
+    fn test_buffer() -> OcclusionBuffer
+    {
+        // looking down +Z from the origin
+        let projection = Mat4::perspective_lh(Angle::from_degrees(90.0).to_radians(), 2.0, 0.5, 100.0);
+        let view = Mat4::look_to_lh(Vec3::ZERO, Vec3::Z, Vec3::Y);
+        let mut buffer = OcclusionBuffer::new(64, 32);
+        buffer.begin(projection * view);
+        buffer
+    }
+
+    #[test]
+    fn occluded_behind_wall()
+    {
+        let mut buffer = test_buffer();
+        let wall = AABB::new(Vec3::new(-4.0, -2.0, 10.0), Vec3::new(4.0, 2.0, 11.0));
+        assert!(buffer.add_box_occluder(wall));
+
+        assert!(buffer.is_occluded(AABB::new(Vec3::new(-1.0, -1.0, 20.0), Vec3::new(1.0, 1.0, 22.0)))); // behind
+        assert!(!buffer.is_occluded(AABB::new(Vec3::new(-1.0, -1.0, 5.0), Vec3::new(1.0, 1.0, 6.0)))); // in front
+        assert!(!buffer.is_occluded(AABB::new(Vec3::new(3.0, -1.0, 20.0), Vec3::new(12.0, 1.0, 22.0)))); // peeking out the side
+        assert!(!buffer.is_occluded(AABB::new(Vec3::new(-1.0, -1.0, 9.0), Vec3::new(1.0, 1.0, 20.0)))); // poking through the wall
+        assert!(!buffer.is_occluded(AABB::new(Vec3::new(-1.0, -1.0, -5.0), Vec3::new(1.0, 1.0, 20.0)))); // crossing the near plane
+        assert!(!buffer.is_occluded(wall));
+    }
+
+    #[test]
+    fn conservative_coverage()
+    {
+        let mut buffer = test_buffer();
+        let wall = AABB::new(Vec3::new(-4.0, -2.0, 10.0), Vec3::new(4.0, 2.0, 11.0));
+        assert!(buffer.add_box_occluder(wall));
+
+        // every written pixel must have its center covered by the wall, and be no nearer than it
+        let (min, max, nearest) = buffer.screen_rect(wall).unwrap();
+        let mut written = 0;
+        for y in 0..buffer.height()
+        {
+            for x in 0..buffer.width()
+            {
+                let depth = buffer.depth(x, y);
+                if depth < 1.0
+                {
+                    assert!(x as f32 + 0.5 >= min.x && x as f32 + 0.5 <= max.x && y as f32 + 0.5 >= min.y && y as f32 + 0.5 <= max.y, "({x}, {y}) is not covered");
+                    assert!(depth >= nearest);
+                    written += 1;
+                }
+            }
+        }
+        assert!(written > 0);
+    }
+
+    #[test]
+    fn coverage_past_edges()
+    {
+        let mut buffer = test_buffer();
+        let wall = AABB::new(Vec3::new(-4.0, -2.0, 10.0), Vec3::new(4.125, 2.0, 11.0)); // right edge at x = 38.6 pixels
+        assert!(buffer.add_box_occluder(wall));
+
+        // entirely right of the wall, but inside the edge pixel whose center the wall covers
+        let sliver = AABB::new(Vec3::new(8.375, -1.0, 20.0), Vec3::new(8.625, 1.0, 20.01));
+        let (_, wall_max, _) = buffer.screen_rect(wall).unwrap();
+        let (sliver_min, sliver_max, _) = buffer.screen_rect(sliver).unwrap();
+        assert!(sliver_min.x > wall_max.x && sliver_max.x < wall_max.x.ceil());
+        assert!(buffer.is_occluded(sliver), "Coverage reaches up to half a pixel past the edges");
+
+        // a pixel further out is not covered
+        assert!(!buffer.is_occluded(AABB::new(Vec3::new(9.0, -1.0, 20.0), Vec3::new(9.75, 1.0, 20.01))));
+    }
+
+    #[test]
+    fn small_occluders_skipped()
+    {
+        let mut buffer = test_buffer();
+        assert!(!buffer.add_box_occluder(AABB::new(Vec3::new(-0.1, -0.1, 50.0), Vec3::new(0.1, 0.1, 50.2))));
+        assert!(buffer.depths.iter().all(|d| *d == 1.0));
+    }
+}
@@ -0,0 +1,160 @@
+use containers_3l14::AabbTree;
+use math_3l14::Frustum;
+use super::OcclusionBuffer;
+
+// Counters of the work done while culling, for profiling and benchmarking
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CullStats
+{
+    pub nodes_entered: u32, // nodes (internal and leaf) whose bounds were considered
+    pub plane_tests: u32,
+    pub fully_inside: u32, // nodes whose subtree needed no more plane tests
+    pub frustum_culled: u32, // nodes culled along with their subtree
+    pub occlusion_tests: u32,
+    pub occlusion_culled: u32,
+}
+
+// The values of the leaves of a spatial hierarchy (e.g. a map's statics) that survived culling
+// Values are in tree order, so the same tree and view always produce the same set
+#[derive(Debug, Default)]
+pub struct VisibleSet
+{
+    values: Vec<u32>,
+    stats: CullStats,
+}
+impl VisibleSet
+{
+    #[inline] #[must_use]
+    pub fn new() -> Self { Self::default() }
+
+    #[inline] #[must_use] pub fn values(&self) -> &[u32] { &self.values }
+    #[inline] #[must_use] pub fn stats(&self) -> CullStats { self.stats }
+    #[inline] #[must_use] pub fn len(&self) -> usize { self.values.len() }
+    #[inline] #[must_use] pub fn is_empty(&self) -> bool { self.values.is_empty() }
+
+    pub fn clear(&mut self)
+    {
+        self.values.clear();
+        self.stats = CullStats::default();
+    }
+
+    // Add the values of a hierarchy that are inside a frustum, and optionally not hidden by occluders
+    // Subtrees fully inside the frustum are not plane tested again
+    pub fn cull(&mut self, tree: &AabbTree, frustum: &Frustum, occlusion: Option<&OcclusionBuffer>)
+    {
+        puffin::profile_function!();
+
+        let stats = &mut self.stats;
+        let values = &mut self.values;
+        tree.walk(Frustum::ALL_PLANES, |bounds, plane_mask|
+        {
+            stats.nodes_entered += 1;
+
+            // a zero mask means the parent was fully inside, so this is too
+            let plane_mask = match plane_mask
+            {
+                0 => 0,
+                mask =>
+                {
+                    stats.plane_tests += mask.count_ones();
+                    let Some(straddled) = frustum.cull_aabb(bounds, mask) else
+                    {
+                        stats.frustum_culled += 1;
+                        return None;
+                    };
+                    if straddled == 0
+                    {
+                        stats.fully_inside += 1;
+                    }
+                    straddled
+                }
+            };
+
+            if let Some(occlusion) = occlusion
+            {
+                stats.occlusion_tests += 1;
+                if occlusion.is_occluded(bounds)
+                {
+                    stats.occlusion_culled += 1;
+                    return None;
+                }
+            }
+
+            Some(plane_mask)
+        },
+        |_, value, _| values.push(value));
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use glam::{Mat4, Vec3};
+    use math_3l14::{Angle, AABB};
+    use crate::culling::fixtures::grid_tree;
+    use super::*;
+
+    fn test_view_proj() -> Mat4
+    {
+        let projection = Mat4::perspective_lh(Angle::from_degrees(70.0).to_radians(), 16.0 / 9.0, 0.5, 60.0);
+        let view = Mat4::look_to_lh(Vec3::new(1.0, 2.0, -3.0), Vec3::new(0.2, -0.1, 1.0), Vec3::Y);
+        projection * view
+    }
+
+    #[test]
+    fn matches_per_leaf_culling()
+    {
+        let tree = grid_tree(40);
+        let frustum = Frustum::from_matrix(&test_view_proj());
+
+        let mut visible = VisibleSet::new();
+        visible.cull(&tree, &frustum, None);
+
+        let expected: Vec<_> = tree.iter_overlapping(AABB::MIN_MAX)
+            .filter(|(bounds, _)| frustum.cull_aabb(*bounds, Frustum::ALL_PLANES).is_some())
+            .map(|(_, value)| value)
+            .collect();
+        assert_eq!(visible.values(), expected);
+        assert!(!visible.is_empty() && visible.len() < 1600);
+
+        // fully inside subtrees skip plane tests
+        let stats = visible.stats();
+        assert!(stats.fully_inside > 0);
+        assert!(stats.plane_tests < 6 * stats.nodes_entered);
+        assert_eq!(stats.occlusion_tests, 0);
+    }
+
+    #[test]
+    fn occlusion()
+    {
+        let tree = grid_tree(40);
+        let view_proj = test_view_proj();
+        let frustum = Frustum::from_matrix(&view_proj);
+
+        let mut occlusion = OcclusionBuffer::new(128, 72);
+        occlusion.begin(view_proj);
+        assert!(occlusion.add_box_occluder(AABB::new(Vec3::new(-10.0, -1.0, 12.0), Vec3::new(14.0, 8.0, 13.0))));
+
+        let mut frustum_only = VisibleSet::new();
+        frustum_only.cull(&tree, &frustum, None);
+        let mut visible = VisibleSet::new();
+        visible.cull(&tree, &frustum, Some(&occlusion));
+
+        assert!(visible.len() < frustum_only.len());
+        assert!(visible.stats().occlusion_culled > 0);
+
+        // everything culled was behind the wall
+        let bounds: Vec<_> = tree.iter_overlapping(AABB::MIN_MAX).collect();
+        for value in frustum_only.values().iter().filter(|v| !visible.values().contains(v))
+        {
+            let (leaf_bounds, _) = bounds.iter().find(|(_, v)| v == value).unwrap();
+            assert!(leaf_bounds.min.z > 13.0, "{value} at {leaf_bounds:?} should not be occluded");
+        }
+
+        // and culling is deterministic
+        let mut again = VisibleSet::new();
+        again.cull(&tree, &frustum, Some(&occlusion));
+        assert_eq!(again.values(), visible.values());
+        assert_eq!(again.stats(), visible.stats());
+    }
+}
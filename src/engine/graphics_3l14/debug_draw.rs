@@ -52,8 +52,8 @@ impl DebugDraw
     #[must_use]
     pub fn new(renderer: &Renderer) -> Self
     {
+        // debug drawing happens after post processing, so colors are exact (see render_passes::debug())
         let renderer_surface_format = renderer.surface_format();
-        let renderer_msaa_count = renderer.msaa_max_sample_count();
 
         // TODO: load shaders better
         // TODO: buffer pool (and use 16 bit vertices)
@@ -83,7 +83,7 @@ impl DebugDraw
             // TODO
             multisample: MultisampleState
             {
-                count: renderer_msaa_count,
+                count: 1,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
@@ -129,7 +129,7 @@ impl DebugDraw
             // TODO
             multisample: MultisampleState
             {
-                count: renderer_msaa_count,
+                count: 1,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
@@ -200,8 +200,9 @@ impl DebugDraw
 
     pub fn draw_frustum(&mut self, camera: &Camera, color: Rgba)
     {
-        // let clip_mtx = camera.clip_mtx().inverse();
-        let clip_mtx = camera.matrix().inverse();
+        // the unit cube spans -1..1 depth, but wgpu clip space depth is 0..1
+        let clip_depth = Mat4::from_translation(Vec3::new(0.0, 0.0, 0.5)) * Mat4::from_scale(Vec3::new(1.0, 1.0, 0.5));
+        let clip_mtx = camera.matrix().inverse() * clip_depth;
         self.draw_wire_cube(clip_mtx, color);
     }
 
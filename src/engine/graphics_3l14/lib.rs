@@ -1,6 +1,7 @@
 extern crate core;
 
 pub mod render_passes;
+pub mod render_graph;
 pub mod colors;
 pub use colors::Rgba;
 pub mod renderer;
@@ -16,5 +17,9 @@ pub mod camera;
 pub mod dynamic_geo;
 pub mod vertex_layouts;
 pub mod skeleton_poser;
+pub mod skeleton_ik;
+pub mod anim_graph_instance;
 pub mod material_classes;
-pub mod passes;
\ No newline at end of file
+pub mod shader_reflection;
+pub mod passes;
+pub mod culling;
\ No newline at end of file
@@ -1,6 +1,9 @@
 use bitcode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
+use wgpu::{BindGroupLayoutEntry, BindingType, BufferBindingType, BufferSize, SamplerBindingType, ShaderStages, TextureSampleType, TextureViewDimension};
 use crate::Rgba;
+use crate::assets::EngineRenderPass;
+use crate::shader_reflection::{UniformLayout, UniformMemberLayout, UniformType};
 
 #[repr(u8)]
 #[derive(PartialEq, Eq, Copy, Clone, Debug, Hash, Serialize, Deserialize, Encode, Decode)]
@@ -8,10 +11,138 @@ pub enum MaterialClass
 {
     DebugLines,
     PbrOpaque, // todo: split up?
-    // PbrTransparent,
+    PbrTransparent, // alpha blended or masked, outputs premultiplied alpha
 }
 impl MaterialClass
 {
+    // The pass that materials of this class are drawn in
+    #[inline] #[must_use]
+    pub const fn render_pass(self) -> EngineRenderPass
+    {
+        match self
+        {
+            MaterialClass::DebugLines => EngineRenderPass::Debug,
+            MaterialClass::PbrOpaque => EngineRenderPass::Opaque,
+            MaterialClass::PbrTransparent => EngineRenderPass::Transparent,
+        }
+    }
+
+    // The bind group layout entries of the material bind group, pixel shaders are verified against these when built and loaded
+    #[must_use]
+    pub const fn bind_layout_entries(self) -> &'static [BindGroupLayoutEntry]
+    {
+        match self
+        {
+            MaterialClass::DebugLines => const { &[] }, // todo: uniforms?
+            MaterialClass::PbrOpaque | MaterialClass::PbrTransparent => const
+            {&[
+                uniform::<SimpleOpaque>(0),
+                sampler(1),
+                tex2d(PbrTextureSlot::Albedo.binding()),
+                tex2d(PbrTextureSlot::MetallicRoughness.binding()),
+                tex2d(PbrTextureSlot::Normal.binding()),
+                tex2d(PbrTextureSlot::Occlusion.binding()),
+                tex2d(PbrTextureSlot::Emissive.binding()),
+            ]},
+        }
+    }
+
+    // The Rust-side layout of a uniform buffer in the material bind group
+    #[must_use]
+    pub const fn uniform_layout(self, binding: u32) -> Option<UniformLayout>
+    {
+        match (self, binding)
+        {
+            (MaterialClass::PbrOpaque | MaterialClass::PbrTransparent, 0) => Some(SimpleOpaque::LAYOUT),
+            _ => None,
+        }
+    }
+
+    // The number of textures bound in the material bind group, material textures are bound in order after the sampler
+    #[must_use]
+    pub const fn texture_count(self) -> usize
+    {
+        match self
+        {
+            MaterialClass::DebugLines => 0,
+            MaterialClass::PbrOpaque | MaterialClass::PbrTransparent => PbrTextureSlot::COUNT,
+        }
+    }
+}
+
+// The texture bindings of the PBR material classes (matching glTF's metallic-roughness model)
+#[repr(u8)]
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Hash)]
+pub enum PbrTextureSlot
+{
+    Albedo,
+    MetallicRoughness, // metallicity in B, roughness in G
+    Normal,
+    Occlusion, // R
+    Emissive,
+}
+impl PbrTextureSlot
+{
+    pub const COUNT: usize = 5;
+    pub const ALL: [Self; Self::COUNT] = [Self::Albedo, Self::MetallicRoughness, Self::Normal, Self::Occlusion, Self::Emissive];
+
+    #[inline] #[must_use]
+    pub const fn binding(self) -> u32 { self as u32 + 2 }
+
+    // The (RGBA8 unorm) texel bound when a material has no texture in this slot, chosen to leave the material's factors unchanged
+    #[inline] #[must_use]
+    pub const fn default_texel(self) -> [u8; 4]
+    {
+        match self
+        {
+            PbrTextureSlot::Albedo |
+            PbrTextureSlot::MetallicRoughness |
+            PbrTextureSlot::Occlusion => [u8::MAX; 4],
+            PbrTextureSlot::Normal => [128, 128, u8::MAX, u8::MAX], // flat, XY are stored in [0, 1]
+            PbrTextureSlot::Emissive => [0, 0, 0, u8::MAX],
+        }
+    }
+}
+
+const fn uniform<T>(binding: u32) -> BindGroupLayoutEntry
+{
+    BindGroupLayoutEntry
+    {
+        binding,
+        visibility: ShaderStages::FRAGMENT,
+        ty: BindingType::Buffer
+        {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: BufferSize::new(size_of::<T>() as u64),
+        },
+        count: None,
+    }
+}
+const fn tex2d(binding: u32) -> BindGroupLayoutEntry
+{
+    BindGroupLayoutEntry
+    {
+        binding,
+        visibility: ShaderStages::FRAGMENT,
+        ty: BindingType::Texture
+        {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+const fn sampler(binding: u32) -> BindGroupLayoutEntry
+{
+    BindGroupLayoutEntry
+    {
+        binding,
+        visibility: ShaderStages::FRAGMENT,
+        ty: BindingType::Sampler(SamplerBindingType::Filtering),
+        count: None,
+    }
 }
 
 #[repr(C)]
@@ -21,9 +152,22 @@ pub struct PbrProps
     pub albedo_color: Rgba,
     pub metallicity: f32,
     pub roughness: f32,
+    pub alpha_cutoff: f32, // pixels with lower alpha are discarded, only for masked materials
+    pub emissive_color: Rgba, // alpha is unused
 }
 #[repr(C)]
 pub struct SimpleOpaque
 {
     pub pbr: PbrProps,
 }
+impl UniformType for SimpleOpaque
+{
+    const MEMBERS: &'static [UniformMemberLayout] =
+    &[
+        UniformMemberLayout::new(std::mem::offset_of!(Self, pbr) + std::mem::offset_of!(PbrProps, albedo_color), size_of::<Rgba>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, pbr) + std::mem::offset_of!(PbrProps, metallicity), size_of::<f32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, pbr) + std::mem::offset_of!(PbrProps, roughness), size_of::<f32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, pbr) + std::mem::offset_of!(PbrProps, alpha_cutoff), size_of::<f32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, pbr) + std::mem::offset_of!(PbrProps, emissive_color), size_of::<Rgba>()),
+    ];
+}
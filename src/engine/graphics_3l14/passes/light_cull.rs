@@ -0,0 +1,543 @@
+use glam::{Mat4, UVec2, UVec3, Vec2, Vec3, Vec3Swizzles};
+use triomphe::Arc;
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferSize, BufferUsages, ShaderStages};
+use math_3l14::{Angle, AABB};
+use nab_3l14::utils::AsU8Slice;
+use crate::camera::Camera;
+use crate::shader_reflection::{UniformMemberLayout, UniformType};
+use crate::{debug_label, Renderer};
+
+pub const CLUSTER_GRID_SIZE: UVec3 = UVec3::new(16, 9, 24); // screen tiles x depth slices
+pub const MAX_VIEW_LIGHTS: usize = 1024;
+pub const MAX_CLUSTER_LIGHT_INDICES: usize = 64 * 1024;
+
+// A point or spot light, as uploaded to the GPU
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClusterLight
+{
+    pub position: Vec3,
+    pub range: f32, // lights have no influence past this distance
+    pub color: Vec3, // linear, scaled by intensity
+    pub cos_half_angle: f32, // the cone of spot lights, -1 for point lights
+    pub direction: Vec3, // spot lights only
+}
+impl ClusterLight
+{
+    #[inline] #[must_use]
+    pub fn point(position: Vec3, range: f32, color: Vec3) -> Self
+    {
+        Self { position, range, color, cos_half_angle: -1.0, direction: Vec3::Z }
+    }
+
+    #[inline] #[must_use]
+    pub fn spot(position: Vec3, direction: Vec3, half_angle: Angle, range: f32, color: Vec3) -> Self
+    {
+        Self { position, range, color, cos_half_angle: half_angle.to_radians().cos(), direction: direction.normalize_or(Vec3::Z) }
+    }
+
+    // A sphere enclosing this light's influence, returns (center, radius)
+    #[must_use]
+    pub fn bounding_sphere(&self) -> (Vec3, f32)
+    {
+        if self.cos_half_angle <= -1.0
+        {
+            return (self.position, self.range);
+        }
+
+        // see https://bartwronski.com/2017/04/13/cull-that-cone/
+        let cos = self.cos_half_angle.max(f32::EPSILON);
+        if cos < std::f32::consts::FRAC_1_SQRT_2
+        {
+            let sin = (1.0 - cos * cos).sqrt();
+            (self.position + self.direction * (self.range * cos), self.range * sin)
+        }
+        else
+        {
+            let radius = self.range / (2.0 * cos);
+            (self.position + self.direction * radius, radius)
+        }
+    }
+}
+
+// The lights influencing each cluster
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ClusteredLights
+{
+    pub ranges: Vec<[u32; 2]>, // the (offset, count) of each cluster's lights in indices
+    pub indices: Vec<u32>, // indices of lights, ascending per cluster
+}
+
+// A grid of view-space clusters, tiled in screen space and sliced logarithmically in depth
+pub struct ClusterGrid
+{
+    size: UVec3,
+    view: Mat4,
+    projection: Mat4,
+    near_clip: f32,
+    far_clip: f32,
+    depth_scale: f32,
+    depth_bias: f32,
+    bounds: Box<[AABB]>, // the view-space bounds of each cluster
+}
+impl ClusterGrid
+{
+    #[must_use]
+    pub fn new(camera: &Camera, size: UVec3) -> Self
+    {
+        let size = size.max(UVec3::ONE);
+        let near_clip = camera.near_clip();
+        let far_clip = camera.far_clip();
+        let projection = camera.projection().to_matrix(near_clip, far_clip);
+
+        // slice = log(depth) * scale + bias
+        let log_range = (far_clip / near_clip).ln();
+        let depth_scale = size.z as f32 / log_range;
+        let depth_bias = -(size.z as f32) * near_clip.ln() / log_range;
+
+        let mut grid = Self
+        {
+            size,
+            view: camera.transform().to_view_mtx(),
+            projection,
+            near_clip,
+            far_clip,
+            depth_scale,
+            depth_bias,
+            bounds: Box::default(),
+        };
+
+        let inv_projection = projection.inverse();
+        let mut bounds = Vec::with_capacity(grid.cluster_count());
+        for z in 0..size.z
+        {
+            let (slice_near, slice_far) = (grid.slice_depth(z), grid.slice_depth(z + 1));
+            for y in 0..size.y
+            {
+                for x in 0..size.x
+                {
+                    // tiles are ordered top to bottom, like pixels
+                    let ndc_min = Vec2::new(x as f32, (y + 1) as f32) / size.xy().as_vec2();
+                    let ndc_max = Vec2::new((x + 1) as f32, y as f32) / size.xy().as_vec2();
+                    let mut cluster_bounds = AABB::MAX_MIN;
+                    for ndc in [ndc_min, Vec2::new(ndc_min.x, ndc_max.y), Vec2::new(ndc_max.x, ndc_min.y), ndc_max]
+                    {
+                        let ndc = Vec2::new(ndc.x * 2.0 - 1.0, 1.0 - ndc.y * 2.0);
+                        let ray_near = inv_projection.project_point3(ndc.extend(0.0));
+                        let ray_far = inv_projection.project_point3(ndc.extend(1.0));
+                        for depth in [slice_near, slice_far]
+                        {
+                            let t = (depth - ray_near.z) / (ray_far.z - ray_near.z);
+                            let point = ray_near + (ray_far - ray_near) * t;
+                            cluster_bounds.union_with(AABB::new(point, point));
+                        }
+                    }
+                    bounds.push(cluster_bounds);
+                }
+            }
+        }
+        grid.bounds = bounds.into_boxed_slice();
+        grid
+    }
+
+    #[inline] #[must_use] pub fn size(&self) -> UVec3 { self.size }
+    #[inline] #[must_use] pub fn cluster_count(&self) -> usize { (self.size.x * self.size.y * self.size.z) as usize }
+    #[inline] #[must_use] pub fn cluster_bounds(&self, index: usize) -> AABB { self.bounds[index] }
+
+    #[inline] #[must_use]
+    pub fn cluster_index(&self, cluster: UVec3) -> usize
+    {
+        ((cluster.z * self.size.y + cluster.y) * self.size.x + cluster.x) as usize
+    }
+
+    // The depth slice containing a view-space depth, clamped to the grid
+    #[must_use]
+    pub fn depth_slice(&self, view_depth: f32) -> u32
+    {
+        if view_depth <= self.near_clip { return 0; }
+        let slice = (view_depth.ln() * self.depth_scale + self.depth_bias).floor();
+        (slice.max(0.0) as u32).min(self.size.z - 1)
+    }
+
+    // The view-space depth where a slice begins
+    #[inline] #[must_use]
+    pub fn slice_depth(&self, slice: u32) -> f32
+    {
+        self.near_clip * (self.far_clip / self.near_clip).powf(slice as f32 / self.size.z as f32)
+    }
+
+    // Assign (world-space) lights to every cluster they may influence
+    #[must_use]
+    pub fn assign(&self, lights: &[ClusterLight]) -> ClusteredLights
+    {
+        puffin::profile_function!();
+
+        let mut counts = vec![0u32; self.cluster_count()];
+        let mut assignments = Vec::new(); // (cluster, light)
+        for (light_index, light) in lights.iter().enumerate()
+        {
+            let (center, radius) = light.bounding_sphere();
+            let center = self.view.transform_point3(center);
+            if center.z + radius < self.near_clip || center.z - radius > self.far_clip
+            {
+                continue;
+            }
+
+            // bound the tiles by projecting the sphere's box (clipped to the near plane)
+            let min = (center - radius).with_z((center.z - radius).max(self.near_clip));
+            let max = (center + radius).with_z((center.z + radius).min(self.far_clip));
+            let mut ndc_min = Vec2::MAX;
+            let mut ndc_max = Vec2::MIN;
+            for corner in 0..8
+            {
+                let point = Vec3::select(glam::BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0), max, min);
+                let ndc = self.projection.project_point3(point).xy();
+                ndc_min = ndc_min.min(ndc);
+                ndc_max = ndc_max.max(ndc);
+            }
+            if ndc_min.cmpgt(Vec2::ONE).any() || ndc_max.cmplt(Vec2::NEG_ONE).any()
+            {
+                continue;
+            }
+
+            // tiles are ordered top to bottom
+            let tiles = self.size.xy().as_vec2();
+            let to_tile = |ndc: Vec2| ((Vec2::new(ndc.x + 1.0, 1.0 - ndc.y) * 0.5 * tiles).floor().max(Vec2::ZERO).as_uvec2()).min(self.size.xy() - 1);
+            let tile_min = to_tile(Vec2::new(ndc_min.x, ndc_max.y));
+            let tile_max = to_tile(Vec2::new(ndc_max.x, ndc_min.y));
+
+            let radius_sq = radius * radius;
+            for z in self.depth_slice(min.z)..=self.depth_slice(max.z)
+            {
+                for y in tile_min.y..=tile_max.y
+                {
+                    for x in tile_min.x..=tile_max.x
+                    {
+                        let cluster = self.cluster_index(UVec3::new(x, y, z));
+                        if self.bounds[cluster].distance_sq(center) <= radius_sq
+                        {
+                            counts[cluster] += 1;
+                            assignments.push((cluster as u32, light_index as u32));
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut offset = 0;
+        let ranges: Vec<_> = counts.iter().map(|&count|
+        {
+            let range = [offset, count];
+            offset += count;
+            range
+        }).collect();
+
+        // lights were visited in order, so each cluster's indices are sorted
+        let mut cursors: Vec<_> = ranges.iter().map(|r| r[0]).collect();
+        let mut indices = vec![0; assignments.len()];
+        for (cluster, light) in assignments
+        {
+            let cursor = &mut cursors[cluster as usize];
+            indices[*cursor as usize] = light;
+            *cursor += 1;
+        }
+
+        ClusteredLights { ranges, indices }
+    }
+}
+
+#[repr(C, align(16))]
+pub struct ClusterGridUniform
+{
+    pub view: Mat4,
+    pub grid_size: UVec3,
+    pub light_count: u32,
+    pub screen_size: Vec2,
+    pub depth_scale: f32,
+    pub depth_bias: f32,
+}
+impl UniformType for ClusterGridUniform
+{
+    const MEMBERS: &'static [UniformMemberLayout] =
+    &[
+        UniformMemberLayout::new(std::mem::offset_of!(Self, view), size_of::<Mat4>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, grid_size), size_of::<UVec3>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, light_count), size_of::<u32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, screen_size), size_of::<Vec2>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, depth_scale), size_of::<f32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, depth_bias), size_of::<f32>()),
+    ];
+}
+
+const fn storage_entry(binding: u32) -> BindGroupLayoutEntry
+{
+    BindGroupLayoutEntry
+    {
+        binding,
+        visibility: ShaderStages::FRAGMENT,
+        ty: BindingType::Buffer
+        {
+            ty: BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+// The bindings of the lights bind group, sampled by lit pixel shaders
+pub const LIGHTS_BIND_LAYOUT_ENTRIES: &[BindGroupLayoutEntry] =
+&[
+    BindGroupLayoutEntry
+    {
+        binding: 0,
+        visibility: ShaderStages::FRAGMENT,
+        ty: BindingType::Buffer
+        {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: BufferSize::new(size_of::<ClusterGridUniform>() as u64),
+        },
+        count: None,
+    },
+    storage_entry(1), // lights
+    storage_entry(2), // cluster ranges
+    storage_entry(3), // cluster light indices
+];
+
+pub struct LightCullPass
+{
+    renderer: Arc<Renderer>,
+    uniform: Buffer,
+    lights: Buffer,
+    ranges: Buffer,
+    indices: Buffer,
+    bind_group: BindGroup,
+}
+impl LightCullPass
+{
+    #[must_use]
+    pub fn new(renderer: Arc<Renderer>, bind_layout: &BindGroupLayout) -> Self
+    {
+        let create_buffer = |label, size: usize, usage| renderer.device().create_buffer(&BufferDescriptor
+        {
+            label: debug_label!(label),
+            size: size as u64,
+            usage: usage | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let cluster_count = (CLUSTER_GRID_SIZE.x * CLUSTER_GRID_SIZE.y * CLUSTER_GRID_SIZE.z) as usize;
+        let uniform = create_buffer("Cluster grid uniform buffer", size_of::<ClusterGridUniform>(), BufferUsages::UNIFORM);
+        let lights = create_buffer("Cluster lights", size_of::<ClusterLight>() * MAX_VIEW_LIGHTS, BufferUsages::STORAGE);
+        let ranges = create_buffer("Cluster light ranges", size_of::<[u32; 2]>() * cluster_count, BufferUsages::STORAGE);
+        let indices = create_buffer("Cluster light indices", size_of::<u32>() * MAX_CLUSTER_LIGHT_INDICES, BufferUsages::STORAGE);
+
+        let bind_group = renderer.device().create_bind_group(&BindGroupDescriptor
+        {
+            label: debug_label!("Lights bind group"),
+            layout: bind_layout,
+            entries:
+            &[
+                BindGroupEntry { binding: 0, resource: uniform.as_entire_binding() },
+                BindGroupEntry { binding: 1, resource: lights.as_entire_binding() },
+                BindGroupEntry { binding: 2, resource: ranges.as_entire_binding() },
+                BindGroupEntry { binding: 3, resource: indices.as_entire_binding() },
+            ],
+        });
+
+        let light_cull = Self
+        {
+            renderer,
+            uniform,
+            lights,
+            ranges,
+            indices,
+            bind_group,
+        };
+        light_cull.write(&ClusterGrid::new(&Camera::default(), CLUSTER_GRID_SIZE), &[], &ClusteredLights::default());
+        light_cull
+    }
+
+    #[inline] #[must_use] pub fn bind_group(&self) -> &BindGroup { &self.bind_group }
+
+    // Cluster (world-space) lights for a camera and upload them
+    pub fn update(&mut self, camera: &Camera, lights: &[ClusterLight])
+    {
+        puffin::profile_function!();
+
+        if lights.len() > MAX_VIEW_LIGHTS
+        {
+            log::warn!("Only {MAX_VIEW_LIGHTS} of {} lights can be drawn", lights.len());
+        }
+        let lights = &lights[..lights.len().min(MAX_VIEW_LIGHTS)];
+
+        let grid = ClusterGrid::new(camera, CLUSTER_GRID_SIZE);
+        let mut clustered = grid.assign(lights);
+        if clustered.indices.len() > MAX_CLUSTER_LIGHT_INDICES
+        {
+            log::warn!("Clustered lights overflowed ({} > {MAX_CLUSTER_LIGHT_INDICES}), some lights will be missing", clustered.indices.len());
+            for range in &mut clustered.ranges
+            {
+                range[1] = range[1].min((MAX_CLUSTER_LIGHT_INDICES as u32).saturating_sub(range[0]));
+            }
+            clustered.indices.truncate(MAX_CLUSTER_LIGHT_INDICES);
+        }
+
+        self.write(&grid, lights, &clustered);
+    }
+
+    fn write(&self, grid: &ClusterGrid, lights: &[ClusterLight], clustered: &ClusteredLights)
+    {
+        let screen_size: UVec2 = self.renderer.display_size();
+        let uniform = ClusterGridUniform
+        {
+            view: grid.view,
+            grid_size: grid.size,
+            light_count: lights.len() as u32,
+            screen_size: screen_size.as_vec2(),
+            depth_scale: grid.depth_scale,
+            depth_bias: grid.depth_bias,
+        };
+
+        let queue = self.renderer.queue();
+        queue.write_buffer(&self.uniform, 0, unsafe { std::slice::from_ref(&uniform).as_u8_slice() });
+        if !lights.is_empty()
+        {
+            queue.write_buffer(&self.lights, 0, unsafe { lights.as_u8_slice() });
+        }
+        if !clustered.ranges.is_empty()
+        {
+            queue.write_buffer(&self.ranges, 0, unsafe { clustered.ranges.as_u8_slice() });
+        }
+        if !clustered.indices.is_empty()
+        {
+            queue.write_buffer(&self.indices, 0, unsafe { clustered.indices.as_u8_slice() });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use glam::Quat;
+    use math_3l14::Transform;
+    use crate::camera::CameraProjection;
+    use super::*;
+
+    fn test_camera() -> Camera
+    {
+        let mut camera = Camera::default();
+        camera.update_projection(CameraProjection::Perspective { fov: Angle::from_degrees(90.0), aspect_ratio: 16.0 / 9.0 }, 0.1, 100.0);
+        camera.update_view(Transform { position: Vec3::new(0.0, 1.0, -5.0), rotation: Quat::from_rotation_y(0.3), scale: Vec3::ONE });
+        camera
+    }
+
+    #[test]
+    fn depth_slices()
+    {
+        let grid = ClusterGrid::new(&test_camera(), CLUSTER_GRID_SIZE);
+        assert_eq!(grid.cluster_count(), (16 * 9 * 24) as usize);
+        assert!((grid.slice_depth(0) - 0.1).abs() < 1e-5);
+        assert!((grid.slice_depth(CLUSTER_GRID_SIZE.z) - 100.0).abs() < 1e-3);
+
+        assert_eq!(grid.depth_slice(0.0), 0);
+        assert_eq!(grid.depth_slice(-3.0), 0);
+        assert_eq!(grid.depth_slice(1000.0), CLUSTER_GRID_SIZE.z - 1);
+        for slice in 0..CLUSTER_GRID_SIZE.z
+        {
+            let mid = (grid.slice_depth(slice) + grid.slice_depth(slice + 1)) / 2.0;
+            assert_eq!(grid.depth_slice(mid), slice);
+        }
+    }
+
+    #[test]
+    fn cluster_bounds()
+    {
+        let grid = ClusterGrid::new(&test_camera(), CLUSTER_GRID_SIZE);
+
+        // the top-left cluster of the first slice
+        let first = grid.cluster_bounds(0);
+        assert!((first.min.z - 0.1).abs() < 1e-4);
+        assert!(first.max.x < 0.0 && first.min.y > 0.0);
+
+        // the bottom-right cluster of the last slice
+        let last = grid.cluster_bounds(grid.cluster_count() - 1);
+        assert!((last.max.z - 100.0).abs() < 1e-2);
+        assert!(last.min.x > 0.0 && last.max.y < 0.0);
+    }
+
+    #[test]
+    fn assign_conservative()
+    {
+        let camera = test_camera();
+        let grid = ClusterGrid::new(&camera, CLUSTER_GRID_SIZE);
+        let view = camera.transform().to_view_mtx();
+        let lights =
+        [
+            ClusterLight::point(Vec3::new(0.0, 1.0, 5.0), 3.0, Vec3::ONE),
+            ClusterLight::point(Vec3::new(-4.0, 0.0, 2.0), 1.5, Vec3::ONE),
+            ClusterLight::point(Vec3::new(0.0, 1.0, -5.5), 1.0, Vec3::ONE), // straddling the near plane
+            ClusterLight::point(Vec3::new(0.0, 1.0, -20.0), 5.0, Vec3::ONE), // behind the camera
+            ClusterLight::point(Vec3::new(500.0, 0.0, 0.0), 5.0, Vec3::ONE), // off screen
+            ClusterLight::spot(Vec3::new(2.0, 3.0, 10.0), Vec3::NEG_Y, Angle::from_degrees(30.0), 6.0, Vec3::ONE),
+            ClusterLight::spot(Vec3::new(-1.0, 0.0, 8.0), Vec3::X, Angle::from_degrees(70.0), 4.0, Vec3::ONE),
+        ];
+        let clustered = grid.assign(&lights);
+
+        // every cluster overlapping a light's bounds (found by brute force) contains the light
+        let mut expected_count = 0;
+        for cluster in 0..grid.cluster_count()
+        {
+            let [offset, count] = clustered.ranges[cluster];
+            let assigned = &clustered.indices[offset as usize..(offset + count) as usize];
+            assert!(assigned.windows(2).all(|w| w[0] < w[1]), "Cluster {cluster} lights are not sorted: {assigned:?}");
+
+            let expected: Vec<u32> = lights.iter().enumerate().filter_map(|(i, light)|
+            {
+                let (center, radius) = light.bounding_sphere();
+                let center = view.transform_point3(center);
+                (grid.cluster_bounds(cluster).distance_sq(center) <= radius * radius).then_some(i as u32)
+            }).collect();
+            assert_eq!(assigned, expected.as_slice(), "Cluster {cluster} mismatch");
+            expected_count += expected.len();
+        }
+        assert_eq!(clustered.indices.len(), expected_count);
+
+        for (i, light) in lights.iter().enumerate()
+        {
+            let count = clustered.indices.iter().filter(|l| **l == i as u32).count();
+            match i
+            {
+                3 | 4 => assert_eq!(count, 0, "Light {i} should not be visible"),
+                _ => assert!(count > 0, "Light {i} {light:?} was not assigned"),
+            }
+        }
+
+        assert_eq!(grid.assign(&lights), clustered);
+    }
+
+    #[test]
+    fn spot_bounds()
+    {
+        let narrow = ClusterLight::spot(Vec3::ZERO, Vec3::Z, Angle::from_degrees(20.0), 10.0, Vec3::ONE);
+        let (center, radius) = narrow.bounding_sphere();
+        assert!(radius < 10.0);
+        let wide = ClusterLight::spot(Vec3::ZERO, Vec3::Z, Angle::from_degrees(80.0), 10.0, Vec3::ONE);
+        let (wide_center, wide_radius) = wide.bounding_sphere();
+        assert!(wide_radius <= 10.0);
+
+        // the apex and the edge of the cone's cap are enclosed
+        for (light, center, radius) in [(narrow, center, radius), (wide, wide_center, wide_radius)]
+        {
+            let sin = (1.0 - light.cos_half_angle * light.cos_half_angle).sqrt();
+            let rim = Vec3::new(sin, 0.0, light.cos_half_angle) * light.range;
+            assert!(center.distance(Vec3::ZERO) <= radius + 1e-4);
+            assert!(center.distance(rim) <= radius + 1e-4);
+            assert!(center.distance(Vec3::Z * light.range) <= radius + 1e-4);
+        }
+
+        let point = ClusterLight::point(Vec3::ONE, 4.0, Vec3::ONE);
+        assert_eq!(point.bounding_sphere(), (Vec3::ONE, 4.0));
+    }
+}
@@ -1 +1,3 @@
-pub mod shadow;
\ No newline at end of file
+pub mod light_cull;
+pub mod shadow;
+pub mod post;
@@ -0,0 +1,1174 @@
+use std::time::Duration;
+use egui::Ui;
+use glam::{Mat3, UVec2, Vec3};
+use parking_lot::{Mutex, MutexGuard};
+use serde::{Deserialize, Serialize};
+use triomphe::Arc;
+use wgpu::{AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, BlendComponent, BlendFactor, BlendOperation, BlendState, Buffer, BufferBindingType, BufferDescriptor, BufferSize, BufferUsages, Color, ColorTargetState, ColorWrites, CommandEncoder, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Extent3d, FilterMode, FragmentState, LoadOp, MipmapFilterMode, MultisampleState, Operations, Origin3d, PipelineLayoutDescriptor, PrimitiveState, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages, StoreOp, TexelCopyBufferLayout, TexelCopyTextureInfo, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension, VertexState};
+use asset_3l14::{Ash, AssetKey, AssetSnapshot, AssetTypeId, AssetView, Assets};
+use debug_3l14::debug_gui::DebugGui;
+use nab_3l14::utils::AsU8Slice;
+use crate::assets::{shader_key, Shader, ShaderStage, Texture};
+use crate::shader_reflection::{UniformMemberLayout, UniformType};
+use crate::render_graph::{GraphTexture, GraphTextureDesc, GraphTextureSize, NodeContext, RenderGraph};
+use crate::{debug_label, Renderer, HDR_SCENE_FORMAT};
+
+pub const HISTOGRAM_BIN_COUNT: usize = 256; // bin 0 holds black pixels, the rest are spread over the luminance range
+const HISTOGRAM_WORKGROUP_SIZE: u32 = 16;
+
+// The shaders of the post processing chain, in the order they run
+#[repr(u8)]
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Hash, Serialize, Deserialize)]
+pub enum PostEffect
+{
+    Fullscreen, // the vertex shader of every pixel effect
+    LuminanceHistogram,
+    Exposure,
+    BloomPrefilter,
+    BloomDownsample,
+    BloomUpsample,
+    Tonemap, // also color grades
+}
+impl PostEffect
+{
+    pub const COUNT: usize = 7;
+    pub const ALL: [PostEffect; Self::COUNT] =
+    [
+        PostEffect::Fullscreen,
+        PostEffect::LuminanceHistogram,
+        PostEffect::Exposure,
+        PostEffect::BloomPrefilter,
+        PostEffect::BloomDownsample,
+        PostEffect::BloomUpsample,
+        PostEffect::Tonemap,
+    ];
+
+    #[inline] #[must_use]
+    pub const fn stage(self) -> ShaderStage
+    {
+        match self
+        {
+            PostEffect::Fullscreen => ShaderStage::Vertex,
+            PostEffect::LuminanceHistogram | PostEffect::Exposure => ShaderStage::Compute,
+            PostEffect::BloomPrefilter | PostEffect::BloomDownsample | PostEffect::BloomUpsample | PostEffect::Tonemap => ShaderStage::Pixel,
+        }
+    }
+
+    // The bindings (all in group 0) of this effect, shaders are verified against these when built and loaded
+    // 0: PostUniform, 1: source texture, 2: luminance histogram, 3: exposure, 4: linear sampler, 5: bloom texture, 6: color grading LUT
+    #[must_use]
+    pub const fn bind_layout_entries(self) -> &'static [BindGroupLayoutEntry]
+    {
+        const COMPUTE: ShaderStages = ShaderStages::COMPUTE;
+        const FRAGMENT: ShaderStages = ShaderStages::FRAGMENT;
+        match self
+        {
+            PostEffect::Fullscreen => const { &[] },
+            PostEffect::LuminanceHistogram => const
+            {&[
+                uniform_entry(COMPUTE),
+                texture_entry(1, TextureViewDimension::D2, COMPUTE),
+                storage_entry(2, false, COMPUTE),
+            ]},
+            PostEffect::Exposure => const
+            {&[
+                uniform_entry(COMPUTE),
+                storage_entry(2, false, COMPUTE),
+                storage_entry(3, false, COMPUTE),
+            ]},
+            PostEffect::BloomPrefilter => const
+            {&[
+                uniform_entry(FRAGMENT),
+                texture_entry(1, TextureViewDimension::D2, FRAGMENT),
+                storage_entry(3, true, FRAGMENT),
+                sampler_entry(FRAGMENT),
+            ]},
+            PostEffect::BloomDownsample | PostEffect::BloomUpsample => const
+            {&[
+                texture_entry(1, TextureViewDimension::D2, FRAGMENT),
+                sampler_entry(FRAGMENT),
+            ]},
+            PostEffect::Tonemap => const
+            {&[
+                uniform_entry(FRAGMENT),
+                texture_entry(1, TextureViewDimension::D2, FRAGMENT),
+                storage_entry(3, true, FRAGMENT),
+                sampler_entry(FRAGMENT),
+                texture_entry(5, TextureViewDimension::D2, FRAGMENT),
+                texture_entry(6, TextureViewDimension::D3, FRAGMENT),
+            ]},
+        }
+    }
+}
+
+const fn uniform_entry(visibility: ShaderStages) -> BindGroupLayoutEntry
+{
+    BindGroupLayoutEntry
+    {
+        binding: 0,
+        visibility,
+        ty: BindingType::Buffer
+        {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: BufferSize::new(size_of::<PostUniform>() as u64),
+        },
+        count: None,
+    }
+}
+const fn texture_entry(binding: u32, view_dimension: TextureViewDimension, visibility: ShaderStages) -> BindGroupLayoutEntry
+{
+    BindGroupLayoutEntry
+    {
+        binding,
+        visibility,
+        ty: BindingType::Texture
+        {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+const fn storage_entry(binding: u32, read_only: bool, visibility: ShaderStages) -> BindGroupLayoutEntry
+{
+    BindGroupLayoutEntry
+    {
+        binding,
+        visibility,
+        ty: BindingType::Buffer
+        {
+            ty: BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+const fn sampler_entry(visibility: ShaderStages) -> BindGroupLayoutEntry
+{
+    BindGroupLayoutEntry
+    {
+        binding: 4,
+        visibility,
+        ty: BindingType::Sampler(SamplerBindingType::Filtering),
+        count: None,
+    }
+}
+
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PostUniform
+{
+    pub min_log_luminance: f32, // the (log2) luminance range of the histogram
+    pub log_luminance_range: f32,
+    pub low_percentile: f32, // the darkest and brightest pixels are not averaged
+    pub high_percentile: f32,
+    pub exposure_adaptation: f32, // how far to move towards the target exposure this frame [0, 1]
+    pub exposure_compensation: f32, // in stops
+    pub min_log_exposure: f32,
+    pub max_log_exposure: f32,
+    pub manual_exposure: f32,
+    pub auto_exposure: u32,
+    pub bloom_threshold: f32,
+    pub bloom_knee: f32,
+    pub bloom_intensity: f32,
+    pub tonemapper: u32,
+    pub lut_strength: f32,
+    pub encode_srgb: u32, // the output target is not sRGB, so the shader must encode its output
+}
+impl UniformType for PostUniform
+{
+    const MEMBERS: &'static [UniformMemberLayout] =
+    &[
+        UniformMemberLayout::new(std::mem::offset_of!(Self, min_log_luminance), size_of::<f32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, log_luminance_range), size_of::<f32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, low_percentile), size_of::<f32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, high_percentile), size_of::<f32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, exposure_adaptation), size_of::<f32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, exposure_compensation), size_of::<f32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, min_log_exposure), size_of::<f32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, max_log_exposure), size_of::<f32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, manual_exposure), size_of::<f32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, auto_exposure), size_of::<u32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, bloom_threshold), size_of::<f32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, bloom_knee), size_of::<f32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, bloom_intensity), size_of::<f32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, tonemapper), size_of::<u32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, lut_strength), size_of::<f32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, encode_srgb), size_of::<u32>()),
+    ];
+}
+
+// Written by the exposure shader, persists between frames so exposure can adapt over time
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PostExposure
+{
+    pub exposure: f32, // scene colors are multiplied by this
+    pub average_luminance: f32,
+}
+
+#[repr(u32)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Tonemapper
+{
+    #[default]
+    Aces,
+    AgX,
+}
+impl Tonemapper
+{
+    // CPU reference versions of the tonemapping curves in PostTonemap.ps.wgsl, maps exposed linear colors to [0, 1] (linear)
+    #[must_use]
+    pub fn apply(self, color: Vec3) -> Vec3
+    {
+        match self
+        {
+            Tonemapper::Aces => tonemap_aces(color),
+            Tonemapper::AgX => tonemap_agx(color),
+        }
+    }
+}
+
+// Stephen Hill's fit of the ACES RRT and ODT (sRGB output)
+#[must_use]
+fn tonemap_aces(color: Vec3) -> Vec3
+{
+    let input_mtx = Mat3::from_cols(
+        Vec3::new(0.59719, 0.07600, 0.02840),
+        Vec3::new(0.35458, 0.90834, 0.13383),
+        Vec3::new(0.04823, 0.01566, 0.83777));
+    let output_mtx = Mat3::from_cols(
+        Vec3::new(1.60475, -0.10256, -0.00327),
+        Vec3::new(-0.53108, 1.10813, -0.07276),
+        Vec3::new(-0.07367, -0.00605, 1.07602));
+
+    let v = input_mtx * color;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    (output_mtx * (a / b)).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+// Troy Sobotka's AgX, using Benjamin Wrensch's polynomial fit of the default contrast curve
+#[must_use]
+fn tonemap_agx(color: Vec3) -> Vec3
+{
+    let inset_mtx = Mat3::from_cols(
+        Vec3::new(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        Vec3::new(0.0784335999999992, 0.878468636469772, 0.0784336),
+        Vec3::new(0.0792237451477643, 0.0791661274605434, 0.879142973793104));
+    let outset_mtx = Mat3::from_cols(
+        Vec3::new(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        Vec3::new(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        Vec3::new(-0.0990297440797205, -0.0989611768448433, 1.15107367264116));
+    const MIN_EV: f32 = -12.47393;
+    const MAX_EV: f32 = 4.026069;
+
+    let log_color = (inset_mtx * color).max(Vec3::splat(1e-10)).map(f32::log2).clamp(Vec3::splat(MIN_EV), Vec3::splat(MAX_EV));
+    let x = (log_color - MIN_EV) / (MAX_EV - MIN_EV);
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+    (outset_mtx * curve).clamp(Vec3::ZERO, Vec3::ONE).powf(2.2)
+}
+
+#[derive(Debug, Clone)]
+pub struct PostSettings
+{
+    pub auto_exposure: bool,
+    pub manual_exposure: f32, // used when not auto exposing
+    pub exposure_compensation: f32, // in stops, applied after auto exposure
+    pub min_exposure_stops: f32, // auto exposure is clamped to this range
+    pub max_exposure_stops: f32,
+    pub adaptation_speed: f32, // higher adapts faster, roughly the inverse of the time to adapt
+    pub histogram_min_log_luminance: f32,
+    pub histogram_log_luminance_range: f32,
+    pub low_percentile: f32,
+    pub high_percentile: f32,
+
+    pub bloom_intensity: f32, // zero disables bloom
+    pub bloom_threshold: f32, // exposed colors brighter than this bloom
+    pub bloom_soft_knee: f32,
+    pub bloom_mip_count: u32,
+
+    pub tonemapper: Tonemapper,
+    pub lut_strength: f32, // only with a color grading LUT
+}
+impl Default for PostSettings
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            auto_exposure: true,
+            manual_exposure: 1.0,
+            exposure_compensation: 0.0,
+            min_exposure_stops: -10.0,
+            max_exposure_stops: 10.0,
+            adaptation_speed: 1.5,
+            histogram_min_log_luminance: -10.0,
+            histogram_log_luminance_range: 22.0,
+            low_percentile: 0.1,
+            high_percentile: 0.9,
+
+            bloom_intensity: 0.05,
+            bloom_threshold: 1.0,
+            bloom_soft_knee: 0.5,
+            bloom_mip_count: 6,
+
+            tonemapper: Tonemapper::Aces,
+            lut_strength: 1.0,
+        }
+    }
+}
+impl PostSettings
+{
+    // Exposure adapts exponentially, so is frame rate independent
+    #[inline] #[must_use]
+    pub fn exposure_adaptation(&self, delta_time: Duration) -> f32
+    {
+        1.0 - (-delta_time.as_secs_f32() * self.adaptation_speed.max(0.0)).exp()
+    }
+
+    // The (low, high) histogram percentiles, ordered and within [0, 1] (NaN is treated as the widest range)
+    #[must_use]
+    pub fn clamped_percentiles(&self) -> (f32, f32)
+    {
+        let low = if self.low_percentile.is_nan() { 0.0 } else { self.low_percentile.clamp(0.0, 1.0) };
+        let high = if self.high_percentile.is_nan() { 1.0 } else { self.high_percentile.clamp(low, 1.0) };
+        (low, high)
+    }
+}
+
+struct PostPipelines
+{
+    histogram: ComputePipeline,
+    exposure: ComputePipeline,
+    bloom_prefilter: RenderPipeline,
+    bloom_downsample: RenderPipeline,
+    bloom_upsample: RenderPipeline,
+    tonemap: RenderPipeline,
+}
+
+// Exposes, blooms, tonemaps and color grades the HDR scene into the back buffer
+pub struct PostProcessPass
+{
+    renderer: Arc<Renderer>,
+    settings: Mutex<PostSettings>,
+
+    pending_shaders: Option<[Ash<Shader>; PostEffect::COUNT]>, // until all are loaded
+    pipelines: Option<PostPipelines>,
+    bind_layouts: [BindGroupLayout; PostEffect::COUNT],
+
+    uniform: Buffer,
+    histogram: Buffer,
+    exposure: Buffer,
+    sampler: Sampler,
+    identity_lut: TextureView,
+    color_grading_lut: Option<AssetView<Texture>>,
+}
+impl PostProcessPass
+{
+    #[must_use]
+    pub fn new(renderer: Arc<Renderer>, assets: &Assets, settings: PostSettings) -> Self
+    {
+        let shaders = PostEffect::ALL.map(|effect| assets.load::<Shader>(AssetKey::synthetic(AssetTypeId::Shader, shader_key::post(effect))));
+        let mut post = Self::new_without_shaders(renderer, settings);
+        post.pending_shaders = Some(shaders);
+        post
+    }
+
+    // Create the pass with already loaded shaders (e.g. for tests)
+    #[must_use]
+    pub fn with_shaders(renderer: Arc<Renderer>, shaders: [AssetView<Shader>; PostEffect::COUNT], settings: PostSettings) -> Self
+    {
+        let mut post = Self::new_without_shaders(renderer, settings);
+        post.pipelines = Some(post.create_pipelines(&shaders));
+        post
+    }
+
+    #[must_use]
+    fn new_without_shaders(renderer: Arc<Renderer>, settings: PostSettings) -> Self
+    {
+        let device = renderer.device();
+
+        let bind_layouts = PostEffect::ALL.map(|effect| device.create_bind_group_layout(&BindGroupLayoutDescriptor
+        {
+            label: debug_label!(&format!("Post {effect:?} layout")),
+            entries: effect.bind_layout_entries(),
+        }));
+
+        let create_buffer = |label, size: usize, usage| device.create_buffer(&BufferDescriptor
+        {
+            label: debug_label!(label),
+            size: size as u64,
+            usage: usage | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform = create_buffer("Post uniform buffer", size_of::<PostUniform>(), BufferUsages::UNIFORM);
+        let histogram = create_buffer("Luminance histogram", size_of::<u32>() * HISTOGRAM_BIN_COUNT, BufferUsages::STORAGE);
+        let exposure = create_buffer("Exposure", size_of::<PostExposure>(), BufferUsages::STORAGE | BufferUsages::COPY_SRC);
+
+        let initial_exposure = PostExposure { exposure: settings.manual_exposure, average_luminance: 0.18 };
+        renderer.queue().write_buffer(&exposure, 0, unsafe { std::slice::from_ref(&initial_exposure).as_u8_slice() });
+
+        let sampler = device.create_sampler(&SamplerDescriptor
+        {
+            label: debug_label!("Post linear sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: MipmapFilterMode::Nearest,
+            lod_min_clamp: 0.0,
+            lod_max_clamp: 0.0,
+            compare: None,
+            anisotropy_clamp: 1,
+            border_color: None,
+        });
+
+        // a 2x2x2 LUT is an exact identity with linear filtering
+        let identity_lut = create_lut(&renderer, 2, &[
+            0, 0, 0, 255,  255, 0, 0, 255,  0, 255, 0, 255,  255, 255, 0, 255,
+            0, 0, 255, 255,  255, 0, 255, 255,  0, 255, 255, 255,  255, 255, 255, 255,
+        ]).create_view(&TextureViewDescriptor::default());
+
+        Self
+        {
+            renderer,
+            settings: Mutex::new(settings),
+            pending_shaders: None,
+            pipelines: None,
+            bind_layouts,
+            uniform,
+            histogram,
+            exposure,
+            sampler,
+            identity_lut,
+            color_grading_lut: None,
+        }
+    }
+
+    #[inline] #[must_use]
+    pub fn settings(&self) -> MutexGuard<'_, PostSettings> { self.settings.lock() }
+
+    // Grade the tonemapped (sRGB encoded) colors with a 3D LUT texture, see TextureUsage::ColorGradingLut
+    pub fn set_color_grading_lut(&mut self, lut: Option<AssetView<Texture>>)
+    {
+        if let Some(lut) = &lut &&
+            lut.gpu_tex.dimension() != TextureDimension::D3
+        {
+            log::error!("Color grading LUTs must be 3D textures, ignoring {:?}", lut.gpu_tex.size());
+            return;
+        }
+        self.color_grading_lut = lut;
+    }
+
+    // Add the post processing chain to a frame's graph, from the (resolved) HDR scene color into the output
+    // Returns false if the shaders have not loaded yet, in which case the output is only cleared
+    pub fn add_nodes<'f, S>(&'f mut self, graph: &mut RenderGraph<'f, S>, scene_color: GraphTexture, output: GraphTexture, delta_time: Duration) -> bool
+    {
+        puffin::profile_function!();
+
+        if self.pipelines.is_none() &&
+            let Some(pending) = &self.pending_shaders
+        {
+            let loaded: Vec<_> = pending.iter().filter_map(|ash| match ash.data()
+            {
+                AssetSnapshot::Available(shader) => Some(shader),
+                _ => None,
+            }).collect();
+            if let Ok(shaders) = <[AssetView<Shader>; PostEffect::COUNT]>::try_from(loaded)
+            {
+                self.pipelines = Some(self.create_pipelines(&shaders));
+                self.pending_shaders = None;
+            }
+        }
+
+        let settings = self.settings.lock().clone();
+        if self.pipelines.is_none()
+        {
+            graph.add_node("Post processing (not loaded)")
+                .write(output, TextureUsages::RENDER_ATTACHMENT)
+                .run(move |ctx, _|
+                {
+                    drop(ctx.encoder.begin_render_pass(&RenderPassDescriptor
+                    {
+                        label: debug_label!("Post processing (not loaded)"),
+                        color_attachments: &[Some(RenderPassColorAttachment
+                        {
+                            view: ctx.textures.view(output),
+                            depth_slice: None,
+                            resolve_target: None,
+                            ops: Operations { load: LoadOp::Clear(Color::BLACK), store: StoreOp::Store },
+                        })],
+                        depth_stencil_attachment: None,
+                        timestamp_writes: None,
+                        occlusion_query_set: None,
+                        multiview_mask: None,
+                    }));
+                });
+            return false;
+        }
+
+        let (low_percentile, high_percentile) = settings.clamped_percentiles();
+        let uniform = PostUniform
+        {
+            min_log_luminance: settings.histogram_min_log_luminance,
+            log_luminance_range: settings.histogram_log_luminance_range.max(f32::EPSILON),
+            low_percentile,
+            high_percentile,
+            exposure_adaptation: settings.exposure_adaptation(delta_time),
+            exposure_compensation: settings.exposure_compensation,
+            min_log_exposure: settings.min_exposure_stops,
+            max_log_exposure: settings.max_exposure_stops.max(settings.min_exposure_stops),
+            manual_exposure: settings.manual_exposure,
+            auto_exposure: settings.auto_exposure as u32,
+            bloom_threshold: settings.bloom_threshold,
+            bloom_knee: settings.bloom_soft_knee.max(0.0),
+            bloom_intensity: settings.bloom_intensity.max(0.0),
+            tonemapper: settings.tonemapper as u32,
+            lut_strength: if self.color_grading_lut.is_some() { settings.lut_strength.clamp(0.0, 1.0) } else { 0.0 },
+            encode_srgb: (!graph.texture_format(output).is_srgb()) as u32,
+        };
+        self.renderer.queue().write_buffer(&self.uniform, 0, unsafe { std::slice::from_ref(&uniform).as_u8_slice() });
+
+        // a half resolution HDR texture, downsampled and then upsampled (additively) through its mips
+        let scene_size = graph.texture_size(scene_color);
+        let bloom_size = UVec2::new((scene_size.x / 2).max(1), (scene_size.y / 2).max(1));
+        let bloom = graph.create_texture(GraphTextureDesc
+        {
+            mip_level_count: bloom_size.min_element().ilog2().saturating_add(1).min(settings.bloom_mip_count.max(1)),
+            ..GraphTextureDesc::new("Bloom", GraphTextureSize::Fixed(bloom_size), HDR_SCENE_FORMAT)
+        });
+
+        let this = &*self;
+        graph.add_node("Post processing")
+            .read(scene_color, TextureUsages::TEXTURE_BINDING)
+            .write(bloom, TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING)
+            .write(output, TextureUsages::RENDER_ATTACHMENT)
+            .run(move |ctx, _| this.render(ctx, &uniform, scene_color, bloom, output));
+        true
+    }
+
+    fn render(&self, ctx: &mut NodeContext, uniform: &PostUniform, scene_color: GraphTexture, bloom: GraphTexture, output: GraphTexture)
+    {
+        let pipelines = self.pipelines.as_ref().expect("Post processing nodes are only added once the pipelines are created");
+        let scene_size = ctx.textures.texture(scene_color).size();
+        let scene_view = ctx.textures.view(scene_color);
+        let bloom_texture = ctx.textures.texture(bloom);
+        // a view of each mip, for rendering and sampling
+        let bloom_mips: Vec<_> = (0..bloom_texture.mip_level_count()).map(|mip| bloom_texture.create_view(&TextureViewDescriptor
+        {
+            label: debug_label!(&format!("Bloom mip {mip} view")),
+            base_mip_level: mip,
+            mip_level_count: Some(1),
+            ..Default::default()
+        })).collect();
+        let encoder = &mut *ctx.encoder;
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor
+            {
+                label: debug_label!("Exposure"),
+                timestamp_writes: None,
+            });
+            if uniform.auto_exposure != 0
+            {
+                compute_pass.set_pipeline(&pipelines.histogram);
+                compute_pass.set_bind_group(0, &self.bind_group(PostEffect::LuminanceHistogram, scene_view, &bloom_mips[0]), &[]);
+                compute_pass.dispatch_workgroups(
+                    scene_size.width.div_ceil(HISTOGRAM_WORKGROUP_SIZE),
+                    scene_size.height.div_ceil(HISTOGRAM_WORKGROUP_SIZE),
+                    1);
+            }
+            // also applies manual exposure
+            compute_pass.set_pipeline(&pipelines.exposure);
+            compute_pass.set_bind_group(0, &self.bind_group(PostEffect::Exposure, scene_view, &bloom_mips[0]), &[]);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+
+        if uniform.bloom_intensity > 0.0
+        {
+            self.fullscreen_pass(encoder, "Bloom prefilter", &pipelines.bloom_prefilter, PostEffect::BloomPrefilter, scene_view, &bloom_mips[0], &bloom_mips[0], true);
+            for mip in 1..bloom_mips.len()
+            {
+                self.fullscreen_pass(encoder, "Bloom downsample", &pipelines.bloom_downsample, PostEffect::BloomDownsample, &bloom_mips[mip - 1], &bloom_mips[0], &bloom_mips[mip], true);
+            }
+            for mip in (1..bloom_mips.len()).rev()
+            {
+                self.fullscreen_pass(encoder, "Bloom upsample", &pipelines.bloom_upsample, PostEffect::BloomUpsample, &bloom_mips[mip], &bloom_mips[0], &bloom_mips[mip - 1], false);
+            }
+        }
+        else
+        {
+            // the bloom texture may be aliased, so it's cleared rather than left with another node's contents
+            drop(encoder.begin_render_pass(&RenderPassDescriptor
+            {
+                label: debug_label!("Bloom clear"),
+                color_attachments: &[Some(RenderPassColorAttachment
+                {
+                    view: &bloom_mips[0],
+                    depth_slice: None,
+                    resolve_target: None,
+                    ops: Operations { load: LoadOp::Clear(Color::BLACK), store: StoreOp::Store },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+                multiview_mask: None,
+            }));
+        }
+
+        self.fullscreen_pass(encoder, "Tonemap", &pipelines.tonemap, PostEffect::Tonemap, scene_view, &bloom_mips[0], ctx.textures.view(output), true);
+    }
+
+    fn fullscreen_pass(
+        &self,
+        encoder: &mut CommandEncoder,
+        label: &str,
+        pipeline: &RenderPipeline,
+        effect: PostEffect,
+        source: &TextureView,
+        bloom: &TextureView,
+        target: &TextureView,
+        clear: bool)
+    {
+        let bind_group = self.bind_group(effect, source, bloom);
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor
+        {
+            label: debug_label!(label),
+            color_attachments: &[Some(RenderPassColorAttachment
+            {
+                view: target,
+                depth_slice: None,
+                resolve_target: None,
+                ops: Operations
+                {
+                    load: if clear { LoadOp::Clear(Color::BLACK) } else { LoadOp::Load },
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+            multiview_mask: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    // Bind the resources of an effect's layout entries
+    #[must_use]
+    fn bind_group(&self, effect: PostEffect, source: &TextureView, bloom: &TextureView) -> BindGroup
+    {
+        let lut = self.color_grading_lut.as_ref().map_or(&self.identity_lut, |lut| &lut.gpu_view);
+        let entries: Vec<_> = effect.bind_layout_entries().iter().map(|entry| BindGroupEntry
+        {
+            binding: entry.binding,
+            resource: match entry.binding
+            {
+                0 => self.uniform.as_entire_binding(),
+                1 => BindingResource::TextureView(source),
+                2 => self.histogram.as_entire_binding(),
+                3 => self.exposure.as_entire_binding(),
+                4 => BindingResource::Sampler(&self.sampler),
+                5 => BindingResource::TextureView(bloom),
+                6 => BindingResource::TextureView(lut),
+                other => unreachable!("Unknown post binding {other}"),
+            },
+        }).collect();
+
+        self.renderer.device().create_bind_group(&BindGroupDescriptor
+        {
+            label: debug_label!(&format!("Post {effect:?} bind group")),
+            layout: &self.bind_layouts[effect as usize],
+            entries: &entries,
+        })
+    }
+
+    #[must_use]
+    fn create_pipelines(&self, shaders: &[AssetView<Shader>; PostEffect::COUNT]) -> PostPipelines
+    {
+        puffin::profile_function!();
+
+        let device = self.renderer.device();
+        for effect in PostEffect::ALL
+        {
+            // shaders are verified when built, but may be stale
+            if let Err(err) = shaders[effect as usize].reflection.validate_group(0, effect.bind_layout_entries())
+            {
+                log::error!("Post {effect:?} shader does not match its layout: {err}");
+            }
+        }
+
+        let layout = |effect: PostEffect| device.create_pipeline_layout(&PipelineLayoutDescriptor
+        {
+            label: debug_label!(&format!("Post {effect:?} pipeline layout")),
+            bind_group_layouts: &[Some(&self.bind_layouts[effect as usize])],
+            immediate_size: 0,
+        });
+
+        let compute = |effect: PostEffect| device.create_compute_pipeline(&ComputePipelineDescriptor
+        {
+            label: debug_label!(&format!("Post {effect:?}")),
+            layout: Some(&layout(effect)),
+            module: &shaders[effect as usize].module,
+            entry_point: Some(ShaderStage::Compute.entry_point()),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let fullscreen = &shaders[PostEffect::Fullscreen as usize];
+        let render = |effect: PostEffect, format: TextureFormat, blend: Option<BlendState>| device.create_render_pipeline(&RenderPipelineDescriptor
+        {
+            label: debug_label!(&format!("Post {effect:?}")),
+            layout: Some(&layout(effect)),
+            vertex: VertexState
+            {
+                module: &fullscreen.module,
+                entry_point: Some(ShaderStage::Vertex.entry_point()),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState
+            {
+                module: &shaders[effect as usize].module,
+                entry_point: Some(ShaderStage::Pixel.entry_point()),
+                compilation_options: Default::default(),
+                targets: &[Some(ColorTargetState
+                {
+                    format,
+                    blend,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            multiview_mask: None,
+            cache: None,
+        });
+
+        let additive = BlendComponent { src_factor: BlendFactor::One, dst_factor: BlendFactor::One, operation: BlendOperation::Add };
+        PostPipelines
+        {
+            histogram: compute(PostEffect::LuminanceHistogram),
+            exposure: compute(PostEffect::Exposure),
+            bloom_prefilter: render(PostEffect::BloomPrefilter, HDR_SCENE_FORMAT, None),
+            bloom_downsample: render(PostEffect::BloomDownsample, HDR_SCENE_FORMAT, None),
+            bloom_upsample: render(PostEffect::BloomUpsample, HDR_SCENE_FORMAT, Some(BlendState { color: additive, alpha: additive })),
+            tonemap: render(PostEffect::Tonemap, self.renderer.surface_format(), None),
+        }
+    }
+}
+impl DebugGui for PostProcessPass
+{
+    fn display_name(&self) -> &str { "Post processing" }
+
+    fn debug_gui(&self, ui: &mut Ui)
+    {
+        if self.pipelines.is_none()
+        {
+            ui.label("Shaders loading");
+        }
+
+        let mut settings = self.settings.lock();
+        ui.heading("Exposure");
+        ui.checkbox(&mut settings.auto_exposure, "Auto exposure");
+        match settings.auto_exposure
+        {
+            true =>
+            {
+                ui.add(egui::Slider::new(&mut settings.exposure_compensation, -5.0..=5.0).text("Compensation (stops)"));
+                ui.add(egui::Slider::new(&mut settings.adaptation_speed, 0.1..=10.0).logarithmic(true).text("Adaptation speed"));
+                ui.add(egui::Slider::new(&mut settings.low_percentile, 0.0..=1.0).text("Low percentile"));
+                ui.add(egui::Slider::new(&mut settings.high_percentile, 0.0..=1.0).text("High percentile"));
+            }
+            false =>
+            {
+                ui.add(egui::Slider::new(&mut settings.manual_exposure, 0.001..=100.0).logarithmic(true).text("Exposure"));
+            }
+        }
+
+        ui.heading("Bloom");
+        ui.add(egui::Slider::new(&mut settings.bloom_intensity, 0.0..=1.0).text("Intensity"));
+        ui.add(egui::Slider::new(&mut settings.bloom_threshold, 0.0..=10.0).text("Threshold"));
+        ui.add(egui::Slider::new(&mut settings.bloom_soft_knee, 0.0..=1.0).text("Soft knee"));
+        ui.add(egui::Slider::new(&mut settings.bloom_mip_count, 1..=8).text("Mips"));
+
+        ui.heading("Tonemapping");
+        egui::ComboBox::from_label("Tonemapper")
+            .selected_text(format!("{:?}", settings.tonemapper))
+            .show_ui(ui, |cui|
+            {
+                cui.selectable_value(&mut settings.tonemapper, Tonemapper::Aces, "ACES");
+                cui.selectable_value(&mut settings.tonemapper, Tonemapper::AgX, "AgX");
+            });
+        ui.add_enabled(self.color_grading_lut.is_some(), egui::Slider::new(&mut settings.lut_strength, 0.0..=1.0).text("LUT strength"));
+    }
+}
+
+// Create a (linear) RGBA8 3D texture of size^3 texels
+#[must_use]
+fn create_lut(renderer: &Renderer, size: u32, texels: &[u8]) -> wgpu::Texture
+{
+    let extent = Extent3d { width: size, height: size, depth_or_array_layers: size };
+    let texture = renderer.device().create_texture(&TextureDescriptor
+    {
+        label: debug_label!("Color grading LUT"),
+        size: extent,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D3,
+        format: TextureFormat::Rgba8Unorm,
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    renderer.queue().write_texture(
+        TexelCopyTextureInfo
+        {
+            texture: &texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+            aspect: TextureAspect::All,
+        },
+        texels,
+        TexelCopyBufferLayout
+        {
+            offset: 0,
+            bytes_per_row: Some(size * 4),
+            rows_per_image: Some(size),
+        },
+        extent);
+    texture
+}
+
+#[cfg(test)]
+mod tests
+{
+    use image::RgbaImage;
+    use nab_3l14::RenderFrameNumber;
+    use wgpu::{BufferAddress, CommandEncoderDescriptor, MapMode, PollType, ShaderModuleDescriptor, ShaderSource};
+    use crate::shader_reflection::ShaderReflection;
+    use super::*;
+
+    #[inline] #[must_use]
+    fn linear_to_srgb(c: f32) -> f32
+    {
+        if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+    }
+
+    #[test]
+    fn tonemappers()
+    {
+        for tonemapper in [Tonemapper::Aces, Tonemapper::AgX]
+        {
+            // black stays (nearly) black, and very bright colors approach white
+            assert!(tonemapper.apply(Vec3::ZERO).max_element() < 0.01, "{tonemapper:?}");
+            assert!(tonemapper.apply(Vec3::splat(1000.0)).min_element() > 0.9, "{tonemapper:?}");
+
+            // monotonic, and always in the displayable range
+            let mut prev = Vec3::ZERO;
+            for i in 1..200
+            {
+                let exposed = Vec3::splat(i as f32 * 0.05);
+                let mapped = tonemapper.apply(exposed);
+                assert!(mapped.cmpge(Vec3::ZERO).all() && mapped.cmple(Vec3::ONE).all(), "{tonemapper:?} {exposed} -> {mapped}");
+                assert!(mapped.cmpge(prev).all(), "{tonemapper:?} is not monotonic at {exposed}");
+                prev = mapped;
+            }
+
+            // grays stay (roughly) neutral
+            let gray = tonemapper.apply(Vec3::splat(0.18));
+            assert!(gray.max_element() - gray.min_element() < 0.01, "{tonemapper:?} {gray}");
+        }
+    }
+
+    #[test]
+    fn exposure_adaptation()
+    {
+        let settings = PostSettings::default();
+        assert_eq!(settings.exposure_adaptation(Duration::ZERO), 0.0);
+
+        // two half steps adapt the same amount as one whole step
+        let half = settings.exposure_adaptation(Duration::from_millis(8));
+        let whole = settings.exposure_adaptation(Duration::from_millis(16));
+        assert!(((1.0 - half) * (1.0 - half) - (1.0 - whole)).abs() < 1e-5);
+        assert!(settings.exposure_adaptation(Duration::from_secs(100)) > 0.999);
+    }
+
+    #[test]
+    fn clamped_percentiles()
+    {
+        let percentiles = |low_percentile, high_percentile| PostSettings { low_percentile, high_percentile, ..Default::default() }.clamped_percentiles();
+        assert_eq!(percentiles(0.1, 0.9), (0.1, 0.9));
+        assert_eq!(percentiles(-1.0, 2.0), (0.0, 1.0));
+        assert_eq!(percentiles(0.8, 0.2), (0.8, 0.8));
+        assert_eq!(percentiles(1.5, 0.5), (1.0, 1.0));
+        assert_eq!(percentiles(f32::NAN, 0.5), (0.0, 0.5));
+        assert_eq!(percentiles(0.5, f32::NAN), (0.5, 1.0));
+        assert_eq!(percentiles(f32::NAN, f32::NAN), (0.0, 1.0));
+    }
+
+    fn test_shaders(renderer: &Renderer) -> [AssetView<Shader>; PostEffect::COUNT]
+    {
+        PostEffect::ALL.map(|effect|
+        {
+            let source = match effect
+            {
+                PostEffect::Fullscreen => include_str!("../../../../assets/src/shader/PostFullscreen.vs.wgsl"),
+                PostEffect::LuminanceHistogram => include_str!("../../../../assets/src/shader/PostHistogram.cs.wgsl"),
+                PostEffect::Exposure => include_str!("../../../../assets/src/shader/PostExposure.cs.wgsl"),
+                PostEffect::BloomPrefilter => include_str!("../../../../assets/src/shader/PostBloomPrefilter.ps.wgsl"),
+                PostEffect::BloomDownsample => include_str!("../../../../assets/src/shader/PostBloomDownsample.ps.wgsl"),
+                PostEffect::BloomUpsample => include_str!("../../../../assets/src/shader/PostBloomUpsample.ps.wgsl"),
+                PostEffect::Tonemap => include_str!("../../../../assets/src/shader/PostTonemap.ps.wgsl"),
+            };
+            AssetView::new_for_testing(Shader
+            {
+                stage: effect.stage(),
+                module: renderer.device().create_shader_module(ShaderModuleDescriptor
+                {
+                    label: None,
+                    source: ShaderSource::Wgsl(source.into()),
+                }),
+                reflection: ShaderReflection::default(),
+            })
+        })
+    }
+
+    // Render a frame of a scene (added to the graph by add_scene) through the post chain, returning the (sRGB encoded) output
+    fn render_scene(
+        renderer: &Renderer,
+        post: &mut PostProcessPass,
+        frame_number: u64,
+        delta_time: Duration,
+        add_scene: impl FnOnce(&mut RenderGraph) -> GraphTexture) -> RgbaImage
+    {
+        let frame = renderer.begin_frame(RenderFrameNumber(frame_number), egui::RawInput::default());
+        let mut graph: RenderGraph = RenderGraph::new(renderer.display_size());
+        let back_buffer = graph.import_back_buffer(&frame);
+        let scene = add_scene(&mut graph);
+        assert!(post.add_nodes(&mut graph, scene, back_buffer, delta_time));
+
+        let mut encoder = renderer.device().create_command_encoder(&CommandEncoderDescriptor::default());
+        graph.execute(renderer, &mut encoder, &mut ()).expect("The post processing graph is valid");
+        renderer.queue().submit([encoder.finish()]);
+
+        renderer.present_and_read_back(frame).unwrap()
+    }
+
+    // Render a frame of a single HDR color through the post chain, returning the (sRGB encoded) output color
+    fn render_color(renderer: &Renderer, post: &mut PostProcessPass, frame_number: u64, scene_color: Vec3, delta_time: Duration) -> [u8; 4]
+    {
+        let image = render_scene(renderer, post, frame_number, delta_time, |graph|
+        {
+            let scene = graph.create_texture(GraphTextureDesc::new("Scene", GraphTextureSize::Display, HDR_SCENE_FORMAT));
+            // render_passes::opaque() treats clear colors as sRGB, so the HDR color is cleared directly
+            graph.add_node("Scene")
+                .write(scene, TextureUsages::RENDER_ATTACHMENT)
+                .run(move |ctx, _|
+                {
+                    drop(ctx.encoder.begin_render_pass(&RenderPassDescriptor
+                    {
+                        label: None,
+                        color_attachments: &[Some(RenderPassColorAttachment
+                        {
+                            view: ctx.textures.view(scene),
+                            depth_slice: None,
+                            resolve_target: None,
+                            ops: Operations
+                            {
+                                load: LoadOp::Clear(Color { r: scene_color.x as f64, g: scene_color.y as f64, b: scene_color.z as f64, a: 1.0 }),
+                                store: StoreOp::Store,
+                            },
+                        })],
+                        depth_stencil_attachment: None,
+                        timestamp_writes: None,
+                        occlusion_query_set: None,
+                        multiview_mask: None,
+                    }));
+                });
+            scene
+        });
+
+        let center = image.get_pixel(image.width() / 2, image.height() / 2).0;
+        assert!(image.pixels().all(|p| p.0 == center), "A uniform scene should post process uniformly");
+        center
+    }
+
+    fn read_exposure(renderer: &Renderer, post: &PostProcessPass) -> PostExposure
+    {
+        let readback = renderer.device().create_buffer(&BufferDescriptor
+        {
+            label: None,
+            size: size_of::<PostExposure>() as BufferAddress,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = renderer.device().create_command_encoder(&CommandEncoderDescriptor::default());
+        encoder.copy_buffer_to_buffer(&post.exposure, 0, &readback, 0, size_of::<PostExposure>() as BufferAddress);
+        renderer.queue().submit([encoder.finish()]);
+
+        let slice = readback.slice(..);
+        slice.map_async(MapMode::Read, |result| result.expect("Failed to map the exposure read back buffer"));
+        let _ = renderer.device().poll(PollType::Wait { submission_index: None, timeout: None });
+        let mapped = slice.get_mapped_range().expect("Failed to access the mapped exposure buffer");
+        PostExposure
+        {
+            exposure: f32::from_le_bytes(mapped[0..4].try_into().unwrap()),
+            average_luminance: f32::from_le_bytes(mapped[4..8].try_into().unwrap()),
+        }
+    }
+
+    fn assert_color_near(actual: [u8; 4], expected: Vec3, tolerance: u8)
+    {
+        let expected = expected.to_array().map(|c| (linear_to_srgb(c.clamp(0.0, 1.0)) * 255.0 + 0.5) as u8);
+        for (a, e) in actual.iter().zip(expected)
+        {
+            assert!(a.abs_diff(e) <= tolerance, "Expected {expected:?}, got {actual:?}");
+        }
+    }
+
+    #[test]
+    #[ignore = "requires a GPU adapter (or a fallback one)"]
+    fn headless_post_chain()
+    {
+        let renderer = Renderer::new_headless(UVec2::new(40, 24), true).unwrap();
+
+        let settings = PostSettings
+        {
+            auto_exposure: false,
+            manual_exposure: 2.0,
+            bloom_intensity: 0.0,
+            ..Default::default()
+        };
+        let mut post = PostProcessPass::with_shaders(renderer.clone(), test_shaders(&renderer), settings);
+
+        // manual exposure
+        let color = Vec3::new(0.6, 0.3, 0.05);
+        for tonemapper in [Tonemapper::Aces, Tonemapper::AgX]
+        {
+            post.settings().tonemapper = tonemapper;
+            let output = render_color(&renderer, &mut post, 0, color, Duration::from_millis(16));
+            assert_color_near(output, tonemapper.apply(color * 2.0), 2);
+        }
+
+        // auto exposure brings the (uniform) scene to middle gray
+        {
+            let mut settings = post.settings();
+            settings.auto_exposure = true;
+            settings.adaptation_speed = 1000.0;
+            settings.tonemapper = Tonemapper::Aces;
+        }
+        let luminance = 3.0;
+        render_color(&renderer, &mut post, 1, Vec3::splat(luminance), Duration::from_secs(1));
+        let exposure = read_exposure(&renderer, &post);
+        let expected = 0.18 / luminance;
+        assert!((exposure.exposure / expected).log2().abs() < 0.1, "Expected exposure {expected}, got {exposure:?}");
+        assert!((exposure.average_luminance / luminance).log2().abs() < 0.1, "Expected luminance {luminance}, got {exposure:?}");
+
+        // LUTs grade the sRGB encoded output
+        {
+            let mut settings = post.settings();
+            settings.auto_exposure = false;
+            settings.manual_exposure = 1.0;
+        }
+        let inverting: Vec<u8> = (0..8u32).flat_map(|i| [255 * (1 - (i & 1)), 255 * (1 - ((i >> 1) & 1)), 255 * (1 - (i >> 2)), 255].map(|c| c as u8)).collect();
+        let lut = create_lut(&renderer, 2, &inverting);
+        let gpu_view = lut.create_view(&TextureViewDescriptor::default());
+        post.set_color_grading_lut(Some(AssetView::new_for_testing(Texture { gpu_tex: lut, gpu_view })));
+        let output = render_color(&renderer, &mut post, 2, color, Duration::from_millis(16));
+        let ungraded = Tonemapper::Aces.apply(color).to_array().map(|c| (linear_to_srgb(c) * 255.0 + 0.5) as u8);
+        for (graded, ungraded) in output.iter().zip(ungraded)
+        {
+            assert!(graded.abs_diff(255 - ungraded) <= 2, "Expected the inverse of {ungraded:?}, got {output:?}");
+        }
+
+        // bloom only brightens
+        post.set_color_grading_lut(None);
+        post.settings().bloom_intensity = 0.5;
+        let bloomed = render_color(&renderer, &mut post, 0, color, Duration::from_millis(16));
+        let unbloomed = Tonemapper::Aces.apply(color).to_array().map(|c| (linear_to_srgb(c) * 255.0 + 0.5) as u8);
+        assert!(bloomed.iter().zip(unbloomed).all(|(b, u)| *b >= u.saturating_sub(1)));
+    }
+
+    #[test]
+    #[ignore = "requires a GPU adapter (or a fallback one)"]
+    fn headless_bloom()
+    {
+        let size = UVec2::new(64, 48);
+        let renderer = Renderer::new_headless(size, true).unwrap();
+
+        // a dim scene with a small, very bright, block (texels are f16 bits)
+        const DIM: u16 = 0x2c00; // 0.0625
+        const BRIGHT: u16 = 0x5400; // 64.0
+        const ONE: u16 = 0x3c00;
+        let bright_block = 14..18;
+        let texels: Vec<u8> = (0..size.y).flat_map(|y| (0..size.x).map(move |x| (x, y)))
+            .flat_map(|(x, y)|
+            {
+                let c = if bright_block.contains(&x) && bright_block.contains(&y) { BRIGHT } else { DIM };
+                [c, c, c, ONE]
+            })
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let extent = Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 };
+        let scene = renderer.device().create_texture(&TextureDescriptor
+        {
+            label: None,
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: HDR_SCENE_FORMAT,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        renderer.queue().write_texture(
+            TexelCopyTextureInfo { texture: &scene, mip_level: 0, origin: Origin3d::ZERO, aspect: TextureAspect::All },
+            &texels,
+            TexelCopyBufferLayout { offset: 0, bytes_per_row: Some(size.x * 8), rows_per_image: Some(size.y) },
+            extent);
+
+        let settings = PostSettings
+        {
+            auto_exposure: false,
+            manual_exposure: 1.0,
+            bloom_intensity: 0.0,
+            bloom_mip_count: 2, // keeps the bloom local
+            tonemapper: Tonemapper::Aces,
+            ..Default::default()
+        };
+        let mut post = PostProcessPass::with_shaders(renderer.clone(), test_shaders(&renderer), settings);
+        let unbloomed = render_scene(&renderer, &mut post, 0, Duration::from_millis(16), |graph| graph.import_texture("Scene", &scene));
+        post.settings().bloom_intensity = 0.5;
+        let bloomed = render_scene(&renderer, &mut post, 1, Duration::from_millis(16), |graph| graph.import_texture("Scene", &scene));
+
+        // bloom only brightens
+        for (b, u) in bloomed.pixels().zip(unbloomed.pixels())
+        {
+            assert!(b.0.iter().zip(u.0).all(|(b, u)| *b >= u.saturating_sub(1)), "{b:?} is darker than {u:?}");
+        }
+
+        // the bright block spreads into its (dim) neighbours
+        for (x, y) in [(19, 16), (12, 15), (16, 20), (15, 12)]
+        {
+            let (b, u) = (bloomed.get_pixel(x, y).0, unbloomed.get_pixel(x, y).0);
+            assert!(b[0] > u[0].saturating_add(8), "({x}, {y}) was not bloomed: {b:?} vs {u:?}");
+        }
+
+        // and dim pixels far from it are unchanged, as they are below the threshold
+        for (x, y) in [(63, 47), (0, 47), (63, 0), (50, 30)]
+        {
+            let (b, u) = (bloomed.get_pixel(x, y).0, unbloomed.get_pixel(x, y).0);
+            assert!(b.iter().zip(u).all(|(b, u)| b.abs_diff(u) <= 1), "({x}, {y}) should not be bloomed: {b:?} vs {u:?}");
+        }
+    }
+}
@@ -1,31 +1,446 @@
+use arrayvec::ArrayVec;
+use glam::{Mat4, Vec3, Vec3Swizzles};
 use triomphe::Arc;
-use wgpu::{TextureDescriptor, TextureDimension, TextureFormat, TextureUsages};
-use wgpu::wgt::TextureViewDescriptor;
+use wgpu::{AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferSize, BufferUsages, CommandEncoder, CompareFunction, Extent3d, FilterMode, LoadOp, MipmapFilterMode, Operations, RenderPass, RenderPassDepthStencilAttachment, RenderPassDescriptor, SamplerBindingType, SamplerDescriptor, ShaderStages, StoreOp, Texture, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension};
+use containers_3l14::AabbTree;
+use math_3l14::{Frustum, AABB};
+use nab_3l14::utils::AsU8Slice;
+use crate::camera::Camera;
+use crate::shader_reflection::{UniformMemberLayout, UniformType};
 use crate::{debug_label, Renderer};
 
+pub const MAX_SHADOW_CASCADES: usize = 4;
+pub const SHADOW_MAP_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+
+#[derive(Debug, Clone)]
+pub struct CascadeSettings
+{
+    pub cascade_count: u8, // clamped to 1..=MAX_SHADOW_CASCADES
+    pub resolution: u32, // the width and height of each cascade's shadow map
+    pub max_distance: f32, // nothing past this (view) depth is shadowed
+    pub split_lambda: f32, // blends cascade splits between uniform (0) and logarithmic (1)
+    pub caster_distance: f32, // how far past each cascade (towards the light) casters are still drawn
+}
+impl Default for CascadeSettings
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            cascade_count: MAX_SHADOW_CASCADES as u8,
+            resolution: 2048,
+            max_distance: 100.0,
+            split_lambda: 0.8,
+            caster_distance: 50.0,
+        }
+    }
+}
+
+// A shadow map covering a depth slice of the camera's frustum
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowCascade
+{
+    pub light_view: Mat4,
+    pub light_bounds: AABB, // the light-space volume rendered into the shadow map
+    pub view_proj: Mat4,
+    pub near_depth: f32,
+    pub far_depth: f32,
+    pub texel_size: f32, // world-space width of a shadow map texel
+}
+impl ShadowCascade
+{
+    // The world-space box enclosing this cascade, for broad-phase queries
+    #[must_use]
+    pub fn world_bounds(&self) -> AABB
+    {
+        self.light_bounds.transformed(&self.light_view.inverse())
+    }
+
+    // Can an object with these (world-space) bounds cast a shadow into this cascade
+    #[inline] #[must_use]
+    pub fn can_cast(&self, world_bounds: AABB) -> bool
+    {
+        self.light_bounds.overlaps(world_bounds.transformed(&self.light_view))
+    }
+}
+
+// The far depth of each cascade using the 'practical' split scheme, a blend of uniform and logarithmic splits
+#[must_use]
+pub fn cascade_splits(near_clip: f32, far_clip: f32, cascade_count: usize, lambda: f32) -> ArrayVec<f32, MAX_SHADOW_CASCADES>
+{
+    let count = cascade_count.clamp(1, MAX_SHADOW_CASCADES);
+    (1..=count).map(|i|
+    {
+        if i == count { return far_clip; }
+
+        let t = i as f32 / count as f32;
+        let uniform = near_clip + (far_clip - near_clip) * t;
+        let logarithmic = near_clip * (far_clip / near_clip).powf(t);
+        uniform + (logarithmic - uniform) * lambda
+    }).collect()
+}
+
+// Fit shadow cascades for a directional light to the frustum of a camera
+#[must_use]
+pub fn fit_cascades(camera: &Camera, light_direction: Vec3, settings: &CascadeSettings) -> ArrayVec<ShadowCascade, MAX_SHADOW_CASCADES>
+{
+    let light_direction = light_direction.normalize_or(Vec3::NEG_Y);
+    let light_up = if light_direction.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+    let light_view = Mat4::look_to_lh(Vec3::ZERO, light_direction, light_up);
+
+    let view_mtx = camera.transform().to_view_mtx();
+    let near_clip = camera.near_clip();
+    let far_clip = camera.far_clip().min(settings.max_distance).max(near_clip);
+    // leave a texel of border so that the snapped cascade still contains the whole slice
+    let border_scale = settings.resolution as f32 / (settings.resolution.max(3) - 2) as f32;
+
+    let mut cascade_near = near_clip;
+    cascade_splits(near_clip, far_clip, settings.cascade_count as usize, settings.split_lambda).into_iter().map(|cascade_far|
+    {
+        let corners = Frustum::get_corners(&(camera.projection().to_matrix(cascade_near, cascade_far) * view_mtx));
+
+        // fitting a sphere keeps the cascade's size constant as the camera rotates, which avoids shimmering
+        let center = corners.iter().sum::<Vec3>() / corners.len() as f32;
+        let radius = corners.iter().fold(0.0f32, |r, c| r.max(c.distance(center)));
+        let half_extent = ((radius * 16.0).ceil() / 16.0) * border_scale;
+        let texel_size = (2.0 * half_extent) / settings.resolution as f32;
+
+        // likewise, only moving in whole texels avoids shimmering as the camera moves
+        let light_center = light_view.transform_point3(center);
+        let snapped = (light_center.xy() / texel_size).round() * texel_size;
+        let light_bounds = AABB::new(
+            Vec3::new(snapped.x - half_extent, snapped.y - half_extent, light_center.z - half_extent - settings.caster_distance),
+            Vec3::new(snapped.x + half_extent, snapped.y + half_extent, light_center.z + half_extent));
+
+        let projection = Mat4::orthographic_lh(
+            light_bounds.min.x, light_bounds.max.x,
+            light_bounds.min.y, light_bounds.max.y,
+            light_bounds.min.z, light_bounds.max.z);
+
+        let cascade = ShadowCascade
+        {
+            light_view,
+            light_bounds,
+            view_proj: projection * light_view,
+            near_depth: cascade_near,
+            far_depth: cascade_far,
+            texel_size,
+        };
+        cascade_near = cascade_far;
+        cascade
+    }).collect()
+}
+
+// The values in a spatial hierarchy (e.g. a map's statics) that can cast shadows into a cascade
+pub fn cull_casters<'t>(casters: &'t AabbTree, cascade: &ShadowCascade) -> impl Iterator<Item = u32> + 't
+{
+    let cascade = *cascade;
+    casters.iter_overlapping(cascade.world_bounds())
+        .filter(move |(bounds, _)| cascade.can_cast(*bounds))
+        .map(|(_, value)| value)
+}
+
+#[repr(C, align(16))]
+pub struct ShadowsUniform
+{
+    pub cascade_view_projs: [Mat4; MAX_SHADOW_CASCADES],
+    pub light_direction: Vec3,
+    pub cascade_count: u32, // no shadows are sampled if zero
+    pub texel_size: f32, // in shadow map UVs, for filtering
+}
+impl UniformType for ShadowsUniform
+{
+    const MEMBERS: &'static [UniformMemberLayout] =
+    &[
+        UniformMemberLayout::new(std::mem::offset_of!(Self, cascade_view_projs), size_of::<[Mat4; MAX_SHADOW_CASCADES]>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, light_direction), size_of::<Vec3>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, cascade_count), size_of::<u32>()),
+        UniformMemberLayout::new(std::mem::offset_of!(Self, texel_size), size_of::<f32>()),
+    ];
+}
+
+// The bindings of the shadows bind group, sampled by lit pixel shaders
+pub const SHADOWS_BIND_LAYOUT_ENTRIES: &[BindGroupLayoutEntry] =
+&[
+    BindGroupLayoutEntry
+    {
+        binding: 0,
+        visibility: ShaderStages::FRAGMENT,
+        ty: BindingType::Buffer
+        {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: BufferSize::new(size_of::<ShadowsUniform>() as u64),
+        },
+        count: None,
+    },
+    BindGroupLayoutEntry
+    {
+        binding: 1,
+        visibility: ShaderStages::FRAGMENT,
+        ty: BindingType::Texture
+        {
+            sample_type: TextureSampleType::Depth,
+            view_dimension: TextureViewDimension::D2Array,
+            multisampled: false,
+        },
+        count: None,
+    },
+    BindGroupLayoutEntry
+    {
+        binding: 2,
+        visibility: ShaderStages::FRAGMENT,
+        ty: BindingType::Sampler(SamplerBindingType::Comparison),
+        count: None,
+    },
+];
+
 pub struct ShadowPass
 {
-    depth: wgpu::TextureView,
+    renderer: Arc<Renderer>,
+    settings: CascadeSettings,
+    cascades: ArrayVec<ShadowCascade, MAX_SHADOW_CASCADES>,
+
+    shadow_map: Texture, // a layer per cascade
+    cascade_views: ArrayVec<TextureView, MAX_SHADOW_CASCADES>, // a view of each layer, for rendering
+    uniform: Buffer,
+    bind_group: BindGroup,
 }
 impl ShadowPass
 {
-    pub fn new(renderer: Arc<Renderer>) -> Self
+    #[must_use]
+    pub fn new(renderer: Arc<Renderer>, bind_layout: &BindGroupLayout, settings: CascadeSettings) -> Self
     {
-        let depth_tex = renderer.device().create_texture(&TextureDescriptor
+        let cascade_count = (settings.cascade_count as usize).clamp(1, MAX_SHADOW_CASCADES) as u32;
+        let depth = renderer.device().create_texture(&TextureDescriptor
         {
-            label: debug_label!("Shadow pass depth texture"),
-            size: Default::default(),
-            mip_level_count: 0,
-            sample_count: 0,
+            label: debug_label!("Shadow cascades depth texture"),
+            size: Extent3d { width: settings.resolution, height: settings.resolution, depth_or_array_layers: cascade_count },
+            mip_level_count: 1,
+            sample_count: 1,
             dimension: TextureDimension::D2,
-            format: TextureFormat::R8Unorm,
+            format: SHADOW_MAP_FORMAT,
             usage: TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
-            view_formats: &[TextureFormat::R8Unorm],
+            view_formats: &[SHADOW_MAP_FORMAT],
         });
 
-        Self
+        let cascade_views = (0..cascade_count).map(|layer| depth.create_view(&TextureViewDescriptor
+        {
+            label: debug_label!(&format!("Shadow cascade {layer} view")),
+            dimension: Some(TextureViewDimension::D2),
+            aspect: TextureAspect::DepthOnly,
+            base_array_layer: layer,
+            array_layer_count: Some(1),
+            ..Default::default()
+        })).collect();
+
+        let array_view = depth.create_view(&TextureViewDescriptor
+        {
+            label: debug_label!("Shadow cascades view"),
+            dimension: Some(TextureViewDimension::D2Array),
+            aspect: TextureAspect::DepthOnly,
+            ..Default::default()
+        });
+
+        let sampler = renderer.device().create_sampler(&SamplerDescriptor
+        {
+            label: debug_label!("Shadow comparison sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: MipmapFilterMode::Nearest,
+            lod_min_clamp: 0.0,
+            lod_max_clamp: 0.0,
+            compare: Some(CompareFunction::LessEqual),
+            anisotropy_clamp: 1,
+            border_color: None,
+        });
+
+        let uniform = renderer.device().create_buffer(&BufferDescriptor
+        {
+            label: debug_label!("Shadows uniform buffer"),
+            size: size_of::<ShadowsUniform>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = renderer.device().create_bind_group(&BindGroupDescriptor
+        {
+            label: debug_label!("Shadows bind group"),
+            layout: bind_layout,
+            entries:
+            &[
+                BindGroupEntry { binding: 0, resource: uniform.as_entire_binding() },
+                BindGroupEntry { binding: 1, resource: BindingResource::TextureView(&array_view) },
+                BindGroupEntry { binding: 2, resource: BindingResource::Sampler(&sampler) },
+            ],
+        });
+
+        let shadow_pass = Self
+        {
+            renderer,
+            settings,
+            cascades: ArrayVec::new(),
+            shadow_map: depth,
+            cascade_views,
+            uniform,
+            bind_group,
+        };
+        shadow_pass.write_uniform(Vec3::NEG_Y);
+        shadow_pass
+    }
+
+    #[inline] #[must_use] pub fn settings(&self) -> &CascadeSettings { &self.settings }
+    #[inline] #[must_use] pub fn cascades(&self) -> &[ShadowCascade] { &self.cascades }
+    #[inline] #[must_use] pub fn bind_group(&self) -> &BindGroup { &self.bind_group }
+    #[inline] #[must_use] pub fn shadow_map(&self) -> &Texture { &self.shadow_map }
+
+    // Fit the cascades to a camera, no shadows are drawn without a (directional) light
+    pub fn update(&mut self, camera: &Camera, light_direction: Option<Vec3>)
+    {
+        self.cascades = match light_direction
+        {
+            Some(dir) => fit_cascades(camera, dir, &self.settings),
+            None => ArrayVec::new(),
+        };
+        self.write_uniform(light_direction.unwrap_or(Vec3::NEG_Y));
+    }
+
+    // Begin a depth-only render pass into a cascade's shadow map
+    pub fn begin_cascade<'e>(&'e self, encoder: &'e mut CommandEncoder, cascade: usize) -> RenderPass<'e>
+    {
+        encoder.begin_render_pass(&RenderPassDescriptor
+        {
+            label: debug_label!(&format!("Shadow cascade {cascade} render pass")),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment
+            {
+                view: &self.cascade_views[cascade],
+                depth_ops: Some(Operations { load: LoadOp::Clear(1.0), store: StoreOp::Store }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+            multiview_mask: None,
+        })
+    }
+
+    fn write_uniform(&self, light_direction: Vec3)
+    {
+        let mut uniform = ShadowsUniform
+        {
+            cascade_view_projs: [Mat4::IDENTITY; MAX_SHADOW_CASCADES],
+            light_direction: light_direction.normalize_or(Vec3::NEG_Y),
+            cascade_count: self.cascades.len() as u32,
+            texel_size: 1.0 / self.settings.resolution as f32,
+        };
+        for (view_proj, cascade) in uniform.cascade_view_projs.iter_mut().zip(&self.cascades)
+        {
+            *view_proj = cascade.view_proj;
+        }
+        self.renderer.queue().write_buffer(&self.uniform, 0, unsafe { std::slice::from_ref(&uniform).as_u8_slice() });
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use glam::{Quat, Vec4Swizzles};
+    use math_3l14::{Angle, Transform};
+    use crate::camera::CameraProjection;
+    use super::*;
+
+    fn test_camera(position: Vec3, rotation: Quat) -> Camera
+    {
+        let mut camera = Camera::default();
+        camera.update_projection(CameraProjection::Perspective { fov: Angle::from_degrees(70.0), aspect_ratio: 16.0 / 9.0 }, 0.1, 500.0);
+        camera.update_view(Transform { position, rotation, scale: Vec3::ONE });
+        camera
+    }
+
+    #[test]
+    fn splits()
+    {
+        let uniform = cascade_splits(1.0, 101.0, 4, 0.0);
+        assert_eq!(uniform.as_slice(), &[26.0, 51.0, 76.0, 101.0]);
+
+        let practical = cascade_splits(0.1, 100.0, 4, 0.8);
+        assert_eq!(practical.len(), 4);
+        assert!(practical.windows(2).all(|w| w[0] < w[1]));
+        assert!((practical[3] - 100.0).abs() < 1e-3);
+        assert!(practical[0] < uniform[0]); // logarithmic splits favor the near cascades
+
+        assert_eq!(cascade_splits(0.1, 100.0, 10, 0.5).len(), MAX_SHADOW_CASCADES);
+        assert_eq!(cascade_splits(0.1, 100.0, 0, 0.5).len(), 1);
+    }
+
+    #[test]
+    fn cascades_contain_frustum()
+    {
+        let settings = CascadeSettings::default();
+        let camera = test_camera(Vec3::new(3.0, 2.0, -7.0), Quat::from_euler(glam::EulerRot::YXZ, 0.4, 0.3, 0.0));
+        let light_direction = Vec3::new(-0.3, -1.0, 0.4).normalize();
+        let cascades = fit_cascades(&camera, light_direction, &settings);
+        assert_eq!(cascades.len(), settings.cascade_count as usize);
+        assert_eq!(cascades.last().unwrap().far_depth, settings.max_distance);
+
+        let view_mtx = camera.transform().to_view_mtx();
+        for (i, cascade) in cascades.iter().enumerate()
         {
-            depth: depth_tex.create_view(&TextureViewDescriptor::default()),
+            let slice = camera.projection().to_matrix(cascade.near_depth, cascade.far_depth) * view_mtx;
+            for corner in Frustum::get_corners(&slice)
+            {
+                let clip = cascade.view_proj.project_point3(corner);
+                assert!(clip.xy().abs().max_element() <= 1.0, "Cascade {i} does not contain {corner:?} ({clip:?})");
+                assert!(clip.z >= 0.0 && clip.z <= 1.0, "Cascade {i} does not contain {corner:?} ({clip:?})");
+            }
+
+            // the sides of the light volume face the light
+            let light_dir_clip = (cascade.view_proj * light_direction.extend(0.0)).xyz();
+            assert!(light_dir_clip.xy().length() < 1e-4);
+            assert!(light_dir_clip.z > 0.0);
+        }
+    }
+
+    #[test]
+    fn cascades_stable()
+    {
+        let settings = CascadeSettings::default();
+        let light_direction = Vec3::new(0.2, -1.0, 0.1);
+        let a = fit_cascades(&test_camera(Vec3::ZERO, Quat::IDENTITY), light_direction, &settings);
+        let b = fit_cascades(&test_camera(Vec3::new(0.013, 0.0, 0.021), Quat::from_rotation_y(1.3)), light_direction, &settings);
+
+        for (a, b) in a.iter().zip(&b)
+        {
+            // rotating doesn't change the size
+            assert!((a.light_bounds.size() - b.light_bounds.size()).abs().max_element() < 1e-4);
+            assert_eq!(a.texel_size, b.texel_size);
+
+            // moving only moves in whole texels
+            let offset = (b.light_bounds.min - a.light_bounds.min).xy() / a.texel_size;
+            assert!((offset - offset.round()).abs().max_element() < 1e-2, "{offset:?}");
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn cull()
+    {
+        let settings = CascadeSettings { cascade_count: 1, max_distance: 20.0, caster_distance: 30.0, ..Default::default() };
+        let camera = test_camera(Vec3::ZERO, Quat::IDENTITY); // looking down +Z
+        let cascade = fit_cascades(&camera, Vec3::NEG_Y, &settings)[0];
+
+        let mut casters = AabbTree::new();
+        casters.insert(AABB::new(Vec3::new(-1.0, -1.0, 5.0), Vec3::new(1.0, 1.0, 7.0)), 0); // in view
+        casters.insert(AABB::new(Vec3::new(-1.0, 40.0, 5.0), Vec3::new(1.0, 42.0, 7.0)), 1); // above, between the view and the light
+        casters.insert(AABB::new(Vec3::new(-1.0, 100.0, 5.0), Vec3::new(1.0, 102.0, 7.0)), 2); // too far above
+        casters.insert(AABB::new(Vec3::new(-1.0, -60.0, 5.0), Vec3::new(1.0, -58.0, 7.0)), 3); // below, shadows can't be seen
+        casters.insert(AABB::new(Vec3::new(200.0, -1.0, 5.0), Vec3::new(202.0, 1.0, 7.0)), 4); // off to the side
+        casters.insert(AABB::new(Vec3::new(-1.0, -1.0, -200.0), Vec3::new(1.0, 1.0, -198.0)), 5); // behind
+
+        let mut culled: Vec<_> = cull_casters(&casters, &cascade).collect();
+        culled.sort();
+        assert_eq!(culled, vec![0, 1]);
+    }
+}
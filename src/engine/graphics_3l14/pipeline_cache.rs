@@ -1,6 +1,7 @@
-use crate::assets::{Geometry, Material, EngineRenderPass, Shader, ShaderStage, shader_key};
+use crate::assets::{Geometry, Material, EngineRenderPass, Shader, ShaderStage, shader_key, MaterialAlphaMode, MaterialRenderState};
 use crate::uniforms_pool::UniformsPool;
-use crate::{debug_label, Renderer};
+use math_3l14::StaticGeoUniform;
+use crate::{debug_label, Renderer, HDR_SCENE_FORMAT, SCENE_DEPTH_FORMAT};
 use debug_3l14::debug_gui::DebugGui;
 use egui::Ui;
 use metrohash::MetroHash64;
@@ -11,10 +12,15 @@ use dashmap::DashMap;
 use dashmap::mapref::one::Ref;
 use triomphe::Arc;
 use enumflags2::BitFlags;
-use wgpu::{AddressMode, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferBindingType, BufferSize, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState, DepthStencilState, Face, FilterMode, FragmentState, FrontFace, MipmapFilterMode, MultisampleState, PipelineCompilationOptions, PipelineLayout, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPass, RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages, StencilState, TextureFormat, TextureSampleType, TextureViewDimension, VertexState};
+use wgpu::{AddressMode, BindGroupLayout, BindGroupLayoutEntry, BlendState, BindGroupLayoutDescriptor, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState, DepthStencilState, Face, FilterMode, FragmentState, FrontFace, MipmapFilterMode, MultisampleState, PipelineCompilationOptions, PipelineLayout, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPass, RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerDescriptor, StencilState, VertexState};
 use asset_3l14::{Ash, AssetKey, AssetTypeId, Assets, AssetSnapshot, AssetView};
 use crate::assets::shader_key::pixel;
-use crate::material_classes::{MaterialClass, SimpleOpaque};
+use crate::camera::CameraUniform;
+use crate::material_classes::MaterialClass;
+use crate::passes::light_cull::{ClusterGridUniform, LIGHTS_BIND_LAYOUT_ENTRIES};
+use crate::passes::shadow::{ShadowsUniform, SHADOWS_BIND_LAYOUT_ENTRIES, SHADOW_MAP_FORMAT};
+use crate::shader_reflection::{UniformLayout, UniformType};
+use crate::uniforms_pool::SkinnedPosesUniform;
 use crate::vertex_layouts::{VertexCaps, VertexLayoutBuilder};
 
 #[derive(Debug, Clone, Copy, Hash)]
@@ -24,16 +30,64 @@ pub enum DebugMode // debug only?
     Wireframe,
 }
 
+// The bind group index each set of resources is bound to, shared by all pipelines
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindGroupSlot
+{
+    Camera = 0,
+    Transform = 1,
+    Poses = 2,
+    Material = 3,
+    Shadows = 4,
+    Lights = 5,
+}
+impl BindGroupSlot
+{
+    // The per-view bind groups, shared by all lit pixel shaders
+    pub const VIEW_SLOTS: [Self; 2] = [Self::Shadows, Self::Lights];
+    // The number of bind groups a pipeline may use, this is more than WebGPU guarantees (4)
+    pub const COUNT: u32 = Self::Lights as u32 + 1;
+
+    // The Rust-side layout of the uniform buffer bound at binding 0 of the engine's bind groups (materials are per-class)
+    #[must_use]
+    pub const fn uniform_layout(self) -> Option<UniformLayout>
+    {
+        match self
+        {
+            BindGroupSlot::Camera => Some(CameraUniform::LAYOUT),
+            BindGroupSlot::Transform => Some(StaticGeoUniform::LAYOUT),
+            BindGroupSlot::Poses => Some(SkinnedPosesUniform::LAYOUT),
+            BindGroupSlot::Material => None,
+            BindGroupSlot::Shadows => Some(ShadowsUniform::LAYOUT),
+            BindGroupSlot::Lights => Some(ClusterGridUniform::LAYOUT),
+        }
+    }
+
+    // The layout of the per-view bind groups, created by the engine
+    #[must_use]
+    pub const fn view_bind_layout_entries(self) -> Option<&'static [BindGroupLayoutEntry]>
+    {
+        match self
+        {
+            BindGroupSlot::Shadows => Some(SHADOWS_BIND_LAYOUT_ENTRIES),
+            BindGroupSlot::Lights => Some(LIGHTS_BIND_LAYOUT_ENTRIES),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Hash, PartialEq, Eq, Copy, Clone)]
-pub struct PipelineKey(u64);
+pub struct PipelineKey(pub(crate) u64);
 
 enum MaybePipeline
 {
     Pending
     { // box?
+        pass: EngineRenderPass,
         vertex_shader: Ash<Shader>,
         vertex_layout: BitFlags<VertexCaps>,
-        material: Option<(MaterialClass, Ash<Shader>)>,
+        material: Option<(MaterialClass, MaterialRenderState, Ash<Shader>)>,
     },
     Created(RenderPipeline),
 }
@@ -44,6 +98,8 @@ pub struct PipelineCache
     assets: Arc<Assets>,
     pub uniforms: UniformsPool,
     bind_layouts: DashMap<MaterialClass, BindGroupLayout>,
+    shadows_bind_layout: BindGroupLayout,
+    lights_bind_layout: BindGroupLayout,
 
     // TODO: callback from renderer when one of the global settings changes
     // invalidate all pipelines
@@ -55,11 +111,23 @@ pub struct PipelineCache
 impl PipelineCache
 {
     pub fn default_sampler(&self) -> &Sampler { &self.default_sampler }
+    pub fn shadows_bind_layout(&self) -> &BindGroupLayout { &self.shadows_bind_layout }
+    pub fn lights_bind_layout(&self) -> &BindGroupLayout { &self.lights_bind_layout }
 
     #[must_use]
     pub fn new(renderer: Arc<Renderer>, assets: Arc<Assets>) -> Self
     {
         let default_sampler = Self::create_sampler(&renderer);
+        let shadows_bind_layout = renderer.device().create_bind_group_layout(&BindGroupLayoutDescriptor
+        {
+            label: debug_label!("Shadows psh bind layout"),
+            entries: SHADOWS_BIND_LAYOUT_ENTRIES,
+        });
+        let lights_bind_layout = renderer.device().create_bind_group_layout(&BindGroupLayoutDescriptor
+        {
+            label: debug_label!("Lights psh bind layout"),
+            entries: LIGHTS_BIND_LAYOUT_ENTRIES,
+        });
 
         Self
         {
@@ -67,6 +135,8 @@ impl PipelineCache
             assets,
             uniforms: UniformsPool::new(renderer),
             bind_layouts: DashMap::new(),
+            shadows_bind_layout,
+            lights_bind_layout,
             pipelines: DashMap::new(),
             default_sampler,
         }
@@ -74,65 +144,14 @@ impl PipelineCache
 
     pub fn get_or_create_bind_layout(&self, material_class: MaterialClass) -> Ref<MaterialClass, BindGroupLayout>
     {
-        return self.bind_layouts.entry(material_class).or_insert_with(||
+        self.bind_layouts.entry(material_class).or_insert_with(||
         {
             self.renderer.device().create_bind_group_layout(&BindGroupLayoutDescriptor
             {
                 label: debug_label!(&format!("{:?} layout", material_class)),
-                entries: match material_class
-                {
-                    MaterialClass::DebugLines => const { &[] }, // todo: uniforms?
-                    MaterialClass::PbrOpaque => const
-                    {&[
-                        uniform::<SimpleOpaque>(0),
-                        sampler(1),
-                        tex2D(2, "albedo"),
-                    ]},
-
-                }
+                entries: material_class.bind_layout_entries(),
             })
-        }).downgrade();
-
-        const fn uniform<T>(binding: u32) -> BindGroupLayoutEntry
-        {
-            BindGroupLayoutEntry
-            {
-                binding,
-                visibility: ShaderStages::FRAGMENT,
-                ty: BindingType::Buffer
-                {
-                    ty: BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: BufferSize::new(size_of::<T>() as u64),
-                },
-                count: None,
-            }
-        }
-        const fn tex2D(binding: u32, _name: &'static str) -> BindGroupLayoutEntry
-        {
-            BindGroupLayoutEntry
-            {
-                binding,
-                visibility: ShaderStages::FRAGMENT,
-                ty: BindingType::Texture
-                {
-                    sample_type: TextureSampleType::Float { filterable: true },
-                    view_dimension: TextureViewDimension::D2,
-                    multisampled: false,
-                },
-                count: None,
-            }
-        }
-        const fn sampler(binding: u32) -> BindGroupLayoutEntry
-        {
-            BindGroupLayoutEntry
-            {
-                binding,
-                visibility: ShaderStages::FRAGMENT,
-                ty: BindingType::Sampler(SamplerBindingType::Filtering),
-                count: None,
-            }
-        }
+        }).downgrade()
     }
 
     pub fn try_apply(&self, render_pass: &mut RenderPass, pipeline_hash: PipelineKey) -> bool
@@ -157,22 +176,23 @@ impl PipelineCache
         {
             MaybePipeline::Pending
             {
+                pass,
                 vertex_shader,
                 vertex_layout,
                 material,
             } =>
             {
                 let AssetSnapshot::Available(vsh) = vertex_shader.data() else { return false; };
-                let mtl = material.as_ref().and_then(|(material_class, pixel_shader)|
+                let mtl = material.as_ref().and_then(|(material_class, render_state, pixel_shader)|
                 {
                     if let AssetSnapshot::Available(psh) = pixel_shader.data()
                     { 
-                        Some((*material_class, psh)) 
+                        Some((*material_class, *render_state, psh)) 
                     }
                     else { None }
                 });
                 let debug_mode = DebugMode::None; // TODO
-                let pipeline = self.create_pipeline(*vertex_layout, vsh, mtl, debug_mode);
+                let pipeline = self.create_pipeline(*pass, *vertex_layout, vsh, mtl, debug_mode);
                 render_pass.set_pipeline(&pipeline);
                 *maybe_pipeline.value_mut() = MaybePipeline::Created(pipeline);
             }
@@ -189,7 +209,7 @@ impl PipelineCache
         &self,
         pass: EngineRenderPass,
         vertex_layout: BitFlags<VertexCaps>,
-        material_class: Option<MaterialClass>,
+        material: Option<(MaterialClass, MaterialRenderState)>,
         debug_mode: DebugMode) -> PipelineKey
     {
         // if shaders change their hashes and in turn asset keys should change
@@ -199,7 +219,7 @@ impl PipelineCache
             let mut hasher = MetroHash64::default();
             pass.hash(&mut hasher);
             vertex_layout.hash(&mut hasher);
-            material_class.hash(&mut hasher);
+            material.hash(&mut hasher);
             debug_mode.hash(&mut hasher);
 
             PipelineKey(hasher.finish())
@@ -207,15 +227,16 @@ impl PipelineCache
 
         if let None = self.pipelines.get_mut(&pipeline_key)
         {
-            let vsh = shader_key::vertex(vertex_layout, pass);
-            let material = material_class.map(|mc|
+            let vsh = shader_key::vertex(vertex_layout, pass.vertex_pass());
+            let material = material.map(|(mc, render_state)|
             {
                 let key = shader_key::pixel(mc, pass);
-                (mc, self.assets.load(AssetKey::synthetic(AssetTypeId::Shader, key)))
+                (mc, render_state, self.assets.load(AssetKey::synthetic(AssetTypeId::Shader, key)))
             });
 
             let new_pipe = MaybePipeline::Pending
             {
+                pass,
                 vertex_layout,
                 vertex_shader: self.assets.load(AssetKey::synthetic(AssetTypeId::Shader, vsh)),
                 material,
@@ -229,31 +250,63 @@ impl PipelineCache
     #[must_use]
     fn create_pipeline(
         &self,
+        pass: EngineRenderPass,
         vertex_layout: BitFlags<VertexCaps>,
         vertex_shader: AssetView<Shader>,
-        material: Option<(MaterialClass, AssetView<Shader>)>,
+        material: Option<(MaterialClass, MaterialRenderState, AssetView<Shader>)>,
         debug_mode: DebugMode) -> RenderPipeline
     {
         // move up?
         puffin::profile_scope!("Create render pipeline");
 
-        let mtl_layout = material.as_ref().map(|(class, _)|
-            { self.get_or_create_bind_layout(*class) });
+        let mtl_layout = material.as_ref().map(|(class, _, pixel_shader)|
+        {
+            // shaders are verified when built, but may be stale
+            if let Err(err) = pixel_shader.reflection.validate_group(BindGroupSlot::Material as u32, class.bind_layout_entries())
+            {
+                log::error!("Pixel shader does not match the {class:?} material layout: {err}");
+            }
+            self.get_or_create_bind_layout(*class)
+        });
+
+        // only include the bind groups the shaders use
+        let uses_group = |slot: BindGroupSlot|
+        {
+            vertex_shader.reflection.uses_group(slot as u32) ||
+                material.as_ref().is_some_and(|(_, _, psh)| psh.reflection.uses_group(slot as u32))
+        };
 
         let mut bind_group_layouts: ArrayVec<_, 8> = ArrayVec::new();
         // Todo: define based on render pass
-        // TODO: use None for layouts not used
-        bind_group_layouts.push(Some(&self.uniforms.camera_bind_layout));
-        bind_group_layouts.push(Some(&self.uniforms.transform_bind_layout));
-        bind_group_layouts.push(Some(&self.uniforms.poses_bind_layout));
+        bind_group_layouts.push(uses_group(BindGroupSlot::Camera).then_some(&self.uniforms.camera_bind_layout));
+        bind_group_layouts.push(uses_group(BindGroupSlot::Transform).then_some(&self.uniforms.transform_bind_layout));
+        bind_group_layouts.push(uses_group(BindGroupSlot::Poses).then_some(&self.uniforms.poses_bind_layout));
 
         if let Some(layout) = &mtl_layout
         {
             bind_group_layouts.push(Some(layout.value()));
         }
+        for (slot, layout) in [(BindGroupSlot::Shadows, &self.shadows_bind_layout), (BindGroupSlot::Lights, &self.lights_bind_layout)]
+        {
+            if !uses_group(slot)
+            {
+                continue;
+            }
+            if let Some((_, _, psh)) = &material &&
+                let Some(entries) = slot.view_bind_layout_entries() &&
+                let Err(err) = psh.reflection.validate_group(slot as u32, entries)
+            {
+                log::error!("Pixel shader does not match the {slot:?} layout: {err}");
+            }
+            while bind_group_layouts.len() < slot as usize
+            {
+                bind_group_layouts.push(None);
+            }
+            bind_group_layouts.push(Some(layout));
+        }
 
         #[cfg(feature = "debug_gpu_labels")]
-        let layout_name = format!("({vertex_layout})+{:?} pipeline", material.as_ref().map(|m| m.0));
+        let layout_name = format!("({vertex_layout})+{:?} pipeline", material.as_ref().map(|m| (m.0, m.1)));
 
         // if there end up being a lot of pipelines created, it may be worth saving
         let pipeline_layout = self.renderer.device().create_pipeline_layout(&PipelineLayoutDescriptor
@@ -265,19 +318,22 @@ impl PipelineCache
         });
 
         // todo: if these update, this will invalidate the pipeline
-        let renderer_surface_format = self.renderer.surface_format();
-        let renderer_msaa_count = self.renderer.msaa_max_sample_count();
+        let renderer_msaa_count = self.renderer.msaa_sample_count();
+        let is_shadow_map = matches!(pass, EngineRenderPass::ShadowMap);
 
         let vbuffers = VertexLayoutBuilder::from(vertex_layout);
 
+        let render_state = material.as_ref().map(|m| m.1).unwrap_or_default();
+        let (blend, depth_write_enabled) = blend_and_depth_write(pass, render_state);
+
         // todo: only generate if mtl exists
         let fragment_targets = [Some(ColorTargetState
         {
-            format: renderer_surface_format,
-            blend: None, // todo: material settings
+            format: HDR_SCENE_FORMAT, // geometry is drawn into the scene target, before post processing
+            blend,
             write_mask: ColorWrites::ALL,
         })];
-        let fragment = material.as_ref().map(|(_, module)| FragmentState
+        let fragment = material.as_ref().map(|(_, _, module)| FragmentState
         {
             module: &module.module,
             entry_point: Some(ShaderStage::Pixel.entry_point()),
@@ -294,14 +350,14 @@ impl PipelineCache
                 module: &vertex_shader.module,
                 entry_point: Some(ShaderStage::Vertex.entry_point()),
                 compilation_options: PipelineCompilationOptions::default(),
-                buffers: &[Some(vbuffers.as_vertex_buffer_layout())],
+                buffers: &[Some(vbuffers.as_vertex_buffer_layout()), vbuffers.as_instance_buffer_layout()],
             },
             primitive: PrimitiveState
             {
                 topology: PrimitiveTopology::TriangleList,
                 strip_index_format: None,
                 front_face: FrontFace::Cw,
-                cull_mode: Some(Face::Back), // don't cull wireframe?
+                cull_mode: (!render_state.double_sided).then_some(Face::Back), // don't cull wireframe?
                 unclipped_depth: false,
                 polygon_mode: match debug_mode
                 {
@@ -313,16 +369,21 @@ impl PipelineCache
             // TODO: fetch from renderer surface config + material params
             depth_stencil: Some(DepthStencilState
             {
-                format: TextureFormat::Depth32Float,
-                depth_write_enabled: Some(true),
+                format: if is_shadow_map { SHADOW_MAP_FORMAT } else { SCENE_DEPTH_FORMAT },
+                depth_write_enabled: Some(depth_write_enabled),
                 depth_compare: Some(CompareFunction::Less),
                 stencil: StencilState::default(),
-                bias: DepthBiasState::default(),
+                // slope-scaled bias to avoid shadow acne
+                bias: match is_shadow_map
+                {
+                    true => DepthBiasState { constant: 2, slope_scale: 2.0, clamp: 0.0 },
+                    false => DepthBiasState::default(),
+                },
             }),
             // TODO
             multisample: MultisampleState
             {
-                count: renderer_msaa_count,
+                count: if is_shadow_map { 1 } else { renderer_msaa_count },
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
@@ -355,6 +416,21 @@ impl PipelineCache
         })
     }
 }
+
+// The color target blending and whether depth is written, for a material drawn in a pass
+#[must_use]
+fn blend_and_depth_write(pass: EngineRenderPass, render_state: MaterialRenderState) -> (Option<BlendState>, bool)
+{
+    let is_blended = render_state.alpha_mode == MaterialAlphaMode::Blend;
+    let blend = match pass
+    {
+        // transparent pixel shaders output premultiplied alpha
+        EngineRenderPass::Transparent => is_blended.then_some(render_state.blend_mode.blend_state()),
+        _ => is_blended.then_some(BlendState::ALPHA_BLENDING),
+    };
+    (blend, !is_blended) // blended materials are sorted back-to-front instead
+}
+
 impl DebugGui for PipelineCache
 {
     fn display_name(&self) -> &str { "Render pipelines" }
@@ -366,3 +442,54 @@ impl DebugGui for PipelineCache
         ui.collapsing(self.uniforms.display_name(), |cui| self.uniforms.debug_gui(cui));
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use wgpu::{BlendComponent, BlendFactor, BlendOperation};
+    use crate::assets::MaterialBlendMode;
+    use super::*;
+
+    // Evaluate one channel of a blend component (only the factors the blend modes use)
+    fn blend(component: BlendComponent, src: f32, src_alpha: f32, dst: f32) -> f32
+    {
+        let factor = |factor| match factor
+        {
+            BlendFactor::Zero => 0.0,
+            BlendFactor::One => 1.0,
+            BlendFactor::SrcAlpha => src_alpha,
+            BlendFactor::OneMinusSrcAlpha => 1.0 - src_alpha,
+            other => panic!("Unexpected blend factor {other:?}"),
+        };
+        assert_eq!(component.operation, BlendOperation::Add);
+        src * factor(component.src_factor) + dst * factor(component.dst_factor)
+    }
+
+    #[test]
+    fn material_blending()
+    {
+        let render_state = |alpha_mode, blend_mode| MaterialRenderState { alpha_mode, double_sided: false, blend_mode };
+
+        // opaque and masked materials overwrite what is behind them, and hide what is drawn after them
+        for alpha_mode in [MaterialAlphaMode::Opaque, MaterialAlphaMode::Mask]
+        {
+            let pass = MaterialClass::PbrOpaque.render_pass();
+            assert_eq!(blend_and_depth_write(pass, render_state(alpha_mode, MaterialBlendMode::default())), (None, true), "{alpha_mode:?}");
+        }
+
+        // half transparent red (premultiplied) over blue
+        let (src, src_alpha, dst) = ([0.5, 0.0, 0.0], 0.5, [0.0, 0.0, 1.0]);
+        let composite = |blend_mode: MaterialBlendMode| -> [f32; 4]
+        {
+            let pass = MaterialClass::PbrTransparent.render_pass();
+            let (state, depth_write) = blend_and_depth_write(pass, render_state(MaterialAlphaMode::Blend, blend_mode));
+            assert!(!depth_write, "{blend_mode:?} materials should not occlude what is drawn after them");
+            let state = state.expect("Blended materials should blend");
+            let [r, g, b] = std::array::from_fn(|i| blend(state.color, src[i], src_alpha, dst[i]));
+            [r, g, b, blend(state.alpha, src_alpha, src_alpha, 0.25)]
+        };
+        // coverage accumulates 'over' the destination's in both modes
+        assert_eq!(composite(MaterialBlendMode::Premultiplied), [0.5, 0.0, 0.5, 0.625]);
+        assert_eq!(composite(MaterialBlendMode::Additive), [0.5, 0.0, 1.0, 0.625]);
+    }
+}
@@ -1,11 +1,10 @@
-use std::cmp::Ordering;
-use std::collections::hash_map::Drain;
-use std::collections::HashMap;
-use triomphe::Arc;
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::ops::Range;
 use arrayvec::ArrayVec;
 use glam::Mat4;
-use asset_3l14::AssetView;
-use crate::assets::{Geometry, Material, Shader, Texture, MAX_MATERIAL_TEXTURE_BINDINGS};
+use asset_3l14::{AssetKey, AssetView};
+use crate::assets::{EngineRenderPass, Geometry, Material, Texture, MAX_MATERIAL_TEXTURE_BINDINGS};
 use crate::pipeline_cache::PipelineKey;
 
 pub struct Draw
@@ -13,104 +12,363 @@ pub struct Draw
     pub transform: Mat4,
     pub depth: f32,
     pub mesh_index: u32,
+    pub lod: u8,
     pub transform_uniform_id: u32,
     pub poses_uniform_id: Option<u32>, // separate draw call?
     pub pipeline_hash: PipelineKey,
+    pub instancing: Option<(InstanceKey, PipelineKey)>, // draws with the same key may be merged into one draw with the instanced pipeline
     pub geometry: AssetView<Geometry>,
     pub material: Option<(
         AssetView<Material>,
-        ArrayVec<AssetView<Texture>, MAX_MATERIAL_TEXTURE_BINDINGS>
+        ArrayVec<Option<AssetView<Texture>>, MAX_MATERIAL_TEXTURE_BINDINGS>
     )>, 
 }
 // vertex textures?
 
-pub enum SortDirection
+// Draws with equal keys differ only by their transform
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InstanceKey
 {
-    Unsorted,
-    FrontToBack,
-    BackToFront,
+    pub geometry: AssetKey,
+    pub mesh_index: u32,
+    pub lod: u8,
+    pub material: Option<AssetKey>,
 }
 
-#[derive(Default)]
-pub struct PipelineSorter
+// A run of draws that can be submitted together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DrawBatch
 {
-    // track/sort by average depth?
-    pipeline_draws: HashMap<PipelineKey, Vec<Draw>>,
+    pub draws: Range<usize>,
+    pub instanced: bool, // otherwise, a single draw
 }
-impl PipelineSorter
+
+// Reorder draws so that those with matching instance keys are adjacent, and return each run
+// Runs of a single draw, and draws without keys, are not instanced
+// Each run takes the place of its first draw and draws are otherwise kept in order, so depth sorted draws stay sorted by their first instance
+// (blended draws are not reordered against each other, but a run may be drawn over draws that were between its instances)
+#[must_use]
+pub fn batch_instances<T, K: Ord>(draws: &mut [T], instance_key: impl Fn(&T) -> Option<K>) -> Vec<DrawBatch>
 {
-    pub fn push(&mut self, draw: Draw)
+    // each draw is ordered by the position of the first draw with its key
+    let mut firsts = BTreeMap::new();
+    let mut order: Vec<(usize, usize)> = draws.iter().enumerate().map(|(i, draw)| match instance_key(draw)
+    {
+        Some(key) => (*firsts.entry(key).or_insert(i), i),
+        None => (i, i),
+    }).collect();
+    order.sort_unstable();
+
+    // apply the permutation in place, draws before i are already placed
+    for i in 0..order.len()
+    {
+        let mut source = order[i].1;
+        while source < i
+        {
+            source = order[source].1;
+        }
+        draws.swap(i, source);
+    }
+
+    let mut batches = Vec::new();
+    let mut start = 0;
+    while start < draws.len()
     {
-        self.pipeline_draws.entry(draw.pipeline_hash).or_default()
-            .push(draw);
+        let key = instance_key(&draws[start]);
+        let mut end = start + 1;
+        if key.is_some()
+        {
+            while end < draws.len() && instance_key(&draws[end]) == key
+            {
+                end += 1;
+            }
+        }
+
+        batches.push(DrawBatch { draws: start..end, instanced: end - start > 1 });
+        start = end;
     }
+    batches
+}
 
-    // process all the entries in this sorter, if returned iter is fully consumed, the sorter should be empty after
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortDirection
+{
+    Unsorted, // by state only
+    FrontToBack, // by state, then depth within matching state
+    BackToFront, // by depth, then state within matching depth
+}
+impl SortDirection
+{
     #[inline] #[must_use]
-    pub fn sort(&mut self) -> SorterIter
+    pub fn for_pass(pass: EngineRenderPass) -> Self
     {
-        SorterIter
+        match pass
         {
-            iter: self.pipeline_draws.drain(),
-            direction: SortDirection::Unsorted,
+            EngineRenderPass::Opaque => SortDirection::FrontToBack,
+            EngineRenderPass::Transparent => SortDirection::BackToFront,
+            _ => SortDirection::Unsorted,
         }
     }
+}
 
-    #[inline] #[must_use] pub fn is_empty(&self) -> bool { self.pipeline_draws.is_empty() }
+// Orders draws for submission, packed from most to least significant bits:
+// state first: pass (4) | pipeline (16) | material (16) | geometry (12) | depth (16)
+// depth first: pass (4) | depth (16, inverted) | pipeline (16) | material (16) | geometry (12)
+// pipelines, materials, and geometry are hashed down to their bits, so collisions only cost extra state changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SortKey(pub u64);
+impl SortKey
+{
+    #[must_use]
+    pub fn new(pass: EngineRenderPass, direction: SortDirection, pipeline: u64, material: u64, geometry: u64, depth: f32) -> Self
+    {
+        let state = (fold_bits(pipeline, 16) << 28) | (fold_bits(material, 16) << 12) | fold_bits(geometry, 12);
+        let key = match direction
+        {
+            SortDirection::Unsorted => state << 16,
+            SortDirection::FrontToBack => (state << 16) | Self::quantize_depth(depth),
+            SortDirection::BackToFront => ((0xffff - Self::quantize_depth(depth)) << 44) | state,
+        };
+        Self(((pass as u64 & 0xf) << 60) | key)
+    }
 
-    #[inline] pub fn clear(&mut self)
+    // The top bits of a positive float sort the same as the float, with precision relative to its magnitude
+    #[inline] #[must_use]
+    pub fn quantize_depth(depth: f32) -> u64
     {
-        self.pipeline_draws.clear();
+        (depth.max(0.0).to_bits() >> 16) as u64
     }
 }
 
-#[must_use]
-fn sort_front_to_back(a: &Draw, b: &Draw) -> Ordering
+// xor-fold a hash down to its lowest bits
+#[inline] #[must_use]
+fn fold_bits(hash: u64, bits: u32) -> u64
 {
-    match a.depth - b.depth
+    let mut folded = 0;
+    let mut remaining = hash;
+    while remaining != 0
     {
-        t if t < 0.0 => Ordering::Less,
-        0.0 => Ordering::Equal,
-        t if t > 0.0 => Ordering::Greater,
-
-        _ => unreachable!(),
+        folded ^= remaining;
+        remaining >>= bits;
     }
+    folded & ((1 << bits) - 1)
 }
-#[must_use]
-fn sort_back_to_front(a: &Draw, b: &Draw) -> Ordering
+
+// Stable LSD radix sort of (key, value) pairs, a byte at a time. Bytes that match across all keys are skipped
+pub fn radix_sort(items: &mut Vec<(u64, u32)>, scratch: &mut Vec<(u64, u32)>)
 {
-    match a.depth - b.depth
+    let mut counts = [[0usize; 256]; 8];
+    for (key, _) in items.iter()
     {
-        t if t < 0.0 => Ordering::Greater,
-        0.0 => Ordering::Equal,
-        t if t > 0.0 => Ordering::Less,
+        for (byte, count) in counts.iter_mut().enumerate()
+        {
+            count[((key >> (byte * 8)) & 0xff) as usize] += 1;
+        }
+    }
 
-        _ => unreachable!(),
+    scratch.clear();
+    scratch.resize(items.len(), (0, 0));
+    let mut in_scratch = false;
+    for (byte, count) in counts.iter_mut().enumerate()
+    {
+        if count.contains(&items.len())
+        {
+            continue;
+        }
+
+        let mut offset = 0;
+        for c in count.iter_mut()
+        {
+            (*c, offset) = (offset, offset + *c);
+        }
+
+        let (src, dst) = match in_scratch
+        {
+            false => (&*items, &mut *scratch),
+            true => (&*scratch, &mut *items),
+        };
+        for item in src.iter()
+        {
+            let bucket = &mut count[((item.0 >> (byte * 8)) & 0xff) as usize];
+            dst[*bucket] = *item;
+            *bucket += 1;
+        }
+        in_scratch = !in_scratch;
+    }
+
+    if in_scratch
+    {
+        std::mem::swap(items, scratch);
     }
 }
 
-pub struct SorterIter<'s>
+pub struct PipelineSorter
 {
-    iter: Drain<'s, PipelineKey, Vec<Draw>>,
+    pass: EngineRenderPass,
     direction: SortDirection,
+    draws: Vec<Draw>,
+    keys: Vec<(u64, u32)>, // (sort key, index into draws)
+    scratch: Vec<(u64, u32)>,
+}
+impl PipelineSorter
+{
+    #[must_use]
+    pub fn new(pass: EngineRenderPass) -> Self
+    {
+        Self
+        {
+            pass,
+            direction: SortDirection::for_pass(pass),
+            draws: Vec::new(),
+            keys: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, draw: Draw)
+    {
+        // assets are only compared within a frame, so their addresses identify them
+        let material = draw.material.as_ref().map_or(0, |m| std::ptr::from_ref::<Material>(&m.0) as u64 >> 4);
+        let geometry = std::ptr::from_ref::<Geometry>(&draw.geometry) as u64 >> 4;
+        let key = SortKey::new(self.pass, self.direction, draw.pipeline_hash.0, material, geometry, draw.depth);
+
+        self.keys.push((key.0, self.draws.len() as u32));
+        self.draws.push(draw);
+    }
+
+    // Sort and take all the draws in this sorter, leaving it empty
+    #[must_use]
+    pub fn sort(&mut self) -> SorterIter
+    {
+        puffin::profile_function!();
+
+        radix_sort(&mut self.keys, &mut self.scratch);
+
+        let mut unsorted: Vec<_> = self.draws.drain(..).map(Some).collect();
+        let sorted: Vec<_> = self.keys.drain(..).map(|(_, i)| unsorted[i as usize].take().unwrap()).collect();
+        SorterIter { draws: sorted.into_iter().peekable() }
+    }
+
+    #[inline] #[must_use] pub fn pass(&self) -> EngineRenderPass { self.pass }
+    #[inline] #[must_use] pub fn is_empty(&self) -> bool { self.draws.is_empty() }
+
+    #[inline] pub fn clear(&mut self)
+    {
+        self.draws.clear();
+        self.keys.clear();
+    }
 }
-impl<'s> Iterator for SorterIter<'s>
+
+// Sorted draws, grouped into consecutive runs that share a pipeline
+pub struct SorterIter
+{
+    draws: Peekable<std::vec::IntoIter<Draw>>,
+}
+impl Iterator for SorterIter
 {
     type Item = (PipelineKey, Vec<Draw>);
 
     fn next(&mut self) -> Option<Self::Item>
     {
-        self.iter.next().map(|(ph, mut draws)|
+        let first = self.draws.next()?;
+        let pipeline = first.pipeline_hash;
+        let mut run = vec![first];
+        while let Some(draw) = self.draws.next_if(|d| d.pipeline_hash == pipeline)
         {
-            match self.direction
-            {
-                SortDirection::Unsorted => {},
-                // stable sort?
-                SortDirection::FrontToBack => draws.sort_unstable_by(sort_front_to_back),
-                SortDirection::BackToFront => draws.sort_unstable_by(sort_back_to_front),
-            }
+            run.push(draw);
+        }
+        Some((pipeline, run))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn batch_instances_by_key()
+    {
+        // (instance key, draw id)
+        let mut draws = [(Some(2), 0), (None, 1), (Some(1), 2), (Some(2), 3), (None, 4), (Some(3), 5), (Some(2), 6)];
+        let batches = batch_instances(&mut draws, |d| d.0);
+
+        assert_eq!(draws.map(|d| d.1), [0, 3, 6, 1, 2, 4, 5]);
+        assert_eq!(batches,
+        [
+            DrawBatch { draws: 0..3, instanced: true },
+            DrawBatch { draws: 3..4, instanced: false },
+            DrawBatch { draws: 4..5, instanced: false },
+            DrawBatch { draws: 5..6, instanced: false },
+            DrawBatch { draws: 6..7, instanced: false },
+        ]);
+        assert_eq!(batches.iter().map(|b| b.draws.len()).sum::<usize>(), draws.len());
+
+        let mut none: [(Option<u32>, u32); 0] = [];
+        assert!(batch_instances(&mut none, |d| d.0).is_empty());
+    }
+
+    #[test]
+    fn batch_instances_keeps_depth_order()
+    {
+        // (instance key, depth), already sorted near to far
+        let mut draws = [(Some(9), 1.0), (Some(3), 2.0), (None, 3.0), (Some(3), 4.0), (Some(9), 5.0), (Some(1), 6.0), (Some(3), 7.0)];
+        let batches = batch_instances(&mut draws, |d| d.0);
 
-            (ph, draws)
-        })
+        // each run is at its nearest draw, and its draws are still near to far
+        assert_eq!(draws.map(|d| d.1), [1.0, 5.0, 2.0, 4.0, 7.0, 3.0, 6.0]);
+        let nearest: Vec<_> = batches.iter().map(|b| draws[b.draws.start].1).collect();
+        assert!(nearest.is_sorted());
+        assert!(batches.iter().all(|b| draws[b.draws.clone()].is_sorted_by(|a, b| a.1 <= b.1)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn radix_sort_matches_stable_sort()
+    {
+        let mut rng = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = ||
+        {
+            rng ^= rng << 13;
+            rng ^= rng >> 7;
+            rng ^= rng << 17;
+            rng
+        };
+
+        for len in [0, 1, 2, 100, 1000]
+        {
+            // few distinct keys, to exercise stability, and only some bytes varying, to exercise skipping
+            let mut items: Vec<_> = (0..len).map(|i| ((next() % 37) << 40 | (next() % 3), i)).collect();
+            let mut expected = items.clone();
+            expected.sort_by_key(|item| item.0);
+
+            radix_sort(&mut items, &mut Vec::new());
+            assert_eq!(items, expected);
+        }
+    }
+
+    #[test]
+    fn sort_keys()
+    {
+        let key = |direction, pipeline, depth| SortKey::new(EngineRenderPass::Opaque, direction, pipeline, 5, 6, depth);
+
+        // opaque draws group by state, then near to far
+        assert!(key(SortDirection::FrontToBack, 1, 3.0) < key(SortDirection::FrontToBack, 1, 40.0));
+        assert!(key(SortDirection::FrontToBack, 1, 40.0) < key(SortDirection::FrontToBack, 2, 3.0));
+
+        // blended draws are far to near, regardless of state
+        assert!(key(SortDirection::BackToFront, 2, 40.0) < key(SortDirection::BackToFront, 1, 3.0));
+        assert!(key(SortDirection::BackToFront, 1, 3.0) < key(SortDirection::BackToFront, 2, 3.0));
+
+        // depth is ignored when unsorted, and negative depths are clamped
+        assert_eq!(key(SortDirection::Unsorted, 1, 3.0), key(SortDirection::Unsorted, 1, 40.0));
+        assert_eq!(key(SortDirection::FrontToBack, 1, -2.0), key(SortDirection::FrontToBack, 1, 0.0));
+
+        // depth quantization preserves order
+        let depths = [0.0, 0.001, 0.5, 1.0, 1.01, 10.0, 1000.0, 1.0e6];
+        assert!(depths.windows(2).all(|d| SortKey::quantize_depth(d[0]) < SortKey::quantize_depth(d[1])));
+
+        // passes are always the most significant
+        let transparent = SortKey::new(EngineRenderPass::Transparent, SortDirection::BackToFront, 0, 0, 0, 1.0e6);
+        assert!(key(SortDirection::FrontToBack, u64::MAX, f32::MAX) < transparent);
+    }
+}
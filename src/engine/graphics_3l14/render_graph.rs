@@ -0,0 +1,938 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::error::Error;
+use std::ops::Range;
+use debug_3l14::debug_gui::DebugGui;
+use egui::{Color32, Ui};
+use glam::UVec2;
+use wgpu::{CommandEncoder, Device, Extent3d, Texture, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor};
+use crate::{debug_label, RenderFrame, Renderer, MAX_CONSECUTIVE_FRAMES};
+
+// Pooled transient textures are freed after going unused for this many graph executions
+const TRANSIENT_EVICTION_AGE: u64 = MAX_CONSECUTIVE_FRAMES as u64 + 1;
+
+// A texture in a render graph, only valid for the graph that created it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GraphTexture(u16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GraphTextureSize
+{
+    Display,
+    DisplayDivisor(u32), // at least 1x1
+    Fixed(UVec2),
+}
+impl GraphTextureSize
+{
+    #[inline] #[must_use]
+    pub fn resolve(self, display_size: UVec2) -> UVec2
+    {
+        match self
+        {
+            GraphTextureSize::Display => display_size,
+            GraphTextureSize::DisplayDivisor(divisor) => (display_size / divisor.max(1)).max(UVec2::ONE),
+            GraphTextureSize::Fixed(size) => size,
+        }
+    }
+}
+
+// A transient texture, its usages are the union of how each node accesses it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GraphTextureDesc
+{
+    pub label: &'static str,
+    pub size: GraphTextureSize,
+    pub format: TextureFormat,
+    pub mip_level_count: u32,
+    pub sample_count: u32,
+}
+impl GraphTextureDesc
+{
+    #[inline] #[must_use]
+    pub const fn new(label: &'static str, size: GraphTextureSize, format: TextureFormat) -> Self
+    {
+        Self { label, size, format, mip_level_count: 1, sample_count: 1 }
+    }
+}
+
+// How a node uses a texture. A texture's writers run first, then its modifiers, then its readers,
+// writers and modifiers run in the order they were added
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Access
+{
+    Read, // e.g. sampled, copied from, or a (read-only) depth attachment
+    Write, // fully overwritten (e.g. cleared), the previous contents are not needed
+    Modify, // loaded and stored
+}
+impl Access
+{
+    #[inline] #[must_use]
+    pub const fn reads(self) -> bool { !matches!(self, Access::Write) }
+
+    #[inline] #[must_use]
+    pub const fn writes(self) -> bool { !matches!(self, Access::Read) }
+
+    #[inline] #[must_use]
+    const fn merge(self, other: Access) -> Access
+    {
+        match (self, other)
+        {
+            (Access::Read, Access::Read) => Access::Read,
+            (Access::Write, Access::Write) => Access::Write,
+            _ => Access::Modify,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenderGraphError
+{
+    Cycle(Vec<&'static str>), // the nodes that could not be ordered
+    UninitializedRead { node: &'static str, texture: &'static str }, // a transient was read before anything wrote it
+    MissingUsage { texture: &'static str, usage: TextureUsages }, // imported textures must support every declared usage
+}
+impl std::fmt::Display for RenderGraphError
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { std::fmt::Debug::fmt(self, f) }
+}
+impl Error for RenderGraphError { }
+
+enum TextureSource
+{
+    Transient(GraphTextureDesc),
+    Imported(Texture), // contents persist across frames, never aliased
+}
+
+struct GraphTextureEntry
+{
+    label: &'static str,
+    source: TextureSource,
+    usage: TextureUsages,
+    is_output: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct NodeAccess
+{
+    texture: GraphTexture,
+    access: Access,
+}
+
+type NodeFn<'f, S> = Box<dyn FnOnce(&mut NodeContext, &mut S) + 'f>;
+
+struct GraphNode<'f, S>
+{
+    name: &'static str,
+    accesses: Vec<NodeAccess>,
+    has_side_effects: bool,
+    run: NodeFn<'f, S>,
+}
+impl<S> GraphNode<'_, S>
+{
+    #[inline] #[must_use]
+    fn access(&self, texture: usize) -> Option<Access>
+    {
+        self.accesses.iter().find(|a| a.texture.0 as usize == texture).map(|a| a.access)
+    }
+}
+
+// The passes of a frame, as nodes that declare the textures they read and write
+// Nodes are ordered by those accesses (not the order they were added), nodes that don't contribute to an output are culled,
+// and transient textures are allocated from a pool, shared by transients whose lifetimes don't overlap
+// Node functions are given the graph's state (e.g. the View being rendered) when run
+pub struct RenderGraph<'f, S = ()>
+{
+    display_size: UVec2,
+    textures: Vec<GraphTextureEntry>,
+    nodes: Vec<GraphNode<'f, S>>,
+}
+impl<'f, S> RenderGraph<'f, S>
+{
+    #[must_use]
+    pub fn new(display_size: UVec2) -> Self
+    {
+        Self
+        {
+            display_size,
+            textures: Vec::new(),
+            nodes: Vec::new(),
+        }
+    }
+
+    #[inline] #[must_use]
+    pub fn display_size(&self) -> UVec2 { self.display_size }
+
+    #[must_use]
+    pub fn create_texture(&mut self, desc: GraphTextureDesc) -> GraphTexture
+    {
+        self.add_texture(desc.label, TextureSource::Transient(desc))
+    }
+
+    #[must_use]
+    pub fn import_texture(&mut self, label: &'static str, texture: &Texture) -> GraphTexture
+    {
+        self.add_texture(label, TextureSource::Imported(texture.clone()))
+    }
+
+    // Import the frame's back buffer, as an output
+    #[must_use]
+    pub fn import_back_buffer(&mut self, frame: &RenderFrame) -> GraphTexture
+    {
+        let back_buffer = self.import_texture("Back buffer", frame.back_buffer());
+        self.mark_output(back_buffer);
+        back_buffer
+    }
+
+    // Keep a texture's final contents, the nodes writing it are never culled
+    pub fn mark_output(&mut self, texture: GraphTexture)
+    {
+        self.textures[texture.0 as usize].is_output = true;
+    }
+
+    #[inline] #[must_use]
+    pub fn texture_size(&self, texture: GraphTexture) -> UVec2
+    {
+        match &self.textures[texture.0 as usize].source
+        {
+            TextureSource::Transient(desc) => desc.size.resolve(self.display_size),
+            TextureSource::Imported(texture) => UVec2::new(texture.width(), texture.height()),
+        }
+    }
+
+    #[inline] #[must_use]
+    pub fn texture_format(&self, texture: GraphTexture) -> TextureFormat
+    {
+        match &self.textures[texture.0 as usize].source
+        {
+            TextureSource::Transient(desc) => desc.format,
+            TextureSource::Imported(texture) => texture.format(),
+        }
+    }
+
+    // Declare a node's accesses, then give it a function to run with run()
+    #[must_use]
+    pub fn add_node<'g>(&'g mut self, name: &'static str) -> NodeBuilder<'g, 'f, S>
+    {
+        NodeBuilder
+        {
+            graph: self,
+            name,
+            accesses: Vec::new(),
+            has_side_effects: false,
+        }
+    }
+
+    // Order the nodes, cull the unused ones, and assign the transients to allocations
+    pub fn compile(&self) -> Result<CompiledGraph, RenderGraphError>
+    {
+        puffin::profile_function!();
+
+        for entry in &self.textures
+        {
+            if let TextureSource::Imported(texture) = &entry.source &&
+                !texture.usage().contains(entry.usage)
+            {
+                return Err(RenderGraphError::MissingUsage { texture: entry.label, usage: entry.usage.difference(texture.usage()) });
+            }
+        }
+
+        let node_count = self.nodes.len();
+        let mut edges = vec![Vec::new(); node_count];
+        let mut in_degree = vec![0u32; node_count];
+        for texture in 0..self.textures.len()
+        {
+            let accessed_by = |access: Access| self.nodes.iter().enumerate()
+                .filter(move |(_, node)| node.access(texture) == Some(access))
+                .map(|(i, _)| i);
+
+            // each writer and modifier in turn, then all the readers
+            let mut stages: Vec<Vec<usize>> = accessed_by(Access::Write).chain(accessed_by(Access::Modify)).map(|i| vec![i]).collect();
+            let readers: Vec<usize> = accessed_by(Access::Read).collect();
+            if !readers.is_empty()
+            {
+                stages.push(readers);
+            }
+
+            for pair in stages.windows(2)
+            {
+                for &from in &pair[0]
+                {
+                    for &to in &pair[1]
+                    {
+                        edges[from].push(to);
+                        in_degree[to] += 1;
+                    }
+                }
+            }
+        }
+
+        // topological sort, preferring the order the nodes were added
+        let mut ready: BinaryHeap<Reverse<usize>> = (0..node_count).filter(|&i| in_degree[i] == 0).map(Reverse).collect();
+        let mut sorted = Vec::with_capacity(node_count);
+        while let Some(Reverse(node)) = ready.pop()
+        {
+            sorted.push(node);
+            for &next in &edges[node]
+            {
+                in_degree[next] -= 1;
+                if in_degree[next] == 0
+                {
+                    ready.push(Reverse(next));
+                }
+            }
+        }
+        if sorted.len() < node_count
+        {
+            return Err(RenderGraphError::Cycle((0..node_count).filter(|&i| in_degree[i] > 0).map(|i| self.nodes[i].name).collect()));
+        }
+
+        // every node runs after the writers of what it reads, so one pass in reverse finds all the contributing nodes
+        let mut is_needed: Vec<bool> = self.textures.iter().map(|t| t.is_output).collect();
+        let mut is_live = vec![false; node_count];
+        for &index in sorted.iter().rev()
+        {
+            let node = &self.nodes[index];
+            is_live[index] = node.has_side_effects ||
+                node.accesses.iter().any(|a| a.access.writes() && is_needed[a.texture.0 as usize]);
+            if is_live[index]
+            {
+                for access in node.accesses.iter().filter(|a| a.access.reads())
+                {
+                    is_needed[access.texture.0 as usize] = true;
+                }
+            }
+        }
+        let (order, culled): (Vec<usize>, Vec<usize>) = sorted.into_iter().partition(|&i| is_live[i]);
+
+        let mut is_written = vec![false; self.textures.len()];
+        let mut lifetimes: Vec<Option<Range<usize>>> = vec![None; self.textures.len()];
+        for (step, &index) in order.iter().enumerate()
+        {
+            let node = &self.nodes[index];
+            for access in &node.accesses
+            {
+                let texture = access.texture.0 as usize;
+                let entry = &self.textures[texture];
+                if access.access.reads() && !is_written[texture] && matches!(entry.source, TextureSource::Transient(_))
+                {
+                    return Err(RenderGraphError::UninitializedRead { node: node.name, texture: entry.label });
+                }
+                is_written[texture] |= access.access.writes();
+                lifetimes[texture] = Some(lifetimes[texture].take().map_or(step, |l| l.start)..(step + 1));
+            }
+        }
+        for (lifetime, entry) in lifetimes.iter_mut().zip(&self.textures)
+        {
+            if let Some(lifetime) = lifetime &&
+                entry.is_output
+            {
+                lifetime.end = order.len();
+            }
+        }
+
+        // transients are assigned (in the order they're first used) to the first compatible allocation that's free
+        let mut transients: Vec<(usize, TransientKey)> = self.textures.iter().enumerate()
+            .filter(|(t, _)| lifetimes[*t].is_some())
+            .filter_map(|(t, entry)| self.transient_key(entry).map(|key| (t, key)))
+            .collect();
+        transients.sort_by_key(|(t, _)| lifetimes[*t].as_ref().map(|l| l.start));
+
+        let mut texture_allocations = vec![None; self.textures.len()];
+        let mut allocations: Vec<(TransientKey, usize)> = Vec::new(); // with the end of the last lifetime assigned to it
+        for (texture, key) in transients
+        {
+            let lifetime = lifetimes[texture].clone().unwrap();
+            let allocation = match allocations.iter().position(|(k, end)| *k == key && *end <= lifetime.start)
+            {
+                Some(allocation) => allocation,
+                None =>
+                {
+                    allocations.push((key, 0));
+                    allocations.len() - 1
+                }
+            };
+            allocations[allocation].1 = lifetime.end;
+            texture_allocations[texture] = Some(allocation);
+        }
+
+        Ok(CompiledGraph
+        {
+            order,
+            culled,
+            lifetimes,
+            texture_allocations,
+            allocations: allocations.into_iter().map(|(key, _)| key).collect(),
+        })
+    }
+
+    // Compile the graph and record its nodes, transient textures are returned to the renderer's pool afterward
+    pub fn execute(self, renderer: &Renderer, encoder: &mut CommandEncoder, state: &mut S) -> Result<(), RenderGraphError>
+    {
+        puffin::profile_function!();
+
+        let compiled = self.compile()?;
+        let mut report = GraphReport::new(&self, &compiled);
+
+        let allocated: Vec<Texture> =
+        {
+            let mut cache = renderer.render_graph_cache().lock();
+            cache.generation += 1;
+            compiled.allocations.iter().enumerate().map(|(allocation, key)|
+            {
+                let label = self.textures.iter().zip(&compiled.texture_allocations)
+                    .find_map(|(entry, a)| (*a == Some(allocation)).then_some(entry.label))
+                    .unwrap_or("Render graph transient");
+                cache.acquire(renderer.device(), *key, label, &mut report.created_count)
+            }).collect()
+        };
+
+        let textures: Vec<Option<Texture>> = self.textures.iter().zip(&compiled.texture_allocations).map(|(entry, allocation)| match &entry.source
+        {
+            TextureSource::Transient(_) => allocation.map(|a| allocated[a].clone()),
+            TextureSource::Imported(texture) => Some(texture.clone()),
+        }).collect();
+        let views: Vec<Option<TextureView>> = textures.iter()
+            .map(|t| t.as_ref().map(|t| t.create_view(&TextureViewDescriptor::default())))
+            .collect();
+
+        let mut nodes: Vec<Option<GraphNode<S>>> = self.nodes.into_iter().map(Some).collect();
+        for &index in &compiled.order
+        {
+            let node = nodes[index].take().expect("Nodes are only run once");
+            puffin::profile_scope!("Render graph node", node.name);
+
+            encoder.push_debug_group(node.name);
+            {
+                let mut context = NodeContext
+                {
+                    encoder: &mut *encoder,
+                    textures: NodeTextures
+                    {
+                        textures: &textures,
+                        views: &views,
+                        accesses: &node.accesses,
+                    },
+                };
+                (node.run)(&mut context, state);
+            }
+            encoder.pop_debug_group();
+        }
+
+        let mut cache = renderer.render_graph_cache().lock();
+        for (key, texture) in compiled.allocations.into_iter().zip(allocated)
+        {
+            cache.release(key, texture);
+        }
+        cache.evict_unused();
+        cache.last_graph = Some(report);
+        Ok(())
+    }
+
+    #[must_use]
+    fn add_texture(&mut self, label: &'static str, source: TextureSource) -> GraphTexture
+    {
+        let index = u16::try_from(self.textures.len()).expect("Too many render graph textures");
+        self.textures.push(GraphTextureEntry
+        {
+            label,
+            source,
+            usage: TextureUsages::empty(),
+            is_output: false,
+        });
+        GraphTexture(index)
+    }
+
+    #[inline] #[must_use]
+    fn transient_key(&self, entry: &GraphTextureEntry) -> Option<TransientKey>
+    {
+        match &entry.source
+        {
+            TextureSource::Transient(desc) => Some(TransientKey
+            {
+                size: desc.size.resolve(self.display_size),
+                format: desc.format,
+                mip_level_count: desc.mip_level_count.max(1),
+                sample_count: desc.sample_count.max(1),
+                usage: entry.usage,
+            }),
+            TextureSource::Imported(_) => None,
+        }
+    }
+}
+
+pub struct NodeBuilder<'g, 'f, S>
+{
+    graph: &'g mut RenderGraph<'f, S>,
+    name: &'static str,
+    accesses: Vec<NodeAccess>,
+    has_side_effects: bool,
+}
+impl<'f, S> NodeBuilder<'_, 'f, S>
+{
+    #[inline] #[must_use]
+    pub fn read(self, texture: GraphTexture, usage: TextureUsages) -> Self { self.access(texture, Access::Read, usage) }
+
+    #[inline] #[must_use]
+    pub fn write(self, texture: GraphTexture, usage: TextureUsages) -> Self { self.access(texture, Access::Write, usage) }
+
+    #[inline] #[must_use]
+    pub fn modify(self, texture: GraphTexture, usage: TextureUsages) -> Self { self.access(texture, Access::Modify, usage) }
+
+    // Accessing a texture more than once merges the accesses
+    #[must_use]
+    pub fn access(mut self, texture: GraphTexture, access: Access, usage: TextureUsages) -> Self
+    {
+        self.graph.textures[texture.0 as usize].usage |= usage;
+        match self.accesses.iter_mut().find(|a| a.texture == texture)
+        {
+            Some(existing) => existing.access = existing.access.merge(access),
+            None => self.accesses.push(NodeAccess { texture, access }),
+        }
+        self
+    }
+
+    // The node has effects outside of the graph (e.g. writing buffers that are read back), and is never culled
+    #[inline] #[must_use]
+    pub fn side_effects(mut self) -> Self
+    {
+        self.has_side_effects = true;
+        self
+    }
+
+    pub fn run(self, run: impl FnOnce(&mut NodeContext, &mut S) + 'f)
+    {
+        self.graph.nodes.push(GraphNode
+        {
+            name: self.name,
+            accesses: self.accesses,
+            has_side_effects: self.has_side_effects,
+            run: Box::new(run),
+        });
+    }
+}
+
+pub struct NodeContext<'e>
+{
+    pub encoder: &'e mut CommandEncoder,
+    pub textures: NodeTextures<'e>,
+}
+
+// The graph's textures, as seen by a node
+pub struct NodeTextures<'e>
+{
+    textures: &'e [Option<Texture>],
+    views: &'e [Option<TextureView>], // the default view of each texture
+    accesses: &'e [NodeAccess],
+}
+impl<'e> NodeTextures<'e>
+{
+    #[inline] #[must_use]
+    pub fn texture(&self, texture: GraphTexture) -> &'e Texture
+    {
+        debug_assert!(self.accesses.iter().any(|a| a.texture == texture), "Node did not declare {texture:?}");
+        self.textures[texture.0 as usize].as_ref().expect("Used textures are allocated")
+    }
+
+    #[inline] #[must_use]
+    pub fn view(&self, texture: GraphTexture) -> &'e TextureView
+    {
+        debug_assert!(self.accesses.iter().any(|a| a.texture == texture), "Node did not declare {texture:?}");
+        self.views[texture.0 as usize].as_ref().expect("Used textures are allocated")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompiledGraph
+{
+    pub order: Vec<usize>, // the nodes to run, in order
+    pub culled: Vec<usize>,
+    pub lifetimes: Vec<Option<Range<usize>>>, // the steps (in order) each texture is used in, None if unused
+    pub texture_allocations: Vec<Option<usize>>, // the allocation each used transient uses
+    allocations: Vec<TransientKey>,
+}
+impl CompiledGraph
+{
+    #[inline] #[must_use]
+    pub fn allocation_count(&self) -> usize { self.allocations.len() }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct TransientKey
+{
+    size: UVec2,
+    format: TextureFormat,
+    mip_level_count: u32,
+    sample_count: u32,
+    usage: TextureUsages,
+}
+impl TransientKey
+{
+    #[must_use]
+    fn byte_size(&self) -> u64
+    {
+        let texel_size = self.format.block_copy_size(None).unwrap_or(4) as u64;
+        let texel_count: u64 = (0..self.mip_level_count)
+            .map(|mip| (self.size.x >> mip).max(1) as u64 * (self.size.y >> mip).max(1) as u64)
+            .sum();
+        texel_count * texel_size * self.sample_count as u64
+    }
+}
+
+// Kept by the renderer, render graphs' transient textures are pooled across frames
+#[derive(Default)]
+pub struct RenderGraphCache
+{
+    generation: u64, // incremented every graph execution
+    pool: HashMap<TransientKey, Vec<(Texture, u64)>>, // free textures, and the generation they were last used in
+    last_graph: Option<GraphReport>,
+}
+impl RenderGraphCache
+{
+    #[must_use]
+    fn acquire(&mut self, device: &Device, key: TransientKey, label: &'static str, created_count: &mut usize) -> Texture
+    {
+        if let Some((texture, _)) = self.pool.get_mut(&key).and_then(Vec::pop)
+        {
+            return texture;
+        }
+
+        *created_count += 1;
+        device.create_texture(&TextureDescriptor
+        {
+            label: debug_label!(label),
+            size: Extent3d { width: key.size.x, height: key.size.y, depth_or_array_layers: 1 },
+            mip_level_count: key.mip_level_count,
+            sample_count: key.sample_count,
+            dimension: TextureDimension::D2,
+            format: key.format,
+            usage: key.usage,
+            view_formats: &[],
+        })
+    }
+
+    fn release(&mut self, key: TransientKey, texture: Texture)
+    {
+        self.pool.entry(key).or_default().push((texture, self.generation));
+    }
+
+    // free textures that no longer fit any graph (e.g. after resizing)
+    fn evict_unused(&mut self)
+    {
+        let generation = self.generation;
+        self.pool.retain(|_, textures|
+        {
+            textures.retain(|(_, last_used)| last_used + TRANSIENT_EVICTION_AGE > generation);
+            !textures.is_empty()
+        });
+    }
+
+    #[inline] #[must_use]
+    fn pooled_count(&self) -> usize { self.pool.values().map(Vec::len).sum() }
+}
+impl DebugGui for RenderGraphCache
+{
+    fn display_name(&self) -> &str { "Render graph" }
+
+    fn debug_gui(&self, ui: &mut Ui)
+    {
+        let Some(graph) = &self.last_graph else
+        {
+            ui.label("No graph has been executed");
+            return;
+        };
+
+        const MIB: f64 = 1024.0 * 1024.0;
+        let transients = graph.textures.iter().filter(|t| t.allocation.is_some());
+        let unaliased_size: u64 = transients.clone().map(|t| t.byte_size).sum();
+        let mut allocation_sizes = vec![0; graph.allocation_count];
+        for texture in transients.clone()
+        {
+            allocation_sizes[texture.allocation.unwrap()] = texture.byte_size;
+        }
+        ui.label(format!("{} nodes ({} culled), {} transient textures in {} allocations",
+            graph.nodes.len() + graph.culled.len(), graph.culled.len(), transients.count(), graph.allocation_count));
+        ui.label(format!("Transient memory: {:.2} MiB ({:.2} MiB without aliasing)",
+            allocation_sizes.iter().sum::<u64>() as f64 / MIB, unaliased_size as f64 / MIB));
+        ui.label(format!("{} textures pooled, {} created last frame", self.pooled_count(), graph.created_count));
+
+        ui.separator();
+        egui::ScrollArea::horizontal().show(ui, |ui|
+        {
+            egui::Grid::new("render_graph_nodes").striped(true).show(ui, |ui|
+            {
+                ui.label("");
+                for (name, _) in &graph.nodes
+                {
+                    ui.strong(*name);
+                }
+                ui.label("Allocation");
+                ui.end_row();
+
+                for (index, texture) in graph.textures.iter().enumerate()
+                {
+                    ui.label(texture.label).on_hover_text(format!("{}x{} {:?}", texture.size.x, texture.size.y, texture.format));
+                    for (step, (_, accesses)) in graph.nodes.iter().enumerate()
+                    {
+                        let is_alive = texture.lifetime.as_ref().is_some_and(|l| l.contains(&step));
+                        match accesses.iter().find(|(t, _)| *t == index).map(|(_, a)| a)
+                        {
+                            Some(Access::Write) => ui.colored_label(Color32::LIGHT_RED, "W"),
+                            Some(Access::Modify) => ui.colored_label(Color32::YELLOW, "M"),
+                            Some(Access::Read) => ui.colored_label(Color32::LIGHT_GREEN, "R"),
+                            None if is_alive => ui.weak("|"),
+                            None => ui.label(""),
+                        };
+                    }
+                    let allocation = match (texture.is_imported, texture.allocation)
+                    {
+                        (true, _) => "Imported".to_string(),
+                        (false, Some(allocation)) => format!("#{allocation}"),
+                        (false, None) => "Unused".to_string(),
+                    };
+                    match texture.is_output
+                    {
+                        true => ui.label(format!("{allocation} (output)")),
+                        false => ui.label(allocation),
+                    };
+                    ui.end_row();
+                }
+            });
+        });
+
+        if !graph.culled.is_empty()
+        {
+            ui.label(format!("Culled: {}", graph.culled.join(", ")));
+        }
+    }
+}
+
+// What the last graph did, for debugging
+struct GraphReport
+{
+    nodes: Vec<(&'static str, Vec<(usize, Access)>)>, // in execution order
+    culled: Vec<&'static str>,
+    textures: Vec<TextureReport>,
+    allocation_count: usize,
+    created_count: usize,
+}
+struct TextureReport
+{
+    label: &'static str,
+    size: UVec2,
+    format: TextureFormat,
+    is_imported: bool,
+    is_output: bool,
+    lifetime: Option<Range<usize>>,
+    allocation: Option<usize>,
+    byte_size: u64,
+}
+impl GraphReport
+{
+    #[must_use]
+    fn new<S>(graph: &RenderGraph<S>, compiled: &CompiledGraph) -> Self
+    {
+        Self
+        {
+            nodes: compiled.order.iter().map(|&i|
+            {
+                let node = &graph.nodes[i];
+                (node.name, node.accesses.iter().map(|a| (a.texture.0 as usize, a.access)).collect())
+            }).collect(),
+            culled: compiled.culled.iter().map(|&i| graph.nodes[i].name).collect(),
+            textures: graph.textures.iter().enumerate().map(|(index, entry)|
+            {
+                let texture = GraphTexture(index as u16);
+                TextureReport
+                {
+                    label: entry.label,
+                    size: graph.texture_size(texture),
+                    format: graph.texture_format(texture),
+                    is_imported: matches!(entry.source, TextureSource::Imported(_)),
+                    is_output: entry.is_output,
+                    lifetime: compiled.lifetimes[index].clone(),
+                    allocation: compiled.texture_allocations[index],
+                    byte_size: graph.transient_key(entry).map_or(0, |key| key.byte_size()),
+                }
+            }).collect(),
+            allocation_count: compiled.allocation_count(),
+            created_count: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::cell::Cell;
+    use wgpu::{BufferDescriptor, BufferUsages, Color, CommandEncoderDescriptor, LoadOp, MapMode, Operations, PollType, RenderPassColorAttachment, RenderPassDescriptor, StoreOp, TexelCopyBufferInfo, TexelCopyBufferLayout};
+    use super::*;
+
+    const HDR: GraphTextureDesc = GraphTextureDesc::new("HDR", GraphTextureSize::Display, TextureFormat::Rgba16Float);
+    const RA: TextureUsages = TextureUsages::RENDER_ATTACHMENT;
+    const TB: TextureUsages = TextureUsages::TEXTURE_BINDING;
+
+    #[must_use]
+    fn node_names(graph: &RenderGraph, nodes: &[usize]) -> Vec<&'static str>
+    {
+        nodes.iter().map(|&i| graph.nodes[i].name).collect()
+    }
+
+    #[test]
+    fn ordering_and_culling()
+    {
+        let mut graph: RenderGraph = RenderGraph::new(UVec2::new(64, 32));
+        let color = graph.create_texture(HDR);
+        let depth = graph.create_texture(GraphTextureDesc::new("Depth", GraphTextureSize::Display, TextureFormat::Depth32Float));
+        let output = graph.create_texture(GraphTextureDesc::new("Output", GraphTextureSize::Display, TextureFormat::Rgba8Unorm));
+        let unused = graph.create_texture(GraphTextureDesc::new("Unused", GraphTextureSize::DisplayDivisor(4), TextureFormat::R32Float));
+        graph.mark_output(output);
+
+        // added out of order
+        graph.add_node("Debug").modify(output, RA).run(|_, _| { });
+        graph.add_node("Post").read(color, TB).write(output, RA).run(|_, _| { });
+        graph.add_node("Unused").read(depth, TB).write(unused, RA).run(|_, _| { });
+        graph.add_node("Transparent").modify(color, RA).read(depth, RA).run(|_, _| { });
+        graph.add_node("Opaque").write(color, RA).write(depth, RA).run(|_, _| { });
+
+        let compiled = graph.compile().unwrap();
+        assert_eq!(node_names(&graph, &compiled.order), ["Opaque", "Transparent", "Post", "Debug"]);
+        assert_eq!(node_names(&graph, &compiled.culled), ["Unused"]);
+        assert_eq!(compiled.lifetimes, [Some(0..3), Some(0..2), Some(2..4), None]);
+        assert_eq!(compiled.texture_allocations[3], None);
+        assert_eq!(graph.texture_size(unused), UVec2::new(16, 8));
+        assert_eq!(graph.textures[color.0 as usize].usage, RA | TB);
+
+        // side effects keep nodes alive
+        graph.add_node("Read back").read(unused, TextureUsages::COPY_SRC).side_effects().run(|_, _| { });
+        let compiled = graph.compile().unwrap();
+        assert_eq!(node_names(&graph, &compiled.order), ["Opaque", "Unused", "Transparent", "Post", "Debug", "Read back"]);
+        assert!(compiled.culled.is_empty());
+    }
+
+    #[test]
+    fn transient_aliasing()
+    {
+        // a chain of passes ping-ponging through same-sized textures
+        let mut graph: RenderGraph = RenderGraph::new(UVec2::new(64, 32));
+        let a = graph.create_texture(HDR);
+        let b = graph.create_texture(HDR);
+        let c = graph.create_texture(HDR);
+        let d = graph.create_texture(GraphTextureDesc { sample_count: 4, ..HDR });
+        let output = graph.create_texture(HDR);
+        graph.mark_output(output);
+
+        graph.add_node("A").write(a, RA | TB).run(|_, _| { });
+        graph.add_node("B").read(a, RA | TB).write(b, RA | TB).run(|_, _| { });
+        graph.add_node("C").read(b, RA | TB).write(c, RA | TB).write(d, RA | TB).run(|_, _| { });
+        graph.add_node("D").read(c, RA | TB).read(d, RA | TB).write(output, RA | TB).run(|_, _| { });
+
+        let compiled = graph.compile().unwrap();
+        assert_eq!(compiled.lifetimes, [Some(0..2), Some(1..3), Some(2..4), Some(2..4), Some(3..4)]);
+        // a and c don't overlap, neither do b and the output, the multisampled texture can't be shared
+        assert_eq!(compiled.texture_allocations, [Some(0), Some(1), Some(0), Some(2), Some(1)]);
+        assert_eq!(compiled.allocation_count(), 3);
+    }
+
+    #[test]
+    fn invalid_graphs()
+    {
+        let mut graph: RenderGraph = RenderGraph::new(UVec2::new(8, 8));
+        let a = graph.create_texture(HDR);
+        graph.add_node("Read A").read(a, TB).side_effects().run(|_, _| { });
+        assert_eq!(graph.compile(), Err(RenderGraphError::UninitializedRead { node: "Read A", texture: "HDR" }));
+
+        let mut graph: RenderGraph = RenderGraph::new(UVec2::new(8, 8));
+        let a = graph.create_texture(HDR);
+        let b = graph.create_texture(HDR);
+        graph.add_node("A to B").read(a, TB).write(b, RA).side_effects().run(|_, _| { });
+        graph.add_node("B to A").read(b, TB).write(a, RA).side_effects().run(|_, _| { });
+        graph.add_node("Independent").side_effects().run(|_, _| { });
+        assert_eq!(graph.compile(), Err(RenderGraphError::Cycle(vec!["A to B", "B to A"])));
+    }
+
+    #[test]
+    #[ignore = "requires a GPU adapter (or a fallback one)"]
+    fn headless_graph()
+    {
+        let renderer = Renderer::new_headless(UVec2::new(4, 4), true).unwrap();
+
+        let readback = renderer.device().create_buffer(&BufferDescriptor
+        {
+            label: None,
+            size: 256 * 4,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        for frame in 0..2
+        {
+            let ran_unused = Cell::new(false);
+            let mut graph: RenderGraph = RenderGraph::new(renderer.display_size());
+            let desc = GraphTextureDesc::new("Color", GraphTextureSize::Display, TextureFormat::Rgba8Unorm);
+            let cleared = graph.create_texture(desc);
+            let copied = graph.create_texture(desc);
+            let unused = graph.create_texture(desc);
+
+            graph.add_node("Read back").read(copied, TextureUsages::COPY_SRC).side_effects().run(|ctx, _|
+            {
+                let texture = ctx.textures.texture(copied);
+                ctx.encoder.copy_texture_to_buffer(
+                    texture.as_image_copy(),
+                    TexelCopyBufferInfo
+                    {
+                        buffer: &readback,
+                        layout: TexelCopyBufferLayout { offset: 0, bytes_per_row: Some(256), rows_per_image: None },
+                    },
+                    texture.size());
+            });
+            graph.add_node("Copy").read(cleared, TextureUsages::COPY_SRC).write(copied, TextureUsages::COPY_DST).run(|ctx, _|
+            {
+                let (source, target) = (ctx.textures.texture(cleared), ctx.textures.texture(copied));
+                ctx.encoder.copy_texture_to_texture(source.as_image_copy(), target.as_image_copy(), source.size());
+            });
+            graph.add_node("Clear").write(cleared, RA).run(|ctx, _|
+            {
+                drop(ctx.encoder.begin_render_pass(&RenderPassDescriptor
+                {
+                    label: None,
+                    color_attachments: &[Some(RenderPassColorAttachment
+                    {
+                        view: ctx.textures.view(cleared),
+                        depth_slice: None,
+                        resolve_target: None,
+                        ops: Operations { load: LoadOp::Clear(Color::GREEN), store: StoreOp::Store },
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                    multiview_mask: None,
+                }));
+            });
+            graph.add_node("Unused").write(unused, RA).run(|_, _| ran_unused.set(true));
+
+            let mut encoder = renderer.device().create_command_encoder(&CommandEncoderDescriptor::default());
+            graph.execute(&renderer, &mut encoder, &mut ()).unwrap();
+            renderer.queue().submit([encoder.finish()]);
+            assert!(!ran_unused.get());
+
+            let slice = readback.slice(..);
+            slice.map_async(MapMode::Read, |result| result.expect("Failed to map the read back buffer"));
+            let _ = renderer.device().poll(PollType::Wait { submission_index: None, timeout: None });
+            {
+                let mapped = slice.get_mapped_range().expect("Failed to access the mapped read back buffer");
+                for row in mapped.chunks_exact(256)
+                {
+                    assert!(row[..16].chunks_exact(4).all(|p| p == [0, 255, 0, 255]), "{:?}", &row[..16]);
+                }
+            }
+            readback.unmap();
+
+            // the second frame reuses the first's textures
+            let cache = renderer.render_graph_cache().lock();
+            let report = cache.last_graph.as_ref().unwrap();
+            assert_eq!(report.allocation_count, 2);
+            assert_eq!(report.created_count, if frame == 0 { 2 } else { 0 });
+            assert_eq!(cache.pooled_count(), 2);
+        }
+    }
+}
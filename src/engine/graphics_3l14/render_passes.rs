@@ -1,76 +1,207 @@
+use std::time::Duration;
 use wgpu::*;
+use super::render_graph::*;
 use super::renderer::*;
 use super::colors;
+use super::debug_draw::DebugDraw;
+use super::passes::light_cull::LightCullPass;
+use super::passes::post::PostProcessPass;
+use super::passes::shadow::ShadowPass;
+use super::view::View;
 
-pub fn scene<'f>(
-    render_frame: &'f RenderFrame,
-    encoder: &'f mut CommandEncoder,
-    clear_color: Option<colors::Rgba>) -> RenderPass<'f>
+// The scene's (per-frame, transient) targets; post processing reads from the (resolved) color
+#[derive(Clone, Copy)]
+pub struct SceneTargets
 {
-    let (view, resolve_target) = match &render_frame.msaa_config
+    pub color: GraphTexture,
+    pub depth: GraphTexture,
+    pub multisampled_color: Option<GraphTexture>, // resolved into color
+}
+impl SceneTargets
+{
+    #[must_use]
+    pub fn new<S>(graph: &mut RenderGraph<S>, sample_count: u32) -> Self
     {
-        Some(msaa) => (&msaa.buffer, Some(&render_frame.back_buffer_view)),
-        None => (&render_frame.back_buffer_view, None)
-    };
+        let color = graph.create_texture(GraphTextureDesc::new("Scene color", GraphTextureSize::Display, HDR_SCENE_FORMAT));
+        let depth = graph.create_texture(GraphTextureDesc
+        {
+            sample_count,
+            ..GraphTextureDesc::new("Scene depth", GraphTextureSize::Display, SCENE_DEPTH_FORMAT)
+        });
+        let multisampled_color = (sample_count > 1).then(|| graph.create_texture(GraphTextureDesc
+        {
+            sample_count,
+            ..GraphTextureDesc::new("Scene color (MSAA)", GraphTextureSize::Display, HDR_SCENE_FORMAT)
+        }));
+
+        Self { color, depth, multisampled_color }
+    }
 
-    encoder.begin_render_pass(&RenderPassDescriptor
+    // The color attachment for rendering into these targets
+    #[must_use]
+    fn color_attachment<'a>(&self, textures: &NodeTextures<'a>, load: LoadOp<Color>) -> RenderPassColorAttachment<'a>
     {
-        label: Some("Scene render pass"),
-        color_attachments: &[Some(
-            RenderPassColorAttachment
-            {
-                // todo: optimize
-                view,
-                depth_slice: None,
-                resolve_target,
-                ops: Operations
-                {
-                    load: clear_color.map_or(LoadOp::Load, |c| LoadOp::Clear(c.to_srgb().into())),
-                    store: StoreOp::Store,
-                },
-            },
-        )],
-        depth_stencil_attachment: Some(RenderPassDepthStencilAttachment
+        let (view, resolve_target) = match self.multisampled_color
+        {
+            Some(msaa) => (textures.view(msaa), Some(textures.view(self.color))),
+            None => (textures.view(self.color), None),
+        };
+        RenderPassColorAttachment
         {
-            view: &render_frame.depth_buffer_view,
-            depth_ops: Some(Operations { load: LoadOp::Clear(1.0), store: StoreOp::Store }),
-            stencil_ops: None,
-        }),
-        timestamp_writes: None,
-        occlusion_query_set: None,
-        multiview_mask: None,
-    })
+            view,
+            depth_slice: None,
+            resolve_target,
+            ops: Operations { load, store: StoreOp::Store },
+        }
+    }
 }
 
-pub fn debug<'f>(
-    render_frame: &'f RenderFrame,
-    encoder: &'f mut CommandEncoder) -> RenderPass<'f>
+// The passes that a frame's graph is built from
+pub struct FramePasses<'f>
 {
-    let (view, resolve_target) = match &render_frame.msaa_config
+    pub shadows: &'f ShadowPass,
+    pub lights: &'f LightCullPass,
+    pub post: &'f mut PostProcessPass,
+    pub debug_draw: &'f mut DebugDraw,
+}
+
+// A frame's graph: shadows, the opaque then blended scene, post processing into the back buffer, and then debug drawing
+#[must_use]
+pub fn build_frame_graph<'f, 'v>(
+    renderer: &'f Renderer,
+    frame: &RenderFrame,
+    passes: FramePasses<'f>,
+    clear_color: colors::Rgba,
+    delta_time: Duration) -> RenderGraph<'f, View<'v>>
+{
+    let mut graph = RenderGraph::new(renderer.display_size());
+    let back_buffer = graph.import_back_buffer(frame);
+    let scene_targets = SceneTargets::new(&mut graph, renderer.msaa_sample_count());
+    let shadow_map = shadows(&mut graph, passes.shadows);
+    opaque(&mut graph, scene_targets, shadow_map, passes.shadows, passes.lights, clear_color);
+    transparent(&mut graph, scene_targets, shadow_map, passes.shadows, passes.lights);
+    // until its shaders load, post processing only clears the back buffer
+    passes.post.add_nodes(&mut graph, scene_targets.color, back_buffer, delta_time);
+    debug(&mut graph, back_buffer, passes.debug_draw, renderer.queue());
+    graph
+}
+
+// Cascaded shadow depth, read by the scene's nodes; returns the shadow map
+pub fn shadows<'f, 'v>(graph: &mut RenderGraph<'f, View<'v>>, shadow_pass: &'f ShadowPass) -> GraphTexture
+{
+    let shadow_map = graph.import_texture("Shadow map", shadow_pass.shadow_map());
+    graph.add_node("Shadows")
+        .write(shadow_map, TextureUsages::RENDER_ATTACHMENT)
+        .run(move |ctx, view| view.submit_shadows(ctx.encoder, shadow_pass));
+    shadow_map
+}
+
+// Opaque geometry, drawn into the (linear, HDR) scene target
+// The scene targets are transient, so they're always cleared first
+pub fn opaque<'f, 'v>(
+    graph: &mut RenderGraph<'f, View<'v>>,
+    targets: SceneTargets,
+    shadow_map: GraphTexture,
+    shadows: &'f ShadowPass,
+    lights: &'f LightCullPass,
+    clear_color: colors::Rgba)
+{
+    let mut node = graph.add_node("Opaque")
+        .write(targets.depth, TextureUsages::RENDER_ATTACHMENT)
+        .read(shadow_map, TextureUsages::TEXTURE_BINDING);
+    node = match targets.multisampled_color
     {
-        Some(msaa) => (&msaa.buffer, Some(&render_frame.back_buffer_view)),
-        None => (&render_frame.back_buffer_view, None)
+        Some(msaa) => node
+            .write(msaa, TextureUsages::RENDER_ATTACHMENT)
+            .write(targets.color, TextureUsages::RENDER_ATTACHMENT),
+        None => node.write(targets.color, TextureUsages::RENDER_ATTACHMENT),
     };
 
-    encoder.begin_render_pass(&RenderPassDescriptor
+    node.run(move |ctx, view|
+    {
+        let mut render_pass = ctx.encoder.begin_render_pass(&RenderPassDescriptor
+        {
+            label: Some("Scene render pass"),
+            color_attachments: &[Some(targets.color_attachment(&ctx.textures, LoadOp::Clear(clear_color.to_srgb().into())))],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment
+            {
+                view: ctx.textures.view(targets.depth),
+                depth_ops: Some(Operations { load: LoadOp::Clear(1.0), store: StoreOp::Store }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+            multiview_mask: None,
+        });
+        view.submit(&mut render_pass, shadows, lights);
+    });
+}
+
+// Blended geometry, drawn over the opaque color and tested (but not written) against its depth
+pub fn transparent<'f, 'v>(
+    graph: &mut RenderGraph<'f, View<'v>>,
+    targets: SceneTargets,
+    shadow_map: GraphTexture,
+    shadows: &'f ShadowPass,
+    lights: &'f LightCullPass)
+{
+    let mut node = graph.add_node("Transparent")
+        .modify(targets.color, TextureUsages::RENDER_ATTACHMENT)
+        .modify(targets.depth, TextureUsages::RENDER_ATTACHMENT)
+        .read(shadow_map, TextureUsages::TEXTURE_BINDING);
+    if let Some(msaa) = targets.multisampled_color
     {
-        label: Some("Debug render pass"),
-        color_attachments: &[Some(
-            RenderPassColorAttachment
+        node = node.modify(msaa, TextureUsages::RENDER_ATTACHMENT);
+    }
+
+    node.run(move |ctx, view|
+    {
+        let mut render_pass = ctx.encoder.begin_render_pass(&RenderPassDescriptor
+        {
+            label: Some("Transparent render pass"),
+            color_attachments: &[Some(targets.color_attachment(&ctx.textures, LoadOp::Load))],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment
+            {
+                view: ctx.textures.view(targets.depth),
+                depth_ops: Some(Operations { load: LoadOp::Load, store: StoreOp::Store }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+            multiview_mask: None,
+        });
+        view.submit_transparent(&mut render_pass, shadows, lights);
+    });
+}
+
+// Drawn after post processing, directly into the back buffer (not multisampled, without depth)
+pub fn debug<'f, S>(graph: &mut RenderGraph<'f, S>, output: GraphTexture, debug_draw: &'f mut DebugDraw, queue: &'f Queue)
+{
+    graph.add_node("Debug")
+        .modify(output, TextureUsages::RENDER_ATTACHMENT)
+        .run(move |ctx, _|
+        {
+            let mut render_pass = ctx.encoder.begin_render_pass(&RenderPassDescriptor
             {
-                view,
-                resolve_target,
-                depth_slice: None,
-                ops: Operations
-                {
-                    load: LoadOp::Load,
-                    store: StoreOp::Store,
-                },
-            },
-        )],
-        depth_stencil_attachment: None,
-        timestamp_writes: None,
-        occlusion_query_set: None,
-        multiview_mask: None,
-    })
+                label: Some("Debug render pass"),
+                color_attachments: &[Some(
+                    RenderPassColorAttachment
+                    {
+                        view: ctx.textures.view(output),
+                        resolve_target: None,
+                        depth_slice: None,
+                        ops: Operations
+                        {
+                            load: LoadOp::Load,
+                            store: StoreOp::Store,
+                        },
+                    },
+                )],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+                multiview_mask: None,
+            });
+            debug_draw.submit(queue, &mut render_pass);
+        });
 }
@@ -7,16 +7,23 @@ use sdl2::video::Window;
 use triomphe::Arc;
 use egui_wgpu::ScreenDescriptor;
 use glam::UVec2;
+use image::RgbaImage;
 #[allow(deprecated)]
 use wgpu::rwh::{HasRawDisplayHandle, HasRawWindowHandle};
 use wgpu::*;
 use input_3l14::Input;
 use nab_3l14::RenderFrameNumber;
-
-type ArcSwapOpt<T> = arc_swap::ArcSwapAny<Option<Arc<T>>>;
+use crate::pipeline_cache::BindGroupSlot;
+use crate::render_graph::RenderGraphCache;
 
 pub const MAX_CONSECUTIVE_FRAMES: usize = 3;
 
+// The format of offscreen render targets (see Renderer::new_headless())
+pub const OFFSCREEN_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+// The format of the (linear) scene color target, post processing resolves this to the back buffer (see passes::post)
+pub const HDR_SCENE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+pub const SCENE_DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+
 #[macro_export]
 #[cfg(feature = "debug_gpu_labels")]
 macro_rules! debug_label { ($label:expr) => { Some($label) }; }
@@ -27,29 +34,42 @@ macro_rules! debug_label { ($label:expr) => { None }; } // check for/mitigate de
 struct RenderFrameData
 {
     last_submission: Option<SubmissionIndex>,
-    depth_buffer: Texture,
+    offscreen_target: Option<Texture>, // only when rendering offscreen
 }
 
-// TODO: need a way to hold the reference for the duration of the frame
-//  ArcSwap inside RenderFrameData + deref?
-pub struct MSAAConfiguration
+#[derive(Debug)]
+pub enum RendererError
 {
-    pub current_sample_count: u32, // pipelines and ms-framebuffer will need to be recreated if this is changed
-    pub buffer: TextureView,
+    NoAdapter(RequestAdapterError),
+    CreateDevice(RequestDeviceError),
+    ZeroSize,
+    UnsupportedReadBack(TextureFormat, TextureUsages), // the back buffer must be copyable, and RGBA or BGRA
+    TooFewBindGroups { supported: u32, required: u32 }, // see BindGroupSlot
+    PollDevice(PollError),
+    MapReadBack(BufferAsyncError),
+    ReadMappedRange(MapRangeError),
 }
+impl std::fmt::Display for RendererError
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { std::fmt::Debug::fmt(self, f) }
+}
+impl Error for RendererError { }
+
 pub struct Renderer
 {
     device: Device,
     queue: Queue,
 
-    surface: Surface<'static>, // super hack
+    surface: Option<Surface<'static>>, // super hack, none when rendering offscreen
 
     // todo: some of these should probably be spaced apart (cache-line size) to avoid cache line sync serializing
 
     surface_config: RwLock<SurfaceConfiguration>,
 
     max_sample_count: u32,
-    msaa_config: ArcSwapOpt<MSAAConfiguration>,
+    msaa_sample_count: u32, // pipelines will need to be recreated if this is changed
+
+    render_graph: Mutex<RenderGraphCache>, // pooled transient textures (see render_graph)
 
     debug_gui: egui::Context,
     //debug_gui_renderer: Mutex<egui_wgpu_backend::RenderPass>,
@@ -71,11 +91,23 @@ impl Renderer
         };
         let window_size = window.size();
 
-        futures::executor::block_on(Self::new_async(window_handle, window_size))
+        futures::executor::block_on(Self::new_async(Some(window_handle), window_size, false))
+            .expect("Failed to create renderer")
     }
 
-    #[must_use]
-    async fn new_async(window_handle: SurfaceTargetUnsafe, window_size: (u32, u32)) -> Arc<Self>
+    // Create a renderer that renders to offscreen textures instead of a window (e.g. for tests and tools)
+    // Frames can be read back with present_and_read_back()
+    pub fn new_headless(size: UVec2, force_fallback_adapter: bool) -> Result<Arc<Self>, RendererError>
+    {
+        if size.x == 0 || size.y == 0
+        {
+            return Err(RendererError::ZeroSize);
+        }
+
+        futures::executor::block_on(Self::new_async(None, (size.x, size.y), force_fallback_adapter))
+    }
+
+    async fn new_async(window_handle: Option<SurfaceTargetUnsafe>, window_size: (u32, u32), force_fallback_adapter: bool) -> Result<Arc<Self>, RendererError>
     {
         puffin::profile_function!();
 
@@ -97,23 +129,29 @@ impl Renderer
             display: None,
         });
 
-        let surface = unsafe { instance.create_surface_unsafe(window_handle).expect("Failed to create swap-chain") };
+        let surface = window_handle.map(|handle| unsafe { instance.create_surface_unsafe(handle).expect("Failed to create swap-chain") });
 
         // enumerate adapters?
         let adapter =
             instance.request_adapter(&RequestAdapterOptions
             {
                 power_preference: PowerPreference::HighPerformance,
-                force_fallback_adapter: false,
+                force_fallback_adapter,
                 // Request an adapter which can render to our surface
-                compatible_surface: Some(&surface),
+                compatible_surface: surface.as_ref(),
                 apply_limit_buckets: false,
             }).await
-            .expect("Failed to find an appropriate adapter");
+            .map_err(RendererError::NoAdapter)?;
 
         let adapter_info = adapter.get_info();
         println!("Creating render device with {:?}", adapter_info);
 
+        let max_bind_groups = adapter.limits().max_bind_groups;
+        if max_bind_groups < BindGroupSlot::COUNT
+        {
+            return Err(RendererError::TooFewBindGroups { supported: max_bind_groups, required: BindGroupSlot::COUNT });
+        }
+
         // Create the logical device and command queue
         let (device, queue) = adapter.request_device(&DeviceDescriptor
             {
@@ -132,23 +170,45 @@ impl Renderer
                 required_limits: Limits
                 {
                     max_immediate_size: 128,
+                    max_bind_groups: BindGroupSlot::COUNT,
                     .. Default::default()
                 }.using_resolution(adapter.limits()),
                 memory_hints: MemoryHints::Performance,
                 trace: Trace::Off,
             },
             ).await
-            .expect("Failed to create device");
+            .map_err(RendererError::CreateDevice)?;
 
-        let mut surface_config = surface
-            .get_default_config(&adapter, window_size.0, window_size.1)
-            .unwrap();
-        surface_config.present_mode = PresentMode::AutoVsync;
-        // surface_config.format = TextureFormat::Rgba8UnormSrgb;
-        surface.configure(&device, &surface_config);
+        let surface_config = match &surface
+        {
+            Some(surface) =>
+            {
+                let mut surface_config = surface
+                    .get_default_config(&adapter, window_size.0, window_size.1)
+                    .unwrap();
+                surface_config.present_mode = PresentMode::AutoVsync;
+                // surface_config.format = TextureFormat::Rgba8UnormSrgb;
+                // allow reading back frames, if possible
+                surface_config.usage |= surface.get_capabilities(&adapter).usages & TextureUsages::COPY_SRC;
+                surface.configure(&device, &surface_config);
+                surface_config
+            },
+            None => SurfaceConfiguration
+            {
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+                format: OFFSCREEN_FORMAT,
+                width: window_size.0,
+                height: window_size.1,
+                present_mode: PresentMode::AutoVsync,
+                desired_maximum_frame_latency: MAX_CONSECUTIVE_FRAMES as u32,
+                alpha_mode: CompositeAlphaMode::Auto,
+                view_formats: vec![],
+            },
+        };
 
+        // the scene is rendered (and multisampled) in HDR, the back buffer is only written by post processing
         let sample_flags = adapter
-            .get_texture_format_features(surface_config.format)
+            .get_texture_format_features(HDR_SCENE_FORMAT)
             .flags;
         let max_sample_count: u32 =
         {
@@ -159,21 +219,7 @@ impl Renderer
             else { 1 }
         };
 
-        let current_sample_count = max_sample_count;
-        let msaa_config = if
-            allow_msaa &&
-            current_sample_count > 1
-        {
-            Some(MSAAConfiguration
-            {
-                current_sample_count,
-                buffer: Self::create_msaa_buffer(current_sample_count, &device, surface_config.width, surface_config.height, surface_config.format)
-            })
-        }
-        else
-        {
-            None
-        };
+        let msaa_sample_count = if allow_msaa { max_sample_count } else { 1 };
 
         // TODO: log debug
         println!("Created renderer with {surface_config:?} + {sample_flags:?}");
@@ -205,44 +251,28 @@ impl Renderer
                 predictable_texture_filtering: false,
             });
 
-        // todo: recreate on resize
-        let depth_buffer_desc = TextureDescriptor
-        {
-            label: debug_label!("Depth buffer"),
-            size: Extent3d
-            {
-                width: surface_config.width,
-                height: surface_config.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: current_sample_count,
-            dimension: TextureDimension::D2,
-            format: TextureFormat::Depth32Float,
-            usage: TextureUsages::COPY_DST | TextureUsages::RENDER_ATTACHMENT,
-            view_formats: &[],
-        };
         let render_frames = std::array::from_fn::<_, MAX_CONSECUTIVE_FRAMES, _>(|_|
         {
             RenderFrameData
             {
                 last_submission: None,
-                depth_buffer: device.create_texture(&depth_buffer_desc),
+                offscreen_target: surface.is_none().then(|| Self::create_offscreen_target(&device, &surface_config)),
             }
         });
 
-        Arc::new(Renderer
+        Ok(Arc::new(Renderer
         {
             device,
             queue,
             surface,
             surface_config: RwLock::new(surface_config),
             max_sample_count,
-            msaa_config: ArcSwapOpt::from(msaa_config.map(|c| Arc::new(c))),
+            msaa_sample_count,
+            render_graph: Mutex::new(RenderGraphCache::default()),
             debug_gui,
             debug_gui_renderer: Mutex::new(debug_gui_renderer),
             render_frames: RwLock::new(render_frames),
-        })
+        }))
     }
 
     pub fn resize(&self, new_width: u32, new_height: u32)
@@ -254,10 +284,8 @@ impl Renderer
             panic!("Render width/height cannot be zero");
         }
 
-        let surface_format;
         {
             let mut surf_config = self.surface_config.write();
-            surface_format = surf_config.format;
 
             log::info!("Resizing from {}x{} to {}x{}",
                      surf_config.width, surf_config.height,
@@ -265,43 +293,22 @@ impl Renderer
 
             surf_config.width = new_width;
             surf_config.height = new_height;
-            self.surface.configure(&self.device, &surf_config);
-        }
-
-        let mut current_sample_count = 1;
-        // max_sample_count may need to be recreated
-        self.msaa_config.store(self.msaa_config.load().as_ref().map(|c|
-        {
-            current_sample_count = c.current_sample_count;
-            Arc::new(MSAAConfiguration
+            if let Some(surface) = &self.surface
             {
-                current_sample_count,
-                buffer: Self::create_msaa_buffer(current_sample_count, &self.device, new_width, new_height, surface_format),
-            })
-        }));
-
-        let depth_buffer_desc = TextureDescriptor
-        {
-            label: debug_label!("Depth buffer"),
-            size: Extent3d
-            {
-                width: new_width,
-                height: new_height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: current_sample_count,
-            dimension: TextureDimension::D2,
-            format: TextureFormat::Depth32Float,
-            usage: TextureUsages::COPY_DST | TextureUsages::RENDER_ATTACHMENT,
-            view_formats: &[],
-        };
+                surface.configure(&self.device, &surf_config);
+            }
+        }
 
+        // the scene's targets are render graph transients, sized each frame
         {
+            let surf_config = self.surface_config.read();
             let mut render_frames = self.render_frames.write();
             for f in render_frames.as_mut()
             {
-                f.depth_buffer = self.device.create_texture(&depth_buffer_desc);
+                if f.offscreen_target.is_some()
+                {
+                    f.offscreen_target = Some(Self::create_offscreen_target(&self.device, &surf_config));
+                }
                 f.last_submission = None;
             }
         }
@@ -335,18 +342,32 @@ impl Renderer
     #[inline] #[must_use] pub fn surface_format(&self) -> TextureFormat { self.surface_config.read().format }
 
     #[inline] #[must_use] pub fn msaa_max_sample_count(&self) -> u32 { self.max_sample_count }
+    // the sample count of the scene's targets, and the pipelines drawing into them
+    #[inline] #[must_use] pub fn msaa_sample_count(&self) -> u32 { self.msaa_sample_count }
+
+    #[inline] #[must_use] pub(crate) fn render_graph_cache(&self) -> &Mutex<RenderGraphCache> { &self.render_graph }
+
+    #[inline] #[must_use] pub fn is_headless(&self) -> bool { self.surface.is_none() }
 
     #[must_use]
     pub fn frame(&self, frame_number: RenderFrameNumber, input: &Input) -> RenderFrame
+    {
+        let raw_input = input.into_egui(self.debug_gui.zoom_factor());
+        self.begin_frame(frame_number, raw_input)
+    }
+
+    // Start a frame without needing an Input (e.g. when headless)
+    #[must_use]
+    pub fn begin_frame(&self, frame_number: RenderFrameNumber, mut gui_input: egui::RawInput) -> RenderFrame
     {
         puffin::profile_function!();
 
-        let back_buffer;
-        let depth_buffer_view;
+        let surface_texture;
+        let offscreen_target;
 
         {
             puffin::profile_scope!("Wait for frame ready");
-            back_buffer = match self.surface.get_current_texture()
+            surface_texture = self.surface.as_ref().map(|surface| match surface.get_current_texture()
             {
                 // Ok(texture) => texture,
                 // Err(SurfaceError::Timeout) => self.surface.get_current_texture().expect("Get swap chain target timed out"),
@@ -363,15 +384,15 @@ impl Renderer
                 CurrentSurfaceTexture::Outdated =>
                 {
                     let surf_conf = self.surface_config.read();
-                    self.surface.configure(&self.device, &surf_conf);
-                    match self.surface.get_current_texture()
+                    surface.configure(&self.device, &surf_conf);
+                    match surface.get_current_texture()
                     {
                         CurrentSurfaceTexture::Success(tex) => tex,
                         err => panic!("Failed to get updated swap chain target: {err:?}")
                     }
                 }
                 err => panic!("Failed to get swap chain target: {err:?}"), // TODO: recover?
-            };
+            });
 
             let render_frames = self.render_frames.read();
 
@@ -385,25 +406,28 @@ impl Renderer
                 });
                 // TODO: handle poll error
             };
-            depth_buffer_view = rf_data.depth_buffer.create_view(&TextureViewDescriptor::default());
+            offscreen_target = rf_data.offscreen_target.clone();
         }
 
-        let back_buffer_view = back_buffer.texture.create_view(&TextureViewDescriptor::default());
+        let back_buffer = match (surface_texture, offscreen_target)
+        {
+            (Some(surface_texture), _) => BackBuffer::Surface(surface_texture),
+            (None, Some(texture)) => BackBuffer::Offscreen(texture),
+            (None, None) => unreachable!("Headless renderers always have offscreen targets"),
+        };
+        let back_buffer_view = back_buffer.texture().create_view(&TextureViewDescriptor::default());
 
         let debug_gui = self.debug_gui.clone();
-        let mut raw_input= input.into_egui(debug_gui.zoom_factor());
         // todo: raw_input. max_texture_size, time, focused
-        raw_input.screen_rect = Some(Rect::from_min_max(
-            Pos2::ZERO, Pos2::new(back_buffer.texture.width() as f32, back_buffer.texture.height() as f32)));
-        debug_gui.begin_pass(raw_input);
+        gui_input.screen_rect = Some(Rect::from_min_max(
+            Pos2::ZERO, Pos2::new(back_buffer.texture().width() as f32, back_buffer.texture().height() as f32)));
+        debug_gui.begin_pass(gui_input);
 
         RenderFrame
         {
             frame_number,
             back_buffer,
             back_buffer_view,
-            depth_buffer_view,
-            msaa_config: self.msaa_config.load_full(),
         }
     }
 
@@ -470,42 +494,120 @@ impl Renderer
     {
         puffin::profile_function!();
 
-        let back_buffer_size = frame.back_buffer.texture.size();
+        self.submit_frame(frame, None);
+    }
+
+    // Present the frame and copy its pixels back from the GPU, this waits for the GPU to finish the frame
+    // The renderer must be headless, or its surface must support being copied from
+    pub fn present_and_read_back(&self, frame: RenderFrame) -> Result<RgbaImage, RendererError>
+    {
+        puffin::profile_function!();
+
+        let texture = frame.back_buffer.texture();
+        let size = texture.size();
+        let format = texture.format();
+        let swizzle = match format
+        {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => Some(false),
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => Some(true),
+            _ => None,
+        };
+        let Some(swizzle) = swizzle.filter(|_| texture.usage().contains(TextureUsages::COPY_SRC))
+            else { return Err(RendererError::UnsupportedReadBack(format, texture.usage())) };
+
+        let row_size = size.width * 4;
+        let padded_row_size = row_size.next_multiple_of(COPY_BYTES_PER_ROW_ALIGNMENT);
+        let readback_buffer = self.device.create_buffer(&BufferDescriptor
+        {
+            label: debug_label!("Frame read back buffer"),
+            size: (padded_row_size * size.height) as BufferAddress,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut command_encoder = self.device.create_command_encoder(&CommandEncoderDescriptor
+        {
+            label: debug_label!("Frame read back encoder"),
+        });
+        command_encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            TexelCopyBufferInfo
+            {
+                buffer: &readback_buffer,
+                layout: TexelCopyBufferLayout
+                {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_size),
+                    rows_per_image: None,
+                },
+            },
+            size);
+
+        // the copy must happen before the frame is presented
+        self.submit_frame(frame, Some(command_encoder.finish()));
+
+        let readback_slice = readback_buffer.slice(..);
+        let (map_sender, map_receiver) = std::sync::mpsc::channel();
+        readback_slice.map_async(MapMode::Read, move |result| { let _ = map_sender.send(result); });
+        self.device.poll(PollType::Wait
+        {
+            submission_index: None,
+            timeout: None, // TODO: timeout
+        }).map_err(RendererError::PollDevice)?;
+        // waiting on the device runs the callback, so it is only missing if the map was dropped
+        map_receiver.try_recv().unwrap_or(Err(BufferAsyncError)).map_err(RendererError::MapReadBack)?;
+
+        let mut image = RgbaImage::new(size.width, size.height);
+        {
+            let mapped = readback_slice.get_mapped_range().map_err(RendererError::ReadMappedRange)?;
+            for (src_row, dst_row) in mapped.chunks_exact(padded_row_size as usize).zip(image.chunks_exact_mut(row_size as usize))
+            {
+                dst_row.copy_from_slice(&src_row[..row_size as usize]);
+            }
+        }
+        readback_buffer.unmap();
+
+        if swizzle
+        {
+            image.pixels_mut().for_each(|p| p.0.swap(0, 2));
+        }
+        Ok(image)
+    }
+
+    fn submit_frame(&self, frame: RenderFrame, extra_commands: Option<CommandBuffer>)
+    {
+        let back_buffer_size = frame.back_buffer.texture().size();
         let gui_commands = self.render_debug_gui(
             [back_buffer_size.width, back_buffer_size.height],
             &frame.back_buffer_view);
-        self.queue.submit([gui_commands]);
-        self.queue.present(frame.back_buffer);
+        self.queue.submit(std::iter::once(gui_commands).chain(extra_commands));
+
+        match frame.back_buffer
+        {
+            BackBuffer::Surface(surface_texture) => self.queue.present(surface_texture),
+            BackBuffer::Offscreen(_) => { },
+        }
     }
 
-    // taken from MSAA line sample
     #[must_use]
-    fn create_msaa_buffer(sample_count: u32, device: &Device, width: u32, height: u32, surface_format: TextureFormat) -> TextureView
+    fn create_offscreen_target(device: &Device, config: &SurfaceConfiguration) -> Texture
     {
-        let multisampled_texture_extent = Extent3d
+        device.create_texture(&TextureDescriptor
         {
-            width,
-            height,
-            depth_or_array_layers: 1,
-        };
-        let multisampled_frame_descriptor = &TextureDescriptor {
-            label: debug_label!("MSAA framebuffer texture"),
-            size: multisampled_texture_extent,
+            label: debug_label!("Offscreen render target"),
+            size: Extent3d
+            {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
             mip_level_count: 1,
-            sample_count,
+            sample_count: 1,
             dimension: TextureDimension::D2,
-            format: surface_format,
-            usage: TextureUsages::RENDER_ATTACHMENT,
+            format: config.format,
+            usage: config.usage,
             view_formats: &[],
-        };
-
-        device
-            .create_texture(multisampled_frame_descriptor)
-            .create_view(&TextureViewDescriptor
-            {
-                label: debug_label!("MSAA framebuffer view"),
-                .. Default::default()
-            })
+        })
     }
 }
 impl DebugGui for Renderer
@@ -514,7 +616,28 @@ impl DebugGui for Renderer
 
     fn debug_gui(&self, ui: &mut Ui)
     {
-        ui.label("TODO");
+        ui.label(format!("MSAA: {}x (max {}x)", self.msaa_sample_count, self.max_sample_count));
+
+        ui.heading("Render graph");
+        self.render_graph.lock().debug_gui(ui);
+    }
+}
+
+enum BackBuffer
+{
+    Surface(SurfaceTexture),
+    Offscreen(Texture),
+}
+impl BackBuffer
+{
+    #[inline] #[must_use]
+    fn texture(&self) -> &Texture
+    {
+        match self
+        {
+            BackBuffer::Surface(surface_texture) => &surface_texture.texture,
+            BackBuffer::Offscreen(texture) => texture,
+        }
     }
 }
 
@@ -522,12 +645,65 @@ pub struct RenderFrame
 {
     pub frame_number: RenderFrameNumber,
 
-    back_buffer: SurfaceTexture,
-    pub back_buffer_view: TextureView,
-    pub depth_buffer_view: TextureView,
-
-    pub msaa_config: Option<Arc<MSAAConfiguration>>,
+    back_buffer: BackBuffer,
+    pub back_buffer_view: TextureView, // the scene's targets are render graph transients (see render_passes::SceneTargets)
 }
 impl RenderFrame
 {
+    #[inline] #[must_use]
+    pub fn back_buffer(&self) -> &Texture { self.back_buffer.texture() }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::colors;
+    use crate::render_graph::RenderGraph;
+    use super::*;
+
+    #[test]
+    #[ignore = "requires a GPU adapter (or a fallback one)"]
+    fn headless_read_back()
+    {
+        assert!(matches!(Renderer::new_headless(UVec2::new(0, 20), true), Err(RendererError::ZeroSize)));
+
+        let renderer = Renderer::new_headless(UVec2::new(70, 20), true).unwrap();
+        assert!(renderer.is_headless());
+        assert_eq!(renderer.surface_format(), OFFSCREEN_FORMAT);
+
+        let frame = renderer.begin_frame(RenderFrameNumber(0), egui::RawInput::default());
+        let mut graph: RenderGraph = RenderGraph::new(renderer.display_size());
+        let back_buffer = graph.import_back_buffer(&frame);
+        graph.add_node("Clear").write(back_buffer, TextureUsages::RENDER_ATTACHMENT).run(|ctx, _|
+        {
+            drop(ctx.encoder.begin_render_pass(&RenderPassDescriptor
+            {
+                label: None,
+                color_attachments: &[Some(RenderPassColorAttachment
+                {
+                    view: ctx.textures.view(back_buffer),
+                    depth_slice: None,
+                    resolve_target: None,
+                    ops: Operations { load: LoadOp::Clear(colors::RED.to_srgb().into()), store: StoreOp::Store },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+                multiview_mask: None,
+            }));
+        });
+        let mut encoder = renderer.device().create_command_encoder(&CommandEncoderDescriptor::default());
+        graph.execute(&renderer, &mut encoder, &mut ()).unwrap();
+        renderer.queue().submit([encoder.finish()]);
+
+        let image = renderer.present_and_read_back(frame).unwrap();
+        assert_eq!(image.dimensions(), (70, 20)); // not a multiple of the row alignment
+        assert!(image.pixels().all(|p| p.0 == [255, 0, 0, 255]));
+
+        // resizing recreates the targets
+        renderer.resize(8, 8);
+        assert_eq!(renderer.display_size(), UVec2::new(8, 8));
+        let frame = renderer.begin_frame(RenderFrameNumber(1), egui::RawInput::default());
+        assert_eq!(renderer.present_and_read_back(frame).unwrap().dimensions(), (8, 8));
+    }
 }
@@ -0,0 +1,320 @@
+use std::fmt::{Display, Formatter};
+use bitcode::{Decode, Encode};
+use wgpu::{BindGroupLayoutEntry, BindingType, BufferBindingType, SamplerBindingType, TextureSampleType, TextureViewDimension};
+
+// The offset and size (in bytes) of a (flattened) member of a uniform buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct UniformMemberLayout
+{
+    pub offset: u32,
+    pub size: u32,
+}
+impl UniformMemberLayout
+{
+    #[inline] #[must_use]
+    pub const fn new(offset: usize, size: usize) -> Self
+    {
+        Self { offset: offset as u32, size: size as u32 }
+    }
+}
+
+// The memory layout of a Rust type uploaded as a uniform buffer, shaders must agree with this
+#[derive(Debug, Clone, Copy)]
+pub struct UniformLayout
+{
+    pub size: u32,
+    pub members: &'static [UniformMemberLayout],
+}
+
+pub trait UniformType: Sized
+{
+    // nested structs should be flattened, arrays are a single member
+    const MEMBERS: &'static [UniformMemberLayout];
+    const LAYOUT: UniformLayout = UniformLayout { size: size_of::<Self>() as u32, members: Self::MEMBERS };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Encode, Decode)]
+pub enum ShaderScalarKind
+{
+    Float,
+    Sint,
+    Uint,
+    Bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Encode, Decode)]
+pub enum ShaderTextureDimension
+{
+    D1,
+    D2,
+    D2Array,
+    D3,
+    Cube,
+    CubeArray,
+}
+impl From<ShaderTextureDimension> for TextureViewDimension
+{
+    fn from(value: ShaderTextureDimension) -> Self
+    {
+        match value
+        {
+            ShaderTextureDimension::D1 => TextureViewDimension::D1,
+            ShaderTextureDimension::D2 => TextureViewDimension::D2,
+            ShaderTextureDimension::D2Array => TextureViewDimension::D2Array,
+            ShaderTextureDimension::D3 => TextureViewDimension::D3,
+            ShaderTextureDimension::Cube => TextureViewDimension::Cube,
+            ShaderTextureDimension::CubeArray => TextureViewDimension::CubeArray,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum ShaderBindingType
+{
+    UniformBuffer,
+    StorageBuffer { read_only: bool },
+    Sampler { comparison: bool },
+    Texture
+    {
+        dimension: ShaderTextureDimension,
+        sample_kind: Option<ShaderScalarKind>, // None for depth textures
+        multisampled: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct ShaderBinding
+{
+    pub group: u32,
+    pub binding: u32,
+    pub binding_type: ShaderBindingType,
+    pub size: u32, // buffers only
+    pub members: Box<[UniformMemberLayout]>, // uniform buffers only
+}
+impl ShaderBinding
+{
+    // Can this binding be bound with the given layout entry
+    #[must_use]
+    pub fn is_compatible(&self, entry: &BindGroupLayoutEntry) -> bool
+    {
+        if self.binding != entry.binding
+        {
+            return false;
+        }
+
+        match (self.binding_type, &entry.ty)
+        {
+            (ShaderBindingType::UniformBuffer, BindingType::Buffer { ty: BufferBindingType::Uniform, min_binding_size, .. }) =>
+                min_binding_size.is_none_or(|min| min.get() >= self.size as u64),
+            (ShaderBindingType::StorageBuffer { read_only }, BindingType::Buffer { ty: BufferBindingType::Storage { read_only: layout_read_only }, .. }) =>
+                read_only || !layout_read_only,
+            (ShaderBindingType::Sampler { comparison }, BindingType::Sampler(sampler)) =>
+                comparison == matches!(sampler, SamplerBindingType::Comparison),
+            (ShaderBindingType::Texture { dimension, sample_kind, multisampled }, BindingType::Texture { sample_type, view_dimension, multisampled: layout_multisampled }) =>
+            {
+                let kind_matches = matches!((sample_kind, sample_type),
+                    (Some(ShaderScalarKind::Float), TextureSampleType::Float { .. }) |
+                    (Some(ShaderScalarKind::Sint), TextureSampleType::Sint) |
+                    (Some(ShaderScalarKind::Uint), TextureSampleType::Uint) |
+                    (None, TextureSampleType::Depth));
+                kind_matches &&
+                    TextureViewDimension::from(dimension) == *view_dimension &&
+                    multisampled == *layout_multisampled
+            }
+            _ => false,
+        }
+    }
+
+    // Verify that the members of this uniform buffer match the layout of a Rust type
+    // The shader may declare fewer (trailing) members than the Rust type
+    #[must_use]
+    pub fn matches_uniform(&self, layout: &UniformLayout) -> bool
+    {
+        matches!(self.binding_type, ShaderBindingType::UniformBuffer) &&
+            self.size <= layout.size &&
+            self.members.len() <= layout.members.len() &&
+            self.members.iter().zip(layout.members).all(|(a, b)| a == b)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct ShaderVertexInput
+{
+    pub location: u32,
+    pub kind: ShaderScalarKind,
+    pub components: u8,
+}
+
+// The resources and vertex inputs used by a shader's entry point, generated by the shader builder
+#[derive(Debug, Clone, Default, PartialEq, Encode, Decode)]
+pub struct ShaderReflection
+{
+    pub bindings: Box<[ShaderBinding]>, // sorted by group, binding
+    pub vertex_inputs: Box<[ShaderVertexInput]>, // sorted by location
+}
+impl ShaderReflection
+{
+    #[inline] #[must_use]
+    pub fn uses_group(&self, group: u32) -> bool
+    {
+        self.bindings.iter().any(|b| b.group == group)
+    }
+
+    pub fn group(&self, group: u32) -> impl Iterator<Item = &ShaderBinding>
+    {
+        self.bindings.iter().filter(move |b| b.group == group)
+    }
+
+    // Verify that every binding this shader uses in a group can be bound with the given layout entries
+    pub fn validate_group(&self, group: u32, entries: &[BindGroupLayoutEntry]) -> Result<(), ShaderLayoutMismatch>
+    {
+        let mismatches: Vec<_> = self.group(group)
+            .filter(|binding| !entries.iter().any(|e| binding.is_compatible(e)))
+            .map(|binding| format!("{binding:?} does not match any layout entry in group {group}"))
+            .collect();
+
+        match mismatches.is_empty()
+        {
+            true => Ok(()),
+            false => Err(ShaderLayoutMismatch(mismatches)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ShaderLayoutMismatch(pub Vec<String>);
+impl Display for ShaderLayoutMismatch
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+    {
+        writeln!(f, "Shader does not match the engine layout:")?;
+        for m in &self.0
+        {
+            writeln!(f, "\t{m}")?;
+        }
+        Ok(())
+    }
+}
+impl std::error::Error for ShaderLayoutMismatch { }
+
+#[cfg(test)]
+mod tests
+{
+    use crate::material_classes::{MaterialClass, PbrTextureSlot, SimpleOpaque};
+    use crate::passes::light_cull::{ClusterGridUniform, LIGHTS_BIND_LAYOUT_ENTRIES};
+    use crate::passes::shadow::{ShadowsUniform, SHADOWS_BIND_LAYOUT_ENTRIES};
+    use crate::pipeline_cache::BindGroupSlot;
+    use super::*;
+
+    #[repr(C)]
+    struct TestUniform
+    {
+        a: [f32; 4],
+        b: u32,
+        c: f32,
+    }
+    impl UniformType for TestUniform
+    {
+        const MEMBERS: &'static [UniformMemberLayout] =
+        &[
+            UniformMemberLayout::new(std::mem::offset_of!(Self, a), size_of::<[f32; 4]>()),
+            UniformMemberLayout::new(std::mem::offset_of!(Self, b), size_of::<u32>()),
+            UniformMemberLayout::new(std::mem::offset_of!(Self, c), size_of::<f32>()),
+        ];
+    }
+
+    fn uniform(members: &[(u32, u32)], size: u32) -> ShaderBinding
+    {
+        ShaderBinding
+        {
+            group: 0,
+            binding: 0,
+            binding_type: ShaderBindingType::UniformBuffer,
+            size,
+            members: members.iter().map(|(offset, size)| UniformMemberLayout { offset: *offset, size: *size }).collect(),
+        }
+    }
+
+    #[test]
+    fn uniform_layout_matching()
+    {
+        assert!(uniform(&[(0, 16), (16, 4), (20, 4)], 24).matches_uniform(&TestUniform::LAYOUT));
+        // trailing members can be omitted
+        assert!(uniform(&[(0, 16)], 16).matches_uniform(&TestUniform::LAYOUT));
+        // too large
+        assert!(!uniform(&[(0, 16), (16, 4), (20, 4), (24, 4)], 28).matches_uniform(&TestUniform::LAYOUT));
+        // misaligned
+        assert!(!uniform(&[(0, 16), (20, 4)], 24).matches_uniform(&TestUniform::LAYOUT));
+    }
+
+    fn binding(group: BindGroupSlot, binding: u32, binding_type: ShaderBindingType, size: usize) -> ShaderBinding
+    {
+        ShaderBinding { group: group as u32, binding, binding_type, size: size as u32, members: Box::new([]) }
+    }
+
+    fn texture(dimension: ShaderTextureDimension, sample_kind: Option<ShaderScalarKind>) -> ShaderBindingType
+    {
+        ShaderBindingType::Texture { dimension, sample_kind, multisampled: false }
+    }
+
+    // The bindings of the PBR pixel shaders (see Pbr.hlsli)
+    fn pbr_reflection() -> ShaderReflection
+    {
+        let mut bindings = vec!
+        [
+            binding(BindGroupSlot::Material, 0, ShaderBindingType::UniformBuffer, size_of::<SimpleOpaque>()),
+            binding(BindGroupSlot::Material, 1, ShaderBindingType::Sampler { comparison: false }, 0),
+        ];
+        bindings.extend(PbrTextureSlot::ALL.map(|slot|
+            binding(BindGroupSlot::Material, slot.binding(), texture(ShaderTextureDimension::D2, Some(ShaderScalarKind::Float)), 0)));
+        bindings.extend(
+        [
+            binding(BindGroupSlot::Shadows, 0, ShaderBindingType::UniformBuffer, size_of::<ShadowsUniform>()),
+            binding(BindGroupSlot::Shadows, 1, texture(ShaderTextureDimension::D2Array, None), 0),
+            binding(BindGroupSlot::Shadows, 2, ShaderBindingType::Sampler { comparison: true }, 0),
+            binding(BindGroupSlot::Lights, 0, ShaderBindingType::UniformBuffer, size_of::<ClusterGridUniform>()),
+            binding(BindGroupSlot::Lights, 1, ShaderBindingType::StorageBuffer { read_only: true }, 0),
+            binding(BindGroupSlot::Lights, 2, ShaderBindingType::StorageBuffer { read_only: true }, 0),
+            binding(BindGroupSlot::Lights, 3, ShaderBindingType::StorageBuffer { read_only: true }, 0),
+        ]);
+        ShaderReflection { bindings: bindings.into_boxed_slice(), vertex_inputs: Box::new([]) }
+    }
+
+    #[test]
+    fn engine_layouts()
+    {
+        let reflection = pbr_reflection();
+        for class in [MaterialClass::PbrOpaque, MaterialClass::PbrTransparent]
+        {
+            reflection.validate_group(BindGroupSlot::Material as u32, class.bind_layout_entries()).unwrap();
+        }
+        reflection.validate_group(BindGroupSlot::Shadows as u32, SHADOWS_BIND_LAYOUT_ENTRIES).unwrap();
+        reflection.validate_group(BindGroupSlot::Lights as u32, LIGHTS_BIND_LAYOUT_ENTRIES).unwrap();
+
+        // debug lines have no material bindings
+        assert!(reflection.validate_group(BindGroupSlot::Material as u32, MaterialClass::DebugLines.bind_layout_entries()).is_err());
+    }
+
+    #[test]
+    fn engine_layout_mismatches()
+    {
+        let validate = |group: BindGroupSlot, entries: &[BindGroupLayoutEntry], binding: ShaderBinding|
+            ShaderReflection { bindings: Box::new([binding]), vertex_inputs: Box::new([]) }.validate_group(group as u32, entries);
+
+        let material_entries = MaterialClass::PbrOpaque.bind_layout_entries();
+        assert!(validate(BindGroupSlot::Material, material_entries,
+            binding(BindGroupSlot::Material, 0, ShaderBindingType::UniformBuffer, size_of::<SimpleOpaque>() + 4)).is_err(), "Larger than the Rust type");
+        assert!(validate(BindGroupSlot::Material, material_entries,
+            binding(BindGroupSlot::Material, PbrTextureSlot::COUNT as u32 + 2, texture(ShaderTextureDimension::D2, Some(ShaderScalarKind::Float)), 0)).is_err(), "Past the last slot");
+        assert!(validate(BindGroupSlot::Material, material_entries,
+            binding(BindGroupSlot::Material, PbrTextureSlot::Albedo.binding(), texture(ShaderTextureDimension::D3, Some(ShaderScalarKind::Float)), 0)).is_err());
+
+        assert!(validate(BindGroupSlot::Shadows, SHADOWS_BIND_LAYOUT_ENTRIES,
+            binding(BindGroupSlot::Shadows, 1, texture(ShaderTextureDimension::D2Array, Some(ShaderScalarKind::Float)), 0)).is_err(), "The shadow map is a depth texture");
+        assert!(validate(BindGroupSlot::Shadows, SHADOWS_BIND_LAYOUT_ENTRIES,
+            binding(BindGroupSlot::Shadows, 2, ShaderBindingType::Sampler { comparison: false }, 0)).is_err());
+
+        assert!(validate(BindGroupSlot::Lights, LIGHTS_BIND_LAYOUT_ENTRIES,
+            binding(BindGroupSlot::Lights, 1, ShaderBindingType::StorageBuffer { read_only: false }, 0)).is_err(), "The lights are read only");
+    }
+}
@@ -0,0 +1,389 @@
+use arrayvec::ArrayVec;
+use glam::{Quat, Vec3};
+use math_3l14::DualQuat;
+use crate::assets::{Skeleton, MAX_SKINNED_BONES};
+use crate::skeleton_poser::{weighted, SkeletonPoser};
+
+// IK solvers, applied to a poser's local poses after blending and before build_poses() (see SkeletonPoser::solve_ik())
+// Bones are skeleton indices, and targets are in skin/skeleton space
+
+pub trait IkSolver
+{
+    fn apply(&self, poser: &mut SkeletonPoser);
+}
+
+// Blend the solved poses of some bones with their original poses
+struct SolveWeight
+{
+    bones: ArrayVec<(usize, DualQuat), MAX_SKINNED_BONES>,
+    weight: f32,
+}
+impl SolveWeight
+{
+    #[must_use]
+    fn new(poser: &SkeletonPoser, bones: impl IntoIterator<Item = usize>, weight: f32) -> Self
+    {
+        let bones = if weight < 1.0 { bones.into_iter().map(|b| (b, poser.local_poses()[b])).collect() } else { ArrayVec::new() };
+        Self { bones, weight }
+    }
+
+    fn apply(self, poser: &mut SkeletonPoser)
+    {
+        for (bone, original) in self.bones
+        {
+            poser.set_local_pose(bone, weighted(original, poser.local_poses()[bone], self.weight));
+        }
+    }
+}
+
+#[inline] #[must_use]
+fn position(poser: &SkeletonPoser, bone: usize) -> Vec3
+{
+    poser.skeleton_space_pose(bone).translation()
+}
+
+#[inline] #[must_use]
+fn is_parent(skeleton: &Skeleton, parent: usize, child: usize) -> bool
+{
+    skeleton.parent_indices[child] == parent as i16
+}
+
+// Rotate a bone so that the direction from it to a point faces a target
+fn aim_bone(poser: &mut SkeletonPoser, bone: usize, from: Vec3, to: Vec3)
+{
+    let (Some(from), Some(to)) = (from.try_normalize(), to.try_normalize()) else { return };
+    poser.rotate_bone(bone, Quat::from_rotation_arc(from, to));
+}
+
+// Analytically solve a two bone chain (e.g. thigh -> shin -> foot) so that the end bone reaches the target
+pub struct TwoBoneIk
+{
+    pub root: usize,
+    pub mid: usize,
+    pub end: usize,
+    pub target: Vec3,
+    pub pole: Option<Vec3>, // a position the middle bone bends towards (e.g. in front of the knee)
+    pub weight: f32,
+}
+impl TwoBoneIk
+{
+    pub fn solve(&self, poser: &mut SkeletonPoser)
+    {
+        puffin::profile_function!();
+
+        debug_assert!(is_parent(poser.skeleton(), self.root, self.mid) && is_parent(poser.skeleton(), self.mid, self.end),
+            "Two bone IK bones must be a parent chain");
+
+        let weight = SolveWeight::new(poser, [self.root, self.mid], self.weight);
+
+        let (a, b, c) = (position(poser, self.root), position(poser, self.mid), position(poser, self.end));
+        let (ab_len, bc_len) = (a.distance(b), b.distance(c));
+        if ab_len <= f32::EPSILON || bc_len <= f32::EPSILON { return; }
+
+        // clamped slightly short of fully extended, to avoid snapping when straightening
+        let at_len = a.distance(self.target).clamp(f32::EPSILON, (ab_len + bc_len) * 0.9999);
+
+        let angle_between = |u: Vec3, v: Vec3| u.normalize_or_zero().dot(v.normalize_or_zero()).clamp(-1.0, 1.0).acos();
+        let from_sides = |adjacent_a: f32, adjacent_b: f32, opposite: f32|
+            ((adjacent_a * adjacent_a + adjacent_b * adjacent_b - opposite * opposite) / (2.0 * adjacent_a * adjacent_b)).clamp(-1.0, 1.0).acos();
+
+        // the axis the chain bends around, falling back to the pole (or anything) if the chain is straight
+        let bend_axis = (c - a).cross(b - a).try_normalize()
+            .or_else(|| self.pole.and_then(|pole| (c - a).cross(pole - a).try_normalize()))
+            .unwrap_or_else(|| (c - a).any_orthonormal_vector());
+
+        // open/close the middle joint so that the chain spans the distance to the target
+        let mid_angle = from_sides(ab_len, bc_len, at_len) - angle_between(a - b, c - b);
+        poser.rotate_bone(self.mid, Quat::from_axis_angle(bend_axis, mid_angle));
+
+        // swing the chain to face the target
+        let c = position(poser, self.end);
+        aim_bone(poser, self.root, c - a, self.target - a);
+
+        // twist the chain around the root->target axis so the middle bone faces the pole
+        if let Some(pole) = self.pole && let Some(axis) = (self.target - a).try_normalize()
+        {
+            let b = position(poser, self.mid);
+            let to_mid = (b - a).reject_from_normalized(axis);
+            let to_pole = (pole - a).reject_from_normalized(axis);
+            aim_bone(poser, self.root, to_mid, to_pole);
+        }
+
+        weight.apply(poser);
+    }
+}
+impl IkSolver for TwoBoneIk
+{
+    fn apply(&self, poser: &mut SkeletonPoser) { self.solve(poser); }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainSolver
+{
+    Fabrik, // forward and backward reaching, moves the whole chain evenly
+    Ccd, // cyclic coordinate descent, favors bending the bones near the end
+}
+
+// Iteratively solve a chain of any length so that the last bone reaches the target
+pub struct IkChain<'b>
+{
+    pub bones: &'b [usize], // root first, each bone must be the parent of the next
+    pub target: Vec3,
+    pub solver: ChainSolver,
+    pub max_iterations: u32,
+    pub tolerance: f32, // stop once the end is this close to the target
+    pub weight: f32,
+}
+impl IkChain<'_>
+{
+    // Returns true if the target was reached
+    pub fn solve(&self, poser: &mut SkeletonPoser) -> bool
+    {
+        puffin::profile_function!();
+
+        debug_assert!(self.bones.windows(2).all(|w| is_parent(poser.skeleton(), w[0], w[1])), "IK chain bones must be a parent chain");
+        let [.., end] = *self.bones else { return false };
+        if self.bones.len() < 2 { return false; }
+
+        let weight = SolveWeight::new(poser, self.bones[..self.bones.len() - 1].iter().copied(), self.weight);
+        match self.solver
+        {
+            ChainSolver::Fabrik => self.solve_fabrik(poser),
+            ChainSolver::Ccd => self.solve_ccd(poser),
+        }
+        let reached = position(poser, end).distance(self.target) <= self.tolerance;
+
+        weight.apply(poser);
+        reached
+    }
+
+    fn solve_fabrik(&self, poser: &mut SkeletonPoser)
+    {
+        let mut positions: ArrayVec<Vec3, MAX_SKINNED_BONES> = self.bones.iter().map(|b| position(poser, *b)).collect();
+        let lengths: ArrayVec<f32, MAX_SKINNED_BONES> = positions.windows(2).map(|w| w[0].distance(w[1])).collect();
+        let root = positions[0];
+        let last = positions.len() - 1;
+
+        for _ in 0..self.max_iterations
+        {
+            if positions[last].distance(self.target) <= self.tolerance { break; }
+
+            // backward, from the target to the root
+            positions[last] = self.target;
+            for i in (0..last).rev()
+            {
+                positions[i] = positions[i + 1] + (positions[i] - positions[i + 1]).normalize_or_zero() * lengths[i];
+            }
+
+            // forward, from the root back to the end
+            positions[0] = root;
+            for i in 0..last
+            {
+                positions[i + 1] = positions[i] + (positions[i + 1] - positions[i]).normalize_or_zero() * lengths[i];
+            }
+        }
+
+        // rotate each bone to point at its child's solved position
+        for i in 0..last
+        {
+            let bone_pos = position(poser, self.bones[i]);
+            let child_pos = position(poser, self.bones[i + 1]);
+            aim_bone(poser, self.bones[i], child_pos - bone_pos, positions[i + 1] - bone_pos);
+        }
+    }
+
+    fn solve_ccd(&self, poser: &mut SkeletonPoser)
+    {
+        let end = self.bones[self.bones.len() - 1];
+        for _ in 0..self.max_iterations
+        {
+            if position(poser, end).distance(self.target) <= self.tolerance { break; }
+
+            // rotate each bone, from the end to the root, to point the end at the target
+            for &bone in self.bones[..self.bones.len() - 1].iter().rev()
+            {
+                let bone_pos = position(poser, bone);
+                aim_bone(poser, bone, position(poser, end) - bone_pos, self.target - bone_pos);
+            }
+        }
+    }
+}
+impl IkSolver for IkChain<'_>
+{
+    fn apply(&self, poser: &mut SkeletonPoser) { self.solve(poser); }
+}
+
+// Rotate a bone (e.g. a head) to face a target
+pub struct LookAt
+{
+    pub bone: usize,
+    pub forward: Vec3, // the bone's (local space) facing direction
+    pub target: Vec3,
+    pub max_angle: f32, // in radians, from the bone's animated facing
+    pub weight: f32,
+}
+impl LookAt
+{
+    pub fn solve(&self, poser: &mut SkeletonPoser)
+    {
+        puffin::profile_function!();
+
+        let pose = poser.skeleton_space_pose(self.bone);
+        let forward = pose.rotation() * self.forward;
+        let (Some(forward), Some(to_target)) = (forward.try_normalize(), (self.target - pose.translation()).try_normalize()) else { return };
+
+        let angle = forward.angle_between(to_target);
+        if angle <= f32::EPSILON { return; }
+
+        let fraction = (self.max_angle / angle).min(1.0) * self.weight.clamp(0.0, 1.0);
+        poser.rotate_bone(self.bone, Quat::IDENTITY.slerp(Quat::from_rotation_arc(forward, to_target), fraction));
+    }
+}
+impl IkSolver for LookAt
+{
+    fn apply(&self, poser: &mut SkeletonPoser) { self.solve(poser); }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::f32::consts::FRAC_PI_4;
+    use math_3l14::Ratio;
+    use nab_3l14::TickCount;
+    use crate::assets::{AnimCompression, BoneId, SkeletalAnimation};
+    use crate::skeleton_poser::{BoneRemap, PoseBlendMode};
+    use super::*;
+
+    // a chain of bones, each one unit long along +Y, slightly bent along +Z so that it has a natural bend direction
+    fn generate_chain(length: usize) -> Skeleton
+    {
+        let bind_poses: Box<[_]> = (0..length).map(|i|
+        {
+            let offset = if i == 0 { Vec3::ZERO } else { Vec3::Y };
+            DualQuat::from_rot_trans(Quat::from_rotation_x(if i == 1 { 0.1 } else { 0.0 }), offset)
+        }).collect();
+
+        // the inverse bind poses are in skeleton space, each bone's pose is relative to its parent
+        let mut skeleton_space = DualQuat::IDENTITY;
+        let inverse_bind_poses = bind_poses.iter().map(|p|
+        {
+            skeleton_space = skeleton_space * *p;
+            skeleton_space.inverse()
+        }).collect();
+
+        Skeleton
+        {
+            bone_ids: (0..length).map(|i| BoneId(i as u32)).collect(),
+            parent_indices: (0..length).map(|i| i as i16 - 1).collect(),
+            inverse_bind_poses,
+            bind_poses,
+        }
+    }
+
+    #[track_caller]
+    fn assert_near(actual: Vec3, expected: Vec3, epsilon: f32)
+    {
+        assert!(actual.abs_diff_eq(expected, epsilon), "{actual} != {expected}");
+    }
+
+    #[test]
+    fn two_bone()
+    {
+        let skeleton = generate_chain(3);
+
+        let mut poser = SkeletonPoser::new(&skeleton);
+        let target = Vec3::new(1.0, 1.0, 0.0);
+        TwoBoneIk { root: 0, mid: 1, end: 2, target, pole: None, weight: 1.0 }.solve(&mut poser);
+        assert_near(poser.skeleton_space_pose(2).translation(), target, 1e-3);
+        assert_near(poser.skeleton_space_pose(0).translation(), Vec3::ZERO, 1e-5);
+        assert!((poser.skeleton_space_pose(1).translation().length() - 1.0).abs() < 1e-3, "bone lengths are kept");
+
+        // bend towards the pole
+        let mut poser = SkeletonPoser::new(&skeleton);
+        TwoBoneIk { root: 0, mid: 1, end: 2, target, pole: Some(Vec3::new(0.0, 0.0, -5.0)), weight: 1.0 }.solve(&mut poser);
+        assert_near(poser.skeleton_space_pose(2).translation(), target, 1e-3);
+        assert!(poser.skeleton_space_pose(1).translation().z < -0.5);
+
+        // out of reach
+        let mut poser = SkeletonPoser::new(&skeleton);
+        TwoBoneIk { root: 0, mid: 1, end: 2, target: Vec3::new(10.0, 0.0, 0.0), pole: None, weight: 1.0 }.solve(&mut poser);
+        assert_near(poser.skeleton_space_pose(2).translation(), Vec3::new(2.0, 0.0, 0.0), 1e-2);
+
+        // half weight leaves the end between the animated pose and the target
+        let mut poser = SkeletonPoser::new(&skeleton);
+        let start = poser.skeleton_space_pose(2).translation();
+        TwoBoneIk { root: 0, mid: 1, end: 2, target, pole: None, weight: 0.5 }.solve(&mut poser);
+        let end = poser.skeleton_space_pose(2).translation();
+        assert!(end.distance(target) > 0.1 && end.distance(start) > 0.1);
+    }
+
+    #[test]
+    fn chains()
+    {
+        let skeleton = generate_chain(5);
+        let bones = [0, 1, 2, 3, 4];
+
+        for solver in [ChainSolver::Fabrik, ChainSolver::Ccd]
+        {
+            let mut poser = SkeletonPoser::new(&skeleton);
+            let target = Vec3::new(2.0, 2.0, 1.0);
+            let chain = IkChain { bones: &bones, target, solver, max_iterations: 64, tolerance: 1e-3, weight: 1.0 };
+            assert!(chain.solve(&mut poser), "{solver:?}");
+            assert_near(poser.skeleton_space_pose(4).translation(), target, 2e-3);
+            for bone in 1..5
+            {
+                let length = poser.skeleton_space_pose(bone).translation().distance(poser.skeleton_space_pose(bone - 1).translation());
+                assert!((length - 1.0).abs() < 1e-3, "{solver:?} bone lengths are kept");
+            }
+
+            let mut poser = SkeletonPoser::new(&skeleton);
+            let chain = IkChain { bones: &bones, target: Vec3::new(0.0, 0.0, 10.0), solver, max_iterations: 64, tolerance: 1e-3, weight: 1.0 };
+            assert!(!chain.solve(&mut poser));
+            assert!(poser.skeleton_space_pose(4).translation().z > 3.5, "{solver:?} reaches towards the target");
+        }
+    }
+
+    #[test]
+    fn look_at()
+    {
+        let skeleton = generate_chain(2);
+        let forward = |poser: &SkeletonPoser| (poser.skeleton_space_pose(1).rotation() * Vec3::Y).normalize();
+
+        let mut poser = SkeletonPoser::new(&skeleton);
+        LookAt { bone: 1, forward: Vec3::Y, target: Vec3::new(5.0, 1.0, 0.0), max_angle: 10.0, weight: 1.0 }.solve(&mut poser);
+        assert_near(forward(&poser), Vec3::X, 1e-4);
+        assert_near(poser.skeleton_space_pose(1).translation(), Vec3::Y, 1e-5);
+
+        // limited
+        let mut poser = SkeletonPoser::new(&skeleton);
+        let before = forward(&poser);
+        LookAt { bone: 1, forward: Vec3::Y, target: Vec3::new(0.0, 1.0, -5.0), max_angle: FRAC_PI_4, weight: 1.0 }.solve(&mut poser);
+        assert!((forward(&poser).angle_between(before) - FRAC_PI_4).abs() < 1e-3);
+    }
+
+    #[test]
+    fn after_blending()
+    {
+        let skeleton = generate_chain(3);
+        let bent = DualQuat::from_rot_trans(Quat::from_rotation_x(1.0), Vec3::Y);
+        let anim = SkeletalAnimation::compress(Ratio::new(1, 1), Box::new([BoneId(1)]), 1, &[bent.rotation()], &[bent.translation()], &[], AnimCompression::KEEP_KEYS);
+        let remap = BoneRemap::new(&skeleton, &anim);
+
+        let mut poser = SkeletonPoser::new(&skeleton);
+        poser.blend(&anim, &remap, PoseBlendMode::Replace, None, TickCount(0), true);
+
+        let target = Vec3::new(0.5, 1.5, 0.5);
+        let forward = Vec3::Y;
+        poser.solve_ik(&[
+            &TwoBoneIk { root: 0, mid: 1, end: 2, target, pole: None, weight: 1.0 },
+            &LookAt { bone: 2, forward, target: Vec3::new(5.0, 1.5, 0.5), max_angle: 10.0, weight: 1.0 },
+        ]);
+        poser.build_poses();
+        let posed = poser.finalize();
+
+        // the end bone is skinned from its bind pose to the target, facing the look target
+        let end_bind = skeleton.inverse_bind_poses[2].inverse();
+        assert_near(posed[2].pose.transform_point3(end_bind.translation()), target, 1e-3);
+        assert_near((posed[2].pose * end_bind).rotation() * forward, Vec3::X, 1e-3);
+    }
+}
@@ -1,27 +1,199 @@
+use std::collections::HashMap;
 use arrayvec::ArrayVec;
-use glam::Vec3;
-use math_3l14::{DualQuat, Ratio};
+use glam::{Mat4, Quat, Vec3, Vec4};
+use math_3l14::{DualQuat, Ratio, ScaledDualQuat};
 use nab_3l14::{TickCount};
-use crate::assets::{AnimFrameNumber, SkeletalAnimation, Skeleton, MAX_SKINNED_BONES};
+use crate::assets::{AnimFrameNumber, BoneId, SkeletalAnimation, Skeleton, MAX_SKINNED_BONES};
+use crate::skeleton_ik::IkSolver;
 
-type PoseSet = ArrayVec<DualQuat, MAX_SKINNED_BONES>;
+pub type PoseSet = ArrayVec<DualQuat, MAX_SKINNED_BONES>;
+pub type ScaleSet = ArrayVec<Vec3, MAX_SKINNED_BONES>;
 
+// A bone's final skinning transform, as uploaded to the skinning shaders
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SkinnedBone
+{
+    pub pose: DualQuat, // the rigid part of the transform (bind space to model space)
+    pub scale: [Vec4; 3], // the rows of an affine matrix that scales around the bone in bind space, applied before the pose
+    pub bulge_axis: Vec4, // xyz: the (posed) direction of the bone, w: how much bulging to remove along it
+}
+impl SkinnedBone
+{
+    const UNSCALED: [Vec4; 3] = [Vec4::X, Vec4::Y, Vec4::Z];
+}
+pub type SkinnedPoseSet = ArrayVec<SkinnedBone, MAX_SKINNED_BONES>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdditiveReference
+{
+    FirstFrame, // the animation's first frame
+    BindPose, // the skeleton's bind pose
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PoseBlendMode
 {
+    // Replace the animated bones with the animation's pose
     Replace,
-    Additive(f32),
+    // Move the animated bones towards the animation's pose by a weight
+    Blend(f32),
+    // Apply the animation's difference from a reference pose on top of the current pose, scaled by a weight
+    Additive { weight: f32, reference: AdditiveReference },
+    // Replace the animated bones, and reset all other bones to the bind pose
     Exclusive,
 }
 
-// TODO: https://rodolphe-vaillant.fr/entry/72/bulge-free-dual-quaternion-skinning-trick
-// TODO: https://rodolphe-vaillant.fr/entry/78/dual-quaternion-skinning-with-scale
+const NO_BONE: u16 = u16::MAX;
+
+// Maps an animation's bones to a skeleton's bones
+// This should be built once per skeleton/animation pair and reused each time the animation is blended
+pub struct BoneRemap
+{
+    skeleton_indices: Box<[u16]>, // parallel to the animation's bones, NO_BONE if the skeleton does not have the bone
+}
+impl BoneRemap
+{
+    #[must_use]
+    pub fn new(skeleton: &Skeleton, animation: &SkeletalAnimation) -> Self
+    {
+        let bone_indices: HashMap<BoneId, u16> = skeleton.bone_ids.iter()
+            .take(MAX_SKINNED_BONES)
+            .enumerate()
+            .map(|(i, bone)| (*bone, i as u16))
+            .collect();
+
+        let skeleton_indices = animation.bones.iter()
+            .map(|bone| bone_indices.get(bone).copied().unwrap_or(NO_BONE))
+            .collect();
+        Self { skeleton_indices }
+    }
+
+    // The skeleton bone index of an animation bone, if the skeleton has the bone
+    #[inline] #[must_use]
+    pub fn skeleton_index(&self, animation_bone: usize) -> Option<usize>
+    {
+        let index = self.skeleton_indices[animation_bone];
+        (index != NO_BONE).then_some(index as usize)
+    }
+
+    // The number of animated bones that are not in the skeleton (and will be ignored)
+    #[must_use]
+    pub fn missing_bone_count(&self) -> usize
+    {
+        self.skeleton_indices.iter().filter(|i| **i == NO_BONE).count()
+    }
+}
+
+// Per-bone weights for blending an animation, e.g. to only animate the upper body
+#[derive(Debug, Clone)]
+pub struct BoneMask
+{
+    weights: Box<[f32]>, // indexed by skeleton bone
+}
+impl BoneMask
+{
+    #[must_use]
+    pub fn new(skeleton: &Skeleton, weight: f32) -> Self
+    {
+        Self { weights: vec![weight; skeleton.bone_ids.len()].into_boxed_slice() }
+    }
+
+    // A mask that only includes a bone and all of its descendants
+    #[must_use]
+    pub fn from_subtree(skeleton: &Skeleton, root_bone: usize, weight: f32) -> Self
+    {
+        let mut mask = Self::new(skeleton, 0.0);
+        mask.set_subtree(skeleton, root_bone, weight);
+        mask
+    }
+
+    #[inline] #[must_use]
+    pub fn weight(&self, bone: usize) -> f32 { self.weights.get(bone).copied().unwrap_or(0.0) }
+
+    #[inline]
+    pub fn set_weight(&mut self, bone: usize, weight: f32) { self.weights[bone] = weight; }
+
+    // Set the weight of a bone and all of its descendants
+    pub fn set_subtree(&mut self, skeleton: &Skeleton, root_bone: usize, weight: f32)
+    {
+        let mut in_subtree = vec![false; self.weights.len()];
+        in_subtree[root_bone] = true;
+        self.weights[root_bone] = weight;
+
+        // bones are ordered parents first, so descendants always come after the root
+        for bone in (root_bone + 1)..self.weights.len()
+        {
+            let parent = skeleton.parent_indices[bone];
+            if parent >= 0 && in_subtree[parent as usize]
+            {
+                in_subtree[bone] = true;
+                self.weights[bone] = weight;
+            }
+        }
+    }
+}
+
+// The (unwrapped) frame of an animation at a time, and the fraction towards the next frame
+#[must_use]
+fn frame_at_time(animation: &SkeletalAnimation, time: TickCount) -> (u32, f32)
+{
+    // would this be faster to convert to float first? (floating point div may be faster)`
+    // blend multiple animations at once w/ simd?
+
+    let sample_rate = Ratio
+    {
+        numerator: animation.sample_rate.numerator as u64,
+        denominator: animation.sample_rate.denominator as u64 * 1_000_000, // shouldn't this be 1e9?
+    };
+
+    let unclamped = (sample_rate.scale(time.0)) as u32;
+    let fraction =
+    {
+        let delta = time.0 - sample_rate.inverse_scale(unclamped as u64);
+        (delta as f32) * sample_rate.to_f32()
+    };
+    (unclamped, fraction)
+}
+
+// The (unwrapped) frame position of an animation at a time, e.g. for SkeletalAnimation::root_motion_between()
+#[inline] #[must_use]
+pub fn frame_position(animation: &SkeletalAnimation, time: TickCount) -> f32
+{
+    let (frame, fraction) = frame_at_time(animation, time);
+    frame as f32 + fraction
+}
+
+// Move a pose towards a target by a weight
+#[inline] #[must_use]
+pub(crate) fn weighted(from: DualQuat, to: DualQuat, weight: f32) -> DualQuat
+{
+    if weight >= 1.0 { to }
+    else if weight <= 0.0 { from }
+    else { from.nlerp(to, weight) }
+}
+
+// Move a scale towards a target by a weight
+#[inline] #[must_use]
+fn weighted_scale(from: Vec3, to: Vec3, weight: f32) -> Vec3
+{
+    if weight >= 1.0 { to }
+    else if weight <= 0.0 { from }
+    else { from.lerp(to, weight) }
+}
+
+// Bones are scaled separately from their (rigid) poses, so that they can still be skinned with dual quats
+// https://rodolphe-vaillant.fr/entry/78/dual-quaternion-skinning-with-scale
+// Skeletons are expected to have an unscaled bind pose
 
 // rigger?
 pub struct SkeletonPoser<'s>
 {
     poses: PoseSet, // extenral memory (and limit to # of bones)?
+    scales: ScaleSet, // parallel to poses
     skeleton: &'s Skeleton,
-    pub root_pos: Vec3,
+    root_motion: DualQuat,
+    bulge_correction: f32,
 }
 impl<'s> SkeletonPoser<'s>
 {
@@ -34,77 +206,208 @@ impl<'s> SkeletonPoser<'s>
         debug_assert_eq!(skeleton.bone_ids.len(), skeleton.parent_indices.len());
 
         let num_poses = skeleton.bind_poses.len().min(MAX_SKINNED_BONES);
-        let mut poses = PoseSet::new();
-        unsafe { poses.set_len(num_poses); } // better way?
-        // TODO: this should either be explicit or fill in gaps left by animation
-        poses[..num_poses].copy_from_slice(&skeleton.bind_poses[..num_poses]);
+        let poses = skeleton.bind_poses[..num_poses].iter().copied().collect();
+        let scales = (0..num_poses).map(|_| Vec3::ONE).collect();
 
         Self
         {
             poses,
+            scales,
             skeleton,
-            root_pos: Vec3::ZERO,
+            root_motion: DualQuat::IDENTITY,
+            bulge_correction: 1.0,
         }
     }
 
-    // TODO: blend_no_lerp() ?
+    #[inline] #[must_use]
+    pub fn skeleton(&self) -> &'s Skeleton { self.skeleton }
+
+    // The current local-space (relative to each bone's parent) poses
+    #[inline] #[must_use]
+    pub fn local_poses(&self) -> &[DualQuat] { &self.poses }
+
+    // The current local-space scales, parallel to local_poses()
+    #[inline] #[must_use]
+    pub fn local_scales(&self) -> &[Vec3] { &self.scales }
 
-    // Apply an animation to the pose.
-    pub fn blend(&mut self, animation: &SkeletalAnimation, mode: PoseBlendMode, time: TickCount, should_loop: bool)
+    // How much of the bulging around bent joints to remove when skinning, from 0 (plain dual quat skinning) to 1 (the default)
+    #[inline]
+    pub fn set_bulge_correction(&mut self, bulge_correction: f32) { self.bulge_correction = bulge_correction; }
+
+    // The root motion accumulated by blend_root_motion(), for gameplay to apply to the skeleton's owner
+    #[inline] #[must_use]
+    pub fn root_motion(&self) -> DualQuat { self.root_motion }
+
+    // Move the accumulated root motion towards a delta (see SkeletalAnimation::root_motion_between()) by a weight
+    pub fn blend_root_motion(&mut self, delta: DualQuat, weight: f32)
     {
-        puffin::profile_function!();
+        self.root_motion = weighted(self.root_motion, delta, weight);
+    }
+
+    #[inline]
+    pub fn set_local_pose(&mut self, bone: usize, pose: DualQuat) { self.poses[bone] = pose; }
 
-        // TODO: blend mode (additive, replace, exlusive(?))
+    #[inline]
+    pub fn set_local_scale(&mut self, bone: usize, scale: Vec3) { self.scales[bone] = scale; }
 
-        // would this be faster to convert to float first? (floating point div may be faster)`
-        // blend multiple animations at once w/ simd?
+    #[inline] #[must_use]
+    fn scaled_pose(&self, bone: usize) -> ScaledDualQuat { ScaledDualQuat::new(self.poses[bone], self.scales[bone]) }
 
-        let sample_rate = Ratio
+    // The skin/skeleton space pose of a bone, computed from the local poses (so only valid before build_poses())
+    #[must_use]
+    pub fn skeleton_space_pose(&self, bone: usize) -> DualQuat
+    {
+        let mut pose = self.scaled_pose(bone);
+        let mut parent = self.skeleton.parent_indices[bone];
+        while parent >= 0
         {
-            numerator: animation.sample_rate.numerator as u64,
-            denominator: animation.sample_rate.denominator as u64 * 1_000_000, // shouldn't this be 1e9?
-        };
+            pose = self.scaled_pose(parent as usize) * pose;
+            parent = self.skeleton.parent_indices[parent as usize];
+        }
+        pose.rigid
+    }
+
+    // Rotate a bone (and by extension its descendants) around its origin, by a skeleton space rotation
+    pub fn rotate_bone(&mut self, bone: usize, rotation: Quat)
+    {
+        let global = self.skeleton_space_pose(bone).rotation();
+        let local = self.poses[bone];
+        let rotated = (local.rotation() * global.inverse() * rotation * global).normalize();
+        self.poses[bone] = DualQuat::from_rot_trans(rotated, local.translation());
+    }
+
+    // TODO: blend_no_lerp() ?
+
+    // Apply an animation to the pose, optionally weighting each bone with a mask
+    // Bones in the animation that are not in the skeleton are ignored
+    pub fn blend(&mut self, animation: &SkeletalAnimation, remap: &BoneRemap, mode: PoseBlendMode, mask: Option<&BoneMask>, time: TickCount, should_loop: bool)
+    {
+        puffin::profile_function!();
 
-        let unclamped = (sample_rate.scale(time.0)) as u32;
+        debug_assert_eq!(remap.skeleton_indices.len(), animation.bones.len(), "Bone remap was built for a different animation");
+
+        let frc = animation.frame_count.0;
+        if frc == 0 { return; }
+
+        let (unclamped, fraction) = frame_at_time(animation, time);
         let (curr_frame, next_frame) = if should_loop
         {
-            let frc = animation.frame_count.0; // - 1?
             (unclamped % frc, (unclamped + 1) % frc)
         }
         else
         {
-            let frc = animation.frame_count.0;
-            (unclamped.min(frc), (unclamped + 1).min(frc))
+            (unclamped.min(frc - 1), (unclamped + 1).min(frc - 1))
         };
 
-        let fraction =
+        self.blend_frames(animation, remap, mode, mask, (curr_frame, next_frame, fraction));
+    }
+
+    // Apply an animation to the pose at a normalized (0 to 1) point in the animation
+    pub fn blend_at_phase(&mut self, animation: &SkeletalAnimation, remap: &BoneRemap, mode: PoseBlendMode, mask: Option<&BoneMask>, phase: f32, should_loop: bool)
+    {
+        puffin::profile_function!();
+
+        debug_assert_eq!(remap.skeleton_indices.len(), animation.bones.len(), "Bone remap was built for a different animation");
+
+        let frc = animation.frame_count.0;
+        if frc == 0 { return; }
+
+        let position = phase.clamp(0.0, 1.0) * frc as f32;
+        let whole = position as u32;
+        let (curr_frame, next_frame, fraction) = if should_loop
+        {
+            (whole % frc, (whole + 1) % frc, position.fract())
+        }
+        else if whole + 1 >= frc
         {
-            let delta = time.0 - sample_rate.inverse_scale(unclamped as u64);
-            (delta as f32) * sample_rate.to_f32()
+            (frc - 1, frc - 1, 0.0)
+        }
+        else
+        {
+            (whole, whole + 1, position.fract())
         };
 
-        let from = animation.get_pose_for_frame(AnimFrameNumber(curr_frame)).iter();
-        let to = animation.get_pose_for_frame(AnimFrameNumber(next_frame)).iter();
+        self.blend_frames(animation, remap, mode, mask, (curr_frame, next_frame, fraction));
+    }
+
+    fn blend_frames(&mut self, animation: &SkeletalAnimation, remap: &BoneRemap, mode: PoseBlendMode, mask: Option<&BoneMask>, (curr_frame, next_frame, fraction): (u32, u32, f32))
+    {
+        let bone_weight = |bone_idx: usize| mask.map_or(1.0, |m| m.weight(bone_idx));
 
-        for (i, (fp, tp)) in from.zip(to).enumerate()
+        let mut is_animated = [false; MAX_SKINNED_BONES];
+        for i in 0..animation.bones.len()
         {
-            let bone_id = animation.bones[i];
-            // TODO: animation should store bone indices
-            let Some(bone_idx) = self.skeleton.bone_ids.iter().position(|b| *b == animation.bones[i])
-                else { panic!("Did not find matching bone for {:?} (#{i})", animation.bones[i]); }; // skip; TODO this is likely happening when skin.skeleton node is animated
+            // bones the skeleton doesn't have, e.g. the skin's root node is not built into the skeleton
+            let Some(bone_idx) = remap.skeleton_index(i) else { continue };
+            is_animated[bone_idx] = true;
 
-            // TODO: cache reconstructed dual-quats
-            let lerped = DualQuat::nlerp(fp.clone().into(), tp.clone().into(), fraction);
+            let weight = bone_weight(bone_idx);
+            if weight <= 0.0 { continue; }
+
+            // TODO: cache decoded frames
+            let lerped = DualQuat::nlerp(
+                animation.sample_bone(i, AnimFrameNumber(curr_frame)),
+                animation.sample_bone(i, AnimFrameNumber(next_frame)),
+                fraction);
+            let lerped_scale = Vec3::lerp(
+                animation.sample_scale(i, AnimFrameNumber(curr_frame)),
+                animation.sample_scale(i, AnimFrameNumber(next_frame)),
+                fraction);
             // TODO: move the branch out of the loop
-            self.poses[bone_idx] = match mode
+            (self.poses[bone_idx], self.scales[bone_idx]) = match mode
+            {
+                PoseBlendMode::Replace |
+                PoseBlendMode::Exclusive =>
+                (
+                    weighted(self.poses[bone_idx], lerped, weight),
+                    weighted_scale(self.scales[bone_idx], lerped_scale, weight),
+                ),
+                PoseBlendMode::Blend(blend_weight) =>
+                (
+                    weighted(self.poses[bone_idx], lerped, weight * blend_weight),
+                    weighted_scale(self.scales[bone_idx], lerped_scale, weight * blend_weight),
+                ),
+                PoseBlendMode::Additive { weight: additive_weight, reference } =>
+                {
+                    let (reference_pose, reference_scale) = match reference
+                    {
+                        AdditiveReference::FirstFrame => (animation.sample_bone(i, AnimFrameNumber(0)), animation.sample_scale(i, AnimFrameNumber(0))),
+                        AdditiveReference::BindPose => (self.skeleton.bind_poses[bone_idx], Vec3::ONE),
+                    };
+                    // the change from the reference pose, in the bone's local space
+                    let delta = reference_pose.inverse() * lerped;
+                    let delta_scale = lerped_scale / reference_scale;
+                    (
+                        self.poses[bone_idx] * weighted(DualQuat::IDENTITY, delta, weight * additive_weight),
+                        self.scales[bone_idx] * weighted_scale(Vec3::ONE, delta_scale, weight * additive_weight),
+                    )
+                },
+            }
+        }
+
+        if let PoseBlendMode::Exclusive = mode
+        {
+            for (bone_idx, pose) in self.poses.iter_mut().enumerate()
             {
-                PoseBlendMode::Replace => lerped,
-                PoseBlendMode::Additive(frac) => self.poses[bone_idx].nlerp(lerped, frac), // is this correct?
-                PoseBlendMode::Exclusive => todo!("Exclusive skeletal animation blending"),
+                if is_animated[bone_idx] { continue; }
+                *pose = weighted(*pose, self.skeleton.bind_poses[bone_idx], bone_weight(bone_idx));
+                self.scales[bone_idx] = weighted_scale(self.scales[bone_idx], Vec3::ONE, bone_weight(bone_idx));
             }
         }
     }
 
+    // Adjust the blended poses with IK, in order (later solvers take priority)
+    // Call after blending and before build_poses()
+    pub fn solve_ik(&mut self, solvers: &[&dyn IkSolver])
+    {
+        puffin::profile_function!();
+
+        for solver in solvers
+        {
+            solver.apply(self);
+        }
+    }
+
     // Compute (and optionally returns) the skin/skeleton space poses (useful for drawing the skeleton)
     pub fn build_poses(&mut self) -> &[DualQuat]
     {
@@ -114,7 +417,8 @@ impl<'s> SkeletonPoser<'s>
             let parent = self.skeleton.parent_indices[i];
             if parent >= 0
             {
-                self.poses[i] = self.poses[parent as usize] * self.poses[i];
+                let posed = self.scaled_pose(parent as usize) * self.scaled_pose(i);
+                (self.poses[i], self.scales[i]) = (posed.rigid, posed.scale);
             }
         }
 
@@ -122,17 +426,32 @@ impl<'s> SkeletonPoser<'s>
     }
 
     // Transform the poses into model space and return the final poses
-    // build_world_space() must be called first
+    // build_poses() must be called first
     #[must_use]
-    pub fn finalize(mut self) -> PoseSet
+    pub fn finalize(self) -> SkinnedPoseSet
     {
         // bone to model space
-        for i in 0..self.poses.len()
+        (0..self.poses.len()).map(|i|
         {
-            self.poses[i] = self.poses[i] * self.skeleton.inverse_bind_poses[i];
-        }
-
-        self.poses
+            let inverse_bind = self.skeleton.inverse_bind_poses[i];
+            let scale = if self.scales[i] == Vec3::ONE
+            {
+                SkinnedBone::UNSCALED
+            }
+            else
+            {
+                // scale around the bone's bind pose, so that the (rigid) pose can be applied afterwards
+                let scale = Mat4::from(inverse_bind.inverse()) * Mat4::from_scale(self.scales[i]) * Mat4::from(inverse_bind);
+                [scale.row(0), scale.row(1), scale.row(2)]
+            };
+            SkinnedBone
+            {
+                pose: self.poses[i] * inverse_bind,
+                scale,
+                // bones point along their local Y axis
+                bulge_axis: (self.poses[i].rotation() * Vec3::Y).extend(self.bulge_correction),
+            }
+        }).collect()
     }
 }
 
@@ -140,16 +459,15 @@ impl<'s> SkeletonPoser<'s>
 mod tests
 {
     use std::f32::consts::FRAC_PI_2;
-    use glam::{Quat, Vec3};
-    use crate::assets::BoneId;
+    use glam::Vec3;
+    use crate::assets::AnimCompression;
     use super::*;
 
-    // TODO: fix
     fn generate_skeleton() -> Skeleton
     {
         let j0 = DualQuat::IDENTITY;
-        let j1 = j0 * DualQuat::from_rot_trans(Quat::from_rotation_z(FRAC_PI_2), Vec3::new(0.0, 2.0, 0.0));
-        let j2 = j1 * DualQuat::from_rot_trans(Quat::IDENTITY, Vec3::new(0.0, 1.0, 0.0));
+        let j1 = DualQuat::from_rot_trans(Quat::from_rotation_z(FRAC_PI_2), Vec3::new(0.0, 2.0, 0.0));
+        let j2 = DualQuat::from_rot_trans(Quat::IDENTITY, Vec3::new(0.0, 1.0, 0.0));
 
         Skeleton
         {
@@ -170,20 +488,243 @@ mod tests
             ]),
             inverse_bind_poses: Box::new([
                 j0.inverse(),
-                j1.inverse(),
-                j2.inverse(),
+                (j0 * j1).inverse(),
+                (j0 * j1 * j2).inverse(),
             ])
         }
     }
 
+    // an animation sampled at one frame per 1_000_000 ticks
+    fn generate_animation(bones: &[BoneId], frames: &[&[DualQuat]]) -> SkeletalAnimation
+    {
+        // frames are ordered by time, but the animation's samples are ordered by bone
+        let samples = || (0..bones.len()).flat_map(|b| frames.iter().map(move |f| f[b]));
+        SkeletalAnimation::compress(
+            Ratio::new(1, 1),
+            bones.into(),
+            frames.len() as u32,
+            &samples().map(|p| p.rotation()).collect::<Vec<_>>(),
+            &samples().map(|p| p.translation()).collect::<Vec<_>>(),
+            &[],
+            AnimCompression::KEEP_KEYS)
+    }
+
+    fn frame_time(frame: u64) -> TickCount { TickCount(frame * 1_000_000) }
+
+    #[track_caller]
+    fn assert_pose_eq(actual: DualQuat, expected: DualQuat)
+    {
+        // animations are stored compressed, so can only be roughly compared
+        let same_rotation = actual.rotation().abs_diff_eq(expected.rotation(), 1e-2) ||
+            actual.rotation().abs_diff_eq(-expected.rotation(), 1e-2);
+        assert!(same_rotation && actual.translation().abs_diff_eq(expected.translation(), 1e-2),
+            "{actual:?} != {expected:?}");
+    }
+
     #[test]
     pub fn no_anim()
     {
         let skeleton = generate_skeleton();
-        let poser = SkeletonPoser::new(&skeleton);
-        let posed = poser.finalize();
+        let mut poser = SkeletonPoser::new(&skeleton);
+        let posed = poser.build_poses();
         assert_eq!(posed.len(), 3);
+        assert_pose_eq(posed[2], skeleton.inverse_bind_poses[2].inverse());
+
+        // the bind pose has no effect on the skinned verts
+        let posed = poser.finalize();
+        for bone in posed
+        {
+            assert_pose_eq(bone.pose, DualQuat::IDENTITY);
+            assert_eq!(bone.scale, SkinnedBone::UNSCALED);
+        }
+    }
+
+    #[test]
+    pub fn replace_skips_missing_bones()
+    {
+        let skeleton = generate_skeleton();
+        let pose = DualQuat::from_rot_trans(Quat::from_rotation_x(FRAC_PI_2), Vec3::new(0.0, 3.0, 0.0));
+        let anim = generate_animation(&[BoneId(1), BoneId(99)], &[&[pose, DualQuat::IDENTITY]]);
+
+        let remap = BoneRemap::new(&skeleton, &anim);
+        assert_eq!(remap.missing_bone_count(), 1);
+
+        let mut poser = SkeletonPoser::new(&skeleton);
+        poser.blend(&anim, &remap, PoseBlendMode::Replace, None, frame_time(0), true);
+        assert_pose_eq(poser.local_poses()[0], skeleton.bind_poses[0]);
+        assert_pose_eq(poser.local_poses()[1], pose);
+        assert_pose_eq(poser.local_poses()[2], skeleton.bind_poses[2]);
+    }
+
+    #[test]
+    pub fn replace_interpolates_frames()
+    {
+        let skeleton = generate_skeleton();
+        let from = DualQuat::from_rot_trans(Quat::IDENTITY, Vec3::ZERO);
+        let to = DualQuat::from_rot_trans(Quat::IDENTITY, Vec3::new(4.0, 0.0, 0.0));
+        let anim = generate_animation(&[BoneId(2)], &[&[from], &[to]]);
+        let remap = BoneRemap::new(&skeleton, &anim);
+
+        let mut poser = SkeletonPoser::new(&skeleton);
+        poser.blend(&anim, &remap, PoseBlendMode::Replace, None, TickCount(250_000), false);
+        assert_pose_eq(poser.local_poses()[2], DualQuat::from_rot_trans(Quat::IDENTITY, Vec3::new(1.0, 0.0, 0.0)));
+
+        // non-looping animations hold their last frame
+        let mut poser = SkeletonPoser::new(&skeleton);
+        poser.blend(&anim, &remap, PoseBlendMode::Replace, None, frame_time(5), false);
+        assert_pose_eq(poser.local_poses()[2], to);
+    }
+
+    #[test]
+    pub fn empty_animation()
+    {
+        let skeleton = generate_skeleton();
+        let pose = DualQuat::from_rot_trans(Quat::IDENTITY, Vec3::new(4.0, 0.0, 0.0));
+        let mut anim = generate_animation(&[BoneId(1)], &[&[pose]]);
+        anim.frame_count = AnimFrameNumber(0);
+        let remap = BoneRemap::new(&skeleton, &anim);
+
+        // animations without frames have no effect
+        let mut poser = SkeletonPoser::new(&skeleton);
+        poser.blend(&anim, &remap, PoseBlendMode::Replace, None, frame_time(3), true);
+        poser.blend(&anim, &remap, PoseBlendMode::Replace, None, frame_time(3), false);
+        poser.blend_at_phase(&anim, &remap, PoseBlendMode::Replace, None, 0.5, true);
+        poser.blend_at_phase(&anim, &remap, PoseBlendMode::Replace, None, 0.5, false);
+        assert_pose_eq(poser.local_poses()[1], skeleton.bind_poses[1]);
+    }
+
+    #[test]
+    pub fn exclusive()
+    {
+        let skeleton = generate_skeleton();
+        let pose_a = DualQuat::from_rot_trans(Quat::from_rotation_y(FRAC_PI_2), Vec3::new(0.0, 1.0, 1.0));
+        let pose_b = DualQuat::from_rot_trans(Quat::from_rotation_x(FRAC_PI_2), Vec3::new(0.0, 2.0, 0.0));
+        let anim_a = generate_animation(&[BoneId(0), BoneId(2)], &[&[pose_a, pose_a]]);
+        let anim_b = generate_animation(&[BoneId(1)], &[&[pose_b]]);
+
+        let mut poser = SkeletonPoser::new(&skeleton);
+        poser.blend(&anim_a, &BoneRemap::new(&skeleton, &anim_a), PoseBlendMode::Replace, None, frame_time(0), true);
+        poser.blend(&anim_b, &BoneRemap::new(&skeleton, &anim_b), PoseBlendMode::Exclusive, None, frame_time(0), true);
+        assert_pose_eq(poser.local_poses()[0], skeleton.bind_poses[0]);
+        assert_pose_eq(poser.local_poses()[1], pose_b);
+        assert_pose_eq(poser.local_poses()[2], skeleton.bind_poses[2]);
+    }
+
+    #[test]
+    pub fn additive()
+    {
+        let skeleton = generate_skeleton();
+        let delta = DualQuat::from_rot_trans(Quat::from_rotation_y(FRAC_PI_2), Vec3::new(1.0, 0.0, 0.0));
+        let base = DualQuat::from_rot_trans(Quat::from_rotation_x(FRAC_PI_2), Vec3::new(0.0, 5.0, 0.0));
+        let base_anim = generate_animation(&[BoneId(1)], &[&[base]]);
+        let base_remap = BoneRemap::new(&skeleton, &base_anim);
+
+        // relative to the bind pose
+        let anim = generate_animation(&[BoneId(1)], &[&[skeleton.bind_poses[1] * delta]]);
+        let remap = BoneRemap::new(&skeleton, &anim);
+        let mut poser = SkeletonPoser::new(&skeleton);
+        poser.blend(&base_anim, &base_remap, PoseBlendMode::Replace, None, frame_time(0), true);
+        poser.blend(&anim, &remap, PoseBlendMode::Additive { weight: 1.0, reference: AdditiveReference::BindPose }, None, frame_time(0), true);
+        assert_pose_eq(poser.local_poses()[1], base * delta);
+
+        // relative to the first frame
+        let reference = DualQuat::from_rot_trans(Quat::from_rotation_z(1.0), Vec3::new(0.0, 0.0, 3.0));
+        let anim = generate_animation(&[BoneId(1)], &[&[reference], &[reference * delta]]);
+        let remap = BoneRemap::new(&skeleton, &anim);
+        let mut poser = SkeletonPoser::new(&skeleton);
+        poser.blend(&base_anim, &base_remap, PoseBlendMode::Replace, None, frame_time(0), true);
+        poser.blend(&anim, &remap, PoseBlendMode::Additive { weight: 1.0, reference: AdditiveReference::FirstFrame }, None, frame_time(1), false);
+        assert_pose_eq(poser.local_poses()[1], base * delta);
+
+        // the reference pose itself adds nothing
+        let mut poser = SkeletonPoser::new(&skeleton);
+        poser.blend(&base_anim, &base_remap, PoseBlendMode::Replace, None, frame_time(0), true);
+        poser.blend(&anim, &remap, PoseBlendMode::Additive { weight: 1.0, reference: AdditiveReference::FirstFrame }, None, frame_time(0), false);
+        assert_pose_eq(poser.local_poses()[1], base);
+
+        // weighted
+        let rotate = DualQuat::from_rot_trans(Quat::from_rotation_y(FRAC_PI_2), Vec3::ZERO);
+        let anim = generate_animation(&[BoneId(1)], &[&[skeleton.bind_poses[1] * rotate]]);
+        let mut poser = SkeletonPoser::new(&skeleton);
+        poser.blend(&anim, &BoneRemap::new(&skeleton, &anim), PoseBlendMode::Additive { weight: 0.5, reference: AdditiveReference::BindPose }, None, frame_time(0), true);
+        let half_rotate = DualQuat::from_rot_trans(Quat::from_rotation_y(FRAC_PI_2 * 0.5), Vec3::ZERO);
+        assert_pose_eq(poser.local_poses()[1], skeleton.bind_poses[1] * half_rotate);
+    }
+
+    #[test]
+    pub fn scaled()
+    {
+        let skeleton = generate_skeleton();
+        // bone 1 doubles in size over one frame
+        let anim = SkeletalAnimation::compress(
+            Ratio::new(1, 1),
+            Box::new([BoneId(1)]),
+            2,
+            &[skeleton.bind_poses[1].rotation(); 2],
+            &[skeleton.bind_poses[1].translation(); 2],
+            &[Vec3::ONE, Vec3::splat(2.0)],
+            AnimCompression::KEEP_KEYS);
+        let remap = BoneRemap::new(&skeleton, &anim);
+
+        let mut poser = SkeletonPoser::new(&skeleton);
+        poser.blend(&anim, &remap, PoseBlendMode::Replace, None, TickCount(500_000), false);
+        assert!(poser.local_scales()[1].abs_diff_eq(Vec3::splat(1.5), 1e-3));
+        poser.blend(&anim, &remap, PoseBlendMode::Blend(0.5), None, frame_time(1), false);
+        assert!(poser.local_scales()[1].abs_diff_eq(Vec3::splat(1.75), 1e-3));
+
+        let mut poser = SkeletonPoser::new(&skeleton);
+        poser.blend(&anim, &remap, PoseBlendMode::Replace, None, frame_time(1), false);
+
+        // the scale moves the children, and carries down to them
+        let posed = poser.build_poses();
+        assert_pose_eq(posed[2], DualQuat::from_rot_trans(Quat::from_rotation_z(FRAC_PI_2), Vec3::new(-2.0, 2.0, 0.0)));
+        assert_eq!(poser.local_scales()[0], Vec3::ONE);
+        assert!(poser.local_scales()[2].abs_diff_eq(Vec3::splat(2.0), 1e-3));
+
+        // skinned verts are scaled around the bone
+        let skin = |bone: &SkinnedBone, point: Vec3|
+        {
+            let point = point.extend(1.0);
+            bone.pose.transform_point3(Vec3::new(bone.scale[0].dot(point), bone.scale[1].dot(point), bone.scale[2].dot(point)))
+        };
+        let posed = poser.finalize();
+        let bone_1_bind = Vec3::new(0.0, 2.0, 0.0);
+        let bone_2_bind = Vec3::new(-1.0, 2.0, 0.0);
+        assert!(skin(&posed[1], bone_1_bind).abs_diff_eq(bone_1_bind, 1e-2));
+        assert!(skin(&posed[1], bone_2_bind).abs_diff_eq(Vec3::new(-2.0, 2.0, 0.0), 1e-2));
+        assert!(skin(&posed[2], bone_2_bind).abs_diff_eq(Vec3::new(-2.0, 2.0, 0.0), 1e-2));
+        assert!(skin(&posed[2], Vec3::new(-2.0, 2.0, 0.0)).abs_diff_eq(Vec3::new(-4.0, 2.0, 0.0), 1e-2));
+
+        // bones point along their (posed) Y axis
+        assert!(posed[1].bulge_axis.abs_diff_eq(Vec4::new(-1.0, 0.0, 0.0, 1.0), 1e-2));
+    }
+
+    #[test]
+    pub fn masked()
+    {
+        let skeleton = generate_skeleton();
+        let mask = BoneMask::from_subtree(&skeleton, 1, 0.5);
+        assert_eq!(mask.weight(0), 0.0);
+        assert_eq!(mask.weight(1), 0.5);
+        assert_eq!(mask.weight(2), 0.5);
+
+        let pose = DualQuat::from_rot_trans(Quat::from_rotation_z(FRAC_PI_2), Vec3::new(0.0, 4.0, 0.0));
+        let anim = generate_animation(&[BoneId(0), BoneId(1)], &[&[pose, pose]]);
+        let remap = BoneRemap::new(&skeleton, &anim);
+
+        let mut poser = SkeletonPoser::new(&skeleton);
+        poser.blend(&anim, &remap, PoseBlendMode::Replace, Some(&mask), frame_time(0), true);
+        assert_pose_eq(poser.local_poses()[0], skeleton.bind_poses[0]);
+        assert_pose_eq(poser.local_poses()[1], DualQuat::from_rot_trans(Quat::from_rotation_z(FRAC_PI_2), Vec3::new(0.0, 3.0, 0.0)));
+        assert_pose_eq(poser.local_poses()[2], skeleton.bind_poses[2]);
 
-        // TODO
+        // exclusive only resets bones in the mask
+        let mask = BoneMask::from_subtree(&skeleton, 2, 1.0);
+        let mut poser = SkeletonPoser::new(&skeleton);
+        poser.blend(&anim, &remap, PoseBlendMode::Replace, None, frame_time(0), true);
+        let other = generate_animation(&[BoneId(2)], &[&[pose]]);
+        poser.blend(&other, &BoneRemap::new(&skeleton, &other), PoseBlendMode::Exclusive, Some(&mask), frame_time(0), true);
+        assert_pose_eq(poser.local_poses()[0], pose);
+        assert_pose_eq(poser.local_poses()[2], pose);
     }
 }
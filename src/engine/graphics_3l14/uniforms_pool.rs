@@ -4,10 +4,22 @@ use triomphe::Arc;
 use egui::Ui;
 use wgpu::{BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, BufferAddress, BufferBindingType, BufferDescriptor, BufferSize, BufferUsages, QueueWriteBufferView, RenderPass, ShaderStages};
 use containers_3l14::{ReusePool, ObjectPoolEntryGuard};
-use math_3l14::{DualQuat, StaticGeoUniform};
+use math_3l14::StaticGeoUniform;
 use nab_3l14::utils::{AsU8Slice, ShortTypeName};
 use crate::assets::MAX_SKINNED_BONES;
 use crate::camera::CameraUniform;
+use crate::skeleton_poser::SkinnedBone;
+use crate::shader_reflection::{UniformMemberLayout, UniformType};
+
+pub type SkinnedPosesUniform = [SkinnedBone; MAX_SKINNED_BONES];
+impl UniformType for SkinnedPosesUniform
+{
+    const MEMBERS: &'static [UniformMemberLayout] = &[UniformMemberLayout::new(0, size_of::<Self>())];
+}
+impl UniformType for StaticGeoUniform
+{
+    const MEMBERS: &'static [UniformMemberLayout] = &[UniformMemberLayout::new(std::mem::offset_of!(Self, world), size_of::<glam::Mat4>())];
+}
 
 pub struct UniformBufferEntry
 {
@@ -163,7 +175,7 @@ impl UniformsPool
 
     pub fn take_poses(&self) -> ObjectPoolEntryGuard<'_, UniformBufferEntry>
     {
-        self.poses.take(|_| self.create_pool_entry::<[DualQuat; MAX_SKINNED_BONES]>(&self.poses_bind_layout, None))
+        self.poses.take(|_| self.create_pool_entry::<SkinnedPosesUniform>(&self.poses_bind_layout, None))
     }
 }
 impl DebugGui for UniformsPool
@@ -4,16 +4,26 @@ use enumflags2::{bitflags, BitFlags};
 use serde::{Deserialize, Serialize};
 use wgpu::{vertex_attr_array, BufferAddress, VertexAttribute, VertexBufferLayout, VertexFormat, VertexStepMode};
 
-// TODO: generate HLSL structs automatically?
+// Vertex shader inputs are verified against these layouts by the shader builder
 
 #[bitflags]
 #[repr(u16)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum VertexCaps
 {
-    Static  = 0b0001,
-    Skinned = 0b0010,
-    // instancing?
+    Static    = 0b0001,
+    Skinned   = 0b0010,
+    Quantized = 0b0100, // static normals and tex coords are stored in 16 bits
+    Instanced = 0b1000, // world transforms are read per-instance from a second vertex buffer
+}
+impl VertexCaps
+{
+    // Quantized attributes are unpacked to floats before reaching the vertex shader, so layouts that differ only by quantization share shaders
+    #[inline] #[must_use]
+    pub fn shader_layout(layout: BitFlags<VertexCaps>) -> BitFlags<VertexCaps>
+    {
+        layout & !BitFlags::from_flag(VertexCaps::Quantized)
+    }
 }
 impl From<BitFlags<VertexCaps>> for VertexLayoutBuilder
 {
@@ -24,8 +34,11 @@ impl From<BitFlags<VertexCaps>> for VertexLayoutBuilder
         {
             match layout
             {
+                VertexCaps::Static if value.contains(VertexCaps::Quantized) => QuantizedStaticVertex::layout(&mut builder),
                 VertexCaps::Static => StaticVertex::layout(&mut builder),
                 VertexCaps::Skinned => SkinnedVertex::layout(&mut builder),
+                VertexCaps::Quantized => { } // modifies the static layout
+                VertexCaps::Instanced => InstanceVertex::layout(&mut builder),
             }
         }
         builder
@@ -39,13 +52,15 @@ pub struct VertexLayoutBuilder
 {
     attributes: Vec<VertexAttribute>,
     bytes: BufferAddress,
+    instance_attributes: Vec<VertexAttribute>,
+    instance_bytes: BufferAddress,
 }
 impl VertexLayoutBuilder
 {
     #[inline]
     pub fn push(&mut self, attributes: &[VertexAttribute])
     {
-        let len = self.attributes.len();
+        let len = self.attributes.len() + self.instance_attributes.len();
         self.attributes.extend(attributes.iter().map(|a| VertexAttribute
         {
             format: a.format,
@@ -54,6 +69,26 @@ impl VertexLayoutBuilder
         }));
     }
 
+    // Push attributes that step per-instance, shader locations continue on from the per-vertex attributes
+    #[inline]
+    pub fn push_instance(&mut self, attributes: &[VertexAttribute])
+    {
+        let len = self.attributes.len() + self.instance_attributes.len();
+        self.instance_attributes.extend(attributes.iter().map(|a| VertexAttribute
+        {
+            format: a.format,
+            offset: { let b = self.instance_bytes; self.instance_bytes += a.format.size(); b },
+            shader_location: len as u32 + a.shader_location
+        }));
+    }
+
+    // All attributes, across both vertex buffers
+    #[inline]
+    pub fn attributes(&self) -> impl Iterator<Item = &VertexAttribute>
+    {
+        self.attributes.iter().chain(&self.instance_attributes)
+    }
+
     #[inline]
     pub fn as_vertex_buffer_layout(&self) -> VertexBufferLayout
     {
@@ -64,6 +99,18 @@ impl VertexLayoutBuilder
             attributes: &self.attributes,
         }
     }
+
+    // The layout of the second vertex buffer, if this layout has per-instance attributes
+    #[inline]
+    pub fn as_instance_buffer_layout(&self) -> Option<VertexBufferLayout>
+    {
+        (!self.instance_attributes.is_empty()).then(|| VertexBufferLayout
+        {
+            array_stride: self.instance_bytes,
+            step_mode: VertexStepMode::Instance,
+            attributes: &self.instance_attributes,
+        })
+    }
 }
 
 pub trait VertexDecl
@@ -94,6 +141,26 @@ impl VertexDecl for StaticVertex
     }
 }
 
+#[repr(C)]
+pub struct QuantizedStaticVertex
+{
+    pub position: [f32; 3],
+    pub normal: [i16; 4], // snorm, w is unused
+    pub tex_coord: [u16; 2], // f16
+}
+impl VertexDecl for QuantizedStaticVertex
+{
+    fn layout(layout_builder: &mut VertexLayoutBuilder)
+    {
+        layout_builder.push(&vertex_attr_array!
+        [
+            0 => Float32x3, // position
+            1 => Snorm16x4, // normal
+            2 => Float16x2, // tex_coord
+        ]);
+    }
+}
+
 #[repr(C)]
 pub struct SkinnedVertex
 {
@@ -111,3 +178,22 @@ impl VertexDecl for SkinnedVertex
         ]);
     }
 }
+
+#[repr(C)]
+pub struct InstanceVertex
+{
+    pub world: [[f32; 4]; 4], // column major
+}
+impl VertexDecl for InstanceVertex
+{
+    fn layout(layout_builder: &mut VertexLayoutBuilder)
+    {
+        layout_builder.push_instance(&vertex_attr_array!
+        [
+            0 => Float32x4, // world columns
+            1 => Float32x4,
+            2 => Float32x4,
+            3 => Float32x4,
+        ]);
+    }
+}
@@ -1,19 +1,28 @@
-use crate::pipeline_sorter::PipelineSorter;
+use crate::pipeline_sorter::{Draw, InstanceKey, PipelineSorter, SorterIter};
 use crate::{debug_label, pipeline_sorter, Renderer};
 use arrayvec::ArrayVec;
-use glam::{Mat4, Vec2, Vec3, Vec4Swizzles};
+use glam::{Mat4, Vec3, Vec4Swizzles};
 use triomphe::Arc;
+use std::collections::BTreeMap;
+use std::ops::Range;
 use std::time::Duration;
-use wgpu::{BindGroupDescriptor, BindGroupEntry, BindingResource, Extent3d, QueueWriteBufferView, RenderPass, Texture, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView};
+use wgpu::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, Buffer, BufferDescriptor, BufferUsages, CommandEncoder, Extent3d, QueueWriteBufferView, RenderPass, Texture, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView};
 use wgpu::util::{DeviceExt, TextureDataOrder};
 use asset_3l14::{Asset, AssetView};
-use math_3l14::{CanSee, DualQuat, Sphere, StaticGeoUniform};
+use containers_3l14::AabbTree;
+use math_3l14::{CanSee, Sphere, StaticGeoUniform, AABB};
 use nab_3l14::utils::array::init_array;
-use crate::assets::{Geometry, Model, EngineRenderPass};
-use crate::camera::{Camera, CameraProjection, CameraUniform};
-use crate::material_classes::MaterialClass;
-use crate::pipeline_cache::{DebugMode, PipelineCache};
+use nab_3l14::utils::AsU8Slice;
+use crate::assets::{Model, EngineRenderPass};
+use crate::camera::{Camera, CameraClip, CameraProjection, CameraUniform};
+use crate::culling::VisibleSet;
+use crate::material_classes::{MaterialClass, PbrTextureSlot};
+use crate::passes::light_cull::LightCullPass;
+use crate::passes::shadow::{cull_casters, ShadowCascade, ShadowPass, MAX_SHADOW_CASCADES};
+use crate::pipeline_cache::{BindGroupSlot, DebugMode, PipelineCache};
+use crate::skeleton_poser::SkinnedBone;
 use crate::uniforms_pool::{UniformsPoolEntryGuard, WgpuBufferWriter, BufferWrite};
+use crate::vertex_layouts::{InstanceVertex, VertexCaps};
 
 struct CurrentUniformsWriter
 {
@@ -22,100 +31,7 @@ struct CurrentUniformsWriter
     next_slot: usize,
 }
 
-#[derive(Default)]
-struct CameraClip
-{
-    eye: Vec3,
-    forward: Vec3,
-    right: Vec3,
-    up: Vec3,
-    near_clip: f32,
-    far_clip: f32,
-    depth_scalar: f32,
-    sphere_scalar: Vec2,
-    aspect_ratio: f32,
-}
-impl CameraClip
-{
-    pub fn new(camera: &Camera) -> Self
-    {
-        let t = camera.transform();
-
-        let (half_fov, aspect_ratio) = match camera.projection()
-        {
-            CameraProjection::Perspective { fov, aspect_ratio } => (fov.to_radians() / 2.0, *aspect_ratio),
-            CameraProjection::Orthographic { left, top, right, bottom } =>
-            {
-                // perspective: W = depth * tan(fov_x /2), H = depth * tan(fov_y / 2)
-                // ortho: W = (right - left) / 2, H = (bottom - top) / 2
-                todo!()
-            }
-        };
-
-        let depth_scalar = f32::tan(half_fov);
-        let scale_x =
-        {
-            let q = depth_scalar * aspect_ratio;
-            f32::sqrt(q * q + 1.0) // equivalent to 1.0 / cos(atan(depth_scalar * ratio))
-        };
-
-        Self
-        {
-            eye: t.position,
-            forward: t.forward(),
-            right: t.right(),
-            up: t.up(),
-            near_clip: camera.near_clip(),
-            far_clip: camera.far_clip(),
-            depth_scalar,
-            sphere_scalar: Vec2::new(scale_x, 1.0 / f32::cos(half_fov)),
-            aspect_ratio,
-        }
-    }
-}
-impl CanSee<Vec3> for CameraClip
-{
-    #[inline]
-    fn can_see(&self, pos: Vec3) -> bool { self.can_see(Sphere::new(pos, 0.0)) }
-}
-impl CanSee<Sphere> for CameraClip
-{
-    // TODO: does this work for orthographic?
-    fn can_see(&self, other: Sphere) -> bool
-    {
-        let v = other.center() - self.eye;
-        let r = other.radius();
-        // TODO: simd (3x3 row matrix of r,u,f * v)
-        let z = v.dot(self.forward);
-        if z < self.near_clip - r || z > self.far_clip + r
-        {
-            return false;
-        }
-
-        let zd = z * self.depth_scalar;
-
-        {
-            let y = v.dot(self.up);
-            let rdy = self.sphere_scalar.y * r;
-            if y.abs() > zd + rdy
-            {
-                return false;
-            }
-        }
-
-        {
-            let x = v.dot(self.right);
-            let rdx = self.sphere_scalar.x * r;
-            let hdist = zd * self.aspect_ratio;
-            if x.abs() > hdist + rdx
-            {
-                return false;
-            }
-        }
-
-        true
-    }
-}
+pub const MAX_VIEW_INSTANCES: usize = 16 * 1024; // across all passes, draws past this are not instanced
 
 // TODO: This needs to exist until the frame has been submitted fully
 pub struct View<'f>
@@ -128,15 +44,21 @@ pub struct View<'f>
     camera_mtx: Mat4,
     camera_pos: Vec3,
     camera_clip: CameraClip,
+    screen_size_scale: f32, // see screen_size()
+    is_perspective: bool,
 
     used_uniforms_pools: Vec<UniformsPoolEntryGuard<'f>>,
     // current_txfms_writer: CurrentUniformsWriter<'f>,
 
-    shadow_pass: PipelineSorter,
+    shadow_cascades: ArrayVec<ShadowCascade, MAX_SHADOW_CASCADES>,
+    shadow_passes: [PipelineSorter; MAX_SHADOW_CASCADES], // one per cascade
     opaque_pass: PipelineSorter,
+    transparent_pass: PipelineSorter, // drawn after opaque, back-to-front
+
+    instances: Buffer, // per-instance vertices of instanced draws
+    next_instance: u32,
 
-    placeholder_texture: Texture,
-    placeholder_texture_view: TextureView,
+    default_textures: [(Texture, TextureView); PbrTextureSlot::COUNT], // bound to unset material texture slots
 }
 impl<'f> View<'f>
 {
@@ -154,26 +76,30 @@ impl<'f> View<'f>
         //     next_slot: 0,
         // };
 
-        const SIZE: usize = 64;
-        let xor: [u8; SIZE * SIZE] = init_array(|i|
+        let default_textures = PbrTextureSlot::ALL.map(|slot|
         {
-            let r = i / SIZE;
-            let c = i % SIZE;
-            (r ^ c) as u8
+            let texture = renderer.device().create_texture_with_data(renderer.queue(), &TextureDescriptor
+            {
+                label: debug_label!(&format!("Default {slot:?} texture")),
+                size: Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8Unorm,
+                usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            }, TextureDataOrder::LayerMajor, &slot.default_texel());
+            let view = texture.create_view(&Default::default());
+            (texture, view)
         });
 
-        let placeholder_texture = renderer.device().create_texture_with_data(renderer.queue(), &TextureDescriptor
+        let instances = renderer.device().create_buffer(&BufferDescriptor
         {
-            label: Some("Placeholder texture"),
-            size: Extent3d { width: SIZE as u32, height: SIZE as u32, depth_or_array_layers: 1 },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: TextureDimension::D2,
-            format: TextureFormat::R8Unorm,
-            usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
-            view_formats: &[TextureFormat::R8Unorm],
-        }, TextureDataOrder::LayerMajor, &xor);
-        let placeholder_texture_view = placeholder_texture.create_view(&Default::default());
+            label: debug_label!("View instances"),
+            size: (size_of::<InstanceVertex>() * MAX_VIEW_INSTANCES) as u64,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
         Self
         {
@@ -183,128 +109,252 @@ impl<'f> View<'f>
             camera_mtx: Mat4::IDENTITY,
             camera_pos: Vec3::ZERO,
             camera_clip: CameraClip::default(),
-            shadow_pass: PipelineSorter::default(),
-            opaque_pass: PipelineSorter::default(),
+            screen_size_scale: 1.0,
+            is_perspective: true,
+            shadow_cascades: ArrayVec::new(),
+            shadow_passes: init_array(|_| PipelineSorter::new(EngineRenderPass::ShadowMap)),
+            opaque_pass: PipelineSorter::new(EngineRenderPass::Opaque),
+            transparent_pass: PipelineSorter::new(EngineRenderPass::Transparent),
+            instances,
+            next_instance: 0,
             used_uniforms_pools: used_uniforms,
             // current_txfms_writer,
             renderer,
-            placeholder_texture,
-            placeholder_texture_view,
+            default_textures,
         }
     }
 
-    pub fn begin(&mut self, runtime: Duration, camera: &Camera, clip_camera: &Camera, debug_mode: DebugMode)
+    pub fn begin(&mut self, runtime: Duration, camera: &Camera, clip_camera: &Camera, shadow_cascades: &[ShadowCascade], debug_mode: DebugMode)
     {
         self.runtime = runtime;
         self.debug_mode = debug_mode;
         self.camera_mtx = camera.matrix();
         self.camera_pos = camera.transform().position;
         self.camera_clip = CameraClip::new(clip_camera);
-        self.shadow_pass.clear();
+        (self.screen_size_scale, self.is_perspective) = match camera.projection()
+        {
+            CameraProjection::Perspective { fov, .. } => (1.0 / f32::tan(fov.to_radians() / 2.0), true),
+            CameraProjection::Orthographic { top, bottom, .. } => (2.0 / (bottom - top).abs(), false),
+        };
+        self.shadow_cascades = shadow_cascades.iter().copied().collect();
+        for shadow_pass in &mut self.shadow_passes
+        {
+            shadow_pass.clear();
+        }
         self.opaque_pass.clear();
+        self.transparent_pass.clear();
+        self.next_instance = 0;
         self.used_uniforms_pools.clear();
     }
 
-    // TODO: compute lights influence
-    // TODO: instancing
+    // Render the shadow casters into each cascade, must be submitted before any passes that sample the shadows
+    pub fn submit_shadows(&mut self, encoder: &mut CommandEncoder, shadows: &ShadowPass)
+    {
+        puffin::profile_scope!("View shadows submission");
+
+        for cascade in 0..self.shadow_cascades.len()
+        {
+            let camera = self.pipeline_cache.uniforms.take_camera();
+            {
+                let mut camera_writer = camera.record(self.renderer.queue());
+                camera_writer.write_type(0, CameraUniform::new(self.shadow_cascades[cascade].view_proj, self.runtime));
+            }
 
-    pub fn submit(&mut self, render_pass: &mut RenderPass)
+            let sorted = self.shadow_passes[cascade].sort();
+            {
+                let mut render_pass = shadows.begin_cascade(encoder, cascade);
+                self.submit_sorted(&mut render_pass, sorted, &camera, &[]);
+            }
+
+            self.used_uniforms_pools.push(camera);
+        }
+    }
+
+    pub fn submit(&mut self, render_pass: &mut RenderPass, shadows: &ShadowPass, lights: &LightCullPass)
     {
         puffin::profile_scope!("View submission");
 
+        let camera = self.take_view_camera();
+        let sorted = self.opaque_pass.sort();
+        let view_bind_groups = [(BindGroupSlot::Shadows, shadows.bind_group()), (BindGroupSlot::Lights, lights.bind_group())];
+        self.submit_sorted(render_pass, sorted, &camera, &view_bind_groups);
+
+        self.used_uniforms_pools.push(camera);
+    }
+
+    // Draw the blended geometry, must be submitted after the opaque geometry into a pass that keeps its depth
+    pub fn submit_transparent(&mut self, render_pass: &mut RenderPass, shadows: &ShadowPass, lights: &LightCullPass)
+    {
+        puffin::profile_scope!("View transparent submission");
+
+        let camera = self.take_view_camera();
+        let sorted = self.transparent_pass.sort();
+        let view_bind_groups = [(BindGroupSlot::Shadows, shadows.bind_group()), (BindGroupSlot::Lights, lights.bind_group())];
+        self.submit_sorted(render_pass, sorted, &camera, &view_bind_groups);
+
+        self.used_uniforms_pools.push(camera);
+    }
+
+    #[must_use]
+    fn take_view_camera(&self) -> UniformsPoolEntryGuard<'f>
+    {
         let camera = self.pipeline_cache.uniforms.take_camera();
         {
             let mut camera_writer = camera.record(self.renderer.queue());
             // TODO: view/proj order may be arch dependent?
             camera_writer.write_type(0, CameraUniform::new(self.camera_mtx, self.runtime));
         }
+        camera
+    }
 
-        // TODO: each pass has explicit rules
-        let passes = [&mut self.shadow_pass, &mut self.opaque_pass];
-        for (pipeline_key, draws) in passes.into_iter().flat_map(|pass| pass.sort())
+    fn submit_sorted(&mut self, render_pass: &mut RenderPass, sorted: SorterIter, camera: &UniformsPoolEntryGuard, view_bind_groups: &[(BindGroupSlot, &BindGroup)])
+    {
+        for (pipeline_key, mut draws) in sorted
         {
-            if !self.pipeline_cache.try_apply(render_pass, pipeline_key)
-            {
-                // panic!("can this happen?");
-                continue;
-            }
+            let batches = pipeline_sorter::batch_instances(&mut draws, |d| d.instancing.map(|(key, _)| key));
 
-            for draw in draws
+            // batches are drawn in order, switching between the instanced and individual pipelines as needed
+            let mut applied = None;
+            for batch in batches
             {
-                let mesh = &draw.geometry.meshes[draw.mesh_index as usize];
-
-                // todo: codify bind group indices into enum?
+                let batch_draws = &draws[batch.draws.clone()];
 
-                camera.bind(render_pass, 0, 0);
-                self.used_uniforms_pools[(draw.transform_uniform_id >> 8) as usize].bind(render_pass, 1, draw.transform_uniform_id as u8);
-                if let Some(poses_uniform_id) = draw.poses_uniform_id
+                // instanced batches fall back to individual draws if the instanced pipeline is unavailable
+                if batch.instanced && let Some((_, instanced_pipeline)) = batch_draws[0].instancing
                 {
-                    self.used_uniforms_pools[(poses_uniform_id >> 8) as usize].bind(render_pass, 2, poses_uniform_id as u8);
-                }
-                else
-                {
-                    // data is unused/unread, so buffer index does not matter
-                    self.pipeline_cache.uniforms.take_poses().bind(render_pass, 2, 0);
-                }
-
-                if let Some(mtl) = draw.material
-                {
-                    // TODO: don't create on the fly
-                    let mut bge = ArrayVec::<_, 18>::new();
-                    bge.push(BindGroupEntry
-                    {
-                        binding: bge.len() as u32,
-                        resource: mtl.0.props.as_entire_binding(),
-                    });
-                    bge.push(BindGroupEntry
+                    if applied != Some(instanced_pipeline) && self.pipeline_cache.try_apply(render_pass, instanced_pipeline)
                     {
-                        binding: bge.len() as u32,
-                        resource: BindingResource::Sampler(self.pipeline_cache.default_sampler())
-                    });
-                    for tex in &mtl.1
-                    {
-                        bge.push(BindGroupEntry
-                        {
-                            binding: bge.len() as u32,
-                            resource: BindingResource::TextureView(&tex.gpu_view),
-                        })
+                        applied = Some(instanced_pipeline);
                     }
-                    // TODO: TEMP HACK
-                    if mtl.1.is_empty()
+                    if applied == Some(instanced_pipeline) && let Some(instances) = self.write_instances(batch_draws)
                     {
-                        bge.push(BindGroupEntry
-                        {
-                            binding: bge.len() as u32,
-                            resource: BindingResource::TextureView(&self.placeholder_texture_view),
-                        })
+                        self.bind_draw(render_pass, &batch_draws[0], camera, view_bind_groups);
+                        render_pass.set_vertex_buffer(1, self.instances.slice(..));
+                        Self::draw_mesh(render_pass, &batch_draws[0], instances);
+                        continue;
                     }
+                }
 
-                    let mtl_bind_group = self.renderer.device().create_bind_group(&BindGroupDescriptor
-                    {
-                        label: debug_label!("TODO mtl bind group"),
-                        layout: &self.pipeline_cache.get_or_create_bind_layout(mtl.0.class),
-                        entries: &bge,
-                    });
-                    render_pass.set_bind_group(3, &mtl_bind_group, &[]);
+                if applied != Some(pipeline_key)
+                {
+                    if !self.pipeline_cache.try_apply(render_pass, pipeline_key) { continue; }
+                    applied = Some(pipeline_key);
                 }
+                for draw in batch_draws
+                {
+                    self.bind_draw(render_pass, draw, camera, view_bind_groups);
+                    Self::draw_mesh(render_pass, draw, 0..1);
+                }
+            }
+        }
+    }
+
+    // Copy the transforms of a batch of draws into the instances buffer, returns None if it is full
+    fn write_instances(&mut self, draws: &[Draw]) -> Option<Range<u32>>
+    {
+        let first = self.next_instance;
+        let end = first + draws.len() as u32;
+        if end as usize > MAX_VIEW_INSTANCES
+        {
+            return None;
+        }
 
-                // bind sub-buffers?
-                render_pass.set_vertex_buffer(0, draw.geometry.vertices.slice(..));
-                render_pass.set_index_buffer(draw.geometry.indices.slice(..), draw.geometry.index_format);
-                render_pass.draw_indexed(mesh.index_range.0..mesh.index_range.1, mesh.vertex_range.0 as i32, 0..1);
+        let instances: Vec<_> = draws.iter().map(|d| InstanceVertex { world: d.transform.to_cols_array_2d() }).collect();
+        let offset = (first as usize * size_of::<InstanceVertex>()) as u64;
+        self.renderer.queue().write_buffer(&self.instances, offset, unsafe { instances.as_u8_slice() });
+        self.next_instance = end;
+        Some(first..end)
+    }
+
+    fn bind_draw(&self, render_pass: &mut RenderPass, draw: &Draw, camera: &UniformsPoolEntryGuard, view_bind_groups: &[(BindGroupSlot, &BindGroup)])
+    {
+        camera.bind(render_pass, BindGroupSlot::Camera as u32, 0);
+        self.used_uniforms_pools[(draw.transform_uniform_id >> 8) as usize].bind(render_pass, BindGroupSlot::Transform as u32, draw.transform_uniform_id as u8);
+        if let Some(poses_uniform_id) = draw.poses_uniform_id
+        {
+            self.used_uniforms_pools[(poses_uniform_id >> 8) as usize].bind(render_pass, BindGroupSlot::Poses as u32, poses_uniform_id as u8);
+        }
+        else
+        {
+            // data is unused/unread, so buffer index does not matter
+            self.pipeline_cache.uniforms.take_poses().bind(render_pass, BindGroupSlot::Poses as u32, 0);
+        }
+
+        if let Some(mtl) = &draw.material
+        {
+            // TODO: don't create on the fly
+            let mut bge = ArrayVec::<_, 18>::new();
+            bge.push(BindGroupEntry
+            {
+                binding: bge.len() as u32,
+                resource: mtl.0.props.as_entire_binding(),
+            });
+            bge.push(BindGroupEntry
+            {
+                binding: bge.len() as u32,
+                resource: BindingResource::Sampler(self.pipeline_cache.default_sampler())
+            });
+            // only the PBR classes have textures
+            for i in 0..mtl.0.class.texture_count()
+            {
+                let view = match mtl.1.get(i)
+                {
+                    Some(Some(tex)) => &tex.gpu_view,
+                    _ => &self.default_textures[i].1,
+                };
+                bge.push(BindGroupEntry
+                {
+                    binding: bge.len() as u32,
+                    resource: BindingResource::TextureView(view),
+                })
+            }
+
+            let mtl_bind_group = self.renderer.device().create_bind_group(&BindGroupDescriptor
+            {
+                label: debug_label!("TODO mtl bind group"),
+                layout: &self.pipeline_cache.get_or_create_bind_layout(mtl.0.class),
+                entries: &bge,
+            });
+            render_pass.set_bind_group(BindGroupSlot::Material as u32, &mtl_bind_group, &[]);
+            for (slot, bind_group) in view_bind_groups
+            {
+                render_pass.set_bind_group(*slot as u32, *bind_group, &[]);
             }
         }
+    }
 
-        self.used_uniforms_pools.push(camera);
+    fn draw_mesh(render_pass: &mut RenderPass, draw: &Draw, instances: Range<u32>)
+    {
+        let mesh = &draw.geometry.meshes[draw.mesh_index as usize];
+
+        // bind sub-buffers?
+        render_pass.set_vertex_buffer(0, draw.geometry.vertices.slice(..));
+        render_pass.set_index_buffer(draw.geometry.indices.slice(..), draw.geometry.index_format);
+        let index_range = mesh.lods[draw.lod as usize].index_range;
+        render_pass.draw_indexed(index_range.0..index_range.1, mesh.vertex_range.0 as i32, instances);
     }
 
-    fn can_see(&self, geo: &Geometry, object_transform: Mat4) -> bool
+    // The approximate fraction of the screen height covered by a (world space) sphere, used for selecting LODs
+    #[must_use]
+    fn screen_size(&self, bounds: Sphere) -> f32
     {
-        let geo_transform = geo.bounds_sphere.transform(&(object_transform));
-        self.camera_clip.can_see(geo_transform)
+        match self.is_perspective
+        {
+            true => bounds.radius() * self.screen_size_scale / bounds.center().distance(self.camera_pos).max(f32::EPSILON),
+            false => bounds.radius() * self.screen_size_scale,
+        }
     }
 
-    fn draw_model_common(&mut self, model: AssetView<Model>, world_transform: Mat4, poses_uniforms: Option<u32>) -> bool
+    // Models that have already been culled (e.g. by a VisibleSet or shadow::cull_casters) pass in their visibility and shadow cascades,
+    // otherwise (None) the model's bounds are tested against the view and each cascade
+    // Returns whether the model was queued into any pass (the view or a shadow cascade)
+    fn draw_model_common(
+        &mut self,
+        model: AssetView<Model>,
+        world_transform: Mat4,
+        poses: Option<&[SkinnedBone]>,
+        is_visible: Option<bool>,
+        shadow_cascades: Option<ArrayVec<usize, MAX_SHADOW_CASCADES>>) -> bool
     {
         // this may be heavy-handed
         if !model.all_dependencies_loaded()
@@ -313,7 +363,39 @@ impl<'f> View<'f>
         }
 
         let geo = model.geometry.data().unwrap();
-        if !self.can_see(geo.as_ref(), world_transform) { return false; }
+        let bounds = geo.bounds_sphere.transform(&world_transform);
+        let is_visible = is_visible.unwrap_or_else(|| self.camera_clip.can_see(bounds));
+
+        // TODO: skinned shadow casters (there is no skinned shadow vertex shader yet)
+        let shadow_cascades = match (poses, shadow_cascades)
+        {
+            (Some(_), _) => ArrayVec::new(),
+            (None, Some(cascades)) => cascades,
+            // objects outside the view can still cast shadows into it
+            (None, None) =>
+            {
+                let caster_bounds = AABB::new(bounds.center() - bounds.radius(), bounds.center() + bounds.radius());
+                self.shadow_cascades.iter().enumerate()
+                    .filter_map(|(i, cascade)| cascade.can_cast(caster_bounds).then_some(i))
+                    .collect()
+            },
+        };
+
+        if !is_visible && shadow_cascades.is_empty() { return false; }
+
+        // todo: cleanup/standardize this logic
+        let poses_uniforms = poses.map(|poses|
+        {
+            let mut poses_uniforms = self.pipeline_cache.uniforms.take_poses();
+            {
+                let mut poses_uniforms_writer = poses_uniforms.record(self.renderer.queue());
+                poses_uniforms_writer.write_slice(0, poses);
+            }
+
+            let poses_uniform_id = (self.used_uniforms_pools.len() << 8) as u32; // todo: ensure bits are enough
+            self.used_uniforms_pools.push(poses_uniforms);
+            poses_uniform_id
+        });
 
         // TODO: these should be per-mesh
         let rad = world_transform.x_axis.x.max(world_transform.y_axis.y.max(world_transform.z_axis.z));
@@ -344,48 +426,83 @@ impl<'f> View<'f>
             uniform_id
         };
 
+        // skinned models have their own poses, so are never instanced
+        let instanced_layout = poses_uniforms.is_none().then(|| geo.vertex_layout | VertexCaps::Instanced);
+
         for mesh_index in 0..model.mesh_count
         {
             let mtl = model.materials[mesh_index as usize].data().unwrap();
-            let textures = mtl.textures.iter().map(|t| t.data().unwrap()).collect();
+            let textures = mtl.textures.iter().map(|t| t.as_ref().map(|t| t.data().unwrap())).collect();
 
-            // TODO: this key needs to be generated based on pass, as shadows don't need mtls/debug mode
-            let shadow_pipeline = self.pipeline_cache.get_or_create(
-                EngineRenderPass::ShadowMap,
-                geo.vertex_layout,
-                None,
-                self.debug_mode,
-            );
+            let mesh = &geo.meshes[mesh_index as usize];
+            let lod = mesh.select_lod(self.screen_size(mesh.bounds_sphere.transform(&world_transform)));
 
-            self.shadow_pass.push(pipeline_sorter::Draw
+            let instance_key = InstanceKey { geometry: model.geometry.key(), mesh_index, lod, material: None };
+
+            if !shadow_cascades.is_empty()
             {
-                transform: world_transform,
-                depth,
-                mesh_index,
-                transform_uniform_id: txfm_uniform_id,
-                poses_uniform_id: poses_uniforms,
-                pipeline_hash: shadow_pipeline,
-                geometry: geo.clone(),
-                material: None,
-            });
+                // TODO: this key needs to be generated based on pass, as shadows don't need mtls/debug mode
+                let shadow_pipeline = self.pipeline_cache.get_or_create(
+                    EngineRenderPass::ShadowMap,
+                    geo.vertex_layout,
+                    None,
+                    self.debug_mode,
+                );
+                let shadow_instancing = instanced_layout.map(|layout|
+                    (instance_key, self.pipeline_cache.get_or_create(EngineRenderPass::ShadowMap, layout, None, self.debug_mode)));
+
+                for &cascade in &shadow_cascades
+                {
+                    self.shadow_passes[cascade].push(pipeline_sorter::Draw
+                    {
+                        transform: world_transform,
+                        depth,
+                        mesh_index,
+                        lod,
+                        transform_uniform_id: txfm_uniform_id,
+                        poses_uniform_id: poses_uniforms,
+                        pipeline_hash: shadow_pipeline,
+                        instancing: shadow_instancing,
+                        geometry: geo.clone(),
+                        material: None,
+                    });
+                }
+            }
+
+            if !is_visible { continue; }
 
-            let opaque_pipeline = self.pipeline_cache.get_or_create(
-                EngineRenderPass::Opaque,
+            let is_transparent = matches!(mtl.class.render_pass(), EngineRenderPass::Transparent);
+            let pass = if is_transparent { EngineRenderPass::Transparent } else { EngineRenderPass::Opaque };
+            let pipeline = self.pipeline_cache.get_or_create(
+                pass,
                 geo.vertex_layout,
-                Some(mtl.class),
+                Some((mtl.class, mtl.render_state)),
                 self.debug_mode);
+            // instancing would break the back-to-front order of transparent draws
+            let instancing = instanced_layout.filter(|_| !is_transparent).map(|layout|
+            {
+                let instance_key = InstanceKey { material: Some(model.materials[mesh_index as usize].key()), ..instance_key };
+                (instance_key, self.pipeline_cache.get_or_create(pass, layout, Some((mtl.class, mtl.render_state)), self.debug_mode))
+            });
 
-            self.opaque_pass.push(pipeline_sorter::Draw
+            let draw = pipeline_sorter::Draw
             {
                 transform: world_transform,
                 depth,
                 mesh_index,
+                lod,
                 transform_uniform_id: txfm_uniform_id,
                 poses_uniform_id: poses_uniforms,
-                pipeline_hash: opaque_pipeline,
+                pipeline_hash: pipeline,
+                instancing,
                 geometry: geo.clone(),
                 material: Some((mtl, textures)),
-            });
+            };
+            match is_transparent
+            {
+                true => self.transparent_pass.push(draw),
+                false => self.opaque_pass.push(draw),
+            }
         }
 
         drop(txfm_uniforms_writer);
@@ -393,25 +510,201 @@ impl<'f> View<'f>
         true
     }
 
+    // Draw a model that is not in a spatial hierarchy (e.g. a dynamic object), it is culled against the view and each shadow cascade by itself
     pub fn draw_model_static(&mut self, model: AssetView<Model>, world_transform: Mat4) -> bool
     {
-        self.draw_model_common(model, world_transform, None)
+        self.draw_model_common(model, world_transform, None, None, None)
     }
 
-    pub fn draw_model_skinned(&mut self, model: AssetView<Model>, world_transform: Mat4, poses: &[DualQuat]) -> bool
+    pub fn draw_model_skinned(&mut self, model: AssetView<Model>, world_transform: Mat4, poses: &[SkinnedBone]) -> bool
     {
-        // TODO: this needs to pass vis-checks first
+        self.draw_model_common(model, world_transform, Some(poses), None, None)
+    }
 
-        // todo: cleanup/standardize this logic
-        let mut poses_uniforms = self.pipeline_cache.uniforms.take_poses();
+    // Draw the static models in a spatial hierarchy into each shadow cascade they can cast into (see shadow::cull_casters)
+    // get_model maps a hierarchy value to its model and world transform
+    pub fn draw_shadow_casters(&mut self, casters: &AabbTree, mut get_model: impl FnMut(u32) -> Option<(AssetView<Model>, Mat4)>) -> usize
+    {
+        puffin::profile_function!();
+
+        // each caster is drawn once, into all of its cascades
+        let mut caster_cascades: BTreeMap<u32, ArrayVec<usize, MAX_SHADOW_CASCADES>> = BTreeMap::new();
+        for (i, cascade) in self.shadow_cascades.iter().enumerate()
+        {
+            for value in cull_casters(casters, cascade)
+            {
+                caster_cascades.entry(value).or_default().push(i);
+            }
+        }
+
+        let mut drawn = 0;
+        for (value, cascades) in caster_cascades
         {
-            let mut poses_uniforms_writer = poses_uniforms.record(self.renderer.queue());
-            poses_uniforms_writer.write_slice(0, poses);
+            let Some((model, world_transform)) = get_model(value) else { continue; };
+            if self.draw_model_common(model, world_transform, None, Some(false), Some(cascades))
+            {
+                drawn += 1;
+            }
         }
+        drawn
+    }
 
-        let poses_uniform_id = (self.used_uniforms_pools.len() << 8) as u32; // todo: ensure bits are enough
-        self.used_uniforms_pools.push(poses_uniforms);
+    // Draw the static models that survived culling, get_model maps a visible value to its model and world transform
+    // These are not drawn into the shadow cascades, the hierarchy's casters are drawn separately (see draw_shadow_casters)
+    pub fn draw_visible(&mut self, visible: &VisibleSet, mut get_model: impl FnMut(u32) -> Option<(AssetView<Model>, Mat4)>) -> usize
+    {
+        puffin::profile_function!();
 
-        self.draw_model_common(model, world_transform, Some(poses_uniform_id))
+        let mut drawn = 0;
+        for value in visible.values()
+        {
+            let Some((model, world_transform)) = get_model(*value) else { continue; };
+            if self.draw_model_common(model, world_transform, None, Some(true), Some(ArrayVec::new()))
+            {
+                drawn += 1;
+            }
+        }
+        drawn
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use arrayvec::ArrayVec;
+    use bitcode::Encode;
+    use enumflags2::BitFlags;
+    use glam::{Quat, UVec2};
+    use asset_3l14::{Ash, AssetKey, AssetKeySynthHash, AssetLifecyclers, AssetTypeId, Assets, AssetsConfig};
+    use math_3l14::{Angle, Transform};
+    use crate::assets::{GeometryFile, GeometryLifecycler, GeometryMesh, GeometryMeshLod, IndexFormat, MaterialFile, MaterialAlphaMode, MaterialLifecycler, MaterialRenderState, ShaderLifecycler};
+    use crate::passes::shadow::{fit_cascades, CascadeSettings};
+    use crate::vertex_layouts::StaticVertex;
+    use super::*;
+
+    // Shaders are not built for tests, so pipelines stay pending (nothing is submitted)
+    fn test_assets(renderer: &Arc<Renderer>) -> Arc<Assets>
+    {
+        let lifecyclers = AssetLifecyclers::default()
+            .add_lifecycler(GeometryLifecycler::new(renderer.clone()))
+            .add_lifecycler(MaterialLifecycler::new(renderer.clone()))
+            .add_lifecycler(ShaderLifecycler::new(renderer.clone()));
+        Assets::new(lifecyclers, AssetsConfig::test())
+    }
+
+    fn load<A: Asset>(assets: &Assets, asset_type: AssetTypeId, id: u64, file: &impl Encode) -> Ash<A>
+    {
+        assets.load_encoded_for_test(AssetKey::synthetic(asset_type, AssetKeySynthHash(id)), file)
+    }
+
+    // A single triangle inside a 2x2x2 box around the origin
+    fn test_model(assets: &Assets, id: u64, class: MaterialClass, render_state: MaterialRenderState) -> AssetView<Model>
+    {
+        let bounds = AABB::new(Vec3::splat(-1.0), Vec3::ONE);
+        let geometry = load(assets, AssetTypeId::Geometry, id, &GeometryFile
+        {
+            bounds_aabb: bounds,
+            bounds_sphere: Sphere::new(Vec3::ZERO, 3.0f32.sqrt()),
+            vertex_layout: BitFlags::from_flag(VertexCaps::Static).bits(),
+            index_format: IndexFormat::U32,
+            vertices: vec![0; 3 * size_of::<StaticVertex>()].into_boxed_slice(),
+            indices: [0u32, 1, 2].iter().flat_map(|i| i.to_le_bytes()).collect(),
+            meshes: Box::new([GeometryMesh
+            {
+                bounds_aabb: bounds,
+                bounds_sphere: Sphere::new(Vec3::ZERO, 3.0f32.sqrt()),
+                vertex_range: (0, 3),
+                lods: ArrayVec::from_iter([GeometryMeshLod { index_range: (0, 3), max_screen_size: f32::INFINITY }]),
+            }]),
+        });
+        let material = load(assets, AssetTypeId::Material, id, &MaterialFile
+        {
+            class,
+            render_state,
+            textures: ArrayVec::new(),
+            props: Box::new([0; 16]),
+        });
+
+        AssetView::new_for_testing(Model
+        {
+            mesh_count: 1,
+            geometry,
+            skeleton: None,
+            materials: Box::new([material]),
+        })
+    }
+
+    fn test_camera() -> Camera
+    {
+        let mut camera = Camera::default();
+        camera.update_projection(CameraProjection::Perspective { fov: Angle::from_degrees(70.0), aspect_ratio: 16.0 / 9.0 }, 0.1, 500.0);
+        camera.update_view(Transform { position: Vec3::ZERO, rotation: Quat::IDENTITY, scale: Vec3::ONE }); // looking down +Z
+        camera
+    }
+
+    #[test]
+    #[ignore = "requires a GPU adapter (or a fallback one)"]
+    fn shadow_casters_outside_view()
+    {
+        let renderer = Renderer::new_headless(UVec2::new(64, 64), true).unwrap();
+        let assets = test_assets(&renderer);
+        let pipeline_cache = PipelineCache::new(renderer.clone(), assets.clone());
+        let mut view = View::new(renderer, &pipeline_cache);
+
+        let camera = test_camera();
+        let settings = CascadeSettings { cascade_count: 1, max_distance: 20.0, caster_distance: 30.0, ..Default::default() };
+        let cascades = fit_cascades(&camera, Vec3::NEG_Y, &settings);
+        view.begin(Duration::ZERO, &camera, &camera, &cascades, DebugMode::None);
+
+        let model = test_model(&assets, 1, MaterialClass::PbrOpaque, MaterialRenderState::default());
+        assert!(model.all_dependencies_loaded());
+
+        // above the view, between it and the light
+        let above = Mat4::from_translation(Vec3::new(0.0, 41.0, 6.0));
+        let mut casters = AabbTree::new();
+        casters.insert(model.geometry.data().unwrap().bounds_aabb.transformed(&above), 0);
+        assert_eq!(view.draw_shadow_casters(&casters, |_| Some((model.clone(), above))), 1);
+        assert!(!view.shadow_passes[0].is_empty());
+        assert!(view.opaque_pass.is_empty(), "Casters are not drawn into the view");
+
+        view.begin(Duration::ZERO, &camera, &camera, &cascades, DebugMode::None);
+        assert!(view.draw_model_static(model.clone(), above), "Models outside the view can still cast shadows into it");
+        assert!(!view.shadow_passes[0].is_empty());
+        assert!(view.opaque_pass.is_empty());
+
+        // off to the side, neither seen nor casting into the cascade
+        view.begin(Duration::ZERO, &camera, &camera, &cascades, DebugMode::None);
+        assert!(!view.draw_model_static(model, Mat4::from_translation(Vec3::new(200.0, 0.0, 6.0))));
+        assert!(view.shadow_passes[0].is_empty() && view.opaque_pass.is_empty());
+    }
+
+    #[test]
+    #[ignore = "requires a GPU adapter (or a fallback one)"]
+    fn material_pass_routing()
+    {
+        let renderer = Renderer::new_headless(UVec2::new(64, 64), true).unwrap();
+        let assets = test_assets(&renderer);
+        let pipeline_cache = PipelineCache::new(renderer.clone(), assets.clone());
+        let mut view = View::new(renderer, &pipeline_cache);
+
+        let camera = test_camera();
+        let cascades = fit_cascades(&camera, Vec3::NEG_Y, &CascadeSettings::default());
+        let in_view = Mat4::from_translation(Vec3::new(0.0, 0.0, 6.0));
+
+        let render_state = |alpha_mode| MaterialRenderState { alpha_mode, ..Default::default() };
+        let cases =
+        [
+            (MaterialClass::PbrOpaque, MaterialAlphaMode::Opaque, false),
+            (MaterialClass::PbrOpaque, MaterialAlphaMode::Mask, false),
+            (MaterialClass::PbrTransparent, MaterialAlphaMode::Blend, true),
+        ];
+        for (id, (class, alpha_mode, is_transparent)) in cases.into_iter().enumerate()
+        {
+            let model = test_model(&assets, id as u64, class, render_state(alpha_mode));
+            view.begin(Duration::ZERO, &camera, &camera, &cascades, DebugMode::None);
+            assert!(view.draw_model_static(model, in_view));
+            assert_eq!(view.opaque_pass.is_empty(), is_transparent, "{class:?} {alpha_mode:?}");
+            assert_eq!(view.transparent_pass.is_empty(), !is_transparent, "{class:?} {alpha_mode:?}");
+        }
     }
 }
@@ -4,6 +4,7 @@ use serde::Deserialize;
 use super::{BlockId, InstRunId, ContextfulLatchBlock, BlockKind};
 use smallvec::SmallVec;
 use asset_3l14::AssetKey;
+use entity_3l14::EntityRef;
 use nab_3l14::utils::alloc_slice::alloc_slice_default;
 use crate::instance::LatchContextStorage;
 use crate::runtime::BlockRef;
@@ -80,9 +81,9 @@ pub enum VarValue
     Vec2 { x: f32, y: f32 },
     Vec3 { x: f32, y: f32, z: f32 },
     Vec4 { x: f32, y: f32, z: f32, w: f32 },
+    Entity(EntityRef), // authored by name, resolved against the entity store by consumers
 
     // Vec2, Vec3, Vec4
-    // Entity
     // Asset?
     // Array/List
     // Map
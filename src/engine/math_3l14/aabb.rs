@@ -1,5 +1,5 @@
 use bitcode::{Decode, Encode};
-use glam::Vec3;
+use glam::{Mat4, Vec3};
 use crate::{Intersection, Intersects, Ray, Sphere};
 
 #[derive(Default, Debug, Clone, Copy, PartialEq, Encode, Decode)]
@@ -59,6 +59,25 @@ impl AABB
         }
     }
     
+    // The AABB enclosing this box after being transformed
+    #[must_use]
+    pub fn transformed(self, transform: &Mat4) -> Self
+    {
+        // Arvo's method: accumulate the min/max contribution of each basis axis
+        let translation = transform.w_axis.truncate();
+        let mut min = translation;
+        let mut max = translation;
+        for (axis, (lo, hi)) in [transform.x_axis, transform.y_axis, transform.z_axis].iter()
+            .zip([(self.min.x, self.max.x), (self.min.y, self.max.y), (self.min.z, self.max.z)])
+        {
+            let a = axis.truncate() * lo;
+            let b = axis.truncate() * hi;
+            min += a.min(b);
+            max += a.max(b);
+        }
+        Self { min, max }
+    }
+
     pub fn scale(self, amount_frac: f32) -> Self
     {
         let scaled = (self.size() * amount_frac) / 2.0;
@@ -82,6 +101,13 @@ impl AABB
         self.min.cmple(rhs.max).all() &&
         self.max.cmpge(rhs.min).all()
     }
+
+    // The squared distance from a point to the nearest point in this box, zero if the point is inside
+    #[inline] #[must_use]
+    pub fn distance_sq(self, point: Vec3) -> f32
+    {
+        (point.clamp(self.min, self.max) - point).length_squared()
+    }
 }
 impl Intersects<AABB> for AABB
 {
@@ -118,6 +144,30 @@ mod tests
         assert_eq!(aabb.surface_area(), 0.0);
     }
 
+    #[test]
+    fn transformed()
+    {
+        let aabb = AABB::new(Vec3::new(-1.0, -2.0, -3.0), Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(aabb.transformed(&Mat4::IDENTITY), aabb);
+
+        let moved = aabb.transformed(&Mat4::from_scale_rotation_translation(Vec3::splat(2.0), glam::Quat::IDENTITY, Vec3::new(10.0, 0.0, 0.0)));
+        assert_eq!(moved, AABB::new(Vec3::new(8.0, -4.0, -6.0), Vec3::new(12.0, 4.0, 6.0)));
+
+        let rotated = aabb.transformed(&Mat4::from_rotation_z(std::f32::consts::FRAC_PI_2));
+        assert!(rotated.min.abs_diff_eq(Vec3::new(-2.0, -1.0, -3.0), 1e-5));
+        assert!(rotated.max.abs_diff_eq(Vec3::new(2.0, 1.0, 3.0), 1e-5));
+    }
+
+    #[test]
+    fn distance()
+    {
+        let aabb = AABB::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0));
+        assert_eq!(aabb.distance_sq(Vec3::ZERO), 0.0);
+        assert_eq!(aabb.distance_sq(Vec3::new(1.0, 0.5, -1.0)), 0.0);
+        assert_eq!(aabb.distance_sq(Vec3::new(3.0, 0.0, 0.0)), 4.0);
+        assert_eq!(aabb.distance_sq(Vec3::new(2.0, -3.0, 1.0)), 5.0);
+    }
+
     #[test]
     fn sizes()
     {
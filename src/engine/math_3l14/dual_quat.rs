@@ -86,16 +86,30 @@ impl DualQuat
         // }.simple_normalized()
     }
 
-    // TODO: Dual-quaternion linear blending -- https://arxiv.org/pdf/2303.13395
+    // Dual-quaternion linear blending (DLB) of any number of poses, weights should sum to 1
+    // https://arxiv.org/pdf/2303.13395
+    #[must_use]
+    pub fn linear_blend(poses: impl IntoIterator<Item = (Self, f32)>) -> Self
+    {
+        let mut poses = poses.into_iter();
+        let Some((first, first_weight)) = poses.next() else { return Self::IDENTITY };
+
+        let mut sum = first * first_weight;
+        for (mut pose, weight) in poses
+        {
+            // keep every pose on the same side as the first (antipodal correction)
+            if first.real.dot(pose.real) < 0.0 { pose = -pose; }
+            sum = sum + pose * weight;
+        }
+        sum.true_normalized()
+    }
 
-    // Calculate the screw linear interpolation between two dual quaternions
+    // Calculate the screw linear interpolation between two (unit) dual quaternions
     #[must_use]
     pub fn sclerp(self, mut rhs: Self, t: f32) -> Self
     {
         // https://borodust.github.io/public/shared/paper_dual-quats.pdf
 
-        // TODO: this seems broken, article above has some tests
-
         // take the shortest path
         let dot = self.real.dot(rhs.real);
         if dot < 0.0 { rhs = -rhs; }
@@ -103,24 +117,30 @@ impl DualQuat
         let diff = self.conjugate() * rhs;
         let vr = diff.real.xyz();
         let vd = diff.dual.xyz();
-        let invr = 1.0 / vr.length();
+
+        // no rotation, so the screw has no axis
+        let sin_half_sq = vr.length_squared();
+        if sin_half_sq < 1e-12
+        {
+            return self * Self::from_rot_trans(Quat::IDENTITY, diff.translation() * t);
+        }
+        let invr = sin_half_sq.sqrt().recip();
 
         // screw params
-        let mut angle = 2.0 * diff.real.w.acos();
+        let mut angle = 2.0 * diff.real.w.clamp(-1.0, 1.0).acos();
         let mut pitch = -2.0 * diff.dual.w * invr;
         let direction = vr * invr;
-        let moment = (vd - direction * pitch * diff.real.w * 0.5) * invr;
+        let moment = (vd - direction * (pitch * diff.real.w * 0.5)) * invr;
 
         // exp power
         angle *= t;
         pitch *= t;
 
         // convert back to dual quats
-        let sin = (angle * 0.5).sin();
-        let cos = (angle * 0.5).cos();
+        let (sin, cos) = (angle * 0.5).sin_cos();
 
-        let real = Quat::from_axis_angle(direction * sin, cos);
-        let dual = Quat::from_axis_angle((sin * moment) + (pitch * 0.5 * cos * direction), -pitch * 0.5 * sin);
+        let real = Quat::from_vec4((direction * sin).extend(cos));
+        let dual = Quat::from_vec4(((sin * moment) + (pitch * 0.5 * cos * direction)).extend(-pitch * 0.5 * sin));
 
         self * Self::from_raw(real, dual)
     }
@@ -202,7 +222,7 @@ impl DualQuat
         Self
         {
             real: real_inv,
-            dual: -(self.dual * real_inv),
+            dual: -(real_inv * self.dual * real_inv),
         }
     }
 
@@ -217,8 +237,8 @@ impl From<&Mat4> for DualQuat
 {
     fn from(value: &Mat4) -> Self
     {
+        // scale is dropped, see ScaledDualQuat
         let (_scale, rotation, translation) = value.to_scale_rotation_translation();
-        // TODO: handle scale?
         Self::from_rot_trans(rotation, translation)
     }
 }
@@ -281,7 +301,7 @@ impl Mul<f32> for DualQuat
         Self::Output
         {
             real: self.real * scalar,
-            dual: self.real * scalar,
+            dual: self.dual * scalar,
         }
     }
 }
@@ -340,6 +360,101 @@ impl Display for DualQuat
     }
 }
 
+// A rigid transform with a (local) scale, the scale is applied first
+// Composing keeps the scale aligned to each transform's own axes, so non-uniform scale on a parent with rotated children loses the shear
+#[derive(Debug, PartialEq, Clone, Copy, Encode, Decode)]
+pub struct ScaledDualQuat
+{
+    pub rigid: DualQuat,
+    pub scale: Vec3,
+}
+impl Default for ScaledDualQuat
+{
+    fn default() -> Self { Self::IDENTITY }
+}
+impl ScaledDualQuat
+{
+    pub const IDENTITY: Self = Self { rigid: DualQuat::IDENTITY, scale: Vec3::ONE };
+
+    #[inline] #[must_use]
+    pub fn new(rigid: DualQuat, scale: Vec3) -> Self { Self { rigid, scale } }
+
+    #[inline] #[must_use]
+    pub fn from_scale_rot_trans(scale: Vec3, rotation: Quat, translation: Vec3) -> Self
+    {
+        Self { rigid: DualQuat::from_rot_trans(rotation, translation), scale }
+    }
+
+    #[inline] #[must_use]
+    pub fn transform_vector3(&self, direction: Vec3) -> Vec3 { self.rigid.transform_vector3(direction * self.scale) }
+    #[inline] #[must_use]
+    pub fn transform_point3(&self, point: Vec3) -> Vec3 { self.rigid.transform_point3(point * self.scale) }
+
+    // lerp the scales and nlerp the rigid transforms
+    // note: t is not clamped internally
+    #[inline] #[must_use]
+    pub fn nlerp(self, rhs: Self, t: f32) -> Self
+    {
+        Self
+        {
+            rigid: self.rigid.nlerp(rhs.rigid, t),
+            scale: self.scale.lerp(rhs.scale, t),
+        }
+    }
+}
+impl From<DualQuat> for ScaledDualQuat { fn from(value: DualQuat) -> Self { Self::new(value, Vec3::ONE) } }
+impl From<&Mat4> for ScaledDualQuat
+{
+    fn from(value: &Mat4) -> Self
+    {
+        let (scale, rotation, translation) = value.to_scale_rotation_translation();
+        Self::from_scale_rot_trans(scale, rotation, translation)
+    }
+}
+impl From<Mat4> for ScaledDualQuat { fn from(value: Mat4) -> Self { Self::from(&value) } }
+impl From<&ScaledDualQuat> for Mat4
+{
+    fn from(value: &ScaledDualQuat) -> Self
+    {
+        Mat4::from_scale_rotation_translation(value.scale, value.rigid.rotation(), value.rigid.translation())
+    }
+}
+impl From<ScaledDualQuat> for Mat4 { fn from(value: ScaledDualQuat) -> Self { Self::from(&value) } }
+impl Mul<ScaledDualQuat> for ScaledDualQuat
+{
+    type Output = Self;
+
+    fn mul(self, rhs: ScaledDualQuat) -> Self::Output
+    {
+        // the parent's scale moves the child, but does not skew it
+        let offset = DualQuat::from_rot_trans(rhs.rigid.rotation(), self.scale * rhs.rigid.translation());
+        Self::Output
+        {
+            rigid: self.rigid * offset,
+            scale: self.scale * rhs.scale,
+        }
+    }
+}
+impl AbsDiffEq for ScaledDualQuat
+{
+    type Epsilon = f32;
+    fn default_epsilon() -> Self::Epsilon { Quat::default_epsilon() }
+    fn abs_diff_eq(&self, other: &Self, epsilon: Self::Epsilon) -> bool
+    {
+        self.rigid.abs_diff_eq(&other.rigid, epsilon) &&
+        self.scale.abs_diff_eq(other.scale, epsilon)
+    }
+}
+impl RelativeEq for ScaledDualQuat
+{
+    fn default_max_relative() -> Self::Epsilon { Quat::default_max_relative() }
+    fn relative_eq(&self, other: &Self, epsilon: Self::Epsilon, max_relative: Self::Epsilon) -> bool
+    {
+        self.rigid.relative_eq(&other.rigid, epsilon, max_relative) &&
+        self.scale.relative_eq(&other.scale, epsilon, max_relative)
+    }
+}
+
 #[cfg(test)]
 mod tests
 {
@@ -419,6 +534,161 @@ mod tests
         // assert_relative_eq!(DualQuat::new(-r, -(t + test)), dq.translated(test)); // TODO
     }
 
-    // TODO: to/from mat4
-    // TODO: multiply, add, conjugate, length, inverse, dot, rotate
+    #[test]
+    fn inverse()
+    {
+        let dq = DualQuat::from_rot_trans(Quat::from_rotation_x(0.8), Vec3::new(3.0, -2.0, 5.0));
+        assert_relative_eq!(dq * dq.inverse(), DualQuat::IDENTITY, epsilon = 0.0001);
+        assert_relative_eq!(dq.inverse() * dq, DualQuat::IDENTITY, epsilon = 0.0001);
+
+        let test = Vec3::new(10.0, 3.032, 8.5);
+        assert_relative_eq!(dq.inverse().transform_point3(dq.transform_point3(test)), test, epsilon = 0.001);
+    }
+
+    #[test]
+    fn to_from_mat4()
+    {
+        let r = Quat::from_rotation_z(-0.6);
+        let t = Vec3::new(-4.0, 0.5, 2.0);
+        let m = Mat4::from_rotation_translation(r, t);
+        let dq = DualQuat::from(m);
+
+        assert_relative_eq!(dq, DualQuat::from_rot_trans(r, t), epsilon = 0.0001);
+        assert_relative_eq!(Mat4::from(dq), m, epsilon = 0.0001);
+        assert_relative_eq!(Mat4::from(DualQuat::IDENTITY), Mat4::IDENTITY);
+
+        // scale is dropped
+        let scaled = m * Mat4::from_scale(Vec3::splat(3.0));
+        assert_relative_eq!(DualQuat::from(scaled), dq, epsilon = 0.0001);
+    }
+
+    #[test]
+    fn multiply()
+    {
+        let a = (Quat::from_rotation_y(0.7), Vec3::new(1.0, 2.0, 3.0));
+        let b = (Quat::from_rotation_x(-1.2), Vec3::new(-5.0, 0.0, 0.5));
+
+        let dq = DualQuat::from_rot_trans(a.0, a.1) * DualQuat::from_rot_trans(b.0, b.1);
+        let m = Mat4::from_rotation_translation(a.0, a.1) * Mat4::from_rotation_translation(b.0, b.1);
+        assert_relative_eq!(Mat4::from(dq), m, epsilon = 0.0001);
+
+        let test = Vec3::new(10.0, 3.032, 8.5);
+        assert_relative_eq!(dq.transform_point3(test), m.transform_point3(test), epsilon = 0.001);
+
+        assert_relative_eq!(dq * DualQuat::IDENTITY, dq);
+        assert_relative_eq!(DualQuat::IDENTITY * dq, dq);
+    }
+
+    #[test]
+    fn add_scale()
+    {
+        let dq = DualQuat::from_rot_trans(Quat::from_rotation_y(0.3), Vec3::new(2.0, 0.0, -1.0));
+        let doubled = dq + dq;
+        assert_relative_eq!(doubled, dq * 2.0);
+        assert_relative_eq!(doubled.dual, dq.dual * 2.0);
+        assert_relative_eq!((dq * 2.0).simple_normalized(), dq, epsilon = 0.0001);
+    }
+
+    #[test]
+    fn conjugate()
+    {
+        let dq = DualQuat::from_rot_trans(Quat::from_rotation_x(0.8), Vec3::new(3.0, -2.0, 5.0));
+
+        // for unit dual quats, the conjugate is the inverse
+        assert_relative_eq!(dq.conjugate(), dq.inverse(), epsilon = 0.0001);
+        assert_relative_eq!(dq.conjugate().conjugate(), dq);
+        assert_relative_eq!(dq.dual_number_conjugate().dual_number_conjugate(), dq);
+        assert_relative_eq!(dq.combined_conjugate(), dq.conjugate().dual_number_conjugate());
+    }
+
+    #[test]
+    fn length_dot()
+    {
+        let dq = DualQuat::from_rot_trans(Quat::from_rotation_z(2.0), Vec3::new(1.0, 1.0, 0.0));
+        assert_relative_eq!(dq.simple_length(), 1.0, epsilon = 0.0001);
+        assert_relative_eq!(dq.true_length(), 1.0, epsilon = 0.0001);
+        assert_relative_eq!(dq.simple_length_squared(), 1.0, epsilon = 0.0001);
+
+        // the real and dual parts of a unit dual quat are orthogonal
+        assert_relative_eq!(dq.dot(dq), 1.0, epsilon = 0.0001);
+        assert_relative_eq!(dq.dot(-dq), -1.0, epsilon = 0.0001);
+    }
+
+    #[test]
+    fn rotate()
+    {
+        let t = Vec3::new(1.0, 0.0, 0.0);
+        let dq = DualQuat::from_rot_trans(Quat::IDENTITY, t);
+        let r = Quat::from_rotation_y(std::f32::consts::FRAC_PI_2);
+
+        // rotates around the origin
+        let rotated = dq.rotated(r);
+        assert_relative_eq!(rotated.translation(), r * t, epsilon = 0.0001);
+        assert_relative_eq!(rotated.rotation(), Quat::IDENTITY, epsilon = 0.0001);
+    }
+
+    #[test]
+    fn sclerp()
+    {
+        let a = DualQuat::from_rot_trans(Quat::from_rotation_x(0.4), Vec3::new(1.0, -2.0, 0.5));
+        let b = DualQuat::from_rot_trans(Quat::from_rotation_y(1.3), Vec3::new(-3.0, 4.0, 2.0));
+
+        assert_relative_eq!(a.sclerp(b, 0.0), a, epsilon = 0.0001);
+        assert_relative_eq!(a.sclerp(b, 1.0), b, epsilon = 0.0001);
+
+        // constant speed along the screw, so two half-steps are a full step
+        let mid = a.sclerp(b, 0.5);
+        let half_step = a.conjugate() * mid;
+        assert_relative_eq!(a * half_step * half_step, b, epsilon = 0.0001);
+        assert_relative_eq!(mid.true_length(), 1.0, epsilon = 0.0001);
+
+        // rotating around an axis that does not pass through the origin
+        let pivot = Vec3::new(2.0, 0.0, 0.0);
+        let about_pivot = |angle: f32| DualQuat::from_rot_trans(Quat::IDENTITY, pivot) *
+            DualQuat::from_rot_trans(Quat::from_rotation_y(angle), -pivot);
+        assert_relative_eq!(DualQuat::IDENTITY.sclerp(about_pivot(1.0), 0.5), about_pivot(0.5), epsilon = 0.0001);
+
+        // translation only
+        let moved = DualQuat::from_rot_trans(Quat::IDENTITY, Vec3::new(0.0, 4.0, 0.0));
+        assert_relative_eq!(DualQuat::IDENTITY.sclerp(moved, 0.25).translation(), Vec3::new(0.0, 1.0, 0.0), epsilon = 0.0001);
+    }
+
+    #[test]
+    fn linear_blend()
+    {
+        let a = DualQuat::from_rot_trans(Quat::from_rotation_z(0.5), Vec3::new(1.0, 0.0, 0.0));
+        let b = DualQuat::from_rot_trans(Quat::from_rotation_z(-0.5), Vec3::new(0.0, 2.0, 0.0));
+
+        assert_relative_eq!(DualQuat::linear_blend([(a, 1.0)]), a, epsilon = 0.0001);
+        assert_relative_eq!(DualQuat::linear_blend([(a, 1.0), (b, 0.0)]), a, epsilon = 0.0001);
+        assert_relative_eq!(DualQuat::linear_blend([]), DualQuat::IDENTITY);
+
+        // antipodal poses are the same transform
+        assert_relative_eq!(DualQuat::linear_blend([(a, 0.5), (-a, 0.5)]), a, epsilon = 0.0001);
+
+        let blended = DualQuat::linear_blend([(a, 0.5), (b, 0.5)]);
+        assert_relative_eq!(blended.true_length(), 1.0, epsilon = 0.0001);
+        assert_relative_eq!(blended.rotation(), Quat::IDENTITY, epsilon = 0.0001);
+        assert_relative_eq!(blended, a.nlerp(b, 0.5), epsilon = 0.0001);
+    }
+
+    #[test]
+    fn scaled()
+    {
+        let m = Mat4::from_scale_rotation_translation(Vec3::new(1.0, 2.0, 3.0), Quat::from_rotation_x(0.7), Vec3::new(4.0, 5.0, 6.0));
+        let sdq = ScaledDualQuat::from(m);
+        assert_relative_eq!(Mat4::from(sdq), m, epsilon = 0.0001);
+
+        let test = Vec3::new(10.0, 3.032, 8.5);
+        assert_relative_eq!(sdq.transform_point3(test), m.transform_point3(test), epsilon = 0.001);
+        assert_relative_eq!(sdq.transform_vector3(test), m.transform_vector3(test), epsilon = 0.001);
+
+        // composing matches matrices when the parent's scale is uniform
+        let parent = Mat4::from_scale_rotation_translation(Vec3::splat(2.0), Quat::from_rotation_y(1.1), Vec3::new(0.0, 1.0, 0.0));
+        let composed = ScaledDualQuat::from(parent) * sdq;
+        assert_relative_eq!(Mat4::from(composed), parent * m, epsilon = 0.001);
+        assert_relative_eq!(composed.transform_point3(test), (parent * m).transform_point3(test), epsilon = 0.01);
+
+        assert_relative_eq!(ScaledDualQuat::IDENTITY * sdq, sdq, epsilon = 0.0001);
+    }
 }
\ No newline at end of file
@@ -1,27 +1,29 @@
 use std::fmt::{Debug, Formatter};
 use glam::{Mat4, Vec3};
-use crate::{Facing, GetFacing, Intersection, Intersects, IsOnOrInside, Plane, Sphere};
+use crate::{Facing, GetFacing, Intersection, Intersects, IsOnOrInside, Plane, Sphere, AABB};
 use nab_3l14::utils::ShortTypeName;
 
 #[derive(Clone, PartialEq)]
 pub struct Frustum
 {
-    pub planes: [Plane; 6], // ordered left, right, top, bottom, near, far
+    pub planes: [Plane; 6], // ordered left, right, top, bottom, near, far. Normals point inward
 }
 impl Frustum
 {
     pub const NULL: Frustum = Frustum { planes: [Plane::NULL; 6] }; // an invalid frustum acting as a placeholder
+    pub const ALL_PLANES: u8 = 0b11_1111; // a plane mask of every plane, see cull_aabb()
 
     // if input is projection, planes are in view space
     // if view projection, planes are in world space
     // if model view projection, planes are in model space
+    // works for both perspective and orthographic projections, depth is in wgpu's 0..1 range
     #[must_use]
     pub fn from_matrix(col_major_mtx: &Mat4) -> Self
     {
         let rows = col_major_mtx.transpose(); // glam stores in column-major
         let planes =
         [
-            // not sure why all of these need to be mirrored...
+            // Gribb-Hartmann planes are ax + by + cz + d >= 0, but Plane stores n.p = distance, so d is negated
             Plane::from(rows.w_axis + rows.x_axis).negated_distance().normalized(), // left
             Plane::from(rows.w_axis - rows.x_axis).negated_distance().normalized(), // right
             Plane::from(rows.w_axis - rows.y_axis).negated_distance().normalized(), // top
@@ -33,6 +35,30 @@ impl Frustum
         Self { planes }
     }
 
+    // Test an AABB against the planes set in plane_mask (one bit per plane, in planes order)
+    // Returns None if the box is outside, otherwise the mask of the planes the box straddles (zero if fully inside)
+    // Children of a box only need to be tested against the planes their parent straddles
+    #[must_use]
+    pub fn cull_aabb(&self, aabb: AABB, plane_mask: u8) -> Option<u8>
+    {
+        let center = aabb.centroid();
+        let half = aabb.half();
+
+        let mut straddled = 0;
+        for (i, plane) in self.planes.iter().enumerate()
+        {
+            let bit = 1 << i;
+            if plane_mask & bit == 0 { continue; }
+
+            // planes point inward
+            let distance = plane.normal().dot(center) - plane.distance();
+            let radius = plane.normal().abs().dot(half);
+            if distance < -radius { return None; }
+            if distance < radius { straddled |= bit; }
+        }
+        Some(straddled)
+    }
+
     #[inline] #[must_use] pub fn left(&self) -> Plane { self.planes[0] }
     #[inline] #[must_use] pub fn right(&self) -> Plane { self.planes[1] }
     #[inline] #[must_use] pub fn top(&self) -> Plane { self.planes[2] }
@@ -40,20 +66,21 @@ impl Frustum
     #[inline] #[must_use] pub fn near(&self) -> Plane { self.planes[4] }
     #[inline] #[must_use] pub fn far(&self) -> Plane { self.planes[5] }
 
+    // The corners of the clip volume of a (view) projection matrix, depth is in wgpu's 0..1 range
+    // corners are in the same space as the planes from from_matrix()
     #[must_use]
     pub fn get_corners(projected_mtx: &Mat4) -> [Vec3; 8]
     {
-        // todo: wrong?
         let mtx = projected_mtx.inverse();
         [
-            mtx.transform_vector3(Vec3::new(-1.0, -1.0, -1.0)), // near bottom left
-            mtx.transform_vector3(Vec3::new( 1.0, -1.0, -1.0)), // near bottom right
-            mtx.transform_vector3(Vec3::new(-1.0,  1.0, -1.0)), // near top left
-            mtx.transform_vector3(Vec3::new( 1.0,  1.0, -1.0)), // near top right
-            mtx.transform_vector3(Vec3::new(-1.0, -1.0,  1.0)), // far bottom left
-            mtx.transform_vector3(Vec3::new( 1.0, -1.0,  1.0)), // far bottom right
-            mtx.transform_vector3(Vec3::new(-1.0,  1.0,  1.0)), // far top left
-            mtx.transform_vector3(Vec3::new( 1.0,  1.0,  1.0)), // far top right
+            mtx.project_point3(Vec3::new(-1.0, -1.0, 0.0)), // near bottom left
+            mtx.project_point3(Vec3::new( 1.0, -1.0, 0.0)), // near bottom right
+            mtx.project_point3(Vec3::new(-1.0,  1.0, 0.0)), // near top left
+            mtx.project_point3(Vec3::new( 1.0,  1.0, 0.0)), // near top right
+            mtx.project_point3(Vec3::new(-1.0, -1.0, 1.0)), // far bottom left
+            mtx.project_point3(Vec3::new( 1.0, -1.0, 1.0)), // far bottom right
+            mtx.project_point3(Vec3::new(-1.0,  1.0, 1.0)), // far top left
+            mtx.project_point3(Vec3::new( 1.0,  1.0, 1.0)), // far top right
         ]
     }
 }
@@ -81,7 +108,8 @@ impl Intersects<Vec3> for Frustum
         let mut inside = true;
         for p in &self.planes
         {
-            inside &= matches!(p.get_facing(other), Facing::Behind);
+            // planes point inward
+            inside &= !matches!(p.get_facing(other), Facing::Behind);
         }
         match inside
         {
@@ -97,7 +125,7 @@ impl IsOnOrInside<Sphere> for Frustum
         // TODO: simd
         for p in &self.planes
         {
-            // planes point outward
+            // planes point inward
             let z = p.get_facing(other);
             match z
             {
@@ -130,15 +158,15 @@ mod tests
         let frustum = Frustum::from_matrix(&view_projection);
         let recip_sqrt2 = 1.0 / 2.0_f32.sqrt();
 
-        // TODO: these values are wrong
+        // looking down +Z with a 90deg fov, so the side planes are at 45deg
         let expected_planes = [
             Plane::new(Vec3::new(recip_sqrt2, 0.0, recip_sqrt2), 0.0),
             Plane::new(Vec3::new(-recip_sqrt2, 0.0, recip_sqrt2), 0.0),
             Plane::new(Vec3::new(0.0, -recip_sqrt2, recip_sqrt2), 0.0),
             Plane::new(Vec3::new(0.0, recip_sqrt2, recip_sqrt2), 0.0),
             Plane::new(Vec3::new(0.0, 0.0, 1.0), 1.0),
-            Plane::new(Vec3::new(0.0, 0.0, -1.0), -10.0), // TODO: This seems wrong
-    ];
+            Plane::new(Vec3::new(0.0, 0.0, -1.0), -10.0), // -z >= -10
+        ];
 
         for (i, plane) in frustum.planes.iter().enumerate() {
             let expected = &expected_planes[i];
@@ -177,5 +205,129 @@ mod tests
         assert!(frustum.rhs_is_on_or_inside(sphere));
     }
 
-    // TODO: corners
-}
\ No newline at end of file
+    #[test]
+    fn corners()
+    {
+        let projection = Mat4::perspective_lh(Angle::PI_OVER_TWO.to_radians(), 1.0, 1.0, 10.0);
+        let view = Mat4::look_at_lh(Vec3::new(0.0, 0.0, -5.0), Vec3::ZERO, Vec3::Y);
+        let corners = Frustum::get_corners(&(projection * view));
+
+        // 90deg fov, so half extents are equal to the depth
+        let expected =
+        [
+            Vec3::new(-1.0, -1.0, -4.0),
+            Vec3::new( 1.0, -1.0, -4.0),
+            Vec3::new(-1.0,  1.0, -4.0),
+            Vec3::new( 1.0,  1.0, -4.0),
+            Vec3::new(-10.0, -10.0, 5.0),
+            Vec3::new( 10.0, -10.0, 5.0),
+            Vec3::new(-10.0,  10.0, 5.0),
+            Vec3::new( 10.0,  10.0, 5.0),
+        ];
+        for (i, (corner, expected)) in corners.iter().zip(expected).enumerate()
+        {
+            assert!(corner.abs_diff_eq(expected, 1e-4), "Corner {i} mismatch: got {corner:?}, expected {expected:?}");
+        }
+    }
+
+    // brute-force check against the clip volume
+    fn is_in_clip_volume(view_projection: &Mat4, point: Vec3) -> bool
+    {
+        let clip = *view_projection * point.extend(1.0);
+        clip.x.abs() <= clip.w && clip.y.abs() <= clip.w && clip.z >= 0.0 && clip.z <= clip.w
+    }
+
+    #[test]
+    fn ortho_planes()
+    {
+        let projection = Mat4::orthographic_lh(-2.0, 4.0, -1.0, 3.0, 1.0, 10.0);
+        let frustum = Frustum::from_matrix(&projection);
+
+        let expected_planes = [
+            Plane::new(Vec3::new(1.0, 0.0, 0.0), -2.0), // x >= -2
+            Plane::new(Vec3::new(-1.0, 0.0, 0.0), -4.0), // -x >= -4
+            Plane::new(Vec3::new(0.0, -1.0, 0.0), -3.0),
+            Plane::new(Vec3::new(0.0, 1.0, 0.0), -1.0),
+            Plane::new(Vec3::new(0.0, 0.0, 1.0), 1.0),
+            Plane::new(Vec3::new(0.0, 0.0, -1.0), -10.0),
+        ];
+        for (i, (plane, expected)) in frustum.planes.iter().zip(expected_planes).enumerate()
+        {
+            assert!(plane.0.abs_diff_eq(expected.0, 1e-5), "Plane {i} mismatch: got {plane:?}, expected {expected:?}");
+        }
+    }
+
+    #[test]
+    fn points_match_clip_volume()
+    {
+        let view = Mat4::look_at_lh(Vec3::new(1.0, 2.0, -5.0), Vec3::new(0.0, 0.5, 3.0), Vec3::Y);
+        let projections =
+        [
+            Mat4::perspective_lh(Angle::from_degrees(70.0).to_radians(), 16.0 / 9.0, 0.5, 20.0),
+            Mat4::orthographic_lh(-6.0, 4.0, -3.0, 5.0, 0.5, 20.0),
+        ];
+
+        for projection in projections
+        {
+            let view_projection = projection * view;
+            let frustum = Frustum::from_matrix(&view_projection);
+            let mut num_inside = 0;
+            for x in -20..=20
+            {
+                for y in -20..=20
+                {
+                    for z in -10..=40
+                    {
+                        let point = Vec3::new(x as f32, y as f32, z as f32) * 0.73;
+                        let expected = is_in_clip_volume(&view_projection, point);
+                        let inside = matches!(frustum.get_intersection(point), Intersection::Overlapping);
+                        assert_eq!(inside, expected, "{point:?} mismatch");
+                        num_inside += inside as usize;
+                    }
+                }
+            }
+            assert!(num_inside > 100);
+        }
+    }
+
+    #[test]
+    fn cull_aabb()
+    {
+        let projection = Mat4::orthographic_lh(-2.0, 4.0, -1.0, 3.0, 1.0, 10.0);
+        let frustum = Frustum::from_matrix(&projection);
+
+        let inside = AABB::new(Vec3::new(-1.0, 0.0, 2.0), Vec3::new(1.0, 1.0, 3.0));
+        assert_eq!(frustum.cull_aabb(inside, Frustum::ALL_PLANES), Some(0));
+
+        let outside = AABB::new(Vec3::new(5.0, 0.0, 2.0), Vec3::new(6.0, 1.0, 3.0));
+        assert_eq!(frustum.cull_aabb(outside, Frustum::ALL_PLANES), None);
+        assert_eq!(frustum.cull_aabb(outside, Frustum::ALL_PLANES & !0b10), Some(0)); // right plane not tested
+
+        let across_left_and_far = AABB::new(Vec3::new(-3.0, 0.0, 8.0), Vec3::new(0.0, 1.0, 12.0));
+        assert_eq!(frustum.cull_aabb(across_left_and_far, Frustum::ALL_PLANES), Some(0b10_0001));
+    }
+
+    #[test]
+    fn ortho_corners()
+    {
+        let projection = Mat4::orthographic_lh(-2.0, 4.0, -1.0, 3.0, 1.0, 10.0);
+        let view = Mat4::look_at_lh(Vec3::new(0.0, 0.0, -5.0), Vec3::ZERO, Vec3::Y);
+        let corners = Frustum::get_corners(&(projection * view));
+
+        let expected =
+        [
+            Vec3::new(-2.0, -1.0, -4.0),
+            Vec3::new( 4.0, -1.0, -4.0),
+            Vec3::new(-2.0,  3.0, -4.0),
+            Vec3::new( 4.0,  3.0, -4.0),
+            Vec3::new(-2.0, -1.0, 5.0),
+            Vec3::new( 4.0, -1.0, 5.0),
+            Vec3::new(-2.0,  3.0, 5.0),
+            Vec3::new( 4.0,  3.0, 5.0),
+        ];
+        for (i, (corner, expected)) in corners.iter().zip(expected).enumerate()
+        {
+            assert!(corner.abs_diff_eq(expected, 1e-4), "Corner {i} mismatch: got {corner:?}, expected {expected:?}");
+        }
+    }
+}
@@ -34,9 +34,6 @@ pub use lerp::*;
 mod nquat48;
 pub use nquat48::*;
 
-mod packed_transform;
-pub use packed_transform::*;
-
 mod morton;
 pub use morton::*;
 
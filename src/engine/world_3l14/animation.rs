@@ -0,0 +1,26 @@
+use triomphe::Arc;
+use graphics_3l14::anim_graph_instance::AnimGraphInstance;
+use graphics_3l14::assets::{AnimEvent, AnimGraphDef};
+use latch_3l14::{Runtime, VarValue};
+
+// Set an anim graph parameter from a latch var. Returns false if the parameter doesn't exist or the value isn't numeric
+pub fn set_anim_parameter_from_var(instance: &mut AnimGraphInstance, def: &AnimGraphDef, name: &str, value: &VarValue) -> bool
+{
+    let value = match value
+    {
+        VarValue::Bool(b) => if *b { 1.0 } else { 0.0 },
+        VarValue::Int(i) => *i as f32,
+        VarValue::Float(f) => *f,
+        _ => return false,
+    };
+    instance.set_parameter_named(def, name, value)
+}
+
+// Wake up any circuits listening for an animation event's signal
+pub fn signal_anim_event(runtime: &Arc<Runtime>, event: &AnimEvent)
+{
+    if let Some(signal) = event.signal
+    {
+        Runtime::signal(runtime, signal);
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use bitcode::{Decode, Encode};
+use triomphe::Arc;
+use asset_3l14::{Ash, Asset, AssetKey, AssetLifecycler, AssetLoadError, AssetLoadRequest, AssetSnapshot, AssetTypeId};
+use entity_3l14::component_meta::ComponentRuntimeMeta;
+use entity_3l14::{EntityError, EntityId, EntityStore};
+use graphics_3l14::assets::Model;
+use latch_3l14::{Circuit, InstRunId, Runtime};
+use proc_macros_3l14::LayoutHash;
+
+// An entity archetype, instantiated once per placement
+pub struct EntityDef
+{
+    pub components: Box<[EntityDefComponent]>, // default values for each component
+    pub model: Option<Ash<Model>>,
+    pub circuit: Option<Ash<Circuit>>, // spawned for each instance
+}
+impl Asset for EntityDef
+{
+    type DebugData = ();
+    fn asset_type() -> AssetTypeId { AssetTypeId::EntityDef }
+    fn all_dependencies_loaded(&self) -> bool
+    {
+        self.model.as_ref().map_or(true, |m| m.is_loaded_recursive()) &&
+        self.circuit.as_ref().map_or(true, |c| c.is_loaded_recursive())
+    }
+}
+impl EntityDef
+{
+    // Add this definition's components to an entity and start its circuit (which must be loaded)
+    // Entities instantiated with a circuit should be despawned with despawn_entity()
+    pub fn instantiate(&self, entity: EntityId, store: &mut EntityStore, latch: &Arc<Runtime>) -> Result<(), Box<dyn Error>>
+    {
+        if !store.is_alive(entity)
+        {
+            return Err(Box::new(EntityError::NotAlive(entity)));
+        }
+
+        for component in &self.components
+        {
+            (component.meta.decode_and_insert_fn)(&component.data, entity, store)?;
+        }
+
+        if let Some(model) = &self.model
+        {
+            store.insert(entity, EntityModel(model.clone()))?;
+        }
+
+        if let Some(circuit) = &self.circuit
+        {
+            let AssetSnapshot::Available(circuit_view) = circuit.data()
+            else { return Err(Box::new(EntityDefError::NotLoaded(circuit.key()))) };
+
+            let run_id = Runtime::spawn(latch, circuit_view, None);
+            store.insert(entity, EntityCircuit(run_id))?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct EntityDefComponent
+{
+    meta: &'static ComponentRuntimeMeta,
+    data: Box<[u8]>,
+}
+impl Debug for EntityDefComponent
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+    {
+        #[cfg(debug_assertions)]
+        return f.write_str(self.meta.type_name);
+        #[cfg(not(debug_assertions))]
+        return f.write_fmt(format_args!("{:x}", self.meta.type_name_hash));
+    }
+}
+
+#[derive(LayoutHash, Encode, Decode)]
+pub struct EntityDefFile
+{
+    pub components: Box<[EntityDefFileComponent]>,
+    pub model: Option<AssetKey>,
+    pub circuit: Option<AssetKey>,
+}
+
+#[derive(Encode, Decode)]
+pub struct EntityDefFileComponent
+{
+    pub type_name_hash: u64,
+    pub data: Box<[u8]>, // the bitcode encoded component
+}
+
+// The model an entity was defined with
+pub struct EntityModel(pub Ash<Model>);
+
+// The circuit instance running for an entity
+#[derive(Debug, Clone, Copy)]
+pub struct EntityCircuit(pub InstRunId);
+
+// Despawn an entity, destroying its circuit instance (if it has one)
+pub fn despawn_entity(entity: EntityId, store: &mut EntityStore, latch: &Arc<Runtime>) -> bool
+{
+    if let Some(EntityCircuit(run_id)) = store.remove::<EntityCircuit>(entity)
+    {
+        Runtime::destroy(latch, run_id);
+    }
+    store.despawn(entity)
+}
+
+#[derive(Debug)]
+pub enum EntityDefError
+{
+    NotLoaded(AssetKey), // a dependency has not (or failed to) load
+}
+impl Display for EntityDefError
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { Debug::fmt(self, f) }
+}
+impl Error for EntityDefError { }
+
+pub struct EntityDefLifecycler
+{
+    known_components: HashMap<u64, &'static ComponentRuntimeMeta>,
+}
+impl Default for EntityDefLifecycler
+{
+    fn default() -> Self
+    {
+        let known_components = inventory::iter::<ComponentRuntimeMeta>()
+            .map(|c| (c.type_name_hash, c)).collect();
+        Self { known_components }
+    }
+}
+impl AssetLifecycler for EntityDefLifecycler
+{
+    type Asset = EntityDef;
+
+    fn load(&self, mut request: AssetLoadRequest) -> Result<Self::Asset, Box<dyn Error>>
+    {
+        let file: EntityDefFile = request.deserialize()?;
+
+        let components = file.components.into_iter().map(|component|
+        {
+            let Some(meta) = self.known_components.get(&component.type_name_hash)
+            else
+            {
+                // this may be due to a component being inaccessible during runtime but accessible during build
+                log::error!("Failed to find entity component definition for {:x}", component.type_name_hash);
+                return Err(AssetLoadError::Parse);
+            };
+            Ok(EntityDefComponent { meta, data: component.data })
+        }).collect::<Result<_, _>>()?;
+
+        Ok(EntityDef
+        {
+            components,
+            model: file.model.map(|model| request.load_dependency(model)),
+            circuit: file.circuit.map(|circuit| request.load_dependency(circuit)),
+        })
+    }
+}
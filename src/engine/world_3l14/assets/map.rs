@@ -1,44 +1,196 @@
 use bitcode::{Decode, Encode};
-use asset_3l14::{Ash, AssetKey, AssetLifecycler, AssetLoadRequest};
-use proc_macros_3l14::{asset, LayoutHash};
+use asset_3l14::{Ash, Asset, AssetKey, AssetLifecycler, AssetLoadRequest, AssetSnapshot, AssetTypeId};
+use proc_macros_3l14::LayoutHash;
 use std::error::Error;
+use glam::{IVec3, Mat4};
+use triomphe::Arc;
 use containers_3l14::AabbTree;
-use graphics_3l14::assets::Model;
+use entity_3l14::{EntityId, EntityStore};
+use latch_3l14::Runtime;
+use math_3l14::{Transform, AABB};
+use nab_3l14::Ident;
+use crate::assets::entity_def::{despawn_entity, EntityDef, EntityDefError};
 use crate::Light;
 
-#[asset]
+// Each bit is a named activation flag, see Map::activation_flag()
+pub type ActivationFlags = u64;
+pub const MAX_ACTIVATION_FLAGS: usize = ActivationFlags::BITS as usize;
+
+// An index of the map's chunks, which are streamed in separately (see MapStreamer)
 pub struct Map
 {
-    pub statics: Statics,
+    pub activation_flags: Box<[String]>, // the name of each activation flag bit
+    pub layers: Box<[MapLayerInfo]>,
+    pub chunk_size: f32,
+    pub chunk_hierarchy: AabbTree, // values are indices into chunks
+    pub chunks: Box<[MapChunkRef]>,
+    pub lights: Box<[StaticLight]>, // lights are not placed spatially, so are not chunked
+    pub entities: Entities,
+}
+impl Asset for Map
+{
+    type DebugData = ();
+    fn asset_type() -> AssetTypeId { AssetTypeId::Map }
+    fn all_dependencies_loaded(&self) -> bool
+    {
+        // chunks are streamed, so are not dependencies
+        self.entities.palette.iter().all(Ash::is_loaded_recursive)
+    }
+}
+impl Map
+{
+    // Get the bit for a named activation flag
+    #[must_use]
+    pub fn activation_flag(&self, name: &str) -> Option<ActivationFlags>
+    {
+        self.activation_flags.iter().position(|f| f == name).map(|i| 1 << i)
+    }
+
+    // Is a layer active given the currently set activation flags
+    #[inline] #[must_use]
+    pub fn is_layer_active(&self, layer: u16, active_flags: ActivationFlags) -> bool
+    {
+        self.layers[layer as usize].is_active(active_flags)
+    }
+
+    // Spawn the entities placed in all active layers and instantiate their definitions (which must be loaded)
+    // Returns the spawned entities in placement order. Nothing is spawned if any entity fails to spawn
+    pub fn spawn_entities(&self, store: &mut EntityStore, active_flags: ActivationFlags, latch: &Arc<Runtime>) -> Result<Vec<EntityId>, Box<dyn Error>>
+    {
+        self.spawn_with(store, active_flags,
+            |entity, placement, store|
+            {
+                let definition = &self.entities.palette[placement.entity as usize];
+                let AssetSnapshot::Available(definition) = definition.data()
+                else { return Err(Box::new(EntityDefError::NotLoaded(definition.key()))) };
+                definition.instantiate(entity, store, latch)
+            },
+            |entity, store| { despawn_entity(entity, store, latch); })
+    }
+
+    // Spawn the entities placed in all active layers, with only their placement (Transform and MapPlacement)
+    pub fn spawn_placements(&self, store: &mut EntityStore, active_flags: ActivationFlags) -> Result<Vec<EntityId>, Box<dyn Error>>
+    {
+        self.spawn_with(store, active_flags, |_, _, _| Ok(()), |entity, store| { store.despawn(entity); })
+    }
+
+    fn spawn_with(
+        &self,
+        store: &mut EntityStore,
+        active_flags: ActivationFlags,
+        mut instantiate: impl FnMut(EntityId, &MapPlacement, &mut EntityStore) -> Result<(), Box<dyn Error>>,
+        mut despawn: impl FnMut(EntityId, &mut EntityStore))
+        -> Result<Vec<EntityId>, Box<dyn Error>>
+    {
+        let mut spawned = Vec::new();
+        let mut spawn_all = |spawned: &mut Vec<EntityId>, store: &mut EntityStore|
+        {
+            for (index, instance) in self.entities.instances.iter().enumerate()
+            {
+                if !self.is_layer_active(instance.layer, active_flags) { continue; }
+
+                let entity = store.spawn_named(instance.id)?;
+                spawned.push(entity);
+
+                let placement = MapPlacement
+                {
+                    instance: index as u32,
+                    entity: instance.entity,
+                    layer: instance.layer,
+                };
+                store.insert(entity, Transform::from(instance.transform))?;
+                store.insert(entity, placement)?;
+                instantiate(entity, &placement, store)?;
+            }
+            Ok::<_, Box<dyn Error>>(())
+        };
+
+        match spawn_all(&mut spawned, store)
+        {
+            Ok(()) => Ok(spawned),
+            Err(err) =>
+            {
+                for entity in spawned
+                {
+                    despawn(entity, store);
+                }
+                Err(err)
+            }
+        }
+    }
 }
 
 #[derive(LayoutHash, Encode, Decode)]
 pub struct MapFile
 {
-    // FUTURE: Split into map chunks (possibly spatially or by activation)
-    pub statics: StaticsFile,
+    pub activation_flags: Box<[String]>,
+    pub layers: Box<[MapLayerInfo]>,
+    pub chunk_size: f32,
+    pub chunk_hierarchy: AabbTree,
+    pub chunks: Box<[MapChunkRef]>,
+    pub lights: Box<[StaticLight]>,
+    pub entities: EntitiesFile,
+}
+
+#[derive(Debug, Clone, Copy, Encode, Decode)]
+pub struct MapChunkRef
+{
+    pub coord: IVec3, // the grid cell of this chunk, in multiples of the map's chunk size
+    pub bounds: AABB, // the bounds of everything in the chunk, which may extend outside its cell
+    pub chunk: AssetKey,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct MapLayerInfo
+{
+    pub name: String,
+    pub activation: ActivationFlags, // all of these flags must be set for the layer to be active, none means always active
+}
+impl MapLayerInfo
+{
+    #[inline] #[must_use]
+    pub fn is_active(&self, active_flags: ActivationFlags) -> bool
+    {
+        (self.activation & active_flags) == self.activation
+    }
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct StaticLight
+{
+    pub light: Light,
+    pub layer: u16,
+}
+
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct MapEntity
+{
+    pub entity: u32, // index into the entity palette
+    pub id: Ident,
+    pub layer: u16,
+    pub transform: Mat4,
 }
 
-#[repr(u8)]
-pub enum StaticClassification
+// A component on entities that were spawned from a map
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MapPlacement
 {
-    Model = 0,
-    Light = 1,
+    pub instance: u32, // index into the map's entity instances
+    pub entity: u32, // index into the map's entity palette
+    pub layer: u16,
 }
 
-pub struct Statics
+pub struct Entities
 {
-    pub hierarchy: AabbTree,
-    pub geo: Box<[Ash<Model>]>,
-    pub lights: Box<[Light]>,
+    pub palette: Box<[Ash<EntityDef>]>,
+    pub instances: Box<[MapEntity]>,
 }
 
 #[derive(Encode, Decode)]
-struct StaticsFile
+pub struct EntitiesFile
 {
-    pub hierarchy: AabbTree,
-    pub geo: Box<[AssetKey]>,
-    pub lights: Box<[Light]>,
+    pub palette: Box<[AssetKey]>,
+    pub instances: Box<[MapEntity]>,
 }
 
 pub struct MapLifecycler;
@@ -51,13 +203,147 @@ impl AssetLifecycler for MapLifecycler
         let input: MapFile = request.deserialize()?;
         let map = Map
         {
-            statics: Statics
+            activation_flags: input.activation_flags,
+            layers: input.layers,
+            chunk_size: input.chunk_size,
+            chunk_hierarchy: input.chunk_hierarchy,
+            chunks: input.chunks,
+            lights: input.lights,
+            entities: Entities
             {
-                hierarchy: input.statics.hierarchy,
-                geo: input.statics.geo.iter().map(|asset_key| request.load_dependency(*asset_key)).collect(),
-                lights: input.statics.lights,
+                palette: input.entities.palette.iter().map(|asset_key| request.load_dependency(*asset_key)).collect(),
+                instances: input.entities.instances,
             },
         };
         Ok(map)
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use asset_3l14::{AssetKeySynthHash, AssetLifecyclers, Assets, AssetsConfig};
+    use entity_3l14::EntityError;
+    use crate::assets::entity_def::{EntityDefFile, EntityDefLifecycler};
+    use super::*;
+
+    #[test]
+    fn layer_activation()
+    {
+        let always = MapLayerInfo { name: "base".to_string(), activation: 0 };
+        assert!(always.is_active(0));
+        assert!(always.is_active(0b101));
+
+        let gated = MapLayerInfo { name: "night".to_string(), activation: 0b101 };
+        assert!(!gated.is_active(0));
+        assert!(!gated.is_active(0b100));
+        assert!(gated.is_active(0b101));
+        assert!(gated.is_active(0b111));
+    }
+
+    fn test_map(instances: Vec<MapEntity>) -> Map
+    {
+        Map
+        {
+            activation_flags: Box::new(["night".to_string()]),
+            layers: Box::new(
+            [
+                MapLayerInfo { name: "base".to_string(), activation: 0 },
+                MapLayerInfo { name: "night".to_string(), activation: 0b1 },
+            ]),
+            chunk_size: 64.0,
+            chunk_hierarchy: AabbTree::new(),
+            chunks: Box::new([]),
+            lights: Box::new([]),
+            entities: Entities
+            {
+                palette: Box::new([]),
+                instances: instances.into_boxed_slice(),
+            },
+        }
+    }
+
+    #[test]
+    fn spawn_placements()
+    {
+        let map = test_map(vec![
+            MapEntity { entity: 0, id: Ident::test('a'), layer: 0, transform: Mat4::from_translation(glam::Vec3::X) },
+            MapEntity { entity: 0, id: Ident::test('b'), layer: 1, transform: Mat4::IDENTITY },
+        ]);
+
+        let mut store = EntityStore::new();
+        let spawned = map.spawn_placements(&mut store, 0).unwrap();
+        assert_eq!(spawned.len(), 1);
+        assert_eq!(store.find(Ident::test('a')), Some(spawned[0]));
+        assert_eq!(store.get::<Transform>(spawned[0]).unwrap().position, glam::Vec3::X);
+        assert_eq!(store.get::<MapPlacement>(spawned[0]).unwrap().instance, 0);
+
+        let mut store = EntityStore::new();
+        assert_eq!(map.spawn_placements(&mut store, map.activation_flag("night").unwrap()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn spawn_placements_rolls_back()
+    {
+        let map = test_map(vec![
+            MapEntity { entity: 0, id: Ident::test('a'), layer: 0, transform: Mat4::IDENTITY },
+            MapEntity { entity: 0, id: Ident::test('a'), layer: 0, transform: Mat4::IDENTITY },
+        ]);
+
+        let mut store = EntityStore::new();
+        let err = map.spawn_placements(&mut store, 0).unwrap_err();
+        assert!(matches!(err.downcast_ref::<EntityError>(), Some(EntityError::DuplicateIdent(..))));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn spawn_entities()
+    {
+        let lifecyclers = AssetLifecyclers::default()
+            .add_lifecycler(EntityDefLifecycler::default());
+        let assets = Assets::new(lifecyclers, AssetsConfig::test());
+        let latch = Runtime::new();
+
+        let mut map = test_map(vec![
+            MapEntity { entity: 0, id: Ident::test('a'), layer: 0, transform: Mat4::from_translation(glam::Vec3::Y) },
+            MapEntity { entity: 0, id: Ident::test('b'), layer: 1, transform: Mat4::IDENTITY },
+        ]);
+        map.entities.palette = Box::new([assets.load_encoded_for_test(
+            AssetKey::synthetic(AssetTypeId::EntityDef, AssetKeySynthHash(1)),
+            &EntityDefFile { components: Box::new([]), model: None, circuit: None })]);
+        assert!(map.all_dependencies_loaded());
+
+        let mut store = EntityStore::new();
+        let spawned = map.spawn_entities(&mut store, 0, &latch).unwrap();
+        assert_eq!(spawned.len(), 1);
+        assert_eq!(store.find(Ident::test('a')), Some(spawned[0]));
+        assert_eq!(store.get::<Transform>(spawned[0]).unwrap().position, glam::Vec3::Y);
+        assert_eq!(store.get::<MapPlacement>(spawned[0]).unwrap().entity, 0);
+
+        assert!(despawn_entity(spawned[0], &mut store, &latch));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn spawn_entities_requires_definitions()
+    {
+        let lifecyclers = AssetLifecyclers::default()
+            .add_lifecycler(EntityDefLifecycler::default());
+        let assets = Assets::new(lifecyclers, AssetsConfig::test());
+        let latch = Runtime::new();
+
+        // an entity def that fails to load
+        let mut map = test_map(vec![
+            MapEntity { entity: 0, id: Ident::test('a'), layer: 0, transform: Mat4::IDENTITY },
+        ]);
+        let def = assets.load_from(AssetKey::synthetic(AssetTypeId::EntityDef, AssetKeySynthHash(2)), Box::new([]));
+        assert!(matches!(futures::executor::block_on(&def), AssetSnapshot::Unavailable(_)));
+        map.entities.palette = Box::new([def]);
+        assert!(!map.all_dependencies_loaded());
+
+        let mut store = EntityStore::new();
+        let err = map.spawn_entities(&mut store, 0, &latch).unwrap_err();
+        assert!(matches!(err.downcast_ref::<EntityDefError>(), Some(EntityDefError::NotLoaded(..))));
+        assert!(store.is_empty(), "Spawned entities are rolled back");
+    }
+}
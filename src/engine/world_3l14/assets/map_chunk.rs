@@ -0,0 +1,133 @@
+use bitcode::{Decode, Encode};
+use asset_3l14::{Ash, Asset, AssetKey, AssetLifecycler, AssetLoadRequest, AssetTypeId};
+use proc_macros_3l14::LayoutHash;
+use std::error::Error;
+use glam::Mat4;
+use containers_3l14::AabbTree;
+use graphics_3l14::assets::Model;
+
+// A spatial partition of a map's statics
+pub struct MapChunk
+{
+    pub statics: Statics,
+}
+impl Asset for MapChunk
+{
+    type DebugData = ();
+    fn asset_type() -> AssetTypeId { AssetTypeId::MapChunk }
+    fn all_dependencies_loaded(&self) -> bool
+    {
+        // #[asset] only sees top-level handles, the models are nested in the statics
+        self.statics.geo.iter().all(Ash::is_loaded_recursive)
+    }
+}
+
+#[derive(LayoutHash, Encode, Decode)]
+pub struct MapChunkFile
+{
+    pub statics: StaticsFile,
+}
+
+#[repr(u8)]
+pub enum StaticClassification
+{
+    Model = 0,
+    Light = 1,
+}
+
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct StaticModel
+{
+    pub model: u32, // index into the chunk's model palette
+    pub layer: u16, // index into the map's layers
+    pub transform: Mat4,
+}
+
+pub struct Statics
+{
+    pub hierarchy: AabbTree, // the models' world bounds, values are indices into models
+    pub geo: Box<[Ash<Model>]>, // the model palette
+    pub models: Box<[StaticModel]>,
+}
+
+#[derive(Encode, Decode)]
+pub struct StaticsFile
+{
+    pub hierarchy: AabbTree,
+    pub geo: Box<[AssetKey]>,
+    pub models: Box<[StaticModel]>,
+}
+
+pub struct MapChunkLifecycler;
+impl AssetLifecycler for MapChunkLifecycler
+{
+    type Asset = MapChunk;
+
+    fn load(&self, mut request: AssetLoadRequest) -> Result<Self::Asset, Box<dyn Error>>
+    {
+        let input: MapChunkFile = request.deserialize()?;
+        let chunk = MapChunk
+        {
+            statics: Statics
+            {
+                hierarchy: input.statics.hierarchy,
+                geo: input.statics.geo.iter().map(|asset_key| request.load_dependency(*asset_key)).collect(),
+                models: input.statics.models,
+            },
+        };
+        Ok(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::mpsc::{channel, Receiver};
+    use std::sync::Mutex;
+    use asset_3l14::{AssetKeySynthHash, AssetLifecyclers, Assets, AssetsConfig};
+    use super::*;
+
+    // Holds model loads until released, and then fails them
+    struct GatedModelLifecycler(Mutex<Receiver<()>>);
+    impl AssetLifecycler for GatedModelLifecycler
+    {
+        type Asset = Model;
+        fn load(&self, _request: AssetLoadRequest) -> Result<Self::Asset, Box<dyn Error>>
+        {
+            let _ = self.0.lock().unwrap().recv();
+            Err("Model loads are not supported in tests".into())
+        }
+    }
+
+    fn test_chunk(geo: Vec<Ash<Model>>) -> MapChunk
+    {
+        MapChunk
+        {
+            statics: Statics
+            {
+                hierarchy: AabbTree::new(),
+                geo: geo.into_boxed_slice(),
+                models: Box::new([]),
+            },
+        }
+    }
+
+    #[test]
+    fn nested_dependencies()
+    {
+        let (release, gate) = channel();
+        let lifecyclers = AssetLifecyclers::default()
+            .add_lifecycler(GatedModelLifecycler(Mutex::new(gate)));
+        let assets = Assets::new(lifecyclers, AssetsConfig::test());
+
+        let empty = test_chunk(Vec::new());
+        assert!(empty.all_dependencies_loaded());
+
+        let pending = assets.load_from::<Model>(AssetKey::synthetic(AssetTypeId::Model, AssetKeySynthHash(1)), Box::new([]));
+        let chunk = test_chunk(vec![pending]);
+        assert!(!chunk.all_dependencies_loaded(), "A chunk is not loaded until its models are");
+
+        drop(release);
+        drop(chunk);
+    }
+}
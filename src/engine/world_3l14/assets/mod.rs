@@ -1 +1,3 @@
-pub mod map;
\ No newline at end of file
+pub mod entity_def;
+pub mod map;
+pub mod map_chunk;
\ No newline at end of file
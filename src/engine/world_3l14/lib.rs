@@ -1,4 +1,10 @@
 mod light;
 pub use light::*;
 
+mod animation;
+pub use animation::*;
+
+mod map_streamer;
+pub use map_streamer::*;
+
 pub mod assets;
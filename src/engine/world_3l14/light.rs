@@ -1,17 +1,60 @@
 use bitcode::{Decode, Encode};
-use glam::Vec3;
+use glam::{Vec3, Vec4};
+use graphics_3l14::colors::Rgba;
+use graphics_3l14::passes::light_cull::ClusterLight;
 use math_3l14::Angle;
+use proc_macros_3l14::EntityComponent;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Encode, Decode, Serialize, Deserialize)]
+// Placed in maps, or attached to entities as a component
+#[derive(Debug, Encode, Decode, Serialize, Deserialize, EntityComponent)]
 pub enum Light
 {
-    Point(Vec3),
+    Point
+    {
+        position: Vec3,
+        color: Rgba, // sRGB
+        intensity: f32,
+        range: f32,
+    },
     Directional(Vec3),
     Spot
     {
-        angle: Angle,
+        position: Vec3,
+        direction: Vec3,
+        angle: Angle, // from the direction to the edge of the cone
+        color: Rgba, // sRGB
+        intensity: f32,
         range: f32,
     },
     // rect/disc area lights
 }
+impl Light
+{
+    // The direction of lights that cast cascaded shadows
+    #[inline] #[must_use]
+    pub fn shadow_direction(&self) -> Option<Vec3>
+    {
+        match self
+        {
+            Light::Directional(direction) => Some(*direction),
+            _ => None,
+        }
+    }
+
+    // Local lights, for clustering per view
+    #[must_use]
+    pub fn cluster_light(&self) -> Option<ClusterLight>
+    {
+        // convert to linear before scaling, so that dark colors keep their precision
+        let radiance = |color: Rgba, intensity: f32| Vec4::from_array(color.to_linear_f32()).truncate() * intensity;
+        match self
+        {
+            Light::Point { position, color, intensity, range } =>
+                Some(ClusterLight::point(*position, *range, radiance(*color, *intensity))),
+            Light::Directional(_) => None,
+            Light::Spot { position, direction, angle, color, intensity, range } =>
+                Some(ClusterLight::spot(*position, *direction, *angle, *range, radiance(*color, *intensity))),
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use glam::Vec3;
+use asset_3l14::{Ash, Assets};
+use math_3l14::AABB;
+use crate::assets::map::Map;
+use crate::assets::map_chunk::MapChunk;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MapStreamerConfig
+{
+    pub load_radius: f32, // chunks closer than this to any focus point are loaded
+    pub unload_radius: f32, // loaded chunks further than this from all focus points are unloaded, should be larger than the load radius
+}
+impl Default for MapStreamerConfig
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            load_radius: 128.0,
+            unload_radius: 160.0,
+        }
+    }
+}
+impl MapStreamerConfig
+{
+    // Should a chunk be resident, given whether or not it already is
+    // The gap between the load and unload radii keeps chunks on the boundary from thrashing
+    #[must_use]
+    pub fn wants_resident(&self, chunk_bounds: AABB, focus_points: &[Vec3], is_resident: bool) -> bool
+    {
+        let radius = if is_resident { self.unload_radius.max(self.load_radius) } else { self.load_radius };
+        let radius_sq = radius * radius;
+        focus_points.iter().any(|p| chunk_bounds.distance_sq(*p) <= radius_sq)
+    }
+}
+
+// Streams map chunks in and out around a set of focus points (e.g. cameras or players)
+pub struct MapStreamer
+{
+    config: MapStreamerConfig,
+    resident: HashMap<u32, Ash<MapChunk>>, // chunk index -> chunk
+}
+impl MapStreamer
+{
+    #[must_use]
+    pub fn new(config: MapStreamerConfig) -> Self
+    {
+        Self
+        {
+            config,
+            resident: HashMap::new(),
+        }
+    }
+
+    #[inline] #[must_use]
+    pub fn config(&self) -> &MapStreamerConfig { &self.config }
+
+    // Load any chunks that have come into range of the focus points, and drop any that have left it
+    // Chunks are loaded asynchronously, and their models are loaded as dependencies of the chunk
+    pub fn update(&mut self, map: &Map, focus_points: &[Vec3], assets: &Assets)
+    {
+        self.resident.retain(|index, _|
+        {
+            let chunk = &map.chunks[*index as usize];
+            self.config.wants_resident(chunk.bounds, focus_points, true)
+        });
+
+        let reach = Vec3::splat(self.config.load_radius);
+        for focus in focus_points
+        {
+            let query = AABB::new(*focus - reach, *focus + reach);
+            for (bounds, index) in map.chunk_hierarchy.iter_overlapping(query)
+            {
+                if self.resident.contains_key(&index) ||
+                    !self.config.wants_resident(bounds, focus_points, false)
+                {
+                    continue;
+                }
+
+                let chunk_ref = &map.chunks[index as usize];
+                log::debug!("Streaming in map chunk {} ({:?})", chunk_ref.coord, chunk_ref.chunk);
+                self.resident.insert(index, assets.load(chunk_ref.chunk));
+            }
+        }
+    }
+
+    // Drop all streamed chunks, e.g. when changing maps
+    pub fn clear(&mut self)
+    {
+        self.resident.clear();
+    }
+
+    #[inline] #[must_use]
+    pub fn is_resident(&self, chunk_index: u32) -> bool { self.resident.contains_key(&chunk_index) }
+
+    // All chunks that have been requested, some may still be loading
+    #[inline]
+    pub fn resident_chunks(&self) -> impl Iterator<Item=&Ash<MapChunk>>
+    {
+        self.resident.values()
+    }
+
+    // Are all requested chunks (and their models) loaded
+    #[must_use]
+    pub fn is_fully_loaded(&self) -> bool
+    {
+        self.resident.values().all(|chunk| chunk.is_loaded_recursive())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::error::Error;
+    use std::path::Path;
+    use std::sync::mpsc::{channel, Receiver};
+    use std::sync::Mutex;
+    use glam::IVec3;
+    use asset_3l14::{AssetFileType, AssetKey, AssetKeySynthHash, AssetLifecycler, AssetLifecyclers, AssetLoadRequest, AssetTypeId, AssetsConfig};
+    use containers_3l14::AabbTree;
+    use nab_3l14::utils::varint;
+    use crate::assets::map::{Entities, MapChunkRef};
+    use crate::assets::map_chunk::{MapChunkFile, MapChunkLifecycler, StaticsFile};
+    use super::*;
+
+    #[test]
+    fn hysteresis()
+    {
+        let config = MapStreamerConfig { load_radius: 10.0, unload_radius: 20.0 };
+        let chunk = AABB::new(Vec3::ZERO, Vec3::splat(5.0));
+
+        assert!(config.wants_resident(chunk, &[Vec3::new(12.0, 0.0, 0.0)], false));
+        assert!(!config.wants_resident(chunk, &[Vec3::new(16.0, 0.0, 0.0)], false));
+        // once loaded, the chunk stays until outside the unload radius
+        assert!(config.wants_resident(chunk, &[Vec3::new(16.0, 0.0, 0.0)], true));
+        assert!(!config.wants_resident(chunk, &[Vec3::new(26.0, 0.0, 0.0)], true));
+    }
+
+    #[test]
+    fn multiple_focus_points()
+    {
+        let config = MapStreamerConfig { load_radius: 10.0, unload_radius: 20.0 };
+        let chunk = AABB::new(Vec3::ZERO, Vec3::splat(5.0));
+
+        assert!(!config.wants_resident(chunk, &[], false));
+        assert!(config.wants_resident(chunk, &[Vec3::splat(100.0), Vec3::new(0.0, -8.0, 0.0)], false));
+        assert!(!config.wants_resident(chunk, &[Vec3::splat(100.0), Vec3::splat(-100.0)], true));
+    }
+
+    // Holds chunk loads until released
+    struct GatedChunkLifecycler(Mutex<Receiver<()>>);
+    impl AssetLifecycler for GatedChunkLifecycler
+    {
+        type Asset = MapChunk;
+        fn load(&self, request: AssetLoadRequest) -> Result<Self::Asset, Box<dyn Error>>
+        {
+            let _ = self.0.lock().unwrap().recv();
+            MapChunkLifecycler.load(request)
+        }
+    }
+
+    fn write_asset(assets_root: &Path, asset_key: AssetKey, file: &impl bitcode::Encode)
+    {
+        let encoded = bitcode::encode(file);
+        let mut data = Vec::new();
+        varint::encode_into(encoded.len() as u64, &mut data).unwrap();
+        data.extend_from_slice(&encoded);
+        std::fs::write(assets_root.join(asset_key.as_file_name(AssetFileType::Asset)), data).unwrap();
+    }
+
+    // A row of empty 64x16x64 chunks along +X, with their files written to the assets root
+    fn test_map(assets_root: &Path, chunk_count: u32) -> Map
+    {
+        let mut chunk_hierarchy = AabbTree::new();
+        let chunks: Box<[_]> = (0..chunk_count).map(|i|
+        {
+            let min = Vec3::new(i as f32 * 64.0, 0.0, 0.0);
+            let chunk = AssetKey::synthetic(AssetTypeId::MapChunk, AssetKeySynthHash(i as u64 + 1));
+            write_asset(assets_root, chunk, &MapChunkFile
+            {
+                statics: StaticsFile { hierarchy: AabbTree::new(), geo: Box::new([]), models: Box::new([]) },
+            });
+
+            let bounds = AABB::new(min, min + Vec3::new(64.0, 16.0, 64.0));
+            chunk_hierarchy.insert(bounds, i);
+            MapChunkRef { coord: IVec3::new(i as i32, 0, 0), bounds, chunk }
+        }).collect();
+        chunk_hierarchy.repack();
+
+        Map
+        {
+            activation_flags: Box::new([]),
+            layers: Box::new([]),
+            chunk_size: 64.0,
+            chunk_hierarchy,
+            chunks,
+            lights: Box::new([]),
+            entities: Entities { palette: Box::new([]), instances: Box::new([]) },
+        }
+    }
+
+    fn resident_indices(streamer: &MapStreamer) -> Vec<u32>
+    {
+        let mut indices: Vec<_> = streamer.resident.keys().copied().collect();
+        indices.sort();
+        indices
+    }
+
+    fn wait_for_loads(streamer: &MapStreamer)
+    {
+        for chunk in streamer.resident_chunks()
+        {
+            let _ = futures::executor::block_on(chunk);
+        }
+    }
+
+    #[test]
+    fn stream_chunks()
+    {
+        let assets_root = std::env::temp_dir().join(format!("3l14_map_streamer_{}", std::process::id()));
+        std::fs::create_dir_all(&assets_root).unwrap();
+        let map = test_map(&assets_root, 3);
+
+        let (release, gate) = channel();
+        let lifecyclers = AssetLifecyclers::default()
+            .add_lifecycler(GatedChunkLifecycler(Mutex::new(gate)));
+        let assets = Assets::new(lifecyclers, AssetsConfig { assets_root: assets_root.clone(), enable_fs_watcher: false });
+
+        let mut streamer = MapStreamer::new(MapStreamerConfig { load_radius: 32.0, unload_radius: 48.0 });
+        assert!(streamer.is_fully_loaded());
+
+        streamer.update(&map, &[Vec3::new(20.0, 8.0, 32.0)], &assets);
+        assert_eq!(resident_indices(&streamer), [0]);
+        assert!(!streamer.is_fully_loaded(), "Chunks are loaded asynchronously");
+        release.send(()).unwrap();
+        wait_for_loads(&streamer);
+        assert!(streamer.is_fully_loaded());
+
+        // chunk 0 is outside the load radius but inside the unload radius
+        streamer.update(&map, &[Vec3::new(100.0, 8.0, 32.0)], &assets);
+        assert_eq!(resident_indices(&streamer), [0, 1, 2]);
+        assert!(!streamer.is_fully_loaded());
+        release.send(()).unwrap();
+        release.send(()).unwrap();
+        wait_for_loads(&streamer);
+        assert!(streamer.is_fully_loaded());
+
+        streamer.update(&map, &[Vec3::new(160.0, 8.0, 32.0)], &assets);
+        assert_eq!(resident_indices(&streamer), [1, 2]);
+        assert!(!streamer.is_resident(0));
+
+        // either focus point keeps its chunks
+        streamer.update(&map, &[Vec3::new(160.0, 8.0, 32.0), Vec3::new(20.0, 8.0, 32.0)], &assets);
+        assert_eq!(resident_indices(&streamer), [0, 1, 2]);
+        release.send(()).unwrap();
+        wait_for_loads(&streamer);
+        assert!(streamer.is_fully_loaded());
+
+        streamer.clear();
+        assert_eq!(streamer.resident_chunks().count(), 0);
+        assert!(streamer.is_fully_loaded());
+
+        drop(assets);
+        let _ = std::fs::remove_dir_all(&assets_root);
+    }
+}
@@ -0,0 +1,421 @@
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::io::Read;
+use glam::Vec2;
+use indexmap::IndexSet;
+use serde::{Deserialize, Serialize};
+use asset_3l14::{AssetKey, AssetTypeId};
+use graphics_3l14::assets::{AnimCompare, AnimCondition, AnimGraphDef, AnimGraphFile, AnimIndex, AnimMotion, AnimParamIndex, AnimParamKind, AnimParameter, AnimState, AnimStateIndex, AnimTransition, BlendSample1D, BlendSample2D};
+use crate::core::{AssetBuilder, BuildOutputs, SourceInput, VersionBuilder};
+
+#[derive(Debug)]
+pub enum AnimGraphBuildError
+{
+    NoStates,
+    DuplicateParameter { name: String },
+    DuplicateState { name: String },
+    UnknownParameter { name: String },
+    UnknownState { name: String },
+    InvalidMotion { state: String }, // states must have exactly one of clip, blend_1d, or blend_2d
+    EmptyBlendSpace { state: String },
+    CoincidentBlendSamples { state: String }, // 2D blend space samples must all be at different positions
+    TooManyItems { kind: &'static str, count: usize },
+}
+impl Display for AnimGraphBuildError
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+    {
+        Debug::fmt(self, f)
+    }
+}
+impl Error for AnimGraphBuildError { }
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ParameterKindSource
+{
+    Float,
+    Bool,
+    Trigger,
+}
+
+#[derive(Deserialize)]
+struct ParameterSource
+{
+    name: String,
+    kind: ParameterKindSource,
+    #[serde(default)]
+    default: f32,
+}
+
+#[derive(Deserialize)]
+struct BlendSample1DSource
+{
+    position: f32,
+    animation: AssetKey,
+}
+
+#[derive(Deserialize)]
+struct BlendSpace1DSource
+{
+    parameter: String,
+    samples: Vec<BlendSample1DSource>,
+}
+
+#[derive(Deserialize)]
+struct BlendSample2DSource
+{
+    position: Vec2,
+    animation: AssetKey,
+}
+
+#[derive(Deserialize)]
+struct BlendSpace2DSource
+{
+    parameters: [String; 2],
+    samples: Vec<BlendSample2DSource>,
+}
+
+fn default_speed() -> f32 { 1.0 }
+fn default_looping() -> bool { true }
+
+#[derive(Deserialize)]
+struct StateSource
+{
+    name: String,
+    clip: Option<AssetKey>,
+    blend_1d: Option<BlendSpace1DSource>,
+    blend_2d: Option<BlendSpace2DSource>,
+    #[serde(default = "default_speed")]
+    speed: f32,
+    #[serde(default = "default_looping")]
+    looping: bool,
+    sync_group: Option<String>,
+}
+
+#[derive(Deserialize)]
+enum CompareSource
+{
+    #[serde(rename = "<")] Less,
+    #[serde(rename = "<=")] LessEqual,
+    #[serde(rename = ">")] Greater,
+    #[serde(rename = ">=")] GreaterEqual,
+    #[serde(rename = "==")] Equal,
+    #[serde(rename = "!=")] NotEqual,
+}
+
+// conditions default to checking if a bool or trigger is set
+#[derive(Deserialize)]
+struct ConditionSource
+{
+    parameter: String,
+    compare: Option<CompareSource>,
+    #[serde(default)]
+    value: f32,
+}
+
+#[derive(Deserialize)]
+struct TransitionSource
+{
+    from: Option<String>, // any state if unset
+    to: String,
+    #[serde(default)]
+    duration: f32, // in seconds
+    exit_phase: Option<f32>,
+    #[serde(default)]
+    conditions: Vec<ConditionSource>,
+}
+
+#[derive(Deserialize)]
+struct AnimGraphSource
+{
+    #[serde(default)]
+    parameters: Vec<ParameterSource>,
+    states: Vec<StateSource>,
+    #[serde(default)]
+    transitions: Vec<TransitionSource>,
+    entry_state: Option<String>, // the first state if unset
+}
+
+// Maps names to indices
+struct Names(IndexSet<String>);
+impl Names
+{
+    fn new(names: impl Iterator<Item=String>, duplicate_error: impl Fn(String) -> AnimGraphBuildError) -> Result<Self, AnimGraphBuildError>
+    {
+        let mut set = IndexSet::new();
+        for name in names
+        {
+            if let Some(duplicate) = set.replace(name)
+            {
+                return Err(duplicate_error(duplicate));
+            }
+        }
+        if set.len() > u16::MAX as usize
+        {
+            return Err(AnimGraphBuildError::TooManyItems { kind: "names", count: set.len() });
+        }
+        Ok(Self(set))
+    }
+
+    fn get(&self, name: &str, unknown_error: impl Fn(String) -> AnimGraphBuildError) -> Result<u16, AnimGraphBuildError>
+    {
+        self.0.get_index_of(name).map(|i| i as u16).ok_or_else(|| unknown_error(name.to_string()))
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct AnimGraphBuilderConfig { }
+
+pub struct AnimGraphBuilder;
+impl AssetBuilder for AnimGraphBuilder
+{
+    type BuildConfig = AnimGraphBuilderConfig;
+
+    fn supported_input_file_extensions(&self) -> &'static [&'static str]
+    {
+        &["animgraph"]
+    }
+
+    fn builder_version(&self, vb: &mut VersionBuilder)
+    {
+        vb.push(b"Anim graph builder - initial");
+    }
+
+    fn format_version(&self, vb: &mut VersionBuilder)
+    {
+        vb.push_prehashed(AnimGraphFile::TYPE_LAYOUT_HASH);
+    }
+
+    fn build_assets(&self, _config: Self::BuildConfig, input: &mut SourceInput, outputs: &mut BuildOutputs) -> Result<(), Box<dyn Error>>
+    {
+        let mut toml_str = String::new();
+        input.read_to_string(&mut toml_str)?;
+        let source: AnimGraphSource = toml::from_str(&toml_str)?;
+
+        let graph = build_graph(source)?;
+
+        outputs.add_output(AssetTypeId::AnimGraph, |output|
+        {
+            output.depends_on_multiple(graph.animations.iter().copied());
+            output.serialize(&graph)?;
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+}
+
+fn build_graph(source: AnimGraphSource) -> Result<AnimGraphFile, AnimGraphBuildError>
+{
+    if source.states.is_empty()
+    {
+        return Err(AnimGraphBuildError::NoStates);
+    }
+
+    let param_names = Names::new(source.parameters.iter().map(|p| p.name.clone()),
+        |name| AnimGraphBuildError::DuplicateParameter { name })?;
+    let param = |name: &str| -> Result<AnimParamIndex, AnimGraphBuildError>
+    {
+        param_names.get(name, |name| AnimGraphBuildError::UnknownParameter { name })
+    };
+    let state_names = Names::new(source.states.iter().map(|s| s.name.clone()),
+        |name| AnimGraphBuildError::DuplicateState { name })?;
+    let state = |name: &str| -> Result<AnimStateIndex, AnimGraphBuildError>
+    {
+        state_names.get(name, |name| AnimGraphBuildError::UnknownState { name })
+    };
+
+    let mut animations = IndexSet::new();
+    let mut animation = |key: AssetKey| -> Result<AnimIndex, AnimGraphBuildError>
+    {
+        let (index, _) = animations.insert_full(key);
+        AnimIndex::try_from(index).map_err(|_| AnimGraphBuildError::TooManyItems { kind: "animations", count: index + 1 })
+    };
+    let mut sync_groups = IndexSet::new();
+
+    let mut states = Vec::with_capacity(source.states.len());
+    for state_source in source.states
+    {
+        let motion = match (state_source.clip, state_source.blend_1d, state_source.blend_2d)
+        {
+            (Some(clip), None, None) => AnimMotion::Clip(animation(clip)?),
+            (None, Some(blend), None) =>
+            {
+                if blend.samples.is_empty()
+                {
+                    return Err(AnimGraphBuildError::EmptyBlendSpace { state: state_source.name });
+                }
+                let mut samples = blend.samples.into_iter()
+                    .map(|s| Ok(BlendSample1D { position: s.position, animation: animation(s.animation)? }))
+                    .collect::<Result<Vec<_>, AnimGraphBuildError>>()?;
+                samples.sort_by(|a, b| a.position.total_cmp(&b.position));
+                AnimMotion::BlendSpace1D { parameter: param(&blend.parameter)?, samples: samples.into_boxed_slice() }
+            },
+            (None, None, Some(blend)) =>
+            {
+                if blend.samples.is_empty()
+                {
+                    return Err(AnimGraphBuildError::EmptyBlendSpace { state: state_source.name });
+                }
+                // the weights are relative to the distance between each pair of samples
+                if blend.samples.iter().enumerate().any(|(i, a)| blend.samples[i + 1..].iter().any(|b| a.position == b.position))
+                {
+                    return Err(AnimGraphBuildError::CoincidentBlendSamples { state: state_source.name });
+                }
+                let samples = blend.samples.into_iter()
+                    .map(|s| Ok(BlendSample2D { position: s.position, animation: animation(s.animation)? }))
+                    .collect::<Result<Box<_>, AnimGraphBuildError>>()?;
+                AnimMotion::BlendSpace2D { parameters: [param(&blend.parameters[0])?, param(&blend.parameters[1])?], samples }
+            },
+            _ => return Err(AnimGraphBuildError::InvalidMotion { state: state_source.name }),
+        };
+
+        states.push(AnimState
+        {
+            name: state_source.name,
+            motion,
+            speed: state_source.speed,
+            looping: state_source.looping,
+            sync_group: state_source.sync_group.map(|g| sync_groups.insert_full(g).0 as u16),
+        });
+    }
+
+    let transitions = source.transitions.into_iter().map(|transition|
+    {
+        let conditions = transition.conditions.into_iter().map(|condition| Ok(AnimCondition
+        {
+            parameter: param(&condition.parameter)?,
+            compare: match condition.compare
+            {
+                Some(CompareSource::Less) => AnimCompare::Less,
+                Some(CompareSource::LessEqual) => AnimCompare::LessEqual,
+                Some(CompareSource::Greater) => AnimCompare::Greater,
+                Some(CompareSource::GreaterEqual) => AnimCompare::GreaterEqual,
+                Some(CompareSource::Equal) => AnimCompare::Equal,
+                Some(CompareSource::NotEqual) | None => AnimCompare::NotEqual,
+            },
+            value: condition.value,
+        })).collect::<Result<_, AnimGraphBuildError>>()?;
+
+        Ok(AnimTransition
+        {
+            from: transition.from.map(|from| state(&from)).transpose()?,
+            to: state(&transition.to)?,
+            duration_secs: transition.duration.max(0.0),
+            exit_phase: transition.exit_phase,
+            conditions,
+        })
+    }).collect::<Result<_, AnimGraphBuildError>>()?;
+
+    let entry_state = match &source.entry_state
+    {
+        Some(name) => state(name)?,
+        None => 0,
+    };
+
+    let parameters = source.parameters.into_iter().map(|p| AnimParameter
+    {
+        name: p.name,
+        kind: match p.kind
+        {
+            ParameterKindSource::Float => AnimParamKind::Float,
+            ParameterKindSource::Bool => AnimParamKind::Bool,
+            ParameterKindSource::Trigger => AnimParamKind::Trigger,
+        },
+        default: p.default,
+    }).collect();
+
+    Ok(AnimGraphFile
+    {
+        def: AnimGraphDef
+        {
+            parameters,
+            states: states.into_boxed_slice(),
+            transitions,
+            entry_state,
+        },
+        animations: animations.into_iter().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn build_toml(toml_str: &str) -> Result<AnimGraphFile, AnimGraphBuildError>
+    {
+        build_graph(toml::from_str(toml_str).unwrap())
+    }
+
+    #[test]
+    fn build()
+    {
+        let graph = build_toml(r#"
+            entry_state = "idle"
+
+            [[parameters]]
+            name = "speed"
+            kind = "float"
+
+            [[parameters]]
+            name = "jump"
+            kind = "trigger"
+
+            [[states]]
+            name = "move"
+            sync_group = "feet"
+            blend_1d = { parameter = "speed", samples = [{ position = 3.0, animation = "2" }, { position = 1.0, animation = "1" }] }
+
+            [[states]]
+            name = "idle"
+            clip = "1"
+            sync_group = "feet"
+
+            [[transitions]]
+            from = "idle"
+            to = "move"
+            duration = 0.25
+            conditions = [{ parameter = "speed", compare = ">", value = 0.1 }]
+
+            [[transitions]]
+            to = "idle"
+            conditions = [{ parameter = "jump" }]
+        "#).unwrap();
+
+        assert_eq!(graph.animations.len(), 2);
+        assert_eq!(graph.def.entry_state, 1);
+        assert_eq!(graph.def.states[0].sync_group, graph.def.states[1].sync_group);
+        let AnimMotion::BlendSpace1D { parameter, samples } = &graph.def.states[0].motion else { panic!("Expected a 1D blend space") };
+        assert_eq!(*parameter, 0);
+        assert_eq!(samples[0].position, 1.0);
+        assert!(matches!(graph.def.states[1].motion, AnimMotion::Clip(anim) if anim == samples[0].animation));
+
+        assert_eq!(graph.def.transitions[0].from, Some(1));
+        assert_eq!(graph.def.transitions[1].from, None);
+        assert_eq!(graph.def.transitions[1].conditions[0].compare, AnimCompare::NotEqual);
+    }
+
+    #[test]
+    fn invalid()
+    {
+        assert!(matches!(build_toml("states = []"), Err(AnimGraphBuildError::NoStates)));
+        assert!(matches!(build_toml(r#"states = [{ name = "a" }]"#), Err(AnimGraphBuildError::InvalidMotion { .. })));
+        assert!(matches!(build_toml(r#"states = [{ name = "a", clip = "1" }, { name = "a", clip = "1" }]"#),
+            Err(AnimGraphBuildError::DuplicateState { .. })));
+        assert!(matches!(build_toml(r#"states = [{ name = "a", blend_1d = { parameter = "x", samples = [{ position = 0.0, animation = "1" }] } }]"#),
+            Err(AnimGraphBuildError::UnknownParameter { .. })));
+        assert!(matches!(build_toml(r#"
+            states = [{ name = "a", clip = "1" }]
+            transitions = [{ to = "b" }]
+        "#), Err(AnimGraphBuildError::UnknownState { .. })));
+        assert!(matches!(build_toml(r#"
+            parameters = [{ name = "x", kind = "float" }, { name = "y", kind = "float" }]
+            states = [{ name = "a", blend_2d = { parameters = ["x", "y"], samples = [
+                { position = [1.0, 0.0], animation = "1" },
+                { position = [1.0, 0.0], animation = "2" },
+            ] } }]
+        "#), Err(AnimGraphBuildError::CoincidentBlendSamples { .. })));
+    }
+}
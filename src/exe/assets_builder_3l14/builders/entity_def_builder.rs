@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::io::Read;
+use serde::{Deserialize, Serialize};
+use unicase::UniCase;
+use asset_3l14::{AssetKey, AssetTypeId};
+use entity_3l14::component_meta::ComponentBuildMeta;
+use world_3l14::assets::entity_def::{EntityDefFile, EntityDefFileComponent};
+use crate::core::{AssetBuilder, BuildOutputs, SourceInput, VersionBuilder};
+
+#[derive(Debug)]
+pub enum EntityDefBuildError
+{
+    UnknownComponent { type_name: String },
+    DuplicateComponent { type_name: String },
+    ComponentDeserializeError { type_name: String, error: erased_serde::Error },
+}
+impl Display for EntityDefBuildError
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+    {
+        Debug::fmt(self, f)
+    }
+}
+impl Error for EntityDefBuildError { }
+
+// The source format, each component is a table named after its type, e.g.
+//   model = "<asset key>"
+//   [components.Health]
+//   max = 100
+#[derive(Deserialize)]
+struct EntityDefSource
+{
+    model: Option<AssetKey>,
+    circuit: Option<AssetKey>,
+    #[serde(default)]
+    components: toml::Table,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct EntityDefBuilderConfig { }
+
+pub struct EntityDefBuilder
+{
+    known_components: HashMap<UniCase<&'static str>, &'static ComponentBuildMeta>,
+}
+impl Default for EntityDefBuilder
+{
+    fn default() -> Self
+    {
+        let known_components = inventory::iter::<ComponentBuildMeta>()
+            .map(|c| (UniCase::unicode(c.type_name), c)).collect();
+        Self { known_components }
+    }
+}
+impl EntityDefBuilder
+{
+    // Parse a source and encode each of its components
+    fn compile(&self, toml_str: &str) -> Result<EntityDefFile, Box<dyn Error>>
+    {
+        let source: EntityDefSource = toml::from_str(toml_str)?;
+
+        let mut components = Vec::with_capacity(source.components.len());
+        for (type_name, value) in source.components
+        {
+            let Some(meta) = self.known_components.get(&UniCase::unicode(type_name.as_str()))
+            else { return Err(Box::new(EntityDefBuildError::UnknownComponent { type_name })) };
+
+            // component names are case-insensitive, so the TOML keys alone don't catch duplicates
+            if components.iter().any(|c: &EntityDefFileComponent| c.type_name_hash == meta.type_name_hash)
+            {
+                return Err(Box::new(EntityDefBuildError::DuplicateComponent { type_name }));
+            }
+
+            let data = match (meta.deserialize_and_encode_fn)(&mut <dyn erased_serde::Deserializer>::erase(value))
+            {
+                Ok(data) => data,
+                Err(error) => return Err(Box::new(EntityDefBuildError::ComponentDeserializeError { type_name, error })),
+            };
+
+            components.push(EntityDefFileComponent
+            {
+                type_name_hash: meta.type_name_hash,
+                data: data.into_boxed_slice(),
+            });
+        }
+
+        Ok(EntityDefFile
+        {
+            components: components.into_boxed_slice(),
+            model: source.model,
+            circuit: source.circuit,
+        })
+    }
+}
+impl AssetBuilder for EntityDefBuilder
+{
+    type BuildConfig = EntityDefBuilderConfig;
+
+    fn supported_input_file_extensions(&self) -> &'static [&'static str]
+    {
+        &["entitydef"]
+    }
+
+    fn builder_version(&self, vb: &mut VersionBuilder)
+    {
+        vb.push(b"Entity def builder - initial");
+    }
+
+    fn format_version(&self, vb: &mut VersionBuilder)
+    {
+        vb.push_prehashed(EntityDefFile::TYPE_LAYOUT_HASH);
+    }
+
+    fn build_assets(&self, _config: Self::BuildConfig, input: &mut SourceInput, outputs: &mut BuildOutputs) -> Result<(), Box<dyn Error>>
+    {
+        let mut toml_str = String::new();
+        input.read_to_string(&mut toml_str)?;
+        let entity_def = self.compile(&toml_str)?;
+
+        outputs.add_output(AssetTypeId::EntityDef, |output|
+        {
+            output.depends_on_multiple(entity_def.model.into_iter().chain(entity_def.circuit));
+            output.serialize(&entity_def)?;
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use asset_3l14::{Ash, AssetKeySynthHash, AssetLifecyclers, AssetSnapshot, Assets, AssetsConfig};
+    use entity_3l14::EntityStore;
+    use latch_3l14::Runtime;
+    use world_3l14::assets::entity_def::{EntityDef, EntityDefLifecycler};
+    use world_3l14::Light;
+    use super::*;
+
+    const POINT_LIGHT: &str = r#"
+        [components.Light.Point]
+        position = [0.0, 2.0, 0.0]
+        color = { red = 255, green = 128, blue = 0, alpha = 255 }
+        intensity = 10.0
+        range = 5.0
+    "#;
+
+    #[test]
+    fn round_trip()
+    {
+        let file = EntityDefBuilder::default().compile(POINT_LIGHT).unwrap();
+        assert_eq!(file.components.len(), 1);
+        assert!(file.model.is_none() && file.circuit.is_none());
+
+        // load the built file as the runtime would
+        let lifecyclers = AssetLifecyclers::default()
+            .add_lifecycler(EntityDefLifecycler::default());
+        let assets = Assets::new(lifecyclers, AssetsConfig::test());
+        let entity_def: Ash<EntityDef> = assets.load_encoded_for_test(AssetKey::synthetic(AssetTypeId::EntityDef, AssetKeySynthHash(1)), &file);
+        let AssetSnapshot::Available(entity_def) = entity_def.data()
+        else { unreachable!("The entity def was loaded") };
+
+        let mut store = EntityStore::new();
+        let entity = store.spawn();
+        entity_def.instantiate(entity, &mut store, &Runtime::new()).unwrap();
+        match store.get::<Light>(entity)
+        {
+            Some(Light::Point { position, color, intensity, range }) =>
+            {
+                assert_eq!(*position, glam::Vec3::new(0.0, 2.0, 0.0));
+                assert_eq!((color.red, color.green, color.blue, color.alpha), (255, 128, 0, 255));
+                assert_eq!((*intensity, *range), (10.0, 5.0));
+            }
+            other => panic!("Unexpected light component: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn no_components()
+    {
+        let file = EntityDefBuilder::default().compile("").unwrap();
+        assert!(file.components.is_empty());
+    }
+
+    #[test]
+    fn invalid_components()
+    {
+        let builder = EntityDefBuilder::default();
+        let build_error = |source: &str| *builder.compile(source).err().expect("The entity def should not build").downcast::<EntityDefBuildError>().unwrap();
+
+        assert!(matches!(build_error("[components.NotAComponent]"), EntityDefBuildError::UnknownComponent { .. }));
+        assert!(matches!(
+            build_error("[components.Light]\nDirectional = [0.0, -1.0, 0.0]\n[components.light]\nDirectional = [0.0, 1.0, 0.0]"),
+            EntityDefBuildError::DuplicateComponent { .. }));
+        assert!(matches!(
+            build_error("[components.Light.Point]\nposition = \"up\""),
+            EntityDefBuildError::ComponentDeserializeError { .. }));
+    }
+}
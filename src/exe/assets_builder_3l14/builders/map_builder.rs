@@ -1,23 +1,48 @@
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::ffi::{OsStr, OsString};
 use std::fs::File;
 use std::io::Read;
+use glam::{IVec3, Mat4};
 use serde::{Deserialize, Serialize};
+use asset_3l14::{AssetKey, AssetTypeId};
+use containers_3l14::AabbTree;
+use graphics_3l14::assets::{GeometryFile, ModelFile};
 use map_design_3l14::{MapDef, MapLayer};
+use math_3l14::AABB;
 use nab_3l14::utils::osstr::OsStrUtils;
-use world_3l14::assets::map::MapFile;
+use world_3l14::assets::map::{ActivationFlags, EntitiesFile, MapChunkRef, MapEntity, MapFile, MapLayerInfo, StaticLight, MAX_ACTIVATION_FLAGS};
+use world_3l14::assets::map_chunk::{MapChunkFile, StaticModel, StaticsFile};
 use crate::core::{AssetBuilder, BuildOutputs, SourceInput, VersionBuilder};
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct MapBuilderConfig
 {
-
+    pub chunk_size: f32, // the width of each (cubic) chunk cell, in world units
+}
+impl Default for MapBuilderConfig
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            chunk_size: 64.0,
+        }
+    }
 }
 
 #[derive(Debug)]
 enum MapBuildError
 {
     NoLayers,
+    InvalidChunkSize(f32),
+    TooManyLayers(usize),
+    TooManyActivationFlags(usize),
+    UnknownActivationFlag { layer: String, flag: String },
+    InvalidModelIndex { layer: String, index: u32 },
+    InvalidEntityIndex { layer: String, index: u32 },
+    UnbuiltModel { index: usize, model: AssetKey, error: String },
 }
 impl std::fmt::Display for MapBuildError
 {
@@ -26,6 +51,13 @@ impl std::fmt::Display for MapBuildError
         match self
         {
             MapBuildError::NoLayers => write!(f, "Map definitions must have at least one layer"),
+            MapBuildError::InvalidChunkSize(size) => write!(f, "Chunk size must be positive, got {size}"),
+            MapBuildError::TooManyLayers(count) => write!(f, "Map has {count} layers, at most {} are supported", u16::MAX),
+            MapBuildError::TooManyActivationFlags(count) => write!(f, "Map has {count} activation flags, at most {MAX_ACTIVATION_FLAGS} are supported"),
+            MapBuildError::UnknownActivationFlag { layer, flag } => write!(f, "Layer '{layer}' references activation flag '{flag}' that is not defined by the map"),
+            MapBuildError::InvalidModelIndex { layer, index } => write!(f, "Layer '{layer}' references model {index} which is not in the model palette"),
+            MapBuildError::InvalidEntityIndex { layer, index } => write!(f, "Layer '{layer}' references entity {index} which is not in the entity palette"),
+            MapBuildError::UnbuiltModel { index, model, error } => write!(f, "Failed to build or read model {index} ({model:?}) for its bounds: {error}"),
         }
     }
 }
@@ -43,12 +75,17 @@ impl AssetBuilder for MapBuilder
 
     fn builder_version(&self, vb: &mut VersionBuilder)
     {
-        vb.push(b"Map builder - initial");
+        vb.push(b"Map builder - spatial chunks");
+        vb.push(b"Map builder - light color and range");
+        vb.push(b"Map builder - load time bounds");
+        vb.push(b"Map builder - build time bounds");
+        vb.push(b"Map builder - models as build inputs");
     }
 
     fn format_version(&self, vb: &mut VersionBuilder)
     {
         vb.push_prehashed(MapFile::TYPE_LAYOUT_HASH);
+        vb.push_prehashed(MapChunkFile::TYPE_LAYOUT_HASH);
     }
 
     fn build_assets(&self, config: Self::BuildConfig, input: &mut SourceInput, outputs: &mut BuildOutputs) -> Result<(), Box<dyn Error>>
@@ -96,11 +133,401 @@ impl AssetBuilder for MapBuilder
             layers
         };
         
-        println!("map: {}", map_def.name);
-        for (layer_name, _) in &layers
+        if layers.is_empty()
+        {
+            return Err(Box::new(MapBuildError::NoLayers));
+        }
+        if config.chunk_size.is_nan() || config.chunk_size <= 0.0
+        {
+            return Err(Box::new(MapBuildError::InvalidChunkSize(config.chunk_size)));
+        }
+        if layers.len() > u16::MAX as usize
+        {
+            return Err(Box::new(MapBuildError::TooManyLayers(layers.len())));
+        }
+        if map_def.activation_flags.len() > MAX_ACTIVATION_FLAGS
+        {
+            return Err(Box::new(MapBuildError::TooManyActivationFlags(map_def.activation_flags.len())));
+        }
+
+        // directory order is not stable
+        let mut layers = layers;
+        layers.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        // chunk bounds and culling depend on the models' bounds, so the models are built first and the map is rebuilt when they change
+        // (read before adding any outputs, so that all of them are versioned with the models)
+        let model_bounds = map_def.model_palette.iter().enumerate()
+            .map(|(index, model)| model_bounds(*model, outputs)
+                .map_err(|err| MapBuildError::UnbuiltModel { index, model: *model, error: err.to_string() }))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut layer_infos = Vec::with_capacity(layers.len());
+        let mut models = Vec::new(); // models with their world bounds, palette indices are for the whole map
+        let mut lights = Vec::new();
+        let mut entities = Vec::new();
+        for (layer_index, (layer_name, layer)) in layers.into_iter().enumerate()
+        {
+            let layer_index = layer_index as u16;
+
+            let activation = layer_activation(&layer_name, &layer.activation_flags, &map_def.activation_flags)?;
+
+            for placement in layer.models
+            {
+                let Some(bounds) = model_bounds.get(placement.model as usize)
+                else
+                {
+                    return Err(Box::new(MapBuildError::InvalidModelIndex { layer: layer_name, index: placement.model }));
+                };
+
+                let transform = Mat4::from_scale_rotation_translation(placement.scale, placement.orientation, placement.position);
+                models.push((StaticModel
+                {
+                    model: placement.model,
+                    layer: layer_index,
+                    transform,
+                }, bounds.transformed(&transform)));
+            }
+
+            lights.extend(layer.lights.into_iter().map(|light| StaticLight { light, layer: layer_index }));
+
+            for placement in layer.entities
+            {
+                if placement.entity as usize >= map_def.entity_palette.len()
+                {
+                    return Err(Box::new(MapBuildError::InvalidEntityIndex { layer: layer_name, index: placement.entity }));
+                }
+
+                entities.push(MapEntity
+                {
+                    entity: placement.entity,
+                    id: placement.id,
+                    layer: layer_index,
+                    transform: Mat4::from_rotation_translation(placement.orientation, placement.position),
+                });
+            }
+
+            layer_infos.push(MapLayerInfo { name: layer_name, activation });
+        }
+
+        let cells = partition_cells(models, config.chunk_size);
+        let mut chunk_hierarchy = AabbTree::new();
+        let mut chunks = Vec::with_capacity(cells.len());
+        for (cell, cell_models) in cells
         {
-            println!("layer: {}", layer_name);
+            let coord = IVec3::from_array(cell);
+            let chunk = build_chunk(&map_def.name, coord, &map_def.model_palette, cell_models, outputs)?;
+            chunk_hierarchy.insert(chunk.bounds, chunks.len() as u32);
+            chunks.push(chunk);
         }
-        todo!()
+        chunk_hierarchy.repack();
+
+        let map_file = MapFile
+        {
+            activation_flags: map_def.activation_flags.into_boxed_slice(),
+            layers: layer_infos.into_boxed_slice(),
+            chunk_size: config.chunk_size,
+            chunk_hierarchy,
+            chunks: chunks.iter().copied().collect(),
+            lights: lights.into_boxed_slice(),
+            entities: EntitiesFile
+            {
+                palette: map_def.entity_palette.into_boxed_slice(),
+                instances: entities.into_boxed_slice(),
+            },
+        };
+
+        outputs.add_output(AssetTypeId::Map, |output|
+        {
+            output.set_name(map_def.name);
+            output.depends_on_multiple(chunks.iter().map(|c| c.chunk));
+            output.serialize(&map_file)?;
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+}
+
+// Resolve a layer's activation flags to bits, each flag's bit is its index in the map's flags
+fn layer_activation(layer_name: &str, layer_flags: &[String], map_flags: &[String]) -> Result<ActivationFlags, MapBuildError>
+{
+    let mut activation: ActivationFlags = 0;
+    for flag in layer_flags
+    {
+        let Some(bit) = map_flags.iter().position(|f| f == flag)
+        else
+        {
+            return Err(MapBuildError::UnknownActivationFlag { layer: layer_name.to_string(), flag: flag.clone() });
+        };
+        activation |= 1 << bit;
+    }
+    Ok(activation)
+}
+
+// Group models (with their world bounds) by the chunk cell containing their origin, ordered by cell so chunk outputs are stable
+fn partition_cells(models: Vec<(StaticModel, AABB)>, chunk_size: f32) -> BTreeMap<[i32; 3], Vec<(StaticModel, AABB)>>
+{
+    let mut cells: BTreeMap<[i32; 3], Vec<(StaticModel, AABB)>> = BTreeMap::new();
+    for (model, bounds) in models
+    {
+        let cell = (model.transform.w_axis.truncate() / chunk_size).floor().as_ivec3();
+        cells.entry(cell.to_array()).or_default().push((model, bounds));
+    }
+    cells
+}
+
+// Build a single chunk from the models in its cell, the chunk is bounded by its models rather than its cell
+fn build_chunk(
+    map_name: &str,
+    coord: IVec3,
+    model_palette: &[AssetKey],
+    models: Vec<(StaticModel, AABB)>,
+    outputs: &mut BuildOutputs) -> Result<MapChunkRef, Box<dyn Error>>
+{
+    let (hierarchy, chunk_bounds) = bound_models(&models);
+
+    let mut geo = Vec::new();
+    let mut palette_remap = HashMap::new();
+    let mut chunk_models = Vec::with_capacity(models.len());
+    for (mut model, _) in models
+    {
+        let map_palette_index = model.model;
+        model.model = *palette_remap.entry(map_palette_index).or_insert_with(||
+        {
+            geo.push(model_palette[map_palette_index as usize]);
+            (geo.len() - 1) as u32
+        });
+        chunk_models.push(model);
+    }
+
+    let chunk_file = MapChunkFile
+    {
+        statics: StaticsFile
+        {
+            hierarchy,
+            geo: geo.clone().into_boxed_slice(),
+            models: chunk_models.into_boxed_slice(),
+        },
+    };
+
+    let chunk = outputs.add_output(AssetTypeId::MapChunk, |output|
+    {
+        output.set_name(format!("{map_name} {coord}"));
+        output.depends_on_multiple(geo);
+        output.serialize(&chunk_file)?;
+        Ok(())
+    })?;
+
+    Ok(MapChunkRef { coord, bounds: chunk_bounds, chunk })
+}
+
+// Build a hierarchy of the models' world bounds (values are indices into models), and the bounds of all of them
+fn bound_models(models: &[(StaticModel, AABB)]) -> (AabbTree, AABB)
+{
+    let mut hierarchy = AabbTree::new();
+    let mut all_bounds = AABB::MAX_MIN;
+    for (index, (_, bounds)) in models.iter().enumerate()
+    {
+        hierarchy.insert(*bounds, index as u32);
+        all_bounds.union_with(*bounds);
+    }
+    hierarchy.repack();
+    (hierarchy, all_bounds)
+}
+
+// Get the local bounds of a built model
+fn model_bounds(model: AssetKey, outputs: &mut BuildOutputs) -> Result<AABB, Box<dyn Error>>
+{
+    let model_file: ModelFile = outputs.read_input(model)?;
+    let geometry_file: GeometryFile = outputs.read_input(model_file.geometry)?;
+    Ok(geometry_file.bounds_aabb)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::path::Path;
+    use asset_3l14::{AssetFileType, AssetKeyDerivedId, AssetKeySourceId, SourceMetadata, TomlWrite, VersionHash};
+    use bitcode::DecodeOwned;
+    use glam::{Quat, Vec3};
+    use graphics_3l14::assets::IndexFormat;
+    use math_3l14::Sphere;
+    use nab_3l14::utils::varint;
+    use crate::core::{AssetsBuilder, AssetsBuilderConfig, BuildRule};
+    use super::*;
+
+    fn flags(names: &[&str]) -> Vec<String>
+    {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn activation_flags()
+    {
+        let map_flags = flags(&["night", "raining", "boss_defeated"]);
+        assert_eq!(layer_activation("base", &[], &map_flags).unwrap(), 0, "Layers without flags are always active");
+        assert_eq!(layer_activation("storm", &flags(&["raining"]), &map_flags).unwrap(), 0b010);
+        assert_eq!(layer_activation("night_storm", &flags(&["boss_defeated", "night"]), &map_flags).unwrap(), 0b101);
+
+        let err = layer_activation("town", &flags(&["night", "day"]), &map_flags).unwrap_err();
+        assert!(matches!(err, MapBuildError::UnknownActivationFlag { layer, flag } if layer == "town" && flag == "day"));
+    }
+
+    fn placed(model: u32, position: Vec3) -> (StaticModel, AABB)
+    {
+        // large enough to cross cells, only the origin decides the cell
+        let transform = Mat4::from_scale_rotation_translation(Vec3::splat(16.0), Quat::IDENTITY, position);
+        (StaticModel { model, layer: 0, transform }, AABB::new(Vec3::splat(-1.0), Vec3::ONE).transformed(&transform))
+    }
+
+    #[test]
+    fn chunk_partitioning()
+    {
+        let models = vec!
+        [
+            placed(0, Vec3::new(1.0, 0.0, 1.0)),
+            placed(1, Vec3::new(-1.0, 0.0, 1.0)),
+            placed(2, Vec3::new(63.9, 10.0, 0.0)),
+            placed(3, Vec3::new(64.0, 0.0, -200.0)),
+        ];
+        let cells: Vec<_> = partition_cells(models, 64.0).into_iter()
+            .map(|(cell, models)| (cell, models.iter().map(|(m, _)| m.model).collect::<Vec<_>>()))
+            .collect();
+        assert_eq!(cells,
+        [
+            ([-1, 0, 0], vec![1]),
+            ([0, 0, 0], vec![0, 2]),
+            ([1, 0, -4], vec![3]),
+        ]);
+
+    }
+
+    #[test]
+    fn chunk_bounds()
+    {
+        let models = [placed(0, Vec3::new(1.0, 0.0, 1.0)), placed(2, Vec3::new(63.9, 10.0, 0.0))];
+        let (hierarchy, bounds) = bound_models(&models);
+        // the models extend outside their cell, so the chunk must too, or streaming would drop them while visible
+        assert_eq!(bounds, AABB::new(Vec3::new(-15.0, -16.0, -16.0), Vec3::new(79.9, 26.0, 17.0)));
+
+        let mut overlapping: Vec<_> = hierarchy.iter_overlapping(AABB::new(Vec3::new(70.0, 0.0, 0.0), Vec3::new(71.0, 1.0, 1.0)))
+            .map(|(_, index)| index).collect();
+        overlapping.sort();
+        assert_eq!(overlapping, [1], "Hierarchy values are indices into the chunk's models");
+    }
+
+    #[derive(Default, Serialize, Deserialize)]
+    struct TestModelBuildConfig { }
+
+    // Models that are only a (cubic) size, so that they can be changed between map builds
+    struct TestModelBuilder;
+    impl AssetBuilder for TestModelBuilder
+    {
+        type BuildConfig = TestModelBuildConfig;
+
+        fn supported_input_file_extensions(&self) -> &'static [&'static str] { &["testmodel"] }
+
+        fn builder_version(&self, vb: &mut VersionBuilder) { vb.push(b"Test model builder"); }
+
+        fn build_assets(&self, _config: Self::BuildConfig, input: &mut SourceInput, outputs: &mut BuildOutputs) -> Result<(), Box<dyn Error>>
+        {
+            let mut size = String::new();
+            input.read_to_string(&mut size)?;
+            let half_size = size.trim().parse::<f32>()? / 2.0;
+
+            let geometry = outputs.add_output(AssetTypeId::Geometry, |output|
+            {
+                output.serialize(&GeometryFile
+                {
+                    bounds_aabb: AABB::new(Vec3::splat(-half_size), Vec3::splat(half_size)),
+                    bounds_sphere: Sphere::new(Vec3::ZERO, half_size * 3.0f32.sqrt()),
+                    vertex_layout: 0,
+                    index_format: IndexFormat::U32,
+                    vertices: Box::new([]),
+                    indices: Box::new([]),
+                    meshes: Box::new([]),
+                })?;
+                Ok(())
+            })?;
+            outputs.add_output(AssetTypeId::Model, |output|
+            {
+                output.serialize(&ModelFile { geometry, skeleton: None, materials: Box::new([]) })?;
+                Ok(())
+            })?;
+            Ok(())
+        }
+    }
+
+    fn read_asset<T: DecodeOwned>(assets_root: &Path, asset_key: AssetKey) -> T
+    {
+        let data = std::fs::read(assets_root.join(asset_key.as_file_name(AssetFileType::Asset))).unwrap();
+        let mut reader = data.as_slice();
+        let size = varint::decode_from(&mut reader).unwrap() as usize;
+        bitcode::decode(&reader[..size]).unwrap()
+    }
+
+    // Build the test map, and get its chunks' bounds along with the bounds in each chunk's hierarchy
+    fn build_map(builder: &AssetsBuilder, assets_root: &Path) -> Option<Vec<(AABB, Vec<AABB>)>>
+    {
+        let results = builder.build_source("test.mapdef", BuildRule::OnlyIfChanged).unwrap();
+        let map_key = *results.iter().find(|key| key.asset_type() == AssetTypeId::Map)?;
+        let map_file: MapFile = read_asset(assets_root, map_key);
+        Some(map_file.chunks.iter().map(|chunk|
+        {
+            let chunk_file: MapChunkFile = read_asset(assets_root, chunk.chunk);
+            (chunk.bounds, chunk_file.statics.hierarchy.iter_overlapping(AABB::MIN_MAX).map(|(bounds, _)| bounds).collect())
+        }).collect())
+    }
+
+    #[test]
+    fn models_are_build_inputs()
+    {
+        let root = std::env::temp_dir().join(format!("3l14_map_builder_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap(); // sources are canonicalized before being made relative to the root
+        let (sources_root, assets_root) = (root.join("sources"), root.join("assets"));
+
+        let mut config = AssetsBuilderConfig::new(sources_root.clone(), assets_root.clone());
+        config.add_builder(TestModelBuilder);
+        config.add_builder(MapBuilder);
+        let builder = AssetsBuilder::new(config);
+
+        // the model has never been built, its source ID is fixed so that the map can reference it
+        let model_source = AssetKeySourceId(0x3114);
+        std::fs::write(sources_root.join("box.testmodel"), "2.0").unwrap();
+        SourceMetadata
+        {
+            source_id: model_source,
+            version_hash: VersionHash(0),
+            build_inputs: Vec::new(),
+            build_inputs_hash: None,
+            build_config: toml::Value::Table(Default::default()),
+            notes: None,
+        }.save(true, &mut File::create(sources_root.join("box.testmodel.sork")).unwrap()).unwrap();
+
+        let model = AssetKey::unique(AssetTypeId::Model, AssetKeyDerivedId(0), model_source);
+        std::fs::write(sources_root.join("test.mapdef"), format!("name = \"test\"\nmodel_palette = [{}]\n", toml::Value::try_from(model).unwrap())).unwrap();
+        std::fs::write(sources_root.join("base.test.layerdef"), r#"
+            [[models]]
+            model = 0
+            position = [1.0, 0.0, 1.0]
+            orientation = [0.0, 0.0, 0.0, 1.0]
+            scale = [1.0, 1.0, 1.0]
+        "#).unwrap();
+
+        // built on demand, regardless of the order sources are visited in
+        let bounds = AABB::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(2.0, 1.0, 2.0));
+        assert_eq!(build_map(&builder, &assets_root), Some(vec![(bounds, vec![bounds])]));
+
+        // rebuilding the model rebuilds the map and its chunks
+        std::fs::write(sources_root.join("box.testmodel"), "4.0").unwrap();
+        let bounds = AABB::new(Vec3::new(-1.0, -2.0, -1.0), Vec3::new(3.0, 2.0, 3.0));
+        assert_eq!(build_map(&builder, &assets_root), Some(vec![(bounds, vec![bounds])]));
+
+        // the model's outputs are unchanged, so the map is up to date
+        assert_eq!(build_map(&builder, &assets_root), None);
+
+        let _ = std::fs::remove_dir_all(&root);
     }
 }
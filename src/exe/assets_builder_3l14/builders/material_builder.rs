@@ -23,6 +23,7 @@ impl AssetBuilder for MaterialBuilder
     fn builder_version(&self, vb: &mut VersionBuilder)
     {
         vb.push(b"Material builder - initial");
+        vb.push(b"Material builder - blend modes");
     }
 
     fn format_version(&self, vb: &mut VersionBuilder)
@@ -0,0 +1,320 @@
+use std::error::Error;
+use half::f16;
+use meshopt::{SimplifyOptions, VertexDataAdapter};
+use serde::{Deserialize, Serialize};
+use graphics_3l14::assets::MAX_GEOMETRY_LODS;
+use graphics_3l14::vertex_layouts::{QuantizedStaticVertex, StaticVertex};
+use nab_3l14::utils::as_u8_array;
+
+// how much worse overdraw ordering may make the vertex cache hit ratio
+const OVERDRAW_THRESHOLD: f32 = 1.05;
+// LODs that remove less than this fraction of their parent's indices are not worth the memory
+const MIN_LOD_REDUCTION: f32 = 0.1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LodConfig
+{
+    pub triangle_ratio: f32, // the target fraction of the full detail triangle count
+    pub max_error: f32, // the max deviation from the full detail mesh, relative to the mesh's extents
+    pub screen_size: f32, // this LOD is used when the mesh covers less than this fraction of the screen height
+}
+
+pub struct MeshLod
+{
+    pub indices: Vec<u32>, // relative to the mesh's first vertex
+    pub max_screen_size: f32,
+}
+
+// The vertices and LODs of a single mesh
+// Vertices are interleaved and start with a StaticVertex (which is always first in the layout)
+pub struct MeshData
+{
+    pub vertices: Vec<u8>,
+    pub vertex_stride: usize,
+    pub lods: Vec<MeshLod>, // LOD 0 is full detail
+}
+impl MeshData
+{
+    #[must_use]
+    pub fn new(vertices: Vec<u8>, vertex_stride: usize, indices: Vec<u32>) -> Self
+    {
+        debug_assert!(vertex_stride >= size_of::<StaticVertex>());
+        debug_assert!(vertices.len().is_multiple_of(vertex_stride));
+        Self
+        {
+            vertices,
+            vertex_stride,
+            lods: vec![MeshLod { indices, max_screen_size: f32::INFINITY }],
+        }
+    }
+
+    #[inline] #[must_use]
+    pub fn vertex_count(&self) -> usize { self.vertices.len() / self.vertex_stride }
+
+    // Reorder the full detail triangles for the post-transform vertex cache, and then to reduce overdraw
+    pub fn optimize_indices(&mut self) -> Result<(), Box<dyn Error>>
+    {
+        let vertex_count = self.vertex_count();
+        let positions = VertexDataAdapter::new(&self.vertices, self.vertex_stride, 0)?;
+
+        let indices = &mut self.lods[0].indices;
+        meshopt::optimize_vertex_cache_in_place(indices, vertex_count);
+        meshopt::optimize_overdraw_in_place(indices, &positions, OVERDRAW_THRESHOLD);
+        Ok(())
+    }
+
+    // Generate a chain of simplified LODs, each simplified from the previous, sharing the same vertices
+    pub fn generate_lods(&mut self, lod_configs: &[LodConfig]) -> Result<(), Box<dyn Error>>
+    {
+        let vertex_count = self.vertex_count();
+        let positions = VertexDataAdapter::new(&self.vertices, self.vertex_stride, 0)?;
+        let full_index_count = self.lods[0].indices.len();
+
+        let mut lod_configs = lod_configs.to_vec();
+        lod_configs.sort_by(|a, b| b.screen_size.total_cmp(&a.screen_size));
+
+        for config in lod_configs.iter().take(MAX_GEOMETRY_LODS - 1)
+        {
+            let parent = &self.lods.last().unwrap().indices;
+            let target_count = (full_index_count as f32 * config.triangle_ratio) as usize / 3 * 3;
+            let mut indices = meshopt::simplify(parent, &positions, target_count, config.max_error, SimplifyOptions::empty(), None);
+
+            // stop once simplification stops making progress (e.g. the error limit was reached)
+            if indices.is_empty() || indices.len() as f32 > parent.len() as f32 * (1.0 - MIN_LOD_REDUCTION)
+            {
+                break;
+            }
+
+            meshopt::optimize_vertex_cache_in_place(&mut indices, vertex_count);
+            self.lods.push(MeshLod { indices, max_screen_size: config.screen_size });
+        }
+
+        Ok(())
+    }
+
+    // Reorder vertices in the order they're first referenced (by any LOD), unreferenced vertices are removed
+    pub fn optimize_vertex_fetch(&mut self)
+    {
+        let mut remap = vec![u32::MAX; self.vertex_count()];
+        let mut next_vertex = 0;
+        for index in self.lods.iter().flat_map(|l| &l.indices)
+        {
+            let new_index = &mut remap[*index as usize];
+            if *new_index == u32::MAX
+            {
+                *new_index = next_vertex;
+                next_vertex += 1;
+            }
+        }
+
+        let stride = self.vertex_stride;
+        let mut vertices = vec![0u8; next_vertex as usize * stride];
+        for (old, new) in remap.iter().enumerate().filter(|(_, n)| **n != u32::MAX)
+        {
+            let new = *new as usize;
+            vertices[(new * stride)..((new + 1) * stride)].copy_from_slice(&self.vertices[(old * stride)..((old + 1) * stride)]);
+        }
+        self.vertices = vertices;
+
+        for index in self.lods.iter_mut().flat_map(|l| &mut l.indices)
+        {
+            *index = remap[*index as usize];
+        }
+    }
+
+    // Convert the leading StaticVertex of each vertex to a QuantizedStaticVertex, the remaining attributes are copied as-is
+    pub fn quantize(&mut self)
+    {
+        let static_size = size_of::<StaticVertex>();
+        let stride = self.vertex_stride - static_size + size_of::<QuantizedStaticVertex>();
+
+        let mut vertices = Vec::with_capacity(self.vertex_count() * stride);
+        for vertex in self.vertices.chunks_exact(self.vertex_stride)
+        {
+            let static_vertex = unsafe { std::ptr::read_unaligned(vertex.as_ptr() as *const StaticVertex) };
+            let snorm16 = |c: f32| (c.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+            let quantized = QuantizedStaticVertex
+            {
+                position: static_vertex.position,
+                normal: [snorm16(static_vertex.normal[0]), snorm16(static_vertex.normal[1]), snorm16(static_vertex.normal[2]), 0],
+                tex_coord: static_vertex.tex_coord.map(|t| f16::from_f32(t).to_bits()),
+            };
+            vertices.extend_from_slice(unsafe { as_u8_array(&quantized) });
+            vertices.extend_from_slice(&vertex[static_size..]);
+        }
+
+        self.vertices = vertices;
+        self.vertex_stride = stride;
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn static_vertices(count: usize) -> Vec<u8>
+    {
+        (0..count).flat_map(|i|
+        {
+            let vertex = StaticVertex
+            {
+                position: [i as f32, 0.0, 0.0],
+                normal: [0.0, -1.0, 0.0],
+                tex_coord: [0.5, 0.25],
+            };
+            unsafe { as_u8_array(&vertex) }.to_vec()
+        }).collect()
+    }
+
+    // A (size x size) quad grid on the XZ plane, with each vertex raised by height(x, z)
+    fn grid(size: u32, height: impl Fn(u32, u32) -> f32) -> MeshData
+    {
+        let vertices = (0..=size).flat_map(|z| (0..=size).map(move |x| (x, z))).flat_map(|(x, z)|
+        {
+            let vertex = StaticVertex
+            {
+                position: [x as f32, height(x, z), z as f32],
+                normal: [0.0, 1.0, 0.0],
+                tex_coord: [0.0, 0.0],
+            };
+            unsafe { as_u8_array(&vertex) }.to_vec()
+        }).collect();
+
+        let indices = (0..size).flat_map(|z| (0..size).map(move |x| (x, z))).flat_map(|(x, z)|
+        {
+            let i = z * (size + 1) + x;
+            [i, i + size + 1, i + 1, i + 1, i + size + 1, i + size + 2]
+        }).collect();
+
+        MeshData::new(vertices, size_of::<StaticVertex>(), indices)
+    }
+
+    fn lod(triangle_ratio: f32, max_error: f32, screen_size: f32) -> LodConfig
+    {
+        LodConfig { triangle_ratio, max_error, screen_size }
+    }
+
+    fn assert_in_range(mesh: &MeshData)
+    {
+        let vertex_count = mesh.vertex_count() as u32;
+        for lod in &mesh.lods
+        {
+            assert!(lod.indices.len().is_multiple_of(3));
+            assert!(lod.indices.iter().all(|i| *i < vertex_count));
+        }
+    }
+
+    #[test]
+    fn optimized_indices()
+    {
+        let mut mesh = grid(8, |_, _| 0.0);
+        // reverse the triangle order so there's something to optimize
+        let reversed: Vec<u32> = mesh.lods[0].indices.chunks_exact(3).rev().flatten().copied().collect();
+        mesh.lods[0].indices = reversed.clone();
+
+        mesh.optimize_indices().unwrap();
+        assert_in_range(&mesh);
+
+        // the same triangles (with the same winding) are drawn, only reordered
+        let canonical = |t: &[u32]|
+        {
+            let first = (0..3).min_by_key(|i| t[*i]).unwrap();
+            [t[first], t[(first + 1) % 3], t[(first + 2) % 3]]
+        };
+        let mut expected: Vec<_> = reversed.chunks_exact(3).map(canonical).collect();
+        let mut optimized: Vec<_> = mesh.lods[0].indices.chunks_exact(3).map(canonical).collect();
+        expected.sort();
+        optimized.sort();
+        assert_eq!(optimized, expected);
+    }
+
+    #[test]
+    fn lod_chain()
+    {
+        let mut mesh = grid(16, |_, _| 0.0);
+        let full_index_count = mesh.lods[0].indices.len();
+
+        // configs are sorted by screen size
+        mesh.generate_lods(&[lod(0.25, 0.01, 0.25), lod(0.5, 0.01, 0.5), lod(0.1, 0.01, 0.1)]).unwrap();
+        assert_eq!(mesh.lods.len(), 4);
+        assert_in_range(&mesh);
+
+        for (lod, ratio) in mesh.lods[1..].iter().zip([0.5, 0.25, 0.1])
+        {
+            assert!(lod.indices.len() as f32 <= full_index_count as f32 * ratio);
+            assert_eq!(lod.max_screen_size, ratio);
+        }
+        for pair in mesh.lods.windows(2)
+        {
+            assert!(pair[1].indices.len() < pair[0].indices.len());
+        }
+    }
+
+    #[test]
+    fn lod_count_is_capped()
+    {
+        let mut mesh = grid(16, |_, _| 0.0);
+        let ratios = [0.8, 0.6, 0.45, 0.3, 0.2, 0.12, 0.08, 0.05];
+        mesh.generate_lods(&ratios.map(|r| lod(r, 0.01, r))).unwrap();
+        assert_eq!(mesh.lods.len(), MAX_GEOMETRY_LODS);
+        assert_in_range(&mesh);
+    }
+
+    #[test]
+    fn lod_error_limit()
+    {
+        // irregular bumps, so (almost) every collapse moves the surface
+        let bumps = |x: u32, z: u32| ((x * x * 31 + z * 17 + x * z * 7) % 11) as f32 * 0.3;
+
+        let mut strict = grid(8, bumps);
+        strict.generate_lods(&[lod(0.25, 0.001, 0.5)]).unwrap();
+        assert_eq!(strict.lods.len(), 1);
+
+        let mut loose = grid(8, bumps);
+        let full_index_count = loose.lods[0].indices.len();
+        loose.generate_lods(&[lod(0.25, 1.0, 0.5)]).unwrap();
+        assert_eq!(loose.lods.len(), 2);
+        assert!(loose.lods[1].indices.len() as f32 <= full_index_count as f32 * 0.25);
+        assert_in_range(&loose);
+    }
+
+    #[test]
+    fn lod_chain_stops_when_stalled()
+    {
+        let mut mesh = grid(16, |_, _| 0.0);
+        // the second LOD removes too few indices to be kept, and nothing is simplified after it
+        mesh.generate_lods(&[lod(0.5, 0.01, 0.5), lod(0.48, 0.01, 0.4), lod(0.1, 0.01, 0.1)]).unwrap();
+        assert_eq!(mesh.lods.len(), 2);
+        assert_eq!(mesh.lods[1].max_screen_size, 0.5);
+    }
+
+    #[test]
+    fn vertex_fetch_order()
+    {
+        let mut mesh = MeshData::new(static_vertices(5), size_of::<StaticVertex>(), vec![3, 1, 4, 4, 1, 0]);
+        mesh.optimize_vertex_fetch();
+
+        // vertex 2 is unused
+        assert_eq!(mesh.vertex_count(), 4);
+        assert_eq!(mesh.lods[0].indices, [0, 1, 2, 2, 1, 3]);
+
+        let first = unsafe { std::ptr::read_unaligned(mesh.vertices.as_ptr() as *const StaticVertex) };
+        assert_eq!(first.position, [3.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn quantized_vertices()
+    {
+        let mut mesh = MeshData::new(static_vertices(2), size_of::<StaticVertex>(), vec![0, 1, 1]);
+        mesh.quantize();
+
+        assert_eq!(mesh.vertex_stride, size_of::<QuantizedStaticVertex>());
+        assert_eq!(mesh.vertex_count(), 2);
+
+        let second = unsafe { std::ptr::read_unaligned(mesh.vertices[mesh.vertex_stride..].as_ptr() as *const QuantizedStaticVertex) };
+        assert_eq!(second.position, [1.0, 0.0, 0.0]);
+        assert_eq!(second.normal, [0, -i16::MAX, 0, 0]);
+        assert_eq!(second.tex_coord.map(|t| f16::from_bits(t).to_f32()), [0.5, 0.25]);
+    }
+}
@@ -1,6 +1,9 @@
 mod model_builder;
 pub use model_builder::*;
 
+mod mesh_optimizer;
+pub use mesh_optimizer::*;
+
 mod circuit_builder;
 pub use circuit_builder::*;
 
@@ -13,6 +16,14 @@ pub use texture_builder::*;
 mod shader_builder;
 pub use shader_builder::*;
 
+mod shader_reflection;
+pub use shader_reflection::*;
+
 mod material_builder;
 pub use material_builder::*;
 
+mod entity_def_builder;
+pub use entity_def_builder::*;
+
+mod anim_graph_builder;
+pub use anim_graph_builder::*;
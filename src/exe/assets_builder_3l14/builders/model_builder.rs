@@ -1,28 +1,34 @@
-use crate::core::{AssetBuilder, BuildOutputs, SourceInput, VersionBuilder};
+use crate::core::{AssetBuilder, BuildOutputs, SourceInput, SymbolsDict, VersionBuilder};
 use arrayvec::ArrayVec;
 use asset_3l14::{AssetKey, AssetKeySynthHash, AssetTypeId};
 use enumflags2::BitFlags;
 use glam::{Mat4, Quat, Vec3};
 use gltf::animation::util::ReadOutputs;
 use gltf::image::Format;
-use gltf::mesh::util::ReadIndices;
-use graphics_3l14::assets::{AnimFrameNumber, BoneId, GeometryFile, GeometryMesh, IndexFormat, MaterialFile, ModelFile, Shader, ShaderDebugData, ShaderFile, ShaderStage, SkeletalAnimation, Skeleton, SkeletonDebugData, TextureFile, TextureFilePixelFormat};
+use gltf::material::AlphaMode;
+use gltf::mesh::Mode;
+use graphics_3l14::assets::{AnimCompression, AnimEvent, BoneId, GeometryFile, GeometryMesh, GeometryMeshLod, IndexFormat, MaterialAlphaMode, MaterialBlendMode, MaterialFile, MaterialRenderState, ModelFile, SkeletalAnimation, Skeleton, SkeletonDebugData, MAX_ANIM_FRAMES};
+use image::Rgba32FImage;
 use graphics_3l14::vertex_layouts::{SkinnedVertex, StaticVertex, VertexCaps, VertexLayoutBuilder};
 use math_3l14::{DualQuat, Ratio, Sphere, AABB};
 use metrohash::MetroHash64;
 use nab_3l14::utils::alloc_slice::{alloc_slice_default, alloc_u8_slice};
 use nab_3l14::utils::as_u8_array;
+use nab_3l14::Signal;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::hash::{Hash, Hasher};
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use triomphe::Arc;
 use unicase::UniCase;
-use graphics_3l14::material_classes::{MaterialClass, PbrProps};
+use graphics_3l14::material_classes::{MaterialClass, PbrProps, PbrTextureSlot};
+use graphics_3l14::Rgba;
+use crate::builders::{build_texture, CompressionQuality, LodConfig, MeshData, TextureBuildConfig, TextureUsage};
 
-const DEFAULT_ANIM_SAMPLE_RATE: Ratio<u32> = Ratio::new(1, 30);
+const DEFAULT_ANIM_SAMPLE_RATE: Ratio<u32> = Ratio::new(30, 1); // frames per second
 
 // bit flags for which vertex types are avail?
 
@@ -32,12 +38,18 @@ pub enum ModelImportError
     NoPositionData,
     NoNormalData,
     NoIndexData,
+    UnsupportedPrimitiveMode(Mode), // only triangle primitives can be drawn
+    IncompleteTriangles(usize), // the index count is not a multiple of three
     MismatchedVertexCount,
     DuplicateBoneIndices,
     DuplicateBoneParents,
     TooManyBones,
     UnnamedBones, // bone names are required
     AnimationTimesOutOfOrder,
+    AnimationTooLong,
+    AnimEventOutOfRange { animation: String, event: String },
+    UnknownAnimEventSignal { event: String, signal: String },
+    TruncatedImage,
 }
 impl Display for ModelImportError
 {
@@ -46,14 +58,61 @@ impl Display for ModelImportError
 impl Error for ModelImportError { }
 
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize)]
 #[serde(default)]
 pub struct ModelBuildConfig
 {
-    // optimize (meshoptimizer)
+    optimize: bool, // reorder triangles and vertices for the GPU (meshoptimizer)
+    quantize: bool, // store normals and tex coords in 16 bits
+    lods: Vec<LodConfig>, // simplified versions of each mesh, in addition to the full detail mesh
     material_mappings: HashMap<String, String>, // maps gltf's material name to an external material def
+    texture_quality: CompressionQuality, // for textures embedded in/referenced by the model
+    anim_compression: AnimCompression, // how much error is allowed when removing animation keys
+    root_motion_bone: Option<String>, // extract this bone's motion along the ground into a separate root motion track
+    anim_events: HashMap<String, Vec<AnimEventConfig>>, // animation name -> events
+}
+impl Default for ModelBuildConfig
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            optimize: true,
+            quantize: false,
+            lods: vec!
+            [
+                LodConfig { triangle_ratio: 0.5, max_error: 0.01, screen_size: 0.25 },
+                LodConfig { triangle_ratio: 0.2, max_error: 0.05, screen_size: 0.1 },
+            ],
+            material_mappings: HashMap::new(),
+            texture_quality: CompressionQuality::default(),
+            anim_compression: AnimCompression::default(),
+            root_motion_bone: None,
+            anim_events: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AnimEventConfig
+{
+    name: String,
+    time: f32, // in seconds
+    signal: Option<String>, // sent to latch circuits
+}
+
+pub struct ModelBuilder
+{
+    symbols_dict: Arc<SymbolsDict>,
+}
+impl ModelBuilder
+{
+    #[must_use]
+    pub fn new(symbols_dict: Arc<SymbolsDict>) -> Self
+    {
+        Self { symbols_dict }
+    }
 }
-pub struct ModelBuilder;
 impl AssetBuilder for ModelBuilder
 {
     type BuildConfig = ModelBuildConfig;
@@ -66,6 +125,14 @@ impl AssetBuilder for ModelBuilder
     fn builder_version(&self, vb: &mut VersionBuilder)
     {
         vb.push(b"Model builder - initial");
+        vb.push(b"Model builder - materials + generated indices");
+        vb.push(b"Model builder - mesh optimization + LODs");
+        vb.push(b"Model builder - compressed animation tracks");
+        vb.push(b"Model builder - root motion + animation events");
+        vb.push(b"Model builder - scaled bones");
+        vb.push(b"Model builder - transparent materials");
+        vb.push(b"Model builder - sample rate in frames per second");
+        vb.push(b"Model builder - emissive color");
     }
 
     fn format_version(&self, vb: &mut VersionBuilder)
@@ -93,14 +160,32 @@ impl AssetBuilder for ModelBuilder
                 .map(|in_skin| self.parse_gltf_skin(&in_skin, &buffers, outputs))
                 .collect::<Result<_, _>>()?;
 
+            // skins may be parented to a (non-joint) root node, which skeletons do not include
+            let skeleton_roots: HashSet<_> = document.skins()
+                .filter_map(|in_skin| in_skin.skeleton())
+                .filter(|root| !document.skins().any(|in_skin| in_skin.joints().any(|joint| joint.index() == root.index())))
+                .map(|root| root.index())
+                .collect();
+
             for anim in document.animations()
             {
-                self.parse_gltf_anim(anim, &buffers, outputs)?;
+                self.parse_gltf_anim(anim, &buffers, &skeleton_roots, &config, outputs)?;
             }
 
+            let mut textures = HashMap::new();
+            let materials = document.materials()
+                .map(|in_mtl| self.parse_gltf_material(&in_mtl, &images, &config, &mut textures, outputs))
+                .collect::<Result<_, _>>()?;
+            let mut materials = GltfMaterials
+            {
+                materials,
+                default_material: None,
+                textures,
+            };
+
             for gltf_node in document.nodes()
             {
-                self.parse_gltf_mesh(gltf_node, &buffers, &images, &skeletons, outputs)?;
+                self.parse_gltf_mesh(gltf_node, &buffers, &images, &skeletons, &config, &mut materials, outputs)?;
             }
         }
 
@@ -115,6 +200,8 @@ impl ModelBuilder
         buffers: &Vec<gltf::buffer::Data>,
         images: &Vec<gltf::image::Data>,
         skeletons: &[SkelInfo],
+        config: &ModelBuildConfig,
+        gltf_materials: &mut GltfMaterials,
         outputs: &mut BuildOutputs)
     -> Result<(), Box<dyn Error>>
     {
@@ -138,6 +225,10 @@ impl ModelBuilder
                 .expect("Node has a skin not in the document skins list??");
             Some(skel)
         } else { None };
+        if config.quantize
+        {
+            vertex_layout |= VertexCaps::Quantized;
+        }
 
         // acts as versioning for the vertex formats
         let vertex_layout_hash =
@@ -151,19 +242,17 @@ impl ModelBuilder
 
         let mut vertex_data = Vec::new();
         let mut total_vertex_count = 0;
-        let mut index_data = Vec::new();
-        let mut total_index_count = 0;
+        let mut indices = Vec::<u32>::new(); // relative to each mesh's first vertex
 
         let mut model_bounds_sphere = Sphere::EMPTY;
 
-        let mut mesh_points = Vec::<Vec3>::new();
+        // vertices are parsed unquantized
+        let vertex_stride = size_of::<StaticVertex>() + maybe_skel_info.map_or(0, |_| size_of::<SkinnedVertex>());
 
         // todo: iter.map() ?
         for in_prim in in_mesh.primitives()
         {
             let bb = in_prim.bounding_box();
-            let mesh_bounds = AABB::new(bb.min.into(), bb.max.into());
-            model_bounds_aabb.union_with(mesh_bounds);
 
             let prim_reader = in_prim.reader(|b| Some(&buffers[b.index()]));
             let positions = prim_reader.read_positions().ok_or(ModelImportError::NoPositionData)?; // not required?
@@ -174,11 +263,10 @@ impl ModelBuilder
             let mut maybe_joints = prim_reader.read_joints(0).map(|j| j.into_u16());
             let mut maybe_weights = prim_reader.read_weights(0).map(|w| w.into_f32());
 
-            let mut mesh_vertex_count = 0;
+            let mut prim_vertices = Vec::new();
+            let mut prim_vertex_count = 0;
             for pos in positions
             {
-                mesh_points.push(pos.into());
-
                 // todo: verify matching attrib counts?
                 let static_vertex = StaticVertex
                 {
@@ -187,8 +275,8 @@ impl ModelBuilder
                     tex_coord: tex_coords.as_mut().and_then(|mut r| r.next()).unwrap_or([0.0, 0.0]),
                     //color: colors.as_mut().and_then(|mut r| r.next()).unwrap_or([u8::MAX, u8::MAX, u8::MAX, u8::MAX]),
                 };
-                vertex_data.write_all(unsafe { as_u8_array(&static_vertex) })?;
-                mesh_vertex_count += 1;
+                prim_vertices.write_all(unsafe { as_u8_array(&static_vertex) })?;
+                prim_vertex_count += 1;
 
                 // todo: cleanup
                 if let Some(skel_info) = &maybe_skel_info
@@ -201,138 +289,67 @@ impl ModelBuilder
                         indices: maybe_joints.as_mut().and_then(|j| j.next()/*.map(iremap)*/).unwrap_or([0, 0, 0, 0]),
                         weights: maybe_weights.as_mut().and_then(|w| w.next()).unwrap_or([0.0, 0.0, 0.0, 0.0]),
                     };
-                    vertex_data.write_all(unsafe { as_u8_array(&skinned_vertex) })?;
+                    prim_vertices.write_all(unsafe { as_u8_array(&skinned_vertex) })?;
                 }
             }
 
-            // TODO: create indices if missing (?)
-
-            let index_format;
-            let mut mesh_index_count = 0;
-            match prim_reader.read_indices()
+            let prim_indices = match prim_reader.read_indices()
             {
-                None => todo!("Need to add create-index fallback support"),
-                Some(in_indices) =>
-                {
-                    match in_indices
-                    {
-                        ReadIndices::U8(_u8s) => todo!("is this common?"),
-                        ReadIndices::U16(u16s) =>
-                        {
-                            index_format = IndexFormat::U16;
-                            for i in u16s
-                            {
-                                index_data.write_all(&i.to_le_bytes())?;
-                                mesh_index_count += 1;
-                            }
-                        }
-                        ReadIndices::U32(u32s) =>
-                        {
-                            index_format = IndexFormat::U32;
-                            for i in u32s
-                            {
-                                index_data.write_all(&i.to_le_bytes())?;
-                                mesh_index_count += 1;
-                            }
-                        }
-                    };
-                }
+                Some(in_indices) => in_indices.into_u32().collect(),
+                // non-indexed primitives draw their vertices in order
+                None => (0..prim_vertex_count).collect(),
+            };
+            let prim_indices = gltf_triangle_list(in_prim.mode(), prim_indices)?;
+
+            let mut mesh_data = MeshData::new(prim_vertices, vertex_stride, prim_indices);
+            if config.optimize
+            {
+                mesh_data.optimize_indices()?;
+            }
+            mesh_data.generate_lods(&config.lods)?;
+            if config.optimize
+            {
+                mesh_data.optimize_vertex_fetch();
+            }
+            if config.quantize
+            {
+                mesh_data.quantize();
             }
 
-            let mut textures = ArrayVec::new();
+            let mesh_vertex_count = mesh_data.vertex_count() as u32;
+            vertex_data.extend_from_slice(&mesh_data.vertices);
 
-            let pbr = in_prim.material().pbr_metallic_roughness();
-            if let Some(tex) = pbr.base_color_texture()
+            let mut lods = ArrayVec::new();
+            for lod in mesh_data.lods
             {
-                let tex_index = tex.texture().source().index();
-                let tex_data = &images[tex_index];
-
-                let tex_asset = outputs.add_output(AssetTypeId::Texture, |mut tex_output|
+                let first_index = indices.len() as u32;
+                indices.extend_from_slice(&lod.indices);
+                lods.push(GeometryMeshLod
                 {
-                    tex.texture().name().map(|n| tex_output.set_name(n));
-
-                    let (pixel_format, need_conv) = match tex_data.format
-                    {
-                        Format::R8 => (TextureFilePixelFormat::R8, false),
-                        Format::R8G8 => (TextureFilePixelFormat::Rg8, false),
-                        Format::R8G8B8 => (TextureFilePixelFormat::Rgba8, true),
-                        Format::R8G8B8A8 => (TextureFilePixelFormat::Rgba8, false),
-                        Format::R16 => todo!("R16 textures"),
-                        Format::R16G16 => todo!("R16G16 textures"),
-                        Format::R16G16B16 => todo!("R16G16B16 textures"),
-                        Format::R16G16B16A16 => todo!("R16G16B16A16 textures"),
-                        Format::R32G32B32FLOAT => todo!("R32G32B32FLOAT textures"),
-                        Format::R32G32B32A32FLOAT => todo!("R32G32B32A32FLOAT textures"),
-                    };
-
-                    tex_output.serialize(&TextureFile
-                    {
-                        width: tex_data.width,
-                        height: tex_data.height,
-                        depth: 1,
-                        mip_count: 1,
-                        mip_offsets: Default::default(),
-                        pixel_format,
-                    })?;
-
-                    // TODO: texture compression
-                    if need_conv
-                    {
-                        match tex_data.format
-                        {
-                            Format::R8G8B8 =>
-                            {
-                                // todo: check length
-                                for i in 0..(tex_data.width * tex_data.height) as usize
-                                {
-                                    tex_output.write_all(&tex_data.pixels[(i * 3)..((i + 1) * 3)])?;
-                                    tex_output.write_all(&[u8::MAX])?;
-                                }
-                            }
-                            _ => todo!("Other texture format conversions"),
-                        }
-                    } else {
-                        tex_output.write_all(&tex_data.pixels)?;
-                    }
-
-                    Ok(())
-                })?;
-
-                textures.try_push(tex_asset)?;
+                    index_range: (first_index, first_index + lod.indices.len() as u32),
+                    max_screen_size: lod.max_screen_size,
+                });
             }
 
-            // TODO: read material info from gltf
-            let material_class = MaterialClass::PbrOpaque; // TODO
-            let material = outputs.add_output(AssetTypeId::Material, |mtl_output|
+            let in_material = in_prim.material();
+            let material = match (in_material.index(), gltf_materials.default_material)
             {
-                // call into MaterialBuilder?
-                mtl_output.set_name(format!("{:?}", material_class));
-                mtl_output.depends_on_multiple(textures.clone());
-
-                mtl_output.serialize(&MaterialFile
+                (Some(index), _) => gltf_materials.materials[index],
+                (None, Some(default_material)) => default_material,
+                (None, None) =>
                 {
-                    class: material_class,
-                    textures,
-                    props: alloc_u8_slice(PbrProps
-                    {
-                        albedo_color: pbr.base_color_factor().into(),
-                        metallicity: pbr.metallic_factor(),
-                        roughness: pbr.roughness_factor(),
-                    }),
-                })?;
-
-                // TODO: add shader dependencies
-
-                Ok(())
-            })?;
+                    let default_material = self.parse_gltf_material(&in_material, images, config, &mut gltf_materials.textures, outputs)?;
+                    gltf_materials.default_material = Some(default_material);
+                    default_material
+                }
+            };
             materials.push(material);
 
             let mesh_bounds_aabb = AABB::new(bb.min.into(), bb.max.into());
             model_bounds_aabb.union_with(mesh_bounds_aabb);
 
-            // TODO
-            let mesh_bounds_sphere = Sphere::new(Vec3::ZERO, 1.0);//Sphere::from_points(&mesh_points);
-            mesh_points.clear();
+            // TODO: tighter fit
+            let mesh_bounds_sphere = Sphere::new(mesh_bounds_aabb.centroid(), mesh_bounds_aabb.half().length());
             model_bounds_sphere += mesh_bounds_sphere;
 
             meshes.push(GeometryMesh
@@ -340,13 +357,24 @@ impl ModelBuilder
                 bounds_aabb: mesh_bounds_aabb,
                 bounds_sphere: mesh_bounds_sphere,
                 vertex_range: (total_vertex_count, total_vertex_count + mesh_vertex_count),
-                index_range: (total_index_count, total_index_count + mesh_index_count),
+                lods,
             });
 
             total_vertex_count += mesh_vertex_count;
-            total_index_count += mesh_index_count;
 
         }
+
+        let index_format = match indices.iter().all(|i| *i <= u16::MAX as u32)
+        {
+            true => IndexFormat::U16,
+            false => IndexFormat::U32,
+        };
+        let index_data: Vec<u8> = match index_format
+        {
+            IndexFormat::U16 => indices.iter().flat_map(|i| (*i as u16).to_le_bytes()).collect(),
+            IndexFormat::U32 => indices.iter().flat_map(|i| i.to_le_bytes()).collect(),
+        };
+
         let geometry = outputs.add_output(AssetTypeId::Geometry, |geom_output|
         {
             in_mesh.name().map(|n| geom_output.set_name(n));
@@ -355,7 +383,7 @@ impl ModelBuilder
                 bounds_aabb: model_bounds_aabb,
                 bounds_sphere: model_bounds_sphere,
                 vertex_layout: vertex_layout.bits(),
-                index_format: IndexFormat::U16,
+                index_format,
                 vertices: vertex_data.into_boxed_slice(),
                 indices: index_data.into_boxed_slice(),
                 meshes: meshes.into_boxed_slice(),
@@ -384,6 +412,123 @@ impl ModelBuilder
         Ok(())
     }
 
+    fn parse_gltf_material(
+        &self,
+        in_mtl: &gltf::Material,
+        images: &[gltf::image::Data],
+        config: &ModelBuildConfig,
+        textures: &mut HashMap<(usize, PbrTextureSlot), AssetKey>,
+        outputs: &mut BuildOutputs)
+    -> Result<AssetKey, Box<dyn Error>>
+    {
+        log::debug!("Parsing gLTF material {:?} '{}'", in_mtl.index(), in_mtl.name().unwrap_or(""));
+
+        if let Some(name) = in_mtl.name() && config.material_mappings.contains_key(name)
+        {
+            // TODO: requires referencing assets from other sources
+            log::warn!("Material mappings are not supported yet, importing material '{name}' from the model");
+        }
+
+        let pbr = in_mtl.pbr_metallic_roughness();
+
+        // only the first set of tex coords is imported
+        let slot_textures =
+        [
+            (PbrTextureSlot::Albedo, pbr.base_color_texture().map(|t| (t.texture(), t.tex_coord()))),
+            (PbrTextureSlot::MetallicRoughness, pbr.metallic_roughness_texture().map(|t| (t.texture(), t.tex_coord()))),
+            (PbrTextureSlot::Normal, in_mtl.normal_texture().map(|t| (t.texture(), t.tex_coord()))),
+            (PbrTextureSlot::Occlusion, in_mtl.occlusion_texture().map(|t| (t.texture(), t.tex_coord()))),
+            (PbrTextureSlot::Emissive, in_mtl.emissive_texture().map(|t| (t.texture(), t.tex_coord()))),
+        ];
+        let mut mtl_textures = ArrayVec::new();
+        for (slot, in_texture) in slot_textures
+        {
+            let texture = match in_texture
+            {
+                Some((in_texture, tex_coord)) =>
+                {
+                    if tex_coord != 0
+                    {
+                        log::warn!("{slot:?} texture uses tex coord set {tex_coord}, but only set 0 is imported");
+                    }
+                    Some(self.parse_gltf_texture(&in_texture, slot, images, config, textures, outputs)?)
+                }
+                None => None,
+            };
+            mtl_textures.push(texture);
+        }
+
+        let emissive = in_mtl.emissive_factor();
+        let (alpha_mode, material_class, alpha_cutoff) = gltf_alpha_mode(in_mtl.alpha_mode(), in_mtl.alpha_cutoff());
+        let render_state = MaterialRenderState
+        {
+            alpha_mode,
+            double_sided: in_mtl.double_sided(),
+            blend_mode: MaterialBlendMode::Premultiplied, // glTF has no additive blending
+        };
+        let material = outputs.add_output(AssetTypeId::Material, |mtl_output|
+        {
+            // call into MaterialBuilder?
+            mtl_output.set_name(in_mtl.name().map_or_else(|| format!("{:?}", material_class), |n| n.to_string()));
+            mtl_output.depends_on_multiple(mtl_textures.iter().flatten().copied());
+
+            mtl_output.serialize(&MaterialFile
+            {
+                class: material_class,
+                render_state,
+                textures: mtl_textures,
+                props: alloc_u8_slice(PbrProps
+                {
+                    albedo_color: pbr.base_color_factor().into(),
+                    metallicity: pbr.metallic_factor(),
+                    roughness: pbr.roughness_factor(),
+                    alpha_cutoff,
+                    emissive_color: Rgba::new_f32(emissive[0], emissive[1], emissive[2], 1.0),
+                }),
+            })?;
+
+            // TODO: add shader dependencies
+
+            Ok(())
+        })?;
+
+        Ok(material)
+    }
+
+    fn parse_gltf_texture(
+        &self,
+        in_texture: &gltf::Texture,
+        slot: PbrTextureSlot,
+        images: &[gltf::image::Data],
+        config: &ModelBuildConfig,
+        textures: &mut HashMap<(usize, PbrTextureSlot), AssetKey>,
+        outputs: &mut BuildOutputs)
+    -> Result<AssetKey, Box<dyn Error>>
+    {
+        // images are re-encoded for each slot they are used in, as the formats differ
+        let image_index = in_texture.source().index();
+        if let Some(texture) = textures.get(&(image_index, slot))
+        {
+            return Ok(*texture);
+        }
+
+        let usage = match slot
+        {
+            PbrTextureSlot::Albedo | PbrTextureSlot::Emissive => TextureUsage::Model,
+            PbrTextureSlot::MetallicRoughness => TextureUsage::Data,
+            PbrTextureSlot::Normal => TextureUsage::NormalMap,
+            PbrTextureSlot::Occlusion => TextureUsage::Detail,
+        };
+        let tex_config = TextureBuildConfig { quality: config.texture_quality, usage };
+
+        let (texels, is_hdr) = gltf_image_to_rgba32f(&images[image_index])?;
+        let name = in_texture.name().or(in_texture.source().name());
+        let texture = build_texture(texels, is_hdr, &tex_config, name, outputs)?;
+
+        textures.insert((image_index, slot), texture);
+        Ok(texture)
+    }
+
     fn parse_gltf_skin(
         &self,
         in_skin: &gltf::Skin,
@@ -413,7 +558,12 @@ impl ModelBuilder
 
             bone_names[i] = joint.name().ok_or(ModelImportError::UnnamedBones)?.to_string();
 
-            let (trans, rot, _scale) = joint.transform().decomposed();
+            let (trans, rot, scale) = joint.transform().decomposed();
+            if !Vec3::from_array(scale).abs_diff_eq(Vec3::ONE, 1e-4)
+            {
+                // bones are only scaled by animations, see SkeletonPoser
+                log::warn!("Ignoring the bind pose scale of bone '{}'", bone_names[i]);
+            }
             skel_bind_poses[i] = DualQuat::from_rot_trans(Quat::from_array(rot), Vec3::from_array(trans));
         }
 
@@ -468,6 +618,8 @@ impl ModelBuilder
         &self,
         in_anim: gltf::Animation,
         buffers: &[gltf::buffer::Data],
+        skeleton_roots: &HashSet<usize>,
+        config: &ModelBuildConfig,
         outputs: &mut BuildOutputs)
     -> Result<(), Box<dyn Error>>
     {
@@ -482,7 +634,7 @@ impl ModelBuilder
         });
 
         let sample_rate = DEFAULT_ANIM_SAMPLE_RATE;
-        let sample_rate_f = sample_rate.to_f32();
+        let frames_per_sec = sample_rate.to_f32();
 
         #[derive(Default)]
         struct BoneData<'n>
@@ -490,32 +642,12 @@ impl ModelBuilder
             name: Option<&'n str>,
             translations: Vec<Vec3>,
             rotations: Vec<Quat>,
-        }
-
-        fn for_each_subval<F: FnMut(f32)>(min: f32, max: f32, rate: f32, mut callback: F) -> Result<(), Box<dyn Error>>
-        {
-            let range = max - min;
-            if range < 0.0
-            {
-                // todo: add more context to error
-                return Err(Box::new(ModelImportError::AnimationTimesOutOfOrder));
-            }
-
-            let start = (min / rate).floor() as u32;
-            let end = (max / rate).floor() as u32;
-
-            for i in start..end
-            {
-                // todo: interoplation method
-                let t = ((i as f32 * rate) - min) / range;
-                callback(t);
-            }
-
-            Ok(())
+            scales: Vec<Vec3>,
         }
 
         let mut bone_keyframes: HashMap<usize, BoneData> = HashMap::new();
         let mut frame_count = 0;
+        let mut source_size = 0; // the size of the source's keyframes (times and values), for logging
 
         // todo: This can be parallelized
         for in_chan in in_anim.channels()
@@ -523,8 +655,19 @@ impl ModelBuilder
             let ch_reader = in_chan.reader(|b| Some(&buffers[b.index()]));
             let target_node = in_chan.target().node();
 
+            // the skeleton root is not a bone, so its motion cannot be played back
+            if skeleton_roots.contains(&target_node.index())
+            {
+                log::warn!("Ignoring animation '{}' of skeleton root '{}', only bones can be animated",
+                    anim_name, target_node.name().unwrap_or(""));
+                continue;
+            }
+
             // todo: figure out if in_chan.sampler().interpolation() is necessary
 
+            let sampler = in_chan.sampler();
+            source_size += sampler.input().count() * sampler.input().size() + sampler.output().count() * sampler.output().size();
+
             let inputs = ch_reader.read_inputs().unwrap();
             match ch_reader.read_outputs().unwrap()
             {
@@ -534,18 +677,7 @@ impl ModelBuilder
                         .or_insert_with(|| BoneData { name: target_node.name(), .. Default::default() })
                         .translations;
 
-                    let mut outputs = inputs.zip(read_translations).peekable();
-                    let mut cur = outputs.peek().cloned().unwrap_or_default();
-                    while let Some(next) = outputs.next()
-                    {
-                        let a = Vec3::from_array(cur.1);
-                        let b = Vec3::from_array(next.1);
-                        for_each_subval(cur.0, next.0, sample_rate_f, |t| translations.push(Vec3::lerp(a, b, t)))?;
-                        cur = next;
-                    }
-
-                    // any frames after the last timestep will just extend the last value
-                    translations.push(Vec3::from_array(cur.1));
+                    translations.extend(resample_keys(inputs.zip(read_translations.map(Vec3::from_array)), frames_per_sec, Vec3::lerp)?);
                     frame_count = frame_count.max(translations.len());
                 }
                 ReadOutputs::Rotations(read_rotations) =>
@@ -554,54 +686,112 @@ impl ModelBuilder
                         .or_insert_with(|| BoneData { name: target_node.name(), .. Default::default() })
                         .rotations;
 
-                    let mut outputs = inputs.zip(read_rotations.into_f32()).peekable();
-                    let mut cur = outputs.peek().cloned().unwrap_or_default();
-                    while let Some(next) = outputs.next()
-                    {
-                        let a = Quat::from_array(cur.1);
-                        let b = Quat::from_array(next.1);
-                        for_each_subval(cur.0, next.0, sample_rate_f, |t| rotations.push(Quat::slerp(a, b, t)))?;
-                        cur = next;
-                    }
-
-                    // any frames after the last timestep will just extend the last value
-                    rotations.push(Quat::from_array(cur.1));
+                    rotations.extend(resample_keys(inputs.zip(read_rotations.into_f32().map(Quat::from_array)), frames_per_sec, Quat::slerp)?);
                     frame_count = frame_count.max(rotations.len());
                 },
-                ReadOutputs::Scales(s) => { } // unsupported (currently)
+                ReadOutputs::Scales(read_scales) =>
+                {
+                    let scales = &mut bone_keyframes.entry(target_node.index())
+                        .or_insert_with(|| BoneData { name: target_node.name(), .. Default::default() })
+                        .scales;
+
+                    scales.extend(resample_keys(inputs.zip(read_scales.map(Vec3::from_array)), frames_per_sec, Vec3::lerp)?);
+                    frame_count = frame_count.max(scales.len());
+                },
                 ReadOutputs::MorphTargetWeights(_) => {} // unsupported
             }
         }
 
-        // TODO: need to sort bones and poses based on bones
-        let mut bone_ids = alloc_slice_default(bone_keyframes.len());
-        let mut poses = alloc_slice_default(bone_ids.len() * frame_count);
-
-        for (bone, (gltf_index, bone_data)) in bone_keyframes.iter().enumerate()
+        if frame_count == 0
+        {
+            log::warn!("Skipping animation '{}' with no supported channels", anim_name);
+            return Ok(());
+        }
+        if frame_count > MAX_ANIM_FRAMES as usize
         {
-            let id = BoneId::from_name(bone_data.name.ok_or(ModelImportError::UnnamedBones)?);
-            bone_ids[bone] = id;
+            return Err(Box::new(ModelImportError::AnimationTooLong));
+        }
+
+        let mut bones: Vec<_> = bone_keyframes.values()
+            .map(|bone_data| Ok((BoneId::from_name(bone_data.name.ok_or(ModelImportError::UnnamedBones)?), bone_data)))
+            .collect::<Result<_, ModelImportError>>()?;
+        bones.sort_by_key(|(id, _)| *id);
+
+        // scales are only stored if any bone is scaled
+        let has_scales = bones.iter().any(|(_, bone_data)| !bone_data.scales.is_empty());
 
+        // bone-major, each bone's frames are contiguous
+        let mut rotations = Vec::with_capacity(bones.len() * frame_count);
+        let mut translations = Vec::with_capacity(bones.len() * frame_count);
+        let mut scales = Vec::with_capacity(if has_scales { bones.len() * frame_count } else { 0 });
+        for (_, bone_data) in &bones
+        {
             for fr in 0..frame_count
             {
-                let translation = bone_data.translations.get(usize::min(fr, bone_data.translations.len() - 1))
-                    .cloned().unwrap_or_default();
-                let rotation = bone_data.rotations.get(usize::min(fr, bone_data.rotations.len() - 1))
-                    .cloned().unwrap_or_default();
-                poses[fr * bone_ids.len() + bone] = DualQuat::from_rot_trans(rotation, translation).into();
+                // channels shorter than the animation hold their last value
+                rotations.push(bone_data.rotations.get(usize::min(fr, bone_data.rotations.len().saturating_sub(1)))
+                    .cloned().unwrap_or_default());
+                translations.push(bone_data.translations.get(usize::min(fr, bone_data.translations.len().saturating_sub(1)))
+                    .cloned().unwrap_or_default());
+                if has_scales
+                {
+                    scales.push(bone_data.scales.get(usize::min(fr, bone_data.scales.len().saturating_sub(1)))
+                        .cloned().unwrap_or(Vec3::ONE));
+                }
+            }
+        }
+
+        let mut root_motion = Box::default();
+        if let Some(root_bone) = &config.root_motion_bone
+        {
+            match bones.iter().position(|(id, _)| *id == BoneId::from_name(root_bone))
+            {
+                Some(root) =>
+                {
+                    let frames = root * frame_count..(root + 1) * frame_count;
+                    root_motion = extract_root_motion(&mut rotations[frames.clone()], &mut translations[frames]);
+                }
+                None => log::warn!("Root motion bone '{}' is not animated by animation '{}', it will have no root motion", root_bone, anim_name),
             }
         }
 
+        let mut events = Vec::new();
+        for event in config.anim_events.get(anim_name).into_iter().flatten()
+        {
+            let frame = event.time * frames_per_sec;
+            if !(0.0..frame_count as f32).contains(&frame)
+            {
+                return Err(Box::new(ModelImportError::AnimEventOutOfRange { animation: anim_name.to_string(), event: event.name.clone() }));
+            }
+
+            let signal = match &event.signal
+            {
+                Some(signal) => Some(self.symbols_dict.get::<Signal>(signal)
+                    .ok_or_else(|| ModelImportError::UnknownAnimEventSignal { event: event.name.clone(), signal: signal.clone() })?),
+                None => None,
+            };
+            events.push(AnimEvent { frame, name: event.name.clone(), signal });
+        }
+        events.sort_by(|a, b| a.frame.total_cmp(&b.frame));
+
+        let mut animation = SkeletalAnimation::compress(
+            sample_rate,
+            bones.iter().map(|(id, _)| *id).collect(),
+            frame_count as u32,
+            &rotations,
+            &translations,
+            &scales,
+            config.anim_compression);
+        animation.root_motion = root_motion;
+        animation.events = events.into_boxed_slice();
+
+        log::info!("Compressed animation '{}' ({} bones, {} frames) from {} to {} bytes ({:.1}:1)",
+            anim_name, bones.len(), frame_count, source_size, animation.data_size(), source_size as f32 / animation.data_size().max(1) as f32);
+
         outputs.add_output(AssetTypeId::SkeletalAnimation, |anim_output|
         {
             in_anim.name().map(|n| anim_output.set_name(n));
-            anim_output.serialize(&SkeletalAnimation
-            {
-                sample_rate,
-                frame_count: AnimFrameNumber(frame_count as u32),
-                bones: bone_ids,
-                poses,
-            })?;
+            anim_output.serialize(&animation)?;
 
             Ok(())
         })?;
@@ -612,8 +802,283 @@ impl ModelBuilder
     }
 }
 
+// Sample keyframes (time in seconds, value) at a fixed rate, interpolating between keys
+// Frames after the last key hold its value
+fn resample_keys<V: Copy>(
+    keys: impl Iterator<Item=(f32, V)>,
+    frames_per_sec: f32,
+    lerp: impl Fn(V, V, f32) -> V)
+    -> Result<Vec<V>, ModelImportError>
+{
+    let mut frames = Vec::new();
+    let mut keys = keys.peekable();
+    let Some(mut cur) = keys.peek().copied() else { return Ok(frames) };
+    for next in keys
+    {
+        let range = next.0 - cur.0;
+        if range < 0.0
+        {
+            // todo: add more context to error
+            return Err(ModelImportError::AnimationTimesOutOfOrder);
+        }
+
+        let start = (cur.0 * frames_per_sec).floor() as u32;
+        let end = (next.0 * frames_per_sec).floor() as u32;
+        for frame in start..end
+        {
+            // todo: interoplation method
+            let t = (frame as f32 / frames_per_sec - cur.0) / range;
+            frames.push(lerp(cur.1, next.1, t));
+        }
+        cur = next;
+    }
+    frames.push(cur.1);
+    Ok(frames)
+}
+
+// Move the root bone's motion along the ground (XZ) plane and around the up (Y) axis into a separate track
+// The motion is relative to the first frame, so the root bone keeps the first frame's offset
+// The track has an extra frame for the motion after looping
+fn extract_root_motion(rotations: &mut [Quat], translations: &mut [Vec3]) -> Box<[DualQuat]>
+{
+    let ground_motion = |rotation: Quat, translation: Vec3|
+    {
+        let yaw = Quat::from_xyzw(0.0, rotation.y, 0.0, rotation.w);
+        let yaw = if yaw.length_squared() > 1e-6 { yaw.normalize() } else { Quat::IDENTITY };
+        DualQuat::from_rot_trans(yaw, Vec3::new(translation.x, 0.0, translation.z))
+    };
+
+    let first_inverse = ground_motion(rotations[0], translations[0]).inverse();
+    let mut motion: Vec<_> = rotations.iter().zip(translations.iter())
+        .map(|(rotation, translation)| ground_motion(*rotation, *translation) * first_inverse)
+        .collect();
+
+    for ((rotation, translation), frame_motion) in rotations.iter_mut().zip(translations.iter_mut()).zip(&motion)
+    {
+        let pose = frame_motion.inverse() * DualQuat::from_rot_trans(*rotation, *translation);
+        *rotation = pose.rotation();
+        *translation = pose.translation();
+    }
+
+    // continue the last frame's motion when looping
+    let last_step = match motion.as_slice()
+    {
+        [.., prev, last] => prev.inverse() * *last,
+        _ => DualQuat::IDENTITY,
+    };
+    motion.push(*motion.last().unwrap() * last_step);
+
+    motion.into_boxed_slice()
+}
+
 struct SkelInfo
 {
     asset: AssetKey,
     gltf_index: usize,
 }
+
+struct GltfMaterials
+{
+    materials: Box<[AssetKey]>, // indexed by gltf material index
+    default_material: Option<AssetKey>, // for primitives without a material, created on first use
+    textures: HashMap<(usize, PbrTextureSlot), AssetKey>, // gltf image index
+}
+
+// Map a glTF alpha mode to a material's alpha mode, class, and alpha cutoff (zero when not masked)
+// Masked materials are opaque, with pixels under the cutoff discarded by the shader
+fn gltf_alpha_mode(alpha_mode: AlphaMode, alpha_cutoff: Option<f32>) -> (MaterialAlphaMode, MaterialClass, f32)
+{
+    match alpha_mode
+    {
+        AlphaMode::Opaque => (MaterialAlphaMode::Opaque, MaterialClass::PbrOpaque, 0.0),
+        AlphaMode::Mask => (MaterialAlphaMode::Mask, MaterialClass::PbrOpaque, alpha_cutoff.unwrap_or(0.5)), // 0.5 is the glTF default
+        AlphaMode::Blend => (MaterialAlphaMode::Blend, MaterialClass::PbrTransparent, 0.0),
+    }
+}
+
+// Convert a primitive's indices to a triangle list, the mesh optimizer and renderer only handle lists
+fn gltf_triangle_list(mode: Mode, indices: Vec<u32>) -> Result<Vec<u32>, ModelImportError>
+{
+    let tri_count = indices.len().saturating_sub(2);
+    match mode
+    {
+        Mode::Triangles if indices.len() % 3 == 0 => Ok(indices),
+        Mode::Triangles => Err(ModelImportError::IncompleteTriangles(indices.len())),
+        // every other triangle in a strip is flipped to keep the winding consistent
+        Mode::TriangleStrip => Ok((0..tri_count)
+            .flat_map(|i| match i % 2
+            {
+                0 => [indices[i], indices[i + 1], indices[i + 2]],
+                _ => [indices[i], indices[i + 2], indices[i + 1]],
+            })
+            .collect()),
+        Mode::TriangleFan => Ok((0..tri_count)
+            .flat_map(|i| [indices[i + 1], indices[i + 2], indices[0]])
+            .collect()),
+        Mode::Points | Mode::Lines | Mode::LineLoop | Mode::LineStrip => Err(ModelImportError::UnsupportedPrimitiveMode(mode)),
+    }
+}
+
+// Convert any gltf image to (non-premultiplied) RGBA, returns whether the image is HDR
+fn gltf_image_to_rgba32f(image: &gltf::image::Data) -> Result<(Rgba32FImage, bool), ModelImportError>
+{
+    let (channel_count, channel_bytes, is_float) = match image.format
+    {
+        Format::R8 => (1, 1, false),
+        Format::R8G8 => (2, 1, false),
+        Format::R8G8B8 => (3, 1, false),
+        Format::R8G8B8A8 => (4, 1, false),
+        Format::R16 => (1, 2, false),
+        Format::R16G16 => (2, 2, false),
+        Format::R16G16B16 => (3, 2, false),
+        Format::R16G16B16A16 => (4, 2, false),
+        Format::R32G32B32FLOAT => (3, 4, true),
+        Format::R32G32B32A32FLOAT => (4, 4, true),
+    };
+
+    let pixel_count = image.width as usize * image.height as usize;
+    let pixel_bytes = channel_count * channel_bytes;
+    if image.pixels.len() < pixel_count * pixel_bytes
+    {
+        return Err(ModelImportError::TruncatedImage);
+    }
+
+    // gltf stores wide channels in native byte order
+    let read_channel = |c: &[u8]| match channel_bytes
+    {
+        1 => c[0] as f32 / u8::MAX as f32,
+        2 => u16::from_ne_bytes([c[0], c[1]]) as f32 / u16::MAX as f32,
+        _ => f32::from_ne_bytes([c[0], c[1], c[2], c[3]]),
+    };
+
+    let mut texels = Vec::with_capacity(pixel_count * 4);
+    for pixel in image.pixels.chunks_exact(pixel_bytes).take(pixel_count)
+    {
+        let mut channels = pixel.chunks_exact(channel_bytes).map(read_channel);
+        let rgba = match channel_count
+        {
+            // single channel images are greyscale
+            1 => { let r = channels.next().unwrap(); [r, r, r, 1.0] },
+            _ => [channels.next().unwrap(), channels.next().unwrap(), channels.next().unwrap_or(0.0), channels.next().unwrap_or(1.0)],
+        };
+        texels.extend(rgba);
+    }
+
+    let texels = Rgba32FImage::from_raw(image.width, image.height, texels).ok_or(ModelImportError::TruncatedImage)?;
+    Ok((texels, is_float))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use graphics_3l14::anim_graph_instance::AnimGraphInstance;
+    use graphics_3l14::assets::{AnimGraphDef, AnimMotion, AnimState};
+    use graphics_3l14::skeleton_poser::SkeletonPoser;
+    use super::*;
+
+    #[test]
+    fn root_motion_translation()
+    {
+        let mut rotations = [Quat::IDENTITY; 3];
+        let mut translations = [Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(2.0, 1.5, 2.0)];
+        let motion = extract_root_motion(&mut rotations, &mut translations);
+
+        // only the ground plane motion is extracted, the height stays with the bone
+        let expected_motion = [Vec3::ZERO, Vec3::new(1.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 2.0), Vec3::new(3.0, 0.0, 4.0)];
+        assert_eq!(motion.len(), expected_motion.len());
+        for (frame, expected) in motion.iter().zip(expected_motion)
+        {
+            assert!(frame.translation().abs_diff_eq(expected, 1e-5), "{:?} != {expected:?}", frame.translation());
+        }
+        for (translation, expected) in translations.iter().zip([1.0, 1.0, 1.5])
+        {
+            assert!(translation.abs_diff_eq(Vec3::new(0.0, expected, 0.0), 1e-5), "{translation:?}");
+        }
+    }
+
+    #[test]
+    fn root_motion_yaw()
+    {
+        let tilt = Quat::from_rotation_x(0.5);
+        let yaw = Quat::from_rotation_y(std::f32::consts::FRAC_PI_2);
+        let mut rotations = [tilt, yaw * tilt];
+        let mut translations = [Vec3::ZERO; 2];
+        let motion = extract_root_motion(&mut rotations, &mut translations);
+
+        // the yaw moves into the root motion, and the bone keeps the rest of its rotation
+        assert!(motion[0].rotation().abs_diff_eq(Quat::IDENTITY, 1e-5));
+        assert!(motion[1].rotation().abs_diff_eq(yaw, 1e-5));
+        assert!(motion[2].rotation().abs_diff_eq(yaw * yaw, 1e-5));
+        for rotation in rotations
+        {
+            assert!(rotation.abs_diff_eq(tilt, 1e-5), "{rotation:?}");
+        }
+    }
+
+    #[test]
+    fn alpha_modes()
+    {
+        assert_eq!(gltf_alpha_mode(AlphaMode::Opaque, None), (MaterialAlphaMode::Opaque, MaterialClass::PbrOpaque, 0.0));
+        // the cutoff only applies to masked materials
+        assert_eq!(gltf_alpha_mode(AlphaMode::Opaque, Some(0.3)), (MaterialAlphaMode::Opaque, MaterialClass::PbrOpaque, 0.0));
+        assert_eq!(gltf_alpha_mode(AlphaMode::Mask, Some(0.3)), (MaterialAlphaMode::Mask, MaterialClass::PbrOpaque, 0.3));
+        assert_eq!(gltf_alpha_mode(AlphaMode::Mask, None), (MaterialAlphaMode::Mask, MaterialClass::PbrOpaque, 0.5));
+        assert_eq!(gltf_alpha_mode(AlphaMode::Blend, Some(0.3)), (MaterialAlphaMode::Blend, MaterialClass::PbrTransparent, 0.0));
+    }
+
+    #[test]
+    fn resampled_animation_playback()
+    {
+        // one second moving along X, resampled and compressed as the builder does
+        let translations = resample_keys([(0.0, Vec3::ZERO), (1.0, Vec3::X)].into_iter(), DEFAULT_ANIM_SAMPLE_RATE.to_f32(), Vec3::lerp).unwrap();
+        assert_eq!(translations.len(), 31, "Every frame, including both ends, is sampled");
+        let frame_count = translations.len();
+        let animation = SkeletalAnimation::compress(
+            DEFAULT_ANIM_SAMPLE_RATE,
+            Box::new([BoneId(0)]),
+            frame_count as u32,
+            &vec![Quat::IDENTITY; frame_count],
+            &translations,
+            &[],
+            AnimCompression::KEEP_KEYS);
+        assert!((animation.duration_secs() - frame_count as f32 / 30.0).abs() < 1e-5);
+
+        let skeleton = Skeleton
+        {
+            bone_ids: Box::new([BoneId(0)]),
+            parent_indices: Box::new([-1]),
+            bind_poses: Box::new([DualQuat::IDENTITY]),
+            inverse_bind_poses: Box::new([DualQuat::IDENTITY]),
+        };
+        let def = AnimGraphDef
+        {
+            parameters: Box::new([]),
+            states: Box::new([AnimState { name: "move".to_string(), motion: AnimMotion::Clip(0), speed: 1.0, looping: true, sync_group: None }]),
+            transitions: Box::new([]),
+            entry_state: 0,
+        };
+        let animations = [&animation];
+
+        let mut instance = AnimGraphInstance::new(&def);
+        instance.update(&def, &animations, 0.5);
+        let mut poser = SkeletonPoser::new(&skeleton);
+        instance.apply(&def, &animations, &mut poser);
+        let translation = poser.local_poses()[0].translation();
+        assert!(translation.abs_diff_eq(Vec3::new(0.5, 0.0, 0.0), 1e-3), "Played at the authored speed: {translation:?}");
+    }
+
+    #[test]
+    fn primitive_modes()
+    {
+        assert_eq!(gltf_triangle_list(Mode::Triangles, vec![0, 1, 2, 2, 1, 3]).unwrap(), [0, 1, 2, 2, 1, 3]);
+        assert_eq!(gltf_triangle_list(Mode::TriangleStrip, vec![0, 1, 2, 3, 4]).unwrap(), [0, 1, 2, 1, 3, 2, 2, 3, 4]);
+        assert_eq!(gltf_triangle_list(Mode::TriangleFan, vec![0, 1, 2, 3]).unwrap(), [1, 2, 0, 2, 3, 0]);
+        assert!(gltf_triangle_list(Mode::TriangleStrip, vec![0, 1]).unwrap().is_empty());
+
+        assert!(matches!(gltf_triangle_list(Mode::Triangles, vec![0, 1, 2, 3]), Err(ModelImportError::IncompleteTriangles(4))));
+        for mode in [Mode::Points, Mode::Lines, Mode::LineLoop, Mode::LineStrip]
+        {
+            assert!(matches!(gltf_triangle_list(mode, vec![0, 1, 2]), Err(ModelImportError::UnsupportedPrimitiveMode(m)) if m == mode));
+        }
+    }
+}
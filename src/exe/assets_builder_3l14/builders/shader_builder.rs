@@ -3,16 +3,23 @@ use std::error::Error;
 use std::io::{Read};
 use std::path::{Path, PathBuf};
 use enumflags2::{bitflags, BitFlag, BitFlags};
-use graphics_3l14::assets::{shader_key, EngineRenderPass, ShaderFile, ShaderStage};
+use graphics_3l14::assets::{shader_key, EngineRenderPass, Shader, ShaderDebugData, ShaderFile, ShaderStage};
 use hassle_rs::{Dxc, DxcCompiler, DxcIncludeHandler, DxcLibrary, DxcValidator, Dxil, HassleError};
+use naga::valid::{Capabilities, ValidationFlags, Validator};
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
+use unicase::UniCase;
 use asset_3l14::AssetTypeId;
 use graphics_3l14::material_classes::MaterialClass;
+use graphics_3l14::passes::post::PostEffect;
 use graphics_3l14::vertex_layouts::VertexCaps;
 use nab_3l14::utils::enumflags2_seq;
+use crate::builders::{naga_stage, ReflectedShader, ShaderReflectionError};
 use crate::core::{AssetBuilder, BuildOutputs, SourceInput, VersionBuilder};
 
+// Built shaders are versioned by the naga that compiled them, bump this alongside the workspace's naga dependency
+const NAGA_VERSION: &str = "30.0.0";
+
 #[bitflags]
 #[repr(u8)]
 #[derive(Hash, Copy, Clone, Debug, Serialize, Deserialize)]
@@ -63,17 +70,47 @@ impl DxcIncludeHandler for Includer
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub enum ShaderStageConfig
 {
     Vertex
     {
         #[serde(with = "enumflags2_seq")]
         layout: BitFlags<VertexCaps>,
+        #[serde(default)]
+        instanced: bool, // also build an instanced permutation of the layout, with INSTANCED defined (HLSL only, instanced WGSL shaders are separate sources with Instanced in their layout)
     },
     Pixel
     {
         class: MaterialClass,
+    },
+    Post
+    {
+        effect: PostEffect,
+    },
+}
+impl ShaderStageConfig
+{
+    #[must_use]
+    pub fn stage(&self) -> ShaderStage
+    {
+        match self
+        {
+            ShaderStageConfig::Vertex { .. } => ShaderStage::Vertex,
+            ShaderStageConfig::Pixel { .. } => ShaderStage::Pixel,
+            ShaderStageConfig::Post { effect } => effect.stage(),
+        }
+    }
+
+    // Verify the reflected shader against the engine's layouts for this stage
+    pub fn validate(&self, reflected: &ReflectedShader) -> Result<(), ShaderReflectionError>
+    {
+        match self
+        {
+            ShaderStageConfig::Vertex { layout, .. } => reflected.validate_vertex(*layout),
+            ShaderStageConfig::Pixel { class } => reflected.validate_pixel(*class),
+            ShaderStageConfig::Post { effect } => reflected.validate_post(*effect),
+        }
     }
 }
 
@@ -94,47 +131,88 @@ impl Default for ShaderBuildConfig
         {
             compile_flags: BitFlags::empty(),
             pass: EngineRenderPass::Opaque,
-            stage: ShaderStageConfig::Vertex { layout: BitFlags::empty() },
+            stage: ShaderStageConfig::Vertex { layout: BitFlags::empty(), instanced: false },
         }
     }
 }
 
-pub struct ShaderBuilder
+struct DxcBackend
 {
     includer: Mutex<Includer>, // must be a mutex due to hassle-rs
-    dxc_compiler: DxcCompiler,
-    dxc_library: DxcLibrary,
-    dxc_validator: DxcValidator,
+    compiler: DxcCompiler,
+    library: DxcLibrary,
+    validator: DxcValidator,
     // these must be at the end b/c ^ don't correctly lifetime these
     dxc: Dxc,
     dxil: Dxil,
 }
-impl ShaderBuilder
+impl DxcBackend
 {
-    pub fn new(shaders_root: impl Into<PathBuf>, dxc_dir: Option<PathBuf>) -> Result<Self, Box<dyn Error>>
+    fn new(shaders_root: PathBuf, dxc_dir: Option<PathBuf>) -> Result<Self, HassleError>
     {
         let dxc = Dxc::new(dxc_dir.clone())?;
-        let dxc_compiler = dxc.create_compiler()?;
-        let dxc_library = dxc.create_library()?;
+        let compiler = dxc.create_compiler()?;
+        let library = dxc.create_library()?;
 
         let dxil = Dxil::new(dxc_dir)?;
-        let dxc_validator = dxil.create_validator()?;
+        let validator = dxil.create_validator()?;
 
         Ok(Self
         {
-            includer: Mutex::new(Includer { shaders_root: shaders_root.into() }),
+            includer: Mutex::new(Includer { shaders_root }),
             dxc,
             dxil,
-            dxc_compiler,
-            dxc_library,
-            dxc_validator,
+            compiler,
+            library,
+            validator,
         })
     }
+}
 
-    pub fn compile_hlsl(&self, mut compilation: ShaderCompilation) -> Result<Box<[u8]>, Box<dyn Error>>
+pub struct CompiledShader
+{
+    pub module_bytes: Box<[u8]>, // Spir-V
+    pub reflected: ReflectedShader,
+}
+
+pub struct ShaderBuilder
+{
+    shaders_root: PathBuf,
+    dxc: Option<DxcBackend>, // HLSL sources can only be built if DXC is available, WGSL is always compiled with naga
+}
+impl ShaderBuilder
+{
+    #[must_use]
+    pub fn new(shaders_root: impl Into<PathBuf>, dxc_dir: Option<PathBuf>) -> Self
+    {
+        let shaders_root = shaders_root.into();
+        let dxc = match DxcBackend::new(shaders_root.clone(), dxc_dir)
+        {
+            Ok(dxc) => Some(dxc),
+            Err(err) =>
+            {
+                log::warn!("DXC is unavailable ({err:?}), only WGSL shaders can be built");
+                None
+            }
+        };
+
+        Self
+        {
+            shaders_root,
+            dxc,
+        }
+    }
+
+    // Compile HLSL source to Spir-V with DXC, verifying the shader against the engine's layout for this stage
+    pub fn compile_hlsl(&self, mut compilation: ShaderCompilation, stage_config: &ShaderStageConfig) -> Result<CompiledShader, Box<dyn Error>>
     {
         // note: mut self only needed for include header, can split out if necessary
 
+        let Some(dxc) = &self.dxc else
+        {
+            return Err(Box::new(sc_err(self.shaders_root.join(compilation.filename), compilation.stage, ShaderCompileError::DxcUnavailable)));
+        };
+
         let entry_point = compilation.stage.entry_point();
         let profile = format!("{}_6_0", compilation.stage.prefix());
 
@@ -163,16 +241,16 @@ impl ShaderBuilder
 
         // matrix ordering? (Zpc vs Zpr for col vs row)
 
-        let mut includer = self.includer.lock();
+        let mut includer = dxc.includer.lock();
         let file_path = includer.shaders_root.join(compilation.filename);
 
         log::debug!("[DXC] Compiling {:?} with arguments {:?}", compilation, dxc_args);
 
-        let blob = self.dxc_library.create_blob_with_encoding_from_str(compilation.source_text)
+        let blob = dxc.library.create_blob_with_encoding_from_str(compilation.source_text)
             .map_err(|e| sc_err(file_path.clone(), compilation.stage, e))?;
 
         // todo: compile_with_debug
-        let spirv = match self.dxc_compiler.compile(
+        let spirv = match dxc.compiler.compile(
             &blob,
             file_path.to_string_lossy().as_ref(),
             entry_point,
@@ -185,7 +263,7 @@ impl ShaderBuilder
             Err(result) =>
             {
                 let error_blob = result.0.get_error_buffer()?;
-                let error_str = self.dxc_library.get_blob_as_string(&error_blob.into())?;
+                let error_str = dxc.library.get_blob_as_string(&error_blob.into())?;
                 Err(HassleError::CompileError(error_str))
             },
             Ok(result) =>
@@ -195,22 +273,70 @@ impl ShaderBuilder
             }
         }.map_err(|e| sc_err(file_path.clone(), compilation.stage, e))?;
 
-        let blob_encoding = self.dxc_library.create_blob_with_encoding(&spirv)
+        let blob_encoding = dxc.library.create_blob_with_encoding(&spirv)
             .map_err(|e| sc_err(file_path.clone(), compilation.stage, e))?;
 
         // TODO: currently broken
-        // let module = match self.dxc_validator.validate(blob_encoding.into())
+        // let module = match dxc.validator.validate(blob_encoding.into())
         // {
         //     Ok(blob) => Ok(blob.to_vec()), // todo: This could be no-copy
         //     Err(result) =>
         //     {
         //         let error_blob = result.0.get_error_buffer()?;
-        //         let error_str = self.dxc_library.get_blob_as_string(&error_blob.into())?;
+        //         let error_str = dxc.library.get_blob_as_string(&error_blob.into())?;
         //         Err(HassleError::ValidationError(error_str))
         //     }
         // }.map_err(|e| sc_err(file_path.clone(), compilation.stage, e))?;
 
-        Ok(spirv.into_boxed_slice())
+        let reflected = ReflectedShader::from_spirv(&spirv, compilation.stage)
+            .and_then(|r| stage_config.validate(&r).map(|_| r))
+            .map_err(|e| sc_err(file_path.clone(), compilation.stage, e))?;
+
+        Ok(CompiledShader
+        {
+            module_bytes: spirv.into_boxed_slice(),
+            reflected,
+        })
+    }
+
+    // Compile WGSL source to Spir-V with naga, verifying the shader against the engine's layout for this stage
+    pub fn compile_wgsl(&self, compilation: ShaderCompilation, stage_config: &ShaderStageConfig) -> Result<CompiledShader, ShaderBuildError>
+    {
+        let file_path = self.shaders_root.join(compilation.filename);
+        let err = |error: ShaderCompileError| sc_err(file_path.clone(), compilation.stage, error);
+
+        log::debug!("[naga] Compiling {:?}", compilation);
+
+        // note: defines are not supported by WGSL (so neither are instanced permutations)
+
+        let module = naga::front::wgsl::parse_str(compilation.source_text)
+            .map_err(|e| err(ShaderCompileError::Parse(e.emit_to_string_with_path(compilation.source_text, &file_path.to_string_lossy()))))?;
+
+        let module_info = Validator::new(ValidationFlags::all(), Capabilities::default() | Capabilities::SHADER_FLOAT16)
+            .validate(&module)
+            .map_err(|e| err(ShaderCompileError::Validation(e.emit_to_string_with_path(compilation.source_text, &file_path.to_string_lossy()))))?;
+
+        let reflected = ReflectedShader::from_module(&module, &module_info, compilation.stage).map_err(|e| err(e.into()))?;
+        stage_config.validate(&reflected).map_err(|e| err(e.into()))?;
+
+        let mut spv_options = naga::back::spv::Options::default();
+        if compilation.flags.contains(ShaderCompileFlag::Debug)
+        {
+            spv_options.flags |= naga::back::spv::WriterFlags::DEBUG;
+        }
+        let spv_pipeline = naga::back::spv::PipelineOptions
+        {
+            shader_stage: naga_stage(compilation.stage),
+            entry_point: compilation.stage.entry_point().to_string(),
+        };
+        let words = naga::back::spv::write_vec(&module, &module_info, &spv_options, Some(&spv_pipeline))
+            .map_err(|e| err(ShaderCompileError::Codegen(e)))?;
+
+        Ok(CompiledShader
+        {
+            module_bytes: words.into_iter().flat_map(u32::to_le_bytes).collect(),
+            reflected,
+        })
     }
 }
 impl AssetBuilder for ShaderBuilder
@@ -219,20 +345,41 @@ impl AssetBuilder for ShaderBuilder
 
     fn supported_input_file_extensions(&self) -> &'static [&'static str]
     {
-        &["hlsl"]
+        &["hlsl", "wgsl"]
     }
 
     fn builder_version(&self, vb: &mut VersionBuilder)
     {
-        let dxc_version = interop_3l14::exe_version::get_exe_version("dxcompiler.dll")
-            .expect("Failed to get DXC version");
+        if self.dxc.is_some()
+        {
+            // TODO: cross platform support
+            let dxc_version = match interop_3l14::exe_version::get_exe_version("dxcompiler.dll")
+            {
+                Ok(version) => version,
+                Err(err) =>
+                {
+                    log::warn!("Failed to get the DXC version ({err}), HLSL shaders will not rebuild when DXC changes");
+                    Default::default()
+                }
+            };
+            vb.append(&[b"DXC", &dxc_version.as_bytes()]);
+        }
         vb.append(
         &[
-            b"DXC", &dxc_version.as_bytes(),
+            b"naga", NAGA_VERSION.as_bytes(),
             b"Shader compiler - initial",
+            b"Shader compiler - naga",
+            b"Shader compiler - reflection",
+            b"Shader compiler - instanced permutations",
+            b"Shader compiler - post effects",
         ]);
     }
 
+    fn format_version(&self, vb: &mut VersionBuilder)
+    {
+        vb.push_prehashed(ShaderFile::TYPE_LAYOUT_HASH);
+    }
+
     fn build_assets(&self, config: Self::BuildConfig, input: &mut SourceInput, outputs: &mut BuildOutputs) -> Result<(), Box<dyn Error>>
     {
         // todo: features; permutation for each feature -- possibly simplify into 'sets' of supported features
@@ -240,65 +387,114 @@ impl AssetBuilder for ShaderBuilder
         let mut source_text = String::new();
         input.read_to_string(&mut source_text)?;
 
-        // todo: correctly stage
-        let hash = match config.stage
-        {
-            ShaderStageConfig::Vertex { layout } => shader_key::vertex(layout, config.pass),
-            ShaderStageConfig::Pixel { class } => shader_key::pixel(class, config.pass),
-        };
+        let is_wgsl = input.file_extension() == &UniCase::new("wgsl");
+        let permutations = permutations(config.stage, is_wgsl)
+            .map_err(|e| sc_err(self.shaders_root.join(input.source_path()), config.stage.stage(), e))?;
 
-        outputs.add_synthetic(AssetTypeId::Shader, hash, |output|
+        for (stage_config, permutation_define) in permutations
         {
-            let mut defines: Vec<(String, Option<String>)> = Vec::new(); // todo: use Cow?
-
-            let defines_ref = Vec::from_iter(defines.iter().map(|(k, v)| (k.as_str(), v.as_deref())));
-            let compilation = ShaderCompilation
+            // todo: correctly stage
+            let hash = match stage_config
             {
-                source_text: &source_text,
-                filename: input.source_path(), // just the filename?
-                stage: match config.stage
-                {
-                    ShaderStageConfig::Vertex { .. } => ShaderStage::Vertex,
-                    ShaderStageConfig::Pixel { .. } => ShaderStage::Pixel,
-                },
-                flags: config.compile_flags, // TODO: global flags?
-                defines: defines_ref,
+                ShaderStageConfig::Vertex { layout, .. } => shader_key::vertex(layout, config.pass),
+                ShaderStageConfig::Pixel { class } => shader_key::pixel(class, config.pass),
+                ShaderStageConfig::Post { effect } => shader_key::post(effect),
             };
 
-            let module_bytes = self.compile_hlsl(compilation)?;
-            output.serialize(&ShaderFile
+            outputs.add_synthetic(AssetTypeId::Shader, hash, |output|
             {
-                stage: match config.stage
+                let mut defines: Vec<(String, Option<String>)> = Vec::new(); // todo: use Cow?
+                if let Some(define) = permutation_define
                 {
-                    ShaderStageConfig::Vertex { .. } => ShaderStage::Vertex,
-                    ShaderStageConfig::Pixel { .. } => ShaderStage::Pixel,
-                },
-                module_bytes,
-            })?;
+                    defines.push((define.to_string(), Some("1".to_string())));
+                }
+
+                let defines_ref = Vec::from_iter(defines.iter().map(|(k, v)| (k.as_str(), v.as_deref())));
+                let compilation = ShaderCompilation
+                {
+                    source_text: &source_text,
+                    filename: input.source_path(), // just the filename?
+                    stage: stage_config.stage(),
+                    flags: config.compile_flags, // TODO: global flags?
+                    defines: defines_ref,
+                };
+
+                let compiled = match is_wgsl
+                {
+                    true => self.compile_wgsl(compilation, &stage_config)?,
+                    false => self.compile_hlsl(compilation, &stage_config)?,
+                };
+                output.serialize(&ShaderFile
+                {
+                    stage: stage_config.stage(),
+                    module_bytes: compiled.module_bytes,
+                    reflection: compiled.reflected.reflection,
+                })?;
+                output.serialize_debug::<Shader>(&ShaderDebugData
+                {
+                    source_file: input.source_path_string(),
+                    binding_names: compiled.reflected.binding_names,
+                })?;
 
-            Ok(())
-        })?;
+                Ok(())
+            })?;
+        }
 
         Ok(())
     }
 }
 
-fn sc_err(file_path: PathBuf, stage: ShaderStage, error: HassleError) -> ShaderBuildError
+// The stage configs to build a shader source with, and the define (if any) that selects each permutation
+fn permutations(stage: ShaderStageConfig, is_wgsl: bool) -> Result<Vec<(ShaderStageConfig, Option<&'static str>)>, ShaderCompileError>
+{
+    let mut permutations = vec![(stage, None)];
+    if let ShaderStageConfig::Vertex { layout, instanced: true } = stage
+    {
+        if is_wgsl
+        {
+            return Err(ShaderCompileError::InstancedWgsl);
+        }
+        permutations.push((ShaderStageConfig::Vertex { layout: layout | VertexCaps::Instanced, instanced: false }, Some("INSTANCED")));
+    }
+    Ok(permutations)
+}
+
+fn sc_err(file_path: PathBuf, stage: ShaderStage, error: impl Into<ShaderCompileError>) -> ShaderBuildError
 {
     ShaderBuildError
     {
         file_path,
         stage,
-        error,
+        error: error.into(),
     }
 }
 
+#[derive(Debug)]
+pub enum ShaderCompileError
+{
+    DxcUnavailable,
+    InstancedWgsl, // WGSL has no defines to select the instanced permutation with, instead author a separate source with Instanced in its layout
+    Dxc(HassleError),
+    Parse(String),
+    Validation(String),
+    Reflection(ShaderReflectionError),
+    Codegen(naga::back::spv::Error),
+}
+impl From<HassleError> for ShaderCompileError
+{
+    fn from(value: HassleError) -> Self { Self::Dxc(value) }
+}
+impl From<ShaderReflectionError> for ShaderCompileError
+{
+    fn from(value: ShaderReflectionError) -> Self { Self::Reflection(value) }
+}
+
 #[derive(Debug)]
 pub struct ShaderBuildError
 {
     pub file_path: PathBuf,
     pub stage: ShaderStage,
-    pub error: HassleError,
+    pub error: ShaderCompileError,
 }
 impl std::fmt::Display for ShaderBuildError
 {
@@ -311,8 +507,176 @@ mod tests
 {
     use std::env;
     use std::path::Path;
+    use graphics_3l14::shader_reflection::UniformType;
     use super::*;
 
+    const TEST_WGSL: &str = r#"
+        struct Camera { proj_view: mat4x4f, total_secs_whole: u32, total_secs_frac: f32 };
+        struct World { transform: mat4x4f };
+        @group(0) @binding(0) var<uniform> camera: Camera;
+        @group(1) @binding(0) var<uniform> world: World;
+
+        @group(3) @binding(1) var tex_sampler: sampler;
+        @group(3) @binding(2) var tex: texture_2d<f32>;
+
+        struct VertexOutput
+        {
+            @builtin(position) clip_position: vec4f,
+            @location(0) tex_coord: vec2f,
+        };
+
+        @vertex
+        fn vs_main(@location(0) in_position: vec3f, @location(2) in_tex_coord: vec2f) -> VertexOutput
+        {
+            var out: VertexOutput;
+            out.clip_position = camera.proj_view * world.transform * vec4(in_position, 1.0);
+            out.tex_coord = in_tex_coord;
+            return out;
+        }
+
+        @fragment
+        fn ps_main(in: VertexOutput) -> @location(0) vec4f
+        {
+            return textureSample(tex, tex_sampler, in.tex_coord);
+        }
+    "#;
+
+    fn compile_test_wgsl(source_text: &str, stage: ShaderStage, stage_config: ShaderStageConfig) -> Result<CompiledShader, ShaderBuildError>
+    {
+        let builder = ShaderBuilder { shaders_root: PathBuf::new(), dxc: None };
+        builder.compile_wgsl(ShaderCompilation
+        {
+            source_text,
+            filename: Path::new("TEST_FILE.wgsl"),
+            stage,
+            flags: BitFlags::empty(),
+            defines: vec![],
+        }, &stage_config)
+    }
+
+    #[test]
+    pub fn compile_wgsl_shader()
+    {
+        let vertex = compile_test_wgsl(TEST_WGSL, ShaderStage::Vertex, ShaderStageConfig::Vertex { layout: VertexCaps::Static.into(), instanced: false }).unwrap();
+        assert_eq!(&vertex.module_bytes[0..4], &0x07230203u32.to_le_bytes()); // Spir-V magic
+
+        let pixel = compile_test_wgsl(TEST_WGSL, ShaderStage::Pixel, ShaderStageConfig::Pixel { class: MaterialClass::PbrOpaque }).unwrap();
+        assert_eq!(&pixel.module_bytes[0..4], &0x07230203u32.to_le_bytes());
+
+        // transparent materials share the opaque material layout
+        compile_test_wgsl(TEST_WGSL, ShaderStage::Pixel, ShaderStageConfig::Pixel { class: MaterialClass::PbrTransparent }).unwrap();
+    }
+
+    #[test]
+    pub fn reflect_wgsl_shader()
+    {
+        let vertex = compile_test_wgsl(TEST_WGSL, ShaderStage::Vertex, ShaderStageConfig::Vertex { layout: VertexCaps::Static.into(), instanced: false }).unwrap();
+        let reflection = &vertex.reflected.reflection;
+        assert_eq!(&*vertex.reflected.binding_names, &["camera".to_string(), "world".to_string()]);
+        assert_eq!(reflection.bindings[0].members.as_ref(), graphics_3l14::camera::CameraUniform::MEMBERS);
+        assert_eq!(reflection.vertex_inputs.iter().map(|v| v.location).collect::<Vec<_>>(), vec![0, 2]);
+
+        // only the resources used by the entry point are reflected
+        let pixel = compile_test_wgsl(TEST_WGSL, ShaderStage::Pixel, ShaderStageConfig::Pixel { class: MaterialClass::PbrOpaque }).unwrap();
+        assert_eq!(pixel.reflected.reflection.bindings.iter().map(|b| (b.group, b.binding)).collect::<Vec<_>>(), vec![(3, 1), (3, 2)]);
+
+        // Spir-V produced by other compilers (i.e. DXC) is reflected the same
+        let from_spirv = ReflectedShader::from_spirv(&pixel.module_bytes, ShaderStage::Pixel).unwrap();
+        assert_eq!(from_spirv.reflection, pixel.reflected.reflection);
+    }
+
+    #[test]
+    pub fn wgsl_layout_mismatch()
+    {
+        // no vertex inputs beyond the static vertex
+        let bad_input = TEST_WGSL.replace("@location(2) in_tex_coord: vec2f", "@location(7) in_tex_coord: vec2f");
+        let result = compile_test_wgsl(&bad_input, ShaderStage::Vertex, ShaderStageConfig::Vertex { layout: VertexCaps::Static.into(), instanced: false });
+        assert!(matches!(result, Err(ShaderBuildError { error: ShaderCompileError::Reflection(ShaderReflectionError::LayoutMismatch(_)), .. })));
+
+        // skinning poses are not bound for static vertices
+        let bad_group = TEST_WGSL.replace("@group(1) @binding(0) var<uniform> world", "@group(2) @binding(0) var<uniform> world");
+        let result = compile_test_wgsl(&bad_group, ShaderStage::Vertex, ShaderStageConfig::Vertex { layout: VertexCaps::Static.into(), instanced: false });
+        assert!(matches!(result, Err(ShaderBuildError { error: ShaderCompileError::Reflection(ShaderReflectionError::LayoutMismatch(_)), .. })));
+
+        // the sampler and texture are swapped
+        let bad_binding = TEST_WGSL.replace("@binding(1) var tex_sampler", "@binding(2) var tex_sampler").replace("@binding(2) var tex:", "@binding(1) var tex:");
+        let result = compile_test_wgsl(&bad_binding, ShaderStage::Pixel, ShaderStageConfig::Pixel { class: MaterialClass::PbrOpaque });
+        assert!(matches!(result, Err(ShaderBuildError { error: ShaderCompileError::Reflection(ShaderReflectionError::LayoutMismatch(_)), .. })));
+
+        // the camera uniform does not match CameraUniform
+        let bad_uniform = TEST_WGSL.replace("total_secs_whole: u32, total_secs_frac: f32", "total_secs: vec2f, padding: vec2f");
+        let result = compile_test_wgsl(&bad_uniform, ShaderStage::Vertex, ShaderStageConfig::Vertex { layout: VertexCaps::Static.into(), instanced: false });
+        assert!(matches!(result, Err(ShaderBuildError { error: ShaderCompileError::Reflection(ShaderReflectionError::LayoutMismatch(_)), .. })));
+    }
+
+    #[test]
+    pub fn wgsl_instanced_inputs()
+    {
+        // instance attributes follow on from the vertex attributes
+        let instanced = TEST_WGSL
+            .replace("@location(2) in_tex_coord: vec2f)", "@location(2) in_tex_coord: vec2f, @location(3) world_0: vec4f, @location(6) world_3: vec4f)")
+            .replace("world.transform * vec4(in_position, 1.0)", "mat4x4f(world_0, world_0, world_0, world_3) * vec4(in_position, 1.0)");
+        let static_instanced = VertexCaps::Static | VertexCaps::Instanced;
+        let vertex = compile_test_wgsl(&instanced, ShaderStage::Vertex, ShaderStageConfig::Vertex { layout: static_instanced, instanced: false }).unwrap();
+        assert_eq!(vertex.reflected.reflection.vertex_inputs.iter().map(|v| v.location).collect::<Vec<_>>(), vec![0, 2, 3, 6]);
+
+        let result = compile_test_wgsl(&instanced, ShaderStage::Vertex, ShaderStageConfig::Vertex { layout: VertexCaps::Static.into(), instanced: false });
+        assert!(matches!(result, Err(ShaderBuildError { error: ShaderCompileError::Reflection(ShaderReflectionError::LayoutMismatch(_)), .. })));
+    }
+
+    #[test]
+    pub fn instanced_permutations()
+    {
+        let stage = ShaderStageConfig::Vertex { layout: VertexCaps::Static.into(), instanced: true };
+        let hlsl: Vec<_> = permutations(stage, false).unwrap().into_iter()
+            .map(|(stage, define)| match stage
+            {
+                ShaderStageConfig::Vertex { layout, instanced } => (layout, instanced, define),
+                _ => panic!("Permutations keep the stage"),
+            })
+            .collect();
+        assert_eq!(hlsl, [
+            (BitFlags::from(VertexCaps::Static), true, None),
+            (VertexCaps::Static | VertexCaps::Instanced, false, Some("INSTANCED")),
+        ]);
+
+        // WGSL can't select the permutation, so it must be a separate source
+        assert!(matches!(permutations(stage, true), Err(ShaderCompileError::InstancedWgsl)));
+        assert_eq!(permutations(ShaderStageConfig::Vertex { layout: VertexCaps::Static | VertexCaps::Instanced, instanced: false }, true).unwrap().len(), 1);
+    }
+
+    #[test]
+    pub fn wgsl_post_effects()
+    {
+        for (effect, source) in
+        [
+            (PostEffect::Fullscreen, include_str!("../../../../assets/src/shader/PostFullscreen.vs.wgsl")),
+            (PostEffect::LuminanceHistogram, include_str!("../../../../assets/src/shader/PostHistogram.cs.wgsl")),
+            (PostEffect::Exposure, include_str!("../../../../assets/src/shader/PostExposure.cs.wgsl")),
+            (PostEffect::BloomPrefilter, include_str!("../../../../assets/src/shader/PostBloomPrefilter.ps.wgsl")),
+            (PostEffect::BloomDownsample, include_str!("../../../../assets/src/shader/PostBloomDownsample.ps.wgsl")),
+            (PostEffect::BloomUpsample, include_str!("../../../../assets/src/shader/PostBloomUpsample.ps.wgsl")),
+            (PostEffect::Tonemap, include_str!("../../../../assets/src/shader/PostTonemap.ps.wgsl")),
+        ]
+        {
+            let compiled = compile_test_wgsl(source, effect.stage(), ShaderStageConfig::Post { effect })
+                .unwrap_or_else(|err| panic!("{effect:?}: {err}"));
+            assert_eq!(&compiled.module_bytes[0..4], &0x07230203u32.to_le_bytes());
+        }
+
+        // the tonemapper does not write the exposure
+        let tonemap = include_str!("../../../../assets/src/shader/PostTonemap.ps.wgsl");
+        let writes_exposure = tonemap.replace("var<storage, read> exposure", "var<storage, read_write> exposure");
+        let result = compile_test_wgsl(&writes_exposure, ShaderStage::Pixel, ShaderStageConfig::Post { effect: PostEffect::Tonemap });
+        assert!(matches!(result, Err(ShaderBuildError { error: ShaderCompileError::Reflection(ShaderReflectionError::LayoutMismatch(_)), .. })));
+
+        // the post uniform must match PostUniform
+        let bad_uniform = tonemap.replace("bloom_threshold: f32,\n    bloom_knee: f32,", "bloom_threshold_knee: vec2f,");
+        assert_ne!(bad_uniform, tonemap);
+        let result = compile_test_wgsl(&bad_uniform, ShaderStage::Pixel, ShaderStageConfig::Post { effect: PostEffect::Tonemap });
+        assert!(matches!(result, Err(ShaderBuildError { error: ShaderCompileError::Reflection(ShaderReflectionError::LayoutMismatch(_)), .. })));
+    }
+
     // #[test]
     // #[cfg(target_os = "windows")] // TODO: cross platform support
     // pub fn compile_vertex_shader()
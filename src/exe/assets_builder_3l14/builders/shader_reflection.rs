@@ -0,0 +1,412 @@
+use std::fmt::{Debug, Display, Formatter};
+use enumflags2::BitFlags;
+use naga::{AddressSpace, Binding, Handle, ImageClass, ImageDimension, Module, ScalarKind, Type, TypeInner};
+use naga::valid::{Capabilities, ModuleInfo, ValidationFlags, Validator};
+use wgpu::VertexFormat;
+use graphics_3l14::assets::ShaderStage;
+use graphics_3l14::material_classes::MaterialClass;
+use graphics_3l14::passes::post::{PostEffect, PostUniform};
+use graphics_3l14::pipeline_cache::BindGroupSlot;
+use graphics_3l14::shader_reflection::{ShaderBinding, ShaderBindingType, ShaderReflection, ShaderScalarKind, ShaderTextureDimension, ShaderVertexInput, UniformMemberLayout, UniformType};
+use graphics_3l14::vertex_layouts::{VertexCaps, VertexLayoutBuilder};
+
+// The reflection of a single entry point, along with debug-only data
+#[derive(Debug, Default)]
+pub struct ReflectedShader
+{
+    pub reflection: ShaderReflection,
+    pub binding_names: Box<[String]>, // parallel to reflection.bindings
+}
+impl ReflectedShader
+{
+    // Reflect the entry point of the given stage. Only the globals the entry point uses are reflected
+    pub fn from_module(module: &Module, module_info: &ModuleInfo, stage: ShaderStage) -> Result<Self, ShaderReflectionError>
+    {
+        let naga_stage = naga_stage(stage);
+        let (ep_index, entry_point) = module.entry_points.iter().enumerate()
+            .find(|(_, ep)| ep.stage == naga_stage && ep.name == stage.entry_point())
+            .ok_or(ShaderReflectionError::MissingEntryPoint(stage))?;
+        let ep_info = module_info.get_entry_point(ep_index);
+
+        let mut bindings = Vec::new();
+        for (handle, global) in module.global_variables.iter()
+        {
+            if ep_info[handle].is_empty() { continue; }
+            let Some(res_binding) = &global.binding else { continue; };
+
+            let name = global.name.clone().unwrap_or_default();
+            let unsupported = || ShaderReflectionError::UnsupportedBinding(name.clone());
+
+            let mut binding = ShaderBinding
+            {
+                group: res_binding.group,
+                binding: res_binding.binding,
+                binding_type: ShaderBindingType::UniformBuffer,
+                size: 0,
+                members: Box::new([]),
+            };
+
+            match (global.space, &module.types[global.ty].inner)
+            {
+                (AddressSpace::Uniform, _) =>
+                {
+                    let mut members = Vec::new();
+                    flatten_members(module, global.ty, 0, &mut members);
+                    // the struct's span may include trailing padding that the Rust type doesn't need
+                    binding.size = members.iter().map(|m| m.offset + m.size).max().unwrap_or(0);
+                    binding.members = members.into_boxed_slice();
+                }
+                (AddressSpace::Storage { access }, inner) =>
+                {
+                    binding.binding_type = ShaderBindingType::StorageBuffer { read_only: !access.contains(naga::StorageAccess::STORE) };
+                    binding.size = inner.size(module.to_ctx());
+                }
+                (AddressSpace::Handle, TypeInner::Sampler { comparison }) =>
+                {
+                    binding.binding_type = ShaderBindingType::Sampler { comparison: *comparison };
+                }
+                (AddressSpace::Handle, TypeInner::Image { dim, arrayed, class }) =>
+                {
+                    let dimension = match (dim, arrayed)
+                    {
+                        (ImageDimension::D1, false) => ShaderTextureDimension::D1,
+                        (ImageDimension::D2, false) => ShaderTextureDimension::D2,
+                        (ImageDimension::D2, true) => ShaderTextureDimension::D2Array,
+                        (ImageDimension::D3, false) => ShaderTextureDimension::D3,
+                        (ImageDimension::Cube, false) => ShaderTextureDimension::Cube,
+                        (ImageDimension::Cube, true) => ShaderTextureDimension::CubeArray,
+                        _ => return Err(unsupported()),
+                    };
+                    binding.binding_type = match class
+                    {
+                        ImageClass::Sampled { kind, multi } => ShaderBindingType::Texture
+                        {
+                            dimension,
+                            sample_kind: Some(scalar_kind(*kind)),
+                            multisampled: *multi,
+                        },
+                        ImageClass::Depth { multi } => ShaderBindingType::Texture
+                        {
+                            dimension,
+                            sample_kind: None,
+                            multisampled: *multi,
+                        },
+                        // todo: storage textures (requires format reflection)
+                        ImageClass::Storage { .. } | ImageClass::External => return Err(unsupported()),
+                    };
+                }
+                _ => return Err(unsupported()),
+            }
+
+            bindings.push((binding, name));
+        }
+        bindings.sort_by_key(|(b, _)| (b.group, b.binding));
+
+        let mut vertex_inputs = Vec::new();
+        if matches!(stage, ShaderStage::Vertex)
+        {
+            for arg in &entry_point.function.arguments
+            {
+                match (&arg.binding, &module.types[arg.ty].inner)
+                {
+                    (Some(Binding::Location { location, .. }), inner) =>
+                    {
+                        vertex_inputs.push(vertex_input(*location, inner)?);
+                    }
+                    // struct inputs
+                    (None, TypeInner::Struct { members, .. }) =>
+                    {
+                        for member in members
+                        {
+                            if let Some(Binding::Location { location, .. }) = member.binding
+                            {
+                                vertex_inputs.push(vertex_input(location, &module.types[member.ty].inner)?);
+                            }
+                        }
+                    }
+                    _ => {}, // builtins
+                }
+            }
+            vertex_inputs.sort_by_key(|v| v.location);
+        }
+
+        let (bindings, binding_names) = bindings.into_iter().unzip::<_, _, Vec<_>, Vec<_>>();
+        Ok(Self
+        {
+            reflection: ShaderReflection
+            {
+                bindings: bindings.into_boxed_slice(),
+                vertex_inputs: vertex_inputs.into_boxed_slice(),
+            },
+            binding_names: binding_names.into_boxed_slice(),
+        })
+    }
+
+    // Reflect a compiled Spir-V module (e.g. from DXC)
+    pub fn from_spirv(spirv: &[u8], stage: ShaderStage) -> Result<Self, ShaderReflectionError>
+    {
+        let module = naga::front::spv::parse_u8_slice(spirv, &naga::front::spv::Options::default())
+            .map_err(|e| ShaderReflectionError::Spirv(e.to_string()))?;
+        // the compiler has already validated the module, this is only needed for the entry point's global usage
+        let module_info = Validator::new(ValidationFlags::empty(), Capabilities::all())
+            .validate(&module)
+            .map_err(|e| ShaderReflectionError::Spirv(e.to_string()))?;
+
+        Self::from_module(&module, &module_info, stage)
+    }
+
+    // Verify that this reflected vertex shader is compatible with the engine's bind groups and the vertex layout
+    pub fn validate_vertex(&self, layout: BitFlags<VertexCaps>) -> Result<(), ShaderReflectionError>
+    {
+        let mut mismatches = Vec::new();
+
+        for (binding, name) in self.bindings()
+        {
+            let slot = match binding.group
+            {
+                g if g == BindGroupSlot::Camera as u32 => Some(BindGroupSlot::Camera),
+                g if g == BindGroupSlot::Transform as u32 => Some(BindGroupSlot::Transform),
+                g if g == BindGroupSlot::Poses as u32 && layout.contains(VertexCaps::Skinned) => Some(BindGroupSlot::Poses),
+                _ => None,
+            };
+            let Some(slot) = slot.filter(|_| binding.binding == 0) else
+            {
+                mismatches.push(format!("{name} {binding:?} is not bound by {layout} vertex pipelines"));
+                continue;
+            };
+
+            if let Some(uniform_layout) = slot.uniform_layout() &&
+                !binding.matches_uniform(&uniform_layout)
+            {
+                mismatches.push(format!("{name} {binding:?} does not match the {slot:?} uniform layout {uniform_layout:?}"));
+            }
+        }
+
+        let vertex_layout = VertexLayoutBuilder::from(layout);
+        for input in &self.reflection.vertex_inputs
+        {
+            match vertex_layout.attributes().find(|a| a.shader_location == input.location)
+            {
+                None => mismatches.push(format!("Vertex input {input:?} is not provided by {layout}")),
+                // component counts may differ, missing components are filled in with defaults
+                Some(attr) if vertex_format_scalar_kind(attr.format) != input.kind =>
+                {
+                    mismatches.push(format!("Vertex input {input:?} does not match {layout} attribute {:?}", attr.format));
+                }
+                Some(_) => {},
+            }
+        }
+
+        match mismatches.is_empty()
+        {
+            true => Ok(()),
+            false => Err(ShaderReflectionError::LayoutMismatch(mismatches)),
+        }
+    }
+
+    // Verify that this reflected pixel shader is compatible with the bind group layout of the material class (and the shadows and lights)
+    pub fn validate_pixel(&self, class: MaterialClass) -> Result<(), ShaderReflectionError>
+    {
+        let mut mismatches = Vec::new();
+
+        for (binding, name) in self.bindings()
+        {
+            if let Some(slot) = BindGroupSlot::VIEW_SLOTS.iter().find(|s| binding.group == **s as u32)
+            {
+                if let Some(uniform_layout) = slot.uniform_layout() &&
+                    matches!(binding.binding_type, ShaderBindingType::UniformBuffer) &&
+                    !binding.matches_uniform(&uniform_layout)
+                {
+                    mismatches.push(format!("{name} {binding:?} does not match the {slot:?} uniform layout {uniform_layout:?}"));
+                }
+                continue;
+            }
+
+            if binding.group != BindGroupSlot::Material as u32
+            {
+                mismatches.push(format!("{name} {binding:?} is not in the material bind group ({})", BindGroupSlot::Material as u32));
+                continue;
+            }
+
+            if matches!(binding.binding_type, ShaderBindingType::UniformBuffer)
+            {
+                match class.uniform_layout(binding.binding)
+                {
+                    None => mismatches.push(format!("{name} {binding:?} has no {class:?} uniform layout")),
+                    Some(uniform_layout) if !binding.matches_uniform(&uniform_layout) =>
+                    {
+                        mismatches.push(format!("{name} {binding:?} does not match the {class:?} uniform layout {uniform_layout:?}"));
+                    }
+                    Some(_) => {},
+                }
+            }
+        }
+
+        if let Err(err) = self.reflection.validate_group(BindGroupSlot::Material as u32, class.bind_layout_entries())
+        {
+            mismatches.extend(err.0);
+        }
+        for slot in BindGroupSlot::VIEW_SLOTS
+        {
+            if let Some(entries) = slot.view_bind_layout_entries() &&
+                let Err(err) = self.reflection.validate_group(slot as u32, entries)
+            {
+                mismatches.extend(err.0);
+            }
+        }
+
+        match mismatches.is_empty()
+        {
+            true => Ok(()),
+            false => Err(ShaderReflectionError::LayoutMismatch(mismatches)),
+        }
+    }
+
+    // Verify that this reflected post processing shader is compatible with the bind group layout of its effect
+    pub fn validate_post(&self, effect: PostEffect) -> Result<(), ShaderReflectionError>
+    {
+        let mut mismatches = Vec::new();
+
+        for (binding, name) in self.bindings()
+        {
+            if binding.group != 0
+            {
+                mismatches.push(format!("{name} {binding:?} is not in the post processing bind group (0)"));
+                continue;
+            }
+
+            if matches!(binding.binding_type, ShaderBindingType::UniformBuffer) &&
+                !binding.matches_uniform(&PostUniform::LAYOUT)
+            {
+                mismatches.push(format!("{name} {binding:?} does not match the post uniform layout {:?}", PostUniform::LAYOUT));
+            }
+        }
+
+        if let Err(err) = self.reflection.validate_group(0, effect.bind_layout_entries())
+        {
+            mismatches.extend(err.0);
+        }
+        // post processing draws without vertex buffers
+        for input in &self.reflection.vertex_inputs
+        {
+            mismatches.push(format!("Vertex input {input:?} is not provided to post processing"));
+        }
+
+        match mismatches.is_empty()
+        {
+            true => Ok(()),
+            false => Err(ShaderReflectionError::LayoutMismatch(mismatches)),
+        }
+    }
+
+    fn bindings(&self) -> impl Iterator<Item = (&ShaderBinding, &String)>
+    {
+        self.reflection.bindings.iter().zip(self.binding_names.iter())
+    }
+}
+
+#[must_use]
+pub fn naga_stage(stage: ShaderStage) -> naga::ShaderStage
+{
+    match stage
+    {
+        ShaderStage::Vertex => naga::ShaderStage::Vertex,
+        ShaderStage::Pixel => naga::ShaderStage::Fragment,
+        ShaderStage::Compute => naga::ShaderStage::Compute,
+        ShaderStage::Mesh => naga::ShaderStage::Mesh,
+    }
+}
+
+// Nested structs are flattened, arrays are a single member
+fn flatten_members(module: &Module, ty: Handle<Type>, base_offset: u32, members: &mut Vec<UniformMemberLayout>)
+{
+    match &module.types[ty].inner
+    {
+        TypeInner::Struct { members: struct_members, .. } =>
+        {
+            for member in struct_members
+            {
+                flatten_members(module, member.ty, base_offset + member.offset, members);
+            }
+        }
+        inner => members.push(UniformMemberLayout { offset: base_offset, size: inner.size(module.to_ctx()) }),
+    }
+}
+
+#[must_use]
+fn scalar_kind(kind: ScalarKind) -> ShaderScalarKind
+{
+    match kind
+    {
+        ScalarKind::Sint | ScalarKind::AbstractInt => ShaderScalarKind::Sint,
+        ScalarKind::Uint => ShaderScalarKind::Uint,
+        ScalarKind::Float | ScalarKind::AbstractFloat => ShaderScalarKind::Float,
+        ScalarKind::Bool => ShaderScalarKind::Bool,
+    }
+}
+
+fn vertex_input(location: u32, inner: &TypeInner) -> Result<ShaderVertexInput, ShaderReflectionError>
+{
+    let (kind, components) = match inner
+    {
+        TypeInner::Scalar(scalar) => (scalar.kind, 1),
+        TypeInner::Vector { size, scalar } => (scalar.kind, *size as u8),
+        _ => return Err(ShaderReflectionError::UnsupportedVertexInput(location)),
+    };
+    Ok(ShaderVertexInput { location, kind: scalar_kind(kind), components })
+}
+
+// The scalar type a vertex attribute appears as to a shader
+#[must_use]
+fn vertex_format_scalar_kind(format: VertexFormat) -> ShaderScalarKind
+{
+    use VertexFormat as VF;
+    match format
+    {
+        VF::Uint8 | VF::Uint8x2 | VF::Uint8x4 |
+        VF::Uint16 | VF::Uint16x2 | VF::Uint16x4 |
+        VF::Uint32 | VF::Uint32x2 | VF::Uint32x3 | VF::Uint32x4 => ShaderScalarKind::Uint,
+
+        VF::Sint8 | VF::Sint8x2 | VF::Sint8x4 |
+        VF::Sint16 | VF::Sint16x2 | VF::Sint16x4 |
+        VF::Sint32 | VF::Sint32x2 | VF::Sint32x3 | VF::Sint32x4 => ShaderScalarKind::Sint,
+
+        // normalized and half formats are read as floats
+        VF::Unorm8 | VF::Unorm8x2 | VF::Unorm8x4 | VF::Snorm8 | VF::Snorm8x2 | VF::Snorm8x4 |
+        VF::Unorm16 | VF::Unorm16x2 | VF::Unorm16x4 | VF::Snorm16 | VF::Snorm16x2 | VF::Snorm16x4 |
+        VF::Float16 | VF::Float16x2 | VF::Float16x4 |
+        VF::Float32 | VF::Float32x2 | VF::Float32x3 | VF::Float32x4 |
+        VF::Float64 | VF::Float64x2 | VF::Float64x3 | VF::Float64x4 |
+        VF::Unorm10_10_10_2 | VF::Unorm8x4Bgra => ShaderScalarKind::Float,
+    }
+}
+
+#[derive(Debug)]
+pub enum ShaderReflectionError
+{
+    Spirv(String),
+    MissingEntryPoint(ShaderStage),
+    UnsupportedBinding(String), // name
+    UnsupportedVertexInput(u32), // location
+    LayoutMismatch(Vec<String>),
+}
+impl Display for ShaderReflectionError
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+    {
+        match self
+        {
+            ShaderReflectionError::LayoutMismatch(mismatches) =>
+            {
+                writeln!(f, "Shader does not match the engine layout:")?;
+                for m in mismatches
+                {
+                    writeln!(f, "\t{m}")?;
+                }
+                Ok(())
+            },
+            _ => Debug::fmt(self, f),
+        }
+    }
+}
+impl std::error::Error for ShaderReflectionError { }
@@ -1,16 +1,17 @@
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 use std::io::{BufReader, Write};
+use ddsfile::{Dds, DxgiFormat};
 use enumflags2::bitflags;
-use image::{ColorType, DynamicImage, GenericImageView, ImageReader, ImageResult};
+use image::{DynamicImage, ImageFormat, ImageReader, Rgba32FImage, RgbaImage};
+use image::imageops::FilterType;
 use serde::{Deserialize, Serialize};
-use asset_3l14::AssetTypeId;
-use graphics_3l14::assets::{TextureFile, TextureFilePixelFormat};
+use unicase::UniCase;
+use asset_3l14::{AssetKey, AssetTypeId};
+use graphics_3l14::assets::{TextureFile, TextureFilePixelFormat, MAX_MIP_COUNT};
 use crate::core::{AssetBuilder, BuildOutputs, SourceInput, VersionBuilder};
 
-// TODO: go back to intel_tex_2? (ISPC is deprecated)
-
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Clone, Copy, Serialize, Deserialize)]
 pub enum CompressionQuality
 {
     Low,
@@ -27,9 +28,27 @@ pub enum TextureUsage
     #[default]
     Model,
     NormalMap,
-    Detail,
+    Detail, // single channel (e.g. masks)
+    Data, // multi-channel linear data (e.g. packed metallic-roughness)
     UI,
     Text,
+    ColorGradingLut, // a horizontal strip of (size) blue slices, each (size x size) red by green, built into a 3D texture
+}
+impl TextureUsage
+{
+    // Color textures are stored (and filtered) as sRGB, everything else is linear data
+    #[inline] #[must_use]
+    pub fn is_srgb(&self) -> bool
+    {
+        matches!(self, TextureUsage::Background | TextureUsage::Model | TextureUsage::UI)
+    }
+
+    // Textures that are not drawn at (roughly) 1:1 scale need mips
+    #[inline] #[must_use]
+    pub fn has_mips(&self) -> bool
+    {
+        matches!(self, TextureUsage::Model | TextureUsage::NormalMap | TextureUsage::Detail | TextureUsage::Data)
+    }
 }
 
 #[bitflags]
@@ -55,6 +74,42 @@ pub struct TextureBuildConfig
     pub quality: CompressionQuality,
     pub usage: TextureUsage,
 }
+impl TextureBuildConfig
+{
+    // The format to store texels as, None if the usage can't store HDR texels (it is encoded in 0-1)
+    #[must_use]
+    pub fn pixel_format(&self, has_alpha: bool, is_hdr: bool) -> Option<TextureFilePixelFormat>
+    {
+        use TextureFilePixelFormat as TFPF;
+        if is_hdr
+        {
+            return match &self.usage
+            {
+                TextureUsage::Background | TextureUsage::Model | TextureUsage::UI | TextureUsage::Data => Some(TFPF::Rgba16Float),
+                TextureUsage::NormalMap | TextureUsage::Detail | TextureUsage::Text | TextureUsage::ColorGradingLut => None,
+            };
+        }
+
+        Some(match (&self.usage, &self.quality)
+        {
+            (TextureUsage::UI, _) => TFPF::Rgba8Srgb,
+            (TextureUsage::ColorGradingLut, _) => TFPF::Rgba8, // LUTs map sRGB encoded colors, and are not filtered as sRGB
+            (TextureUsage::Text, _) => TFPF::R8,
+            (TextureUsage::NormalMap, CompressionQuality::Lossless) => TFPF::Rg8,
+            (TextureUsage::NormalMap, _) => TFPF::Bc5,
+            (TextureUsage::Detail, CompressionQuality::Lossless) => TFPF::R8,
+            (TextureUsage::Detail, _) => TFPF::Bc4,
+            (TextureUsage::Data, CompressionQuality::Lossless) => TFPF::Rgba8,
+            (TextureUsage::Data, CompressionQuality::Low) if has_alpha => TFPF::Bc3,
+            (TextureUsage::Data, CompressionQuality::Low) => TFPF::Bc1,
+            (TextureUsage::Data, _) => TFPF::Bc7,
+            (_, CompressionQuality::Lossless) => TFPF::Rgba8Srgb,
+            (_, CompressionQuality::Low) if has_alpha => TFPF::Bc3Srgb,
+            (_, CompressionQuality::Low) => TFPF::Bc1Srgb,
+            (_, CompressionQuality::Standard | CompressionQuality::HighDetail) => TFPF::Bc7Srgb,
+        })
+    }
+}
 
 pub struct TextureBuilder;
 impl AssetBuilder for TextureBuilder
@@ -63,57 +118,601 @@ impl AssetBuilder for TextureBuilder
 
     fn supported_input_file_extensions(&self) -> &'static [&'static str]
     {
-        // TODO: more input formats
-        &["png"]
+        &["png", "jpg", "jpeg", "tga", "hdr", "exr", "dds"]
     }
 
     fn builder_version(&self, vb: &mut VersionBuilder)
     {
         vb.push(b"Texture builder - initial");
         vb.push_prehashed(1);
+        vb.push(b"Texture builder - mips + BC compression");
+        vb.push(b"Texture builder - color grading LUTs");
+        vb.push(b"Texture builder - HDR usages");
     }
 
     fn build_assets(&self, config: Self::BuildConfig, input: &mut SourceInput, outputs: &mut BuildOutputs) -> Result<(), Box<dyn Error>>
     {
-        let reader = ImageReader::new(BufReader::new(input))
-        .with_guessed_format()?;
-        let image = reader.decode()?;
+        // DDS files are expected to be already processed, so are copied as-is
+        if input.file_extension() == &UniCase::new("dds")
+        {
+            return build_dds(input, outputs);
+        }
 
-        let format = match image.color()
+        let image_format = ImageFormat::from_extension(input.file_extension().as_str())
+            .ok_or(TextureBuilderError::UnsupportedInputFormat)?;
+        let image = ImageReader::with_format(BufReader::new(input), image_format).decode()?;
+
+        let is_hdr = matches!(image, DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_));
+        if matches!(config.usage, TextureUsage::ColorGradingLut)
         {
-            ColorType::Rgba8 => TextureFilePixelFormat::Rgba8,
-            // TODO: do as_image_rgba8?
-            _ => return Err(Box::new(TextureBuilderError::UnsupportedPixelFormat)),
-        };
+            if is_hdr
+            {
+                return Err(Box::new(TextureBuilderError::HdrUnsupportedByUsage));
+            }
+            build_color_grading_lut(&image.to_rgba8(), outputs)?;
+            return Ok(());
+        }
+
+        build_texture(image.to_rgba32f(), is_hdr, &config, None, outputs)?;
+
+        Ok(())
+    }
+}
+
+// Generate mips for and encode an RGBA image (in the color space of its usage) into a texture asset
+// Used by other builders to produce textures from embedded images
+pub fn build_texture(
+    texels: Rgba32FImage,
+    is_hdr: bool,
+    config: &TextureBuildConfig,
+    name: Option<&str>,
+    outputs: &mut BuildOutputs)
+    -> Result<AssetKey, Box<dyn Error>>
+{
+    let has_alpha = texels.pixels().any(|p| p.0[3] < 1.0);
+
+    let mut pixel_format = config.pixel_format(has_alpha, is_hdr).ok_or(TextureBuilderError::HdrUnsupportedByUsage)?;
+    if pixel_format.is_block_compressed() &&
+        (!texels.width().is_multiple_of(pixel_format.block_dimension()) || !texels.height().is_multiple_of(pixel_format.block_dimension()))
+    {
+        log::warn!("Texture is {}x{}, which is not a multiple of the block size; storing uncompressed", texels.width(), texels.height());
+        pixel_format = uncompressed_equivalent(pixel_format);
+    }
+
+    let mips = generate_mips(texels, &config.usage, is_hdr);
+    let mip_count = mips.len();
+
+    let encoded: Vec<Vec<u8>> = mips.iter()
+        .map(|mip| encode_mip(mip, pixel_format, &config.quality, has_alpha))
+        .collect();
+
+    let texture = outputs.add_output(AssetTypeId::Texture, |output|
+    {
+        if let Some(name) = name { output.set_name(name); }
 
-        outputs.add_output(AssetTypeId::Texture, |output|
+        output.serialize(&TextureFile
+        {
+            width: mips[0].width(),
+            height: mips[0].height(),
+            depth: 1,
+            mip_count: mip_count as u8,
+            mip_offsets: smallest_first_offsets(encoded.iter().map(Vec::len)),
+            pixel_format,
+        })?;
+
+        for mip in encoded.iter().rev()
+        {
+            output.write_all(mip)?;
+        }
+
+        Ok(())
+    })?;
+
+    Ok(texture)
+}
+
+// Generate the full mip chain (if the usage has mips) of an image, mips are generated (and returned) in linear space
+#[must_use]
+fn generate_mips(mut texels: Rgba32FImage, usage: &TextureUsage, is_hdr: bool) -> Vec<Rgba32FImage>
+{
+    if usage.is_srgb() && !is_hdr
+    {
+        for p in texels.pixels_mut()
         {
-            output.serialize(&TextureFile
+            for c in &mut p.0[0..3] { *c = srgb_to_linear(*c); }
+        }
+    }
+    let is_normal_map = matches!(usage, TextureUsage::NormalMap);
+
+    let mip_count = match usage.has_mips()
+    {
+        true => (texels.width().max(texels.height()).ilog2() + 1).min(MAX_MIP_COUNT as u32),
+        false => 1,
+    };
+    let mut mips = Vec::with_capacity(mip_count as usize);
+    mips.push(texels);
+    for _ in 1..mip_count
+    {
+        let prev = mips.last().unwrap();
+        let mut mip = image::imageops::resize(prev, (prev.width() / 2).max(1), (prev.height() / 2).max(1), FilterType::Triangle);
+        if is_normal_map
+        {
+            renormalize(&mut mip);
+        }
+        mips.push(mip);
+    }
+    mips
+}
+
+// Convert a LUT strip into a 3D texture, texels are copied as-is
+pub fn build_color_grading_lut(strip: &RgbaImage, outputs: &mut BuildOutputs) -> Result<AssetKey, Box<dyn Error>>
+{
+    let size = strip.height();
+    if size < 2 || strip.width() != size * size
+    {
+        return Err(Box::new(TextureBuilderError::UnsupportedLayout));
+    }
+
+    let texels = lut_strip_to_volume(strip);
+    let pixel_format = TextureFilePixelFormat::Rgba8;
+    let texture = outputs.add_output(AssetTypeId::Texture, |output|
+    {
+        output.serialize(&TextureFile
+        {
+            width: size,
+            height: size,
+            depth: size,
+            mip_count: 1,
+            mip_offsets: smallest_first_offsets(std::iter::once(texels.len())),
+            pixel_format,
+        })?;
+        output.write_all(&texels)?;
+        Ok(())
+    })?;
+
+    Ok(texture)
+}
+
+// Reorder a strip's texels into (blue) slices of rows of (red) texels
+#[must_use]
+fn lut_strip_to_volume(strip: &RgbaImage) -> Vec<u8>
+{
+    let size = strip.height();
+    let mut texels = Vec::with_capacity((size * size * size * 4) as usize);
+    for blue in 0..size
+    {
+        for green in 0..size
+        {
+            for red in 0..size
             {
-                width: image.width(),
-                height: image.height(),
-                depth: 1,
-                mip_count: 1,
-                mip_offsets: [0; _],
-                pixel_format: TextureFilePixelFormat::Rgba8,
-            })?;
+                texels.extend_from_slice(&strip.get_pixel(blue * size + red, green).0);
+            }
+        }
+    }
+    texels
+}
 
-            output.write_all(image.as_bytes())?;
+fn build_dds(input: &mut SourceInput, outputs: &mut BuildOutputs) -> Result<(), Box<dyn Error>>
+{
+    let dds = Dds::read(input)?;
+    let (pixel_format, mips) = dds_mips(&dds)?;
+    let (width, height, depth) = (dds.get_width(), dds.get_height(), dds.get_depth());
 
-            Ok(())
+    outputs.add_output(AssetTypeId::Texture, |output|
+    {
+        output.serialize(&TextureFile
+        {
+            width,
+            height,
+            depth,
+            mip_count: mips.len() as u8,
+            mip_offsets: smallest_first_offsets(mips.iter().map(|m| m.len())),
+            pixel_format,
         })?;
 
+        for mip in mips.iter().rev()
+        {
+            output.write_all(mip)?;
+        }
+
         Ok(())
+    })?;
+
+    Ok(())
+}
+
+// Slice a DDS's data into its mips (largest first)
+fn dds_mips(dds: &Dds) -> Result<(TextureFilePixelFormat, Vec<&[u8]>), TextureBuilderError>
+{
+    use TextureFilePixelFormat as TFPF;
+    let pixel_format = match dds.get_dxgi_format()
+    {
+        Some(DxgiFormat::R8_UNorm) => TFPF::R8,
+        Some(DxgiFormat::R8G8_UNorm) => TFPF::Rg8,
+        Some(DxgiFormat::R8G8B8A8_UNorm) => TFPF::Rgba8,
+        Some(DxgiFormat::R8G8B8A8_UNorm_sRGB) => TFPF::Rgba8Srgb,
+        Some(DxgiFormat::R16G16B16A16_Float) => TFPF::Rgba16Float,
+        Some(DxgiFormat::BC1_UNorm) => TFPF::Bc1,
+        Some(DxgiFormat::BC1_UNorm_sRGB) => TFPF::Bc1Srgb,
+        Some(DxgiFormat::BC3_UNorm) => TFPF::Bc3,
+        Some(DxgiFormat::BC3_UNorm_sRGB) => TFPF::Bc3Srgb,
+        Some(DxgiFormat::BC4_UNorm) => TFPF::Bc4,
+        Some(DxgiFormat::BC5_UNorm) => TFPF::Bc5,
+        Some(DxgiFormat::BC7_UNorm) => TFPF::Bc7,
+        Some(DxgiFormat::BC7_UNorm_sRGB) => TFPF::Bc7Srgb,
+        _ => return Err(TextureBuilderError::UnsupportedPixelFormat),
+    };
+    if dds.get_num_array_layers() > 1
+    {
+        return Err(TextureBuilderError::UnsupportedLayout); // todo: texture arrays/cubemaps
+    }
+
+    let (width, height, depth) = (dds.get_width(), dds.get_height(), dds.get_depth());
+    let mip_count = dds.get_num_mipmap_levels().clamp(1, MAX_MIP_COUNT as u32);
+
+    // DDS mips are stored largest first
+    let data = dds.get_data(0).map_err(|_| TextureBuilderError::TruncatedInput)?;
+    let mut mips = Vec::with_capacity(mip_count as usize);
+    let mut offset = 0;
+    for mip in 0..mip_count
+    {
+        let mip_bytes = pixel_format.mip_bytes((width >> mip).max(1), (height >> mip).max(1), (depth >> mip).max(1));
+        let mip_data = data.get(offset..(offset + mip_bytes)).ok_or(TextureBuilderError::TruncatedInput)?;
+        mips.push(mip_data);
+        offset += mip_bytes;
+    }
+
+    Ok((pixel_format, mips))
+}
+
+// Calculate the offsets of each mip (largest first) when stored smallest first
+#[must_use]
+fn smallest_first_offsets(mip_sizes: impl DoubleEndedIterator<Item = usize> + ExactSizeIterator) -> [usize; MAX_MIP_COUNT]
+{
+    let mut offsets = [0; MAX_MIP_COUNT];
+    let mut offset = 0;
+    for (mip, size) in mip_sizes.enumerate().rev()
+    {
+        offsets[mip] = offset;
+        offset += size;
     }
+    offsets
+}
+
+#[must_use]
+fn uncompressed_equivalent(pixel_format: TextureFilePixelFormat) -> TextureFilePixelFormat
+{
+    use TextureFilePixelFormat as TFPF;
+    match pixel_format
+    {
+        TFPF::Bc1 | TFPF::Bc3 | TFPF::Bc7 => TFPF::Rgba8,
+        TFPF::Bc1Srgb | TFPF::Bc3Srgb | TFPF::Bc7Srgb => TFPF::Rgba8Srgb,
+        TFPF::Bc4 => TFPF::R8,
+        TFPF::Bc5 => TFPF::Rg8,
+        other => other,
+    }
+}
+
+// Convert a mip (in linear space) to the output pixel format
+#[must_use]
+fn encode_mip(mip: &Rgba32FImage, pixel_format: TextureFilePixelFormat, quality: &CompressionQuality, has_alpha: bool) -> Vec<u8>
+{
+    use TextureFilePixelFormat as TFPF;
+
+    let is_srgb = matches!(pixel_format, TFPF::Rgba8Srgb | TFPF::Bc1Srgb | TFPF::Bc3Srgb | TFPF::Bc7Srgb);
+    let unorm = |c: f32| (c.clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+    let rgba8 = |mip: &Rgba32FImage| -> Vec<u8>
+    {
+        mip.pixels().flat_map(|p|
+        {
+            let [r, g, b, a] = p.0;
+            match is_srgb
+            {
+                true => [unorm(linear_to_srgb(r)), unorm(linear_to_srgb(g)), unorm(linear_to_srgb(b)), unorm(a)],
+                false => [unorm(r), unorm(g), unorm(b), unorm(a)],
+            }
+        }).collect()
+    };
+    let channels = |mip: &Rgba32FImage, count: usize| -> Vec<u8>
+    {
+        mip.pixels().flat_map(|p| p.0[0..count].iter().map(|c| unorm(*c)).collect::<Vec<_>>()).collect()
+    };
+
+    if !pixel_format.is_block_compressed()
+    {
+        return match pixel_format
+        {
+            TFPF::R8 => channels(mip, 1),
+            TFPF::Rg8 => channels(mip, 2),
+            TFPF::Rgba16Float => mip.pixels()
+                .flat_map(|p| p.0)
+                .flat_map(|c| half::f16::from_f32(c).to_le_bytes())
+                .collect(),
+            _ => rgba8(mip),
+        };
+    }
+
+    // block compression requires whole blocks, small mips are padded by repeating the edge texels
+    let block_dim = pixel_format.block_dimension();
+    let padded = pad_to_multiple(mip, block_dim);
+    let (width, height) = (padded.width(), padded.height());
+
+    match pixel_format
+    {
+        TFPF::Bc1 | TFPF::Bc1Srgb =>
+        {
+            let data = rgba8(&padded);
+            intel_tex_2::bc1::compress_blocks(&intel_tex_2::RgbaSurface { data: &data, width, height, stride: width * 4 })
+        }
+        TFPF::Bc3 | TFPF::Bc3Srgb =>
+        {
+            let data = rgba8(&padded);
+            intel_tex_2::bc3::compress_blocks(&intel_tex_2::RgbaSurface { data: &data, width, height, stride: width * 4 })
+        }
+        TFPF::Bc4 =>
+        {
+            let data = channels(&padded, 1);
+            intel_tex_2::bc4::compress_blocks(&intel_tex_2::RSurface { data: &data, width, height, stride: width })
+        }
+        TFPF::Bc5 =>
+        {
+            let data = channels(&padded, 2);
+            intel_tex_2::bc5::compress_blocks(&intel_tex_2::RgSurface { data: &data, width, height, stride: width * 2 })
+        }
+        TFPF::Bc7 | TFPF::Bc7Srgb =>
+        {
+            let settings = match (quality, has_alpha)
+            {
+                (CompressionQuality::HighDetail, false) => intel_tex_2::bc7::opaque_slow_settings(),
+                (CompressionQuality::HighDetail, true) => intel_tex_2::bc7::alpha_slow_settings(),
+                (_, false) => intel_tex_2::bc7::opaque_fast_settings(),
+                (_, true) => intel_tex_2::bc7::alpha_fast_settings(),
+            };
+            let data = rgba8(&padded);
+            intel_tex_2::bc7::compress_blocks(&settings, &intel_tex_2::RgbaSurface { data: &data, width, height, stride: width * 4 })
+        }
+        _ => unreachable!("{pixel_format:?} is not block compressed"),
+    }
+}
+
+#[must_use]
+fn pad_to_multiple(mip: &Rgba32FImage, multiple: u32) -> Rgba32FImage
+{
+    let width = mip.width().next_multiple_of(multiple);
+    let height = mip.height().next_multiple_of(multiple);
+    if width == mip.width() && height == mip.height()
+    {
+        return mip.clone();
+    }
+
+    Rgba32FImage::from_fn(width, height, |x, y|
+        *mip.get_pixel(x.min(mip.width() - 1), y.min(mip.height() - 1)))
+}
+
+// Normal maps store XY in [0, 1], re-normalize them after filtering
+fn renormalize(mip: &mut Rgba32FImage)
+{
+    for p in mip.pixels_mut()
+    {
+        let x = p.0[0] * 2.0 - 1.0;
+        let y = p.0[1] * 2.0 - 1.0;
+        let z = p.0[2] * 2.0 - 1.0;
+        let len = (x * x + y * y + z * z).sqrt();
+        if len > f32::EPSILON
+        {
+            p.0[0] = (x / len) * 0.5 + 0.5;
+            p.0[1] = (y / len) * 0.5 + 0.5;
+            p.0[2] = (z / len) * 0.5 + 0.5;
+        }
+    }
+}
+
+#[inline] #[must_use]
+fn srgb_to_linear(c: f32) -> f32
+{
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+#[inline] #[must_use]
+fn linear_to_srgb(c: f32) -> f32
+{
+    if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
 }
 
 #[derive(Debug)]
 enum TextureBuilderError
 {
+    UnsupportedInputFormat,
     UnsupportedPixelFormat,
+    UnsupportedLayout,
+    TruncatedInput,
+    HdrUnsupportedByUsage, // the usage is encoded in 0-1, so HDR sources would be clamped
 }
 impl Display for TextureBuilderError
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { Debug::fmt(self, f) }
 }
 impl Error for TextureBuilderError { }
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn lut_strip_layout()
+    {
+        // an identity LUT, with the blue slices laid out left to right
+        let size = 4;
+        let to_unorm = |i: u32| (i * 255 / (size - 1)) as u8;
+        let strip = RgbaImage::from_fn(size * size, size, |x, y| image::Rgba([to_unorm(x % size), to_unorm(y), to_unorm(x / size), 255]));
+
+        let texels = lut_strip_to_volume(&strip);
+        assert_eq!(texels.len(), (size * size * size * 4) as usize);
+        for (i, texel) in texels.chunks_exact(4).enumerate()
+        {
+            let i = i as u32;
+            let (red, green, blue) = (i % size, (i / size) % size, i / (size * size));
+            assert_eq!(texel, &[to_unorm(red), to_unorm(green), to_unorm(blue), 255]);
+        }
+    }
+
+    #[test]
+    fn pixel_formats()
+    {
+        use TextureFilePixelFormat as TFPF;
+        let format = |usage, quality, has_alpha, is_hdr| TextureBuildConfig { quality, usage }.pixel_format(has_alpha, is_hdr).unwrap();
+
+        assert_eq!(format(TextureUsage::Model, CompressionQuality::Standard, false, false), TFPF::Bc7Srgb);
+        assert_eq!(format(TextureUsage::Model, CompressionQuality::Low, false, false), TFPF::Bc1Srgb);
+        assert_eq!(format(TextureUsage::Model, CompressionQuality::Low, true, false), TFPF::Bc3Srgb);
+        assert_eq!(format(TextureUsage::Model, CompressionQuality::Lossless, true, false), TFPF::Rgba8Srgb);
+        assert_eq!(format(TextureUsage::Background, CompressionQuality::Standard, false, true), TFPF::Rgba16Float);
+        assert_eq!(format(TextureUsage::NormalMap, CompressionQuality::Standard, false, false), TFPF::Bc5);
+        assert_eq!(format(TextureUsage::NormalMap, CompressionQuality::Lossless, false, false), TFPF::Rg8);
+        assert_eq!(format(TextureUsage::Detail, CompressionQuality::HighDetail, false, false), TFPF::Bc4);
+        assert_eq!(format(TextureUsage::Detail, CompressionQuality::Lossless, false, false), TFPF::R8);
+        assert_eq!(format(TextureUsage::Data, CompressionQuality::Standard, true, false), TFPF::Bc7);
+        assert_eq!(format(TextureUsage::Data, CompressionQuality::Low, true, false), TFPF::Bc3);
+        assert_eq!(format(TextureUsage::Data, CompressionQuality::Lossless, false, false), TFPF::Rgba8);
+        assert_eq!(format(TextureUsage::UI, CompressionQuality::Low, true, false), TFPF::Rgba8Srgb);
+        assert_eq!(format(TextureUsage::Text, CompressionQuality::Standard, false, false), TFPF::R8);
+        assert_eq!(format(TextureUsage::ColorGradingLut, CompressionQuality::Standard, false, false), TFPF::Rgba8);
+
+        // HDR sources keep their range, or fail to build if the usage can't store it
+        assert_eq!(format(TextureUsage::Data, CompressionQuality::Standard, false, true), TFPF::Rgba16Float);
+        assert_eq!(format(TextureUsage::UI, CompressionQuality::Lossless, true, true), TFPF::Rgba16Float);
+        for usage in [TextureUsage::NormalMap, TextureUsage::Detail, TextureUsage::Text, TextureUsage::ColorGradingLut]
+        {
+            assert!(TextureBuildConfig { quality: CompressionQuality::Standard, usage }.pixel_format(false, true).is_none());
+        }
+
+        // linear data and color textures never share a format
+        for quality in [CompressionQuality::Low, CompressionQuality::Standard, CompressionQuality::HighDetail, CompressionQuality::Lossless]
+        {
+            let data = format(TextureUsage::Data, quality, false, false);
+            let color = format(TextureUsage::Model, quality, false, false);
+            assert_ne!(data, color);
+            assert_eq!(uncompressed_equivalent(data), TFPF::Rgba8);
+            assert_eq!(uncompressed_equivalent(color), TFPF::Rgba8Srgb);
+        }
+    }
+
+    #[test]
+    fn srgb_aware_mips()
+    {
+        // a half-gray (in sRGB) and a black texel
+        let texels = Rgba32FImage::from_fn(2, 1, |x, _| image::Rgba(if x == 0 { [0.5, 0.5, 0.5, 1.0] } else { [0.0, 0.0, 0.0, 1.0] }));
+
+        // color textures are averaged in linear space
+        let color_mips = generate_mips(texels.clone(), &TextureUsage::Model, false);
+        assert_eq!(color_mips.len(), 2);
+        assert_eq!((color_mips[1].width(), color_mips[1].height()), (1, 1));
+        let expected = srgb_to_linear(0.5) / 2.0;
+        assert!((color_mips[1].get_pixel(0, 0).0[0] - expected).abs() < 1e-4, "{:?}", color_mips[1].get_pixel(0, 0));
+        assert!((color_mips[0].get_pixel(0, 0).0[0] - srgb_to_linear(0.5)).abs() < 1e-6);
+        assert_eq!(color_mips[1].get_pixel(0, 0).0[3], 1.0, "Alpha is always linear");
+
+        // data is averaged as-is
+        let data_mips = generate_mips(texels.clone(), &TextureUsage::Data, false);
+        assert!((data_mips[1].get_pixel(0, 0).0[0] - 0.25).abs() < 1e-4);
+
+        // HDR textures are already linear
+        let hdr_mips = generate_mips(texels.clone(), &TextureUsage::Model, true);
+        assert!((hdr_mips[1].get_pixel(0, 0).0[0] - 0.25).abs() < 1e-4);
+
+        // textures drawn 1:1 have no mips
+        assert_eq!(generate_mips(texels, &TextureUsage::UI, false).len(), 1);
+
+        // normal maps stay unit length
+        let normals = Rgba32FImage::from_fn(4, 4, |x, _| image::Rgba(if x < 2 { [1.0, 0.5, 0.5, 1.0] } else { [0.5, 1.0, 0.5, 1.0] }));
+        let normal_mips = generate_mips(normals, &TextureUsage::NormalMap, false);
+        assert_eq!(normal_mips.len(), 3);
+        for p in normal_mips.iter().flat_map(|mip| mip.pixels())
+        {
+            let n = glam::Vec3::new(p.0[0], p.0[1], p.0[2]) * 2.0 - 1.0;
+            assert!((n.length() - 1.0).abs() < 1e-4, "{n}");
+        }
+    }
+
+    #[test]
+    fn mip_offsets()
+    {
+        // largest mip first, stored smallest first
+        let offsets = smallest_first_offsets([64, 16, 4].into_iter());
+        assert_eq!(&offsets[0..3], &[20, 4, 0]);
+        assert!(offsets[3..].iter().all(|o| *o == 0));
+
+        assert_eq!(smallest_first_offsets(std::iter::once(100))[0], 0);
+    }
+
+    #[must_use]
+    fn test_dds(format: DxgiFormat, size: u32, mip_count: u32, array_layers: Option<u32>) -> Dds
+    {
+        let mut dds = Dds::new_dxgi(ddsfile::NewDxgiParams
+        {
+            height: size,
+            width: size,
+            depth: None,
+            format,
+            mipmap_levels: Some(mip_count),
+            array_layers,
+            caps2: None,
+            is_cubemap: false,
+            resource_dimension: ddsfile::D3D10ResourceDimension::Texture2D,
+            alpha_mode: ddsfile::AlphaMode::Unknown,
+        }).unwrap();
+        for (i, b) in dds.data.iter_mut().enumerate() { *b = i as u8; }
+        dds
+    }
+
+    #[test]
+    fn dds_slicing()
+    {
+        // 8x8, 4x4, 2x2 and 1x1 mips, the last two are padded to a whole block
+        let dds = test_dds(DxgiFormat::BC1_UNorm_sRGB, 8, 4, None);
+        let (pixel_format, mips) = dds_mips(&dds).unwrap();
+        assert_eq!(pixel_format, TextureFilePixelFormat::Bc1Srgb);
+        assert_eq!(mips.iter().map(|m| m.len()).collect::<Vec<_>>(), vec![32, 8, 8, 8]);
+        assert_eq!(mips[0][0], 0);
+        assert_eq!(mips[1][0], 32);
+        assert_eq!(mips[3][7], 55);
+
+        let dds = test_dds(DxgiFormat::R8G8B8A8_UNorm, 4, 3, None);
+        let (_, mips) = dds_mips(&dds).unwrap();
+        assert_eq!(mips.iter().map(|m| m.len()).collect::<Vec<_>>(), vec![64, 16, 4]);
+
+        // missing data
+        let mut truncated = test_dds(DxgiFormat::BC7_UNorm, 8, 2, None);
+        truncated.data.truncate(truncated.data.len() - 1);
+        assert!(matches!(dds_mips(&truncated), Err(TextureBuilderError::TruncatedInput)));
+
+        // unsupported
+        assert!(matches!(dds_mips(&test_dds(DxgiFormat::BC7_UNorm, 8, 1, Some(2))), Err(TextureBuilderError::UnsupportedLayout)));
+        assert!(matches!(dds_mips(&test_dds(DxgiFormat::BC6H_UF16, 8, 1, None)), Err(TextureBuilderError::UnsupportedPixelFormat)));
+    }
+
+    #[test]
+    fn block_padding()
+    {
+        let mip = Rgba32FImage::from_fn(2, 3, |x, y| image::Rgba([x as f32, y as f32, 0.0, 1.0]));
+        let padded = pad_to_multiple(&mip, 4);
+        assert_eq!((padded.width(), padded.height()), (4, 4));
+        // edge texels are repeated
+        assert_eq!(padded.get_pixel(3, 3), mip.get_pixel(1, 2));
+        assert_eq!(padded.get_pixel(0, 3), mip.get_pixel(0, 2));
+        assert_eq!(padded.get_pixel(3, 0), mip.get_pixel(1, 0));
+        assert_eq!(pad_to_multiple(&padded, 4), padded);
+
+        // partial blocks are encoded as whole blocks
+        for pixel_format in [TextureFilePixelFormat::Bc1Srgb, TextureFilePixelFormat::Bc4, TextureFilePixelFormat::Bc5, TextureFilePixelFormat::Bc7]
+        {
+            for size in [1, 2, 6]
+            {
+                let mip = Rgba32FImage::from_pixel(size, size, image::Rgba([0.5, 0.25, 1.0, 1.0]));
+                let encoded = encode_mip(&mip, pixel_format, &CompressionQuality::Standard, false);
+                assert_eq!(encoded.len(), pixel_format.mip_bytes(size, size, 1), "{pixel_format:?} {size}x{size}");
+            }
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use super::*;
 use asset_3l14::{Asset, AssetFileType, AssetKey, AssetKeyDerivedId, AssetKeySourceId, AssetKeySynthHash, AssetMetadata, AssetTypeId, VersionHash, SourceMetadata, TomlRead, TomlWrite};
-use bitcode::Encode;
+use bitcode::{DecodeOwned, Encode};
 use clap::ValueEnum;
 use metrohash::MetroHash64;
 use nab_3l14::utils::inline_hash::InlineWriteHash;
@@ -11,6 +11,7 @@ use std::error::Error;
 use std::ffi::OsStr;
 use std::fmt::{Debug, Display, Formatter};
 use std::fs::File;
+use std::hash::Hasher;
 use std::io;
 use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
 use std::mem::MaybeUninit;
@@ -18,6 +19,7 @@ use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 use dashmap::DashMap;
+use parking_lot::Mutex;
 use unicase::UniCase;
 use walkdir::WalkDir;
 use nab_3l14::Symbol;
@@ -110,7 +112,8 @@ pub struct AssetsBuilder
 {
     config: AssetsBuilderConfig,
     // TODO: use Path -- and make case insensitive?
-    sources: DashMap<String, AssetKeySourceId>, // paths relative to assets root, only tracks sources which have been referenced this run
+    sources: DashMap<String, AssetKeySourceId>, // paths relative to sources root, only tracks sources which have been referenced this run
+    building: Mutex<Vec<AssetKeySourceId>>, // sources currently being built, builds can nest by reading inputs from other sources
 }
 impl AssetsBuilder
 {
@@ -125,6 +128,7 @@ impl AssetsBuilder
         {
             config,
             sources: DashMap::new(),
+            building: Mutex::new(Vec::new()),
         }
     }
 
@@ -176,6 +180,8 @@ impl AssetsBuilder
                 {
                     source_id: meta.source_id,
                     version_hash: builder.version_hash,
+                    build_inputs: Vec::new(),
+                    build_inputs_hash: None,
                     build_config: builder.builder.default_config(),
                     notes: None,
                 }
@@ -187,6 +193,8 @@ impl AssetsBuilder
                 {
                     source_id,
                     version_hash: builder.version_hash,
+                    build_inputs: Vec::new(),
+                    build_inputs_hash: None,
                     build_config: builder.builder.default_config(),
                     notes: None,
                 }
@@ -247,6 +255,8 @@ impl AssetsBuilder
                 {
                     source_id,
                     version_hash: builder.version_hash,
+                    build_inputs: Vec::new(),
+                    build_inputs_hash: None,
                     build_config: builder.builder.default_config(),
                     notes: None,
                 };
@@ -268,6 +278,10 @@ impl AssetsBuilder
             }
         };
 
+        // reading inputs builds their sources, which must not (eventually) read from this one
+        let _building = BuildingSource::begin(&self.building, source_meta.source_id)
+            .ok_or_else(|| BuildError::CyclicBuildInputs(rel_path.to_path_buf()))?;
+
         let mut source_read =
         {
             let fin = File::open(&canonical_path).map_err(BuildError::SourceIOError)?;
@@ -280,7 +294,8 @@ impl AssetsBuilder
             // TODO: this should actually get the min(built derived asset file times) and diff both src and meta time against it
             if let BuildRule::OnlyIfChanged = build_rule &&
                 source_meta.version_hash == builder.version_hash &&
-                src_modtime <= meta_modtime
+                src_modtime <= meta_modtime &&
+                self.inputs_unchanged(&source_meta)
             {
                 log::debug!("Skipped (up-to-date) {:?} ({:?})", source_path.as_ref(), source_meta.source_id);
                 return Ok(BuildResults::default());
@@ -305,18 +320,39 @@ impl AssetsBuilder
             timestamp: build_time,
             rel_source_path: rel_path,
             abs_output_dir: self.config.assets_root.as_path(),
+            builder_version_hash: builder.version_hash,
             version_hash: builder.version_hash,
+            inputs: Vec::new(),
+            inputs_hash: 0,
             derived_ids: HashMap::new(),
             results: HashSet::new(),
         };
 
-        match builder.builder.build_assets(source_meta.build_config, &mut input, &mut outputs)
+        match builder.builder.build_assets(source_meta.build_config.clone(), &mut input, &mut outputs)
         {
             Ok(_) =>
             {
                 // todo: hash can be used for versioning/uniquifying built asssets
                 let _input_hash = source_read.finish();
 
+                // record the inputs so that this source is rebuilt when they change
+                let build_inputs_hash = (!outputs.inputs.is_empty()).then_some(VersionHash(outputs.inputs_hash));
+                if outputs.inputs != source_meta.build_inputs || build_inputs_hash != source_meta.build_inputs_hash
+                {
+                    let source_meta = SourceMetadata
+                    {
+                        build_inputs: std::mem::take(&mut outputs.inputs),
+                        build_inputs_hash,
+                        ..source_meta
+                    };
+                    if let Err(err) = File::create(&source_meta_file_path)
+                        .map_err(|err| Box::new(err) as Box<dyn Error>)
+                        .and_then(|mut fout| source_meta.save(true, &mut fout))
+                    {
+                        log::error!("Failed to record the build inputs in {source_meta_file_path:?}: {err}");
+                    }
+                }
+
                 // bump the modtime for up-to-date tracking
                 match File::options().write(true).open(&source_meta_file_path)
                 {
@@ -336,6 +372,61 @@ impl AssetsBuilder
         }
     }
 
+    // Find a source file by its ID (relative to the sources root), found sources are remembered for the rest of the run
+    fn find_source(&self, source_id: AssetKeySourceId) -> Option<PathBuf>
+    {
+        if let Some(entry) = self.sources.iter().find(|entry| *entry.value() == source_id)
+        {
+            return Some(PathBuf::from(entry.key()));
+        }
+
+        for (source_path, id) in self.scan_sources().flatten()
+        {
+            let Ok(rel_path) = source_path.strip_prefix(&self.config.sources_root) else { continue };
+            self.sources.insert(rel_path.to_string_lossy().to_string(), id);
+            if id == source_id
+            {
+                return Some(rel_path.to_path_buf());
+            }
+        }
+        None
+    }
+
+    // Read the built data of an asset from another source, building the source first if it is out of date
+    fn read_input(&self, asset_key: AssetKey) -> Result<Vec<u8>, BuildError>
+    {
+        // synthetic assets have no source of their own
+        if !asset_key.is_synthetic()
+        {
+            let source_path = self.find_source(asset_key.source_id()).ok_or(BuildError::NoSourceForInput(asset_key))?;
+            self.build_source(&source_path, BuildRule::OnlyIfChanged)
+                .map_err(|err| BuildError::InputBuildError(asset_key, Box::new(err)))?;
+        }
+
+        std::fs::read(self.config.assets_root.join(asset_key.as_file_name(AssetFileType::Asset)))
+            .map_err(BuildError::InputIOError)
+    }
+
+    // Are the inputs of a source's last build unchanged, their sources are built first if they are out of date
+    fn inputs_unchanged(&self, source_meta: &SourceMetadata) -> bool
+    {
+        if source_meta.build_inputs.is_empty()
+        {
+            return true;
+        }
+
+        let mut inputs_hash = 0;
+        for input in &source_meta.build_inputs
+        {
+            match self.read_input(*input)
+            {
+                Ok(data) => inputs_hash = hash_input(inputs_hash, &data),
+                Err(_) => return false, // rebuilding will report the error
+            }
+        }
+        source_meta.build_inputs_hash == Some(VersionHash(inputs_hash))
+    }
+
     // Build all (known) assets. Files without an accompanying .sork are skipped
     pub fn build_all(&self, build_rule: BuildRule) -> Result<(), ()> // TODO
     {
@@ -377,6 +468,10 @@ pub enum BuildError
     AssetMetaIOError(io::Error),
     AssetMetaError(Box<dyn Error>),
     TooManyDerivedIDs,
+    CyclicBuildInputs(PathBuf), // the source (eventually) reads an input from itself
+    NoSourceForInput(AssetKey),
+    InputBuildError(AssetKey, Box<BuildError>),
+    InputIOError(io::Error),
     BuilderError(Box<dyn Error>),
     OutputMetaError(Box<dyn Error>),
     OutputIOError(io::Error),
@@ -390,6 +485,44 @@ impl Error for BuildError { }
 
 pub type BuildResults = HashSet<AssetKey>; // TODO: IndexSet
 
+// Marks a source as being built until dropped
+struct BuildingSource<'b>
+{
+    building: &'b Mutex<Vec<AssetKeySourceId>>,
+    source_id: AssetKeySourceId,
+}
+impl<'b> BuildingSource<'b>
+{
+    // Returns None if the source is already being built
+    #[must_use]
+    fn begin(building: &'b Mutex<Vec<AssetKeySourceId>>, source_id: AssetKeySourceId) -> Option<Self>
+    {
+        let mut sources = building.lock();
+        if sources.contains(&source_id)
+        {
+            return None;
+        }
+        sources.push(source_id);
+        Some(Self { building, source_id })
+    }
+}
+impl Drop for BuildingSource<'_>
+{
+    fn drop(&mut self)
+    {
+        self.building.lock().retain(|id| *id != self.source_id);
+    }
+}
+
+// Chain the built data of an input onto the hash of the inputs before it
+#[inline] #[must_use]
+fn hash_input(inputs_hash: u64, data: &[u8]) -> u64
+{
+    let mut hasher = MetroHash64::with_seed(inputs_hash);
+    hasher.write(data);
+    hasher.finish()
+}
+
 struct Lazy<T, F: FnOnce() -> T>
 {
     value: UnsafeCell<Option<T>>,
@@ -530,7 +663,10 @@ pub struct BuildOutputs<'b>
     rel_source_path: &'b Path,
     abs_output_dir: &'b Path,
 
-    version_hash: VersionHash,
+    builder_version_hash: VersionHash,
+    version_hash: VersionHash, // the builder's version, combined with the inputs read so far
+    inputs: Vec<AssetKey>,
+    inputs_hash: u64,
     derived_ids: HashMap<AssetTypeId, AssetKeyDerivedId>,
 
     results: BuildResults,
@@ -548,6 +684,21 @@ impl<'b> BuildOutputs<'b>
         todo!()
     }
 
+    // Read the leading serialized value of an asset from another source, building the source first if it is out of date
+    // Inputs are hashed into the version of outputs added after them (so read them first), and the source is rebuilt when they change
+    pub fn read_input<T: DecodeOwned>(&mut self, asset_key: AssetKey) -> Result<T, Box<dyn Error>>
+    {
+        let data = self.assets_builder.read_input(asset_key)?;
+        self.inputs_hash = hash_input(self.inputs_hash, &data);
+        self.inputs.push(asset_key);
+        self.version_hash = VersionHash(hash_input(self.builder_version_hash.0, &self.inputs_hash.to_le_bytes()));
+
+        let mut reader = data.as_slice();
+        let size = varint::decode_from(&mut reader)?;
+        let bytes = reader.get(..size as usize).ok_or_else(|| io::Error::from(ErrorKind::UnexpectedEof))?;
+        Ok(bitcode::decode::<T>(bytes)?)
+    }
+
     // Produce an output from this build. Assets of the same type have sequential derived IDs
     #[inline]
     pub fn add_output(
@@ -101,11 +101,13 @@ fn main()
     let symbols_dict = Arc::new(SymbolsDict::new(assets_root.join("symbols")));
 
     let mut builder_cfg = AssetsBuilderConfig::new(&src_assets_root, &built_assets_root);
-    builder_cfg.add_builder(builders::ModelBuilder);
+    builder_cfg.add_builder(builders::ModelBuilder::new(symbols_dict.clone()));
     builder_cfg.add_builder(builders::TextureBuilder);
     builder_cfg.add_builder(builders::MaterialBuilder);
-    builder_cfg.add_builder(builders::ShaderBuilder::new(src_assets_root.join("shaders"), None).unwrap());
+    builder_cfg.add_builder(builders::ShaderBuilder::new(src_assets_root.join("shaders"), None));
     builder_cfg.add_builder(builders::MapBuilder);
+    builder_cfg.add_builder(builders::EntityDefBuilder::default());
+    builder_cfg.add_builder(builders::AnimGraphBuilder);
     builder_cfg.add_builder(builders::CircuitBuilder::new(symbols_dict));
     let builder = AssetsBuilder::new(builder_cfg);
 
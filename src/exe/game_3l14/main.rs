@@ -1,15 +1,22 @@
-use asset_3l14::{Asset, AssetKey, AssetLifecyclers, AssetData, Assets, AssetsConfig, AssetSnapshot};
+use asset_3l14::{Ash, Asset, AssetKey, AssetLifecyclers, AssetData, Assets, AssetsConfig, AssetSnapshot};
 use clap::Parser;
+use containers_3l14::AabbTree;
 use debug_3l14::debug_gui;
 use debug_3l14::debug_menu::{DebugMenu, DebugMenuMemory};
 use debug_3l14::sparkline::Sparkline;
 use egui::Widget;
 use glam::{Mat4, Quat, Vec3};
-use graphics_3l14::assets::{GeometryLifecycler, MaterialLifecycler, Model, ModelLifecycler, ShaderLifecycler, SkeletalAnimation, SkeletalAnimationLifecycler, SkeletonLifecycler, TextureLifecycler};
+use graphics_3l14::anim_graph_instance::AnimGraphInstance;
+use graphics_3l14::assets::{AnimGraph, AnimGraphLifecycler, GeometryLifecycler, MaterialLifecycler, Model, ModelLifecycler, ShaderLifecycler, SkeletalAnimationLifecycler, SkeletonLifecycler, Texture, TextureLifecycler};
 use graphics_3l14::camera::{Camera, CameraProjection};
+use graphics_3l14::culling::VisibleSet;
 use graphics_3l14::debug_draw::DebugDraw;
+use graphics_3l14::passes::light_cull::LightCullPass;
+use graphics_3l14::passes::post::{PostProcessPass, PostSettings};
+use graphics_3l14::passes::shadow::{CascadeSettings, ShadowPass};
 use graphics_3l14::pipeline_cache::{DebugMode, PipelineCache};
-use graphics_3l14::skeleton_poser::{PoseBlendMode, SkeletonPoser};
+use graphics_3l14::render_passes::FramePasses;
+use graphics_3l14::skeleton_poser::SkeletonPoser;
 use graphics_3l14::view::View;
 use graphics_3l14::windows::Windows;
 use graphics_3l14::{colors, render_passes, renderer, Renderer, Rgba};
@@ -18,7 +25,7 @@ use math_3l14::{Angle, Frustum, Transform};
 use nab_3l14::app::{AppFolder, AppRun, ExitReason};
 use nab_3l14::timing::Clock;
 use nab_3l14::utils::array::init_array;
-use nab_3l14::{app, TickCount};
+use nab_3l14::app;
 use nab_3l14::{CompletionState, RenderFrameNumber, ToggleState};
 use sdl2::event::{Event as SdlEvent, WindowEvent as SdlWindowEvent};
 use sdl2::messagebox::MessageBoxFlag;
@@ -27,6 +34,10 @@ use std::ops::Deref;
 use std::time::Duration;
 use wgpu::CommandEncoderDescriptor;
 use latch_3l14::{Circuit, CircuitLifecycler, Runtime};
+use world_3l14::assets::entity_def::EntityDefLifecycler;
+use world_3l14::assets::map::MapLifecycler;
+use world_3l14::assets::map_chunk::MapChunkLifecycler;
+use world_3l14::Light;
 
 #[derive(Debug, Parser)]
 struct CliArgs
@@ -117,7 +128,11 @@ fn main() -> ExitReason
             .add_lifecycler(GeometryLifecycler::new(renderer.clone()))
             .add_lifecycler(SkeletonLifecycler::default())
             .add_lifecycler(SkeletalAnimationLifecycler)
+            .add_lifecycler(AnimGraphLifecycler)
             .add_lifecycler(CircuitLifecycler::default())
+            .add_lifecycler(MapLifecycler)
+            .add_lifecycler(MapChunkLifecycler)
+            .add_lifecycler(EntityDefLifecycler::default())
         , assets_config);
 
     {
@@ -134,8 +149,7 @@ fn main() -> ExitReason
         // let min_frame_time = Duration::from_secs_f32(1.0 / 150.0); // todo: this should be based on display refresh-rate
 
         let model_key = AssetKey::from(0x00a00000e3fb55e4);
-        let base_anim_key = AssetKey::from(0x00c00000e3fb55e4);
-        let overlay_anim_key = AssetKey::from(0x00c00010e3fb55e4);
+        let anim_graph_key = AssetKey::from(0x01100000d5c1a7e3);
 
         let plane_model_key = AssetKey::from(0x00a000005cc338e8);
 
@@ -144,11 +158,16 @@ fn main() -> ExitReason
         let mut latch_rt = Runtime::new();
 
         let test_model = assets.load::<Model>(model_key);
-        let test_base_anim = assets.load::<SkeletalAnimation>(base_anim_key);
-        let test_overlay_anim = assets.load::<SkeletalAnimation>(overlay_anim_key);
+        let test_anim_graph = assets.load::<AnimGraph>(anim_graph_key);
 
         let plane_model = assets.load::<Model>(plane_model_key);
 
+        // the static models (and their world transforms), culled through a hierarchy of their bounds
+        let static_models = [(plane_model, Mat4::from_rotation_x(-std::f32::consts::FRAC_PI_2))];
+        // (the model and geometry generations it was built from, hierarchy)
+        let mut statics_tree: Option<(Box<[u32]>, AabbTree)> = None;
+        let mut visible_statics = VisibleSet::new();
+
         let mut camera = Camera::default();
         camera.update_projection(CameraProjection::Perspective
         {
@@ -164,6 +183,27 @@ fn main() -> ExitReason
         camera.update_view(cam_transform.clone());
 
         let pipeline_cache = PipelineCache::new(renderer.clone(), assets.clone());
+        let mut shadow_pass = ShadowPass::new(renderer.clone(), pipeline_cache.shadows_bind_layout(), CascadeSettings::default());
+        let sun = Light::Directional(Vec3::new(-0.3, -1.0, 0.4).normalize());
+        let mut light_cull_pass = LightCullPass::new(renderer.clone(), pipeline_cache.lights_bind_layout());
+        let mut post_pass = PostProcessPass::new(renderer.clone(), &assets, PostSettings::default());
+        // a warm color grade, bound once it has (re)loaded
+        let color_grading_lut = assets.load::<Texture>(AssetKey::from(0x00600000c4a1d7b2));
+        let mut color_grading_lut_generation = None;
+        let lights =
+        [
+            Light::Point { position: Vec3::new(0.0, 5.0, -5.0), color: colors::WHITE, intensity: 20.0, range: 15.0 },
+            Light::Spot
+            {
+                position: Vec3::new(4.0, 6.0, 0.0),
+                direction: Vec3::new(-0.5, -1.0, 0.0).normalize(),
+                angle: Angle::from_degrees(30.0),
+                color: colors::ORANGE,
+                intensity: 40.0,
+                range: 12.0,
+            },
+        ];
+        let cluster_lights: Vec<_> = lights.iter().filter_map(Light::cluster_light).collect();
         // ꙮ
 
         let mut obj_rot = Quat::IDENTITY;
@@ -175,6 +215,8 @@ fn main() -> ExitReason
         let mut clip_camera = None;
 
         let mut test_f32 = 0.0;
+        // (the skeleton, graph, and animation generations it was created from, instance)
+        let mut test_anim_graph_instance: Option<(Box<[u32]>, AnimGraphInstance)> = None;
 
         let mut app_frame_number = RenderFrameNumber(0);
         let mut fps_sparkline = Sparkline::<100>::new(); // todo: use
@@ -331,7 +373,7 @@ fn main() -> ExitReason
 
                 if let Some(cam) = &clip_camera
                 {
-                    for corner in Frustum::get_corners(&cam.projection().to_matrix(0.1, 50.0))
+                    for corner in Frustum::get_corners(&cam.matrix())
                     {
                         debug_draw.draw_cross3(Mat4::from_translation(corner), colors::RED);
                     }
@@ -350,13 +392,11 @@ fn main() -> ExitReason
                 let mut encoder = renderer.device().create_command_encoder(&CommandEncoderDescriptor::default());
                 {
                     {
-                        let mut scene_pass = render_passes::scene(
-                            &render_frame,
-                            &mut encoder,
-                            Some(colors::DARK_GRAY));
+                        shadow_pass.update(&camera, sun.shadow_direction());
+                        light_cull_pass.update(&camera, &cluster_lights);
 
                         let view = &mut views[app_frame_number.0 as usize % views.len()];
-                        view.begin(frame_time.total_runtime, &camera, clip_camera.as_ref().unwrap_or(&camera), DebugMode::None);
+                        view.begin(frame_time.total_runtime, &camera, clip_camera.as_ref().unwrap_or(&camera), shadow_pass.cascades(), DebugMode::None);
                         //
                         // debug_draw.draw_solid_cube(Mat4::IDENTITY, colors::RED);
                         // debug_draw.draw_wire_cube(Mat4::IDENTITY, colors::YELLOW);
@@ -376,9 +416,31 @@ fn main() -> ExitReason
                         debug_draw.draw_wire_cube(obj_world, colors::WHITE);
                         obj_world *= Mat4::from_scale(Vec3::splat(0.1));
 
-                        if let AssetSnapshot::Available(model) = plane_model.data()
+                        if static_models.iter().all(|(model, _)| model.is_loaded_recursive())
                         {
-                            view.draw_model_static(model, Mat4::from_rotation_x(-std::f32::consts::FRAC_PI_2));
+                            puffin::profile_scope!("Draw statics");
+
+                            let statics: Box<[_]> = static_models.iter().map(|(model, world)| (model.data().unwrap(), *world)).collect();
+                            let generations: Box<[u32]> = static_models.iter().zip(&statics)
+                                .flat_map(|((model, _), (loaded, _))| [model.generation(), loaded.geometry.generation()])
+                                .collect();
+                            if statics_tree.as_ref().is_none_or(|(g, _)| *g != generations)
+                            {
+                                let mut tree = AabbTree::new();
+                                for (i, (model, world)) in statics.iter().enumerate()
+                                {
+                                    tree.insert(model.geometry.data().unwrap().bounds_aabb.transformed(world), i as u32);
+                                }
+                                statics_tree = Some((generations, tree));
+                            }
+                            let (_, tree) = statics_tree.as_ref().unwrap();
+
+                            // casters are drawn separately, as they may be outside the view
+                            let get_model = |i: u32| statics.get(i as usize).cloned();
+                            visible_statics.clear();
+                            visible_statics.cull(tree, &Frustum::from_matrix(&clip_camera.as_ref().unwrap_or(&camera).matrix()), None);
+                            view.draw_visible(&visible_statics, get_model);
+                            view.draw_shadow_casters(tree, get_model);
                         }
 
                         if let AssetSnapshot::Available(model) = test_model.data()
@@ -397,21 +459,28 @@ fn main() -> ExitReason
 
                                 let mut poser = SkeletonPoser::new(&skel);
 
-                                let time = frame_time.total_runtime.as_nanos() as u64;
-                                // time = Ratio::new(3, 10).scale(time);
-                                if let AssetSnapshot::Available(anim) = test_base_anim.data()
-                                {
-                                    poser.blend(&anim, PoseBlendMode::Replace, TickCount(time), true);
-                                }
-                                if let AssetSnapshot::Available(anim) = test_overlay_anim.data()
+                                if let AssetSnapshot::Available(graph) = test_anim_graph.data()
+                                    && let Some(animations) = graph.loaded_animations()
                                 {
+                                    // the instance caches bone remaps and parameters, so is recreated if anything it uses is reloaded
+                                    let generations: Box<[u32]> = [skel_handle.generation(), test_anim_graph.generation()].into_iter()
+                                        .chain(graph.animations.iter().map(Ash::generation))
+                                        .collect();
+                                    if test_anim_graph_instance.as_ref().is_none_or(|(g, _)| *g != generations)
+                                    {
+                                        test_anim_graph_instance = Some((generations, AnimGraphInstance::new(&graph.def)));
+                                    }
+                                    let (_, instance) = test_anim_graph_instance.as_mut().unwrap();
+
                                     egui::Window::new("anim")
                                         .show(renderer.debug_gui(), |ui|
                                             {
                                                 egui::Slider::new(&mut test_f32, 0.0..=1.0)
                                                     .ui(ui);
                                             });
-                                    poser.blend(&anim, PoseBlendMode::Additive(test_f32), TickCount(time), true);
+                                    instance.set_parameter_named(&graph.def, "overlay", test_f32);
+                                    instance.update(&graph.def, &animations, frame_time.delta_time.as_secs_f32());
+                                    instance.apply(&graph.def, &animations, &mut poser);
                                 }
 
                                 let posed_skel = poser.build_poses();
@@ -460,12 +529,25 @@ fn main() -> ExitReason
                             }
                         }
 
-                        view.submit(&mut scene_pass);
-                    }
+                        if color_grading_lut_generation != Some(color_grading_lut.generation()) &&
+                            let AssetSnapshot::Available(lut) = color_grading_lut.data()
+                        {
+                            post_pass.set_color_grading_lut(Some(lut));
+                            color_grading_lut_generation = Some(color_grading_lut.generation());
+                        }
 
-                    {
-                        let mut debug_pass = render_passes::debug(&render_frame, &mut encoder);
-                        debug_draw.submit(renderer.queue(), &mut debug_pass);
+                        let passes = FramePasses
+                        {
+                            shadows: &shadow_pass,
+                            lights: &light_cull_pass,
+                            post: &mut post_pass,
+                            debug_draw: &mut debug_draw,
+                        };
+                        let graph = render_passes::build_frame_graph(&renderer, &render_frame, passes, colors::DARK_GRAY, frame_time.delta_time);
+                        if let Err(err) = graph.execute(&renderer, &mut encoder, view)
+                        {
+                            log::error!("Failed to render the frame: {err}");
+                        }
                     }
                 }
 
@@ -496,6 +578,7 @@ fn main() -> ExitReason
                 debug_menu.add(renderer.deref());
                 debug_menu.add(&debug_draw);
                 debug_menu.add(&pipeline_cache);
+                debug_menu.add(&post_pass);
                 debug_menu.present();
 
                 debug_menu_memory.save_if_dirty(&debug_gui_savestate_path);
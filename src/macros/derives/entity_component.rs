@@ -0,0 +1,64 @@
+use proc_macro::TokenStream;
+use std::hash::Hasher;
+use metrohash::MetroHash64;
+use quote::quote;
+use syn::{parse_macro_input, parse_str, DeriveInput, Path};
+
+// Register a type as an entity component, so that it can be used in entity definitions
+// The type must implement serde::Deserialize and bitcode::{Encode, Decode}
+pub fn entity_component(input: TokenStream) -> TokenStream
+{
+    let input = parse_macro_input!(input as DeriveInput);
+    let typename_ident = &input.ident;
+    let typename_str = typename_ident.to_string();
+
+    let (entity_crate_name, type_name_hash) =
+    {
+        let crate_name = std::env::var("CARGO_PKG_NAME").unwrap();
+        let name_hash =
+        {
+            let mut hasher = MetroHash64::with_seed(0);
+            hasher.write(crate_name.as_bytes());
+            hasher.write(typename_str.as_bytes());
+            hasher.finish()
+        };
+
+        (if crate_name == "entity_3l14" { "crate" } else { "entity_3l14" }, name_hash)
+    };
+
+    let path = |mod_path: &str| -> Path { parse_str(&format!("{entity_crate_name}::{mod_path}")).unwrap() };
+    let path_buildmeta = path("component_meta::ComponentBuildMeta");
+    let path_runtimemeta = path("component_meta::ComponentRuntimeMeta");
+
+    (quote!
+    {
+        ::inventory::submit!
+        {
+            #path_buildmeta
+            {
+                type_name: #typename_str,
+                type_name_hash: #type_name_hash,
+                deserialize_and_encode_fn: |deserializer|
+                {
+                    let component: #typename_ident = ::erased_serde::deserialize(deserializer)?;
+                    Ok(::bitcode::encode(&component))
+                },
+            }
+        }
+        ::inventory::submit!
+        {
+            #path_runtimemeta
+            {
+                #[cfg(debug_assertions)]
+                type_name: #typename_str,
+                type_name_hash: #type_name_hash,
+                decode_and_insert_fn: |bytes, entity, store|
+                {
+                    let component: #typename_ident = ::bitcode::decode(bytes)?;
+                    store.insert(entity, component)?;
+                    Ok(())
+                },
+            }
+        }
+    }).into()
+}
@@ -2,4 +2,5 @@ pub mod fancy_enum;
 pub mod type_layout_hash;
 
 pub mod circuit_block;
+pub mod entity_component;
 pub mod enum_from_str;
\ No newline at end of file
@@ -2,7 +2,7 @@ use proc_macro::TokenStream;
 use syn::token::Token;
 use derives::{fancy_enum, type_layout_hash};
 use attribs::asset;
-use crate::derives::{circuit_block, enum_from_str};
+use crate::derives::{circuit_block, entity_component, enum_from_str};
 
 mod derives;
 mod has_derive;
@@ -28,6 +28,9 @@ pub fn derive_type_layout_hash(input: TokenStream) -> TokenStream { type_layout_
 #[proc_macro_derive(CircuitBlock)]
 pub fn derive_circuit_block(input: TokenStream) -> TokenStream { circuit_block::circuit_block(input) }
 
+#[proc_macro_derive(EntityComponent)]
+pub fn derive_entity_component(input: TokenStream) -> TokenStream { entity_component::entity_component(input) }
+
 #[proc_macro_attribute] // todo: better name?
 pub fn asset(attrib_input: TokenStream, input: TokenStream) -> TokenStream { asset::asset_attrib(attrib_input, input) }
 
@@ -8,28 +8,28 @@ use world_3l14::Light;
 #[derive(Serialize, Deserialize)]
 pub struct ModelPlacement
 {
-    model: u32,
-    position: Vec3,
-    orientation: Quat,
-    scale: Vec3,
+    pub model: u32, // index into the map's model palette
+    pub position: Vec3,
+    pub orientation: Quat,
+    pub scale: Vec3,
 }
 #[derive(Serialize, Deserialize)]
 pub struct EntityPlacement
 {
-    entity: u32,
-    position: Vec3,
-    orientation: Quat,
-    id: Ident,
+    pub entity: u32, // index into the map's entity palette
+    pub position: Vec3,
+    pub orientation: Quat,
+    pub id: Ident,
 }
 
 #[derive(Default, Serialize, Deserialize)]
 #[serde(default)]
 pub struct MapLayer
 {
-    // activation flags
-    models: Vec<ModelPlacement>,
-    lights: Vec<Light>,
-    entities: Vec<EntityPlacement>,
+    pub activation_flags: Vec<String>, // all must be set for this layer to be active, none means always active
+    pub models: Vec<ModelPlacement>,
+    pub lights: Vec<Light>,
+    pub entities: Vec<EntityPlacement>,
 }
 
 #[derive(Default, Serialize, Deserialize)]
@@ -39,5 +39,5 @@ pub struct MapDef
     pub name: String,
     pub model_palette: Vec<AssetKey>,
     pub entity_palette: Vec<AssetKey>,
-    // all activation flags
+    pub activation_flags: Vec<String>, // all activation flags that layers may reference
 }